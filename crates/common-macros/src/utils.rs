@@ -1,5 +1,5 @@
 use quote::quote;
-use syn::{PatType, ReturnType, Type};
+use syn::{GenericArgument, PatType, PathArguments, ReturnType, Type};
 
 #[repr(usize)]
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -50,9 +50,42 @@ pub fn parse_arg(pat: &PatType) -> proc_macro2::TokenStream {
     }
 }
 
+/// 返回值是 `Result<T, E>` 时返回 `T`
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
 pub fn parse_return(ret_ty: &ReturnType) -> proc_macro2::TokenStream {
     match ret_ty {
         syn::ReturnType::Default => quote! {},
+        // 返回 Result 时检查回复的 label，服务返回错误或者已经退出时返回错误码
+        syn::ReturnType::Type(_, ty) if result_ok_type(ty).is_some() => {
+            let ok_ty = result_ok_type(ty).unwrap();
+            let value = match ok_ty {
+                Type::Tuple(tuple) if tuple.elems.is_empty() => quote! { () },
+                _ if get_type(ok_ty) == ReturnTypeEnum::Number => quote! {
+                    sel4::with_ipc_buffer(|ib| ib.msg_regs()[0] as _)
+                },
+                _ => quote! { todo!("Not support non-numeric return type") },
+            };
+            quote! {
+                common::root::check_reply(ret)?;
+                Ok(#value)
+            }
+        }
         syn::ReturnType::Type(_, ty) => {
             let ty = get_type(ty);
             match ty {
@@ -15,27 +15,46 @@ pub const SERVICE_HEAP_SIZE: usize = 0x10_0000;
 
 /// VIRTIO_MMIO 使用的地址
 pub const VIRTIO_MMIO_ADDR: usize = 0xa003e00;
+/// 第一个 VIRTIO_MMIO 设备的地址
+pub const VIRTIO_MMIO_BASE_ADDR: usize = 0xa00_0000;
+/// 所有 VIRTIO_MMIO 设备占用的地址空间大小，QEMU 中有 32 个设备
+pub const VIRTIO_MMIO_SIZE: usize = 0x4000;
 
 /// PL011 设备使用过的地址
 pub const PL011_ADDR: usize = 0x0900_0000;
 /// PL011 对应的虚拟地址
 pub const VIRT_PL011_ADDR: usize = 0x1_2020_0000;
+/// 第二个 PL011 串口映射的虚拟地址，串口服务把日志控制台镜像到这个串口
+pub const VIRT_PL011_MIRROR_ADDR: usize = 0x1_2040_0000;
 
 /// 将要被映射的偏移地址，设备虚拟地址 = VIRT_ADDR + 设备物理地址
 pub const VIRTIO_MMIO_VIRT_ADDR: usize = 0x1_2000_0000;
 
 const VIRTIO_BLK_OFFSET: usize = 0x3e00;
 const VIRTIO_NET_OFFSET: usize = 0x3c00;
+const VIRTIO_CONSOLE_OFFSET: usize = 0x3a00;
+
+/// 每个 VIRTIO_MMIO 设备占用的地址空间大小
+pub const VIRTIO_MMIO_SLOT_SIZE: usize = 0x200;
 
 /// VIRTIO 块设备使用的虚拟地址
 pub const VIRTIO_MMIO_BLK_VIRT_ADDR: usize = VIRTIO_MMIO_VIRT_ADDR + VIRTIO_BLK_OFFSET;
 /// VIRTIO 网络设备使用的虚拟地址
 pub const VIRTIO_MMIO_NET_VIRT_ADDR: usize = VIRTIO_MMIO_VIRT_ADDR + VIRTIO_NET_OFFSET;
+/// VIRTIO 控制台设备使用的虚拟地址
+pub const VIRTIO_MMIO_CONSOLE_VIRT_ADDR: usize = VIRTIO_MMIO_VIRT_ADDR + VIRTIO_CONSOLE_OFFSET;
+
+/// VIRTIO 控制台设备使用的端口数量，每个虚拟控制台使用一个端口
+pub const VIRTIO_CONSOLE_PORTS: usize = 4;
 
 /// 串口的中断号
 pub const SERIAL_DEVICE_IRQ: usize = 33;
+/// VIRTIO 块设备的中断号
+pub const VIRTIO_BLK_IRQ: usize = 0x2f + 0x20;
 /// VIRTIO 网络设备的中断号
-pub const VIRTIO_NET_IRQ: usize = 0x2f + 0x20;
+pub const VIRTIO_NET_IRQ: usize = 0x2e + 0x20;
+/// VIRTIO 控制台设备的中断号
+pub const VIRTIO_CONSOLE_IRQ: usize = 0x2d + 0x20;
 
 /// 默认的 DMA 分配开始的地址
 pub const DMA_ADDR_START: usize = 0x1_0000_3000;
@@ -52,9 +71,18 @@ pub const PAGE_SIZE: usize = 0x1000;
 /// 默认的页的 mask 位
 pub const PAGE_MASK: usize = !0xfff;
 
+/// 大页的大小，设备内存按照大页映射
+pub const LARGE_PAGE_SIZE: usize = 0x20_0000;
+
 /// 默认存储自定义 Capability 的 SLOT
 pub const DEFAULT_CUSTOM_SLOT: u64 = 26;
 
+/// 日志环的 Notification 所在的 SLOT
+///
+/// 输出日志的串口服务为日志环登记 Notification 之后，root-task 把它复制到所有加入日志环的
+/// 任务的这个 SLOT 中，服务放入日志之后通知串口服务
+pub const LOG_NOTIFY_SLOT: u64 = 27;
+
 /// 默认服务可分配的 SLOT 开始的地址
 pub const DEFAULT_EMPTY_SLOT_INDEX: usize = 32;
 
@@ -64,6 +92,12 @@ pub const STACK_ALIGN_SIZE: usize = 16;
 /// 页共享使用的初始地址
 pub const SHARE_PAGE_START: usize = 0x1_001F_0000;
 
+/// 日志环共享内存通道的编号，root-task 启动时创建，所有服务都可以加入
+pub const LOG_CHANNEL_ID: usize = 0;
+
+/// 日志环共享内存通道的页数量
+pub const LOG_CHANNEL_PAGES: usize = 16;
+
 /// 通过 [sel4::cap::Endpoint] 发送一次数据最大数量
 pub const IPC_DATA_LEN: usize = 120 * 8;
 
@@ -0,0 +1,183 @@
+//! 驱动使用的 DMA 内存池
+//!
+//! 内存池通过 [alloc_dma] 向 root-task 申请物理地址连续的内存块，从 [DMA_ADDR_START] 开始依次映射。
+//! 释放的内存放回空闲列表并和同一个内存块中相邻的空闲内存合并，整个内存块空闲之后还给 root-task，
+//! 内存块使用的虚拟地址留给之后申请的内存块使用。
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::{
+    config::{DMA_ADDR_START, PAGE_SIZE},
+    root::{alloc_dma, free_dma},
+};
+
+/// 每次向 root-task 申请的最少的页数
+const DMA_CHUNK_PAGES: usize = 16;
+
+/// 当前服务的 DMA 内存池
+pub static DMA_POOL: Mutex<DmaPool> = Mutex::new(DmaPool::new(DMA_ADDR_START));
+
+/// 一段物理地址连续的 DMA 内存
+#[derive(Debug, Clone, Copy)]
+struct DmaRange {
+    vaddr: usize,
+    paddr: usize,
+    pages: usize,
+}
+
+impl DmaRange {
+    /// `other` 是否紧跟在这段内存之后，虚拟地址和物理地址都连续
+    const fn is_followed_by(&self, other: &Self) -> bool {
+        self.vaddr + self.pages * PAGE_SIZE == other.vaddr
+            && self.paddr + self.pages * PAGE_SIZE == other.paddr
+    }
+}
+
+/// DMA 内存池
+pub struct DmaPool {
+    /// 下一个内存块映射的虚拟地址
+    next_vaddr: usize,
+    /// 已经还给 root-task 的内存块留下的虚拟地址 (虚拟地址, 页数)，按照虚拟地址排序
+    windows: Vec<(usize, usize)>,
+    /// 从 root-task 申请的内存块
+    chunks: Vec<DmaRange>,
+    /// 空闲的内存，按照虚拟地址排序
+    free: Vec<DmaRange>,
+}
+
+impl DmaPool {
+    /// 创建一个从 `start` 开始映射内存块的内存池
+    pub const fn new(start: usize) -> Self {
+        Self {
+            next_vaddr: start,
+            windows: Vec::new(),
+            chunks: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// 申请 `pages` 个物理地址连续的页并清零，返回 (虚拟地址, 物理地址)
+    ///
+    /// root-task 无法分配更多的 DMA 内存时返回 [Option::None]
+    pub fn alloc(&mut self, pages: usize) -> Option<(usize, usize)> {
+        let idx = match self.free.iter().position(|x| x.pages >= pages) {
+            Some(idx) => idx,
+            None => self.grow(pages)?,
+        };
+        let range = &mut self.free[idx];
+        let (vaddr, paddr) = (range.vaddr, range.paddr);
+        range.vaddr += pages * PAGE_SIZE;
+        range.paddr += pages * PAGE_SIZE;
+        range.pages -= pages;
+        if range.pages == 0 {
+            self.free.remove(idx);
+        }
+        unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, pages * PAGE_SIZE) };
+        Some((vaddr, paddr))
+    }
+
+    /// `vaddr` 在内存池的内存块中时返回对应的物理地址
+    pub fn translate(&self, vaddr: usize) -> Option<usize> {
+        self.chunks
+            .iter()
+            .find(|x| (x.vaddr..x.vaddr + x.pages * PAGE_SIZE).contains(&vaddr))
+            .map(|x| x.paddr + (vaddr - x.vaddr))
+    }
+
+    /// 释放 [DmaPool::alloc] 申请的从 `vaddr` 开始的 `pages` 个页
+    pub fn dealloc(&mut self, vaddr: usize, pages: usize) {
+        let Some(chunk) = self
+            .chunks
+            .iter()
+            .find(|x| (x.vaddr..x.vaddr + x.pages * PAGE_SIZE).contains(&vaddr))
+        else {
+            log::warn!("[DmaPool] {:#x} is not a dma address", vaddr);
+            return;
+        };
+        let range = DmaRange {
+            vaddr,
+            paddr: chunk.paddr + (vaddr - chunk.vaddr),
+            pages,
+        };
+        let mut idx = self.free.partition_point(|x| x.vaddr < vaddr);
+        self.free.insert(idx, range);
+
+        if idx + 1 < self.free.len() && self.can_merge(idx) {
+            self.free[idx].pages += self.free.remove(idx + 1).pages;
+        }
+        if idx > 0 && self.can_merge(idx - 1) {
+            self.free[idx - 1].pages += self.free.remove(idx).pages;
+            idx -= 1;
+        }
+        self.release(idx);
+    }
+
+    /// 空闲内存 `idx` 和 `idx + 1` 是否在同一个内存块中并且连续
+    fn can_merge(&self, idx: usize) -> bool {
+        let next = &self.free[idx + 1];
+        self.free[idx].is_followed_by(next) && self.chunks.iter().all(|x| x.vaddr != next.vaddr)
+    }
+
+    /// 向 root-task 申请一个至少 `pages` 个页的内存块，返回内存块在空闲列表中的位置
+    fn grow(&mut self, pages: usize) -> Option<usize> {
+        let pages = pages.max(DMA_CHUNK_PAGES).next_power_of_two();
+        let window = self.windows.iter().position(|x| x.1 >= pages);
+        let vaddr = match window {
+            Some(idx) => self.windows[idx].0,
+            None => self.next_vaddr,
+        };
+        let paddr = alloc_dma(vaddr, pages * PAGE_SIZE, PAGE_SIZE).ok()?;
+        match window {
+            Some(idx) if self.windows[idx].1 == pages => {
+                self.windows.remove(idx);
+            }
+            Some(idx) => {
+                self.windows[idx].0 += pages * PAGE_SIZE;
+                self.windows[idx].1 -= pages;
+            }
+            None => self.next_vaddr += pages * PAGE_SIZE,
+        }
+
+        let chunk = DmaRange {
+            vaddr,
+            paddr,
+            pages,
+        };
+        self.chunks.push(chunk);
+        let idx = self.free.partition_point(|x| x.vaddr < vaddr);
+        self.free.insert(idx, chunk);
+        Some(idx)
+    }
+
+    /// 把还给 root-task 的内存块的虚拟地址放回 [DmaPool::windows]，和相邻的地址合并
+    fn reuse_window(&mut self, vaddr: usize, pages: usize) {
+        let idx = self.windows.partition_point(|x| x.0 < vaddr);
+        self.windows.insert(idx, (vaddr, pages));
+        if idx + 1 < self.windows.len() && vaddr + pages * PAGE_SIZE == self.windows[idx + 1].0 {
+            self.windows[idx].1 += self.windows.remove(idx + 1).1;
+        }
+        if idx > 0 {
+            let (prev, prev_pages) = self.windows[idx - 1];
+            if prev + prev_pages * PAGE_SIZE == vaddr {
+                self.windows[idx - 1].1 += self.windows.remove(idx).1;
+            }
+        }
+    }
+
+    /// 空闲内存 `idx` 是一个完整的内存块时还给 root-task
+    fn release(&mut self, idx: usize) {
+        let range = self.free[idx];
+        let Some(chunk) = self
+            .chunks
+            .iter()
+            .position(|x| x.vaddr == range.vaddr && x.pages == range.pages)
+        else {
+            return;
+        };
+        if free_dma(range.vaddr).is_ok() {
+            self.chunks.remove(chunk);
+            self.free.remove(idx);
+            self.reuse_window(range.vaddr, range.pages);
+        }
+    }
+}
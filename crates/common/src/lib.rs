@@ -7,6 +7,8 @@ mod obj_allocator;
 
 pub mod config;
 #[cfg(feature = "alloc")]
+pub mod dma;
+#[cfg(feature = "alloc")]
 pub mod ipc_saver;
 pub mod ipcrw;
 pub mod log_impl;
@@ -16,6 +18,8 @@ pub mod mem;
 pub mod page;
 pub mod root;
 pub mod slot;
+#[cfg(feature = "virtio")]
+pub mod virtio;
 
 pub use common_macros::{generate_ipc_send, ipc_trait, ipc_trait_impl};
 pub use obj_allocator::*;
@@ -5,8 +5,12 @@ use sel4::{
     with_ipc_buffer_mut,
 };
 use sel4_kit::slot_manager::LeafSlot;
+use syscalls::Errno;
 
-use crate::{ipcrw::IpcTypeWriter, slot::alloc_slot};
+use crate::{
+    ipcrw::IpcTypeWriter,
+    slot::{alloc_slot, recycle_slot},
+};
 
 #[derive(Debug, IntoPrimitive, TryFromPrimitive)]
 #[repr(u64)]
@@ -20,6 +24,127 @@ pub enum RootEvent {
     CreateChannel,
     JoinChannel,
     AllocUntyped,
+    FindDevice,
+    SpawnService,
+    Exit,
+    QueryUsage,
+    LeaveChannel,
+    DestroyChannel,
+    AllocDma,
+    FreeDma,
+    MapDevice,
+    AllocSchedContext,
+    SetChannelNotify,
+    GetChannelNotify,
+}
+
+/// 服务已经退出时 IPC 调用返回的 label，和 Linux 的 `ECONNRESET` 相同
+///
+/// 调用已经退出的服务时 root-task 会让调用直接返回，第一个消息寄存器为 `-1`
+pub const SERVICE_UNAVAILABLE: u64 = 104;
+
+/// 检查服务的回复，label 不为 0 时返回 label 对应的错误码
+///
+/// 服务已经退出时 label 为 [SERVICE_UNAVAILABLE]，返回 `ECONNRESET`
+pub fn check_reply(ret: MessageInfo) -> Result<MessageInfo, Errno> {
+    match ret.label() {
+        0 => Ok(ret),
+        label => Err(Errno::new(label as _)),
+    }
+}
+
+/// 服务使用的 root-task 资源，也用来表示服务的配额
+///
+/// - `untyped`  通过 [alloc_untyped] 申请的 untyped 内存的字节数
+/// - `pages`    通过 [alloc_page]、[create_channel] 和 [alloc_dma] 申请的页数
+/// - `channels` 创建的共享内存通道数
+/// - `slots`    root-task 为服务创建的 Capability 数，包括页、通知和加入通道时复制的页
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub untyped: usize,
+    pub pages: usize,
+    pub channels: usize,
+    pub slots: usize,
+}
+
+impl ResourceUsage {
+    /// 没有限制的配额
+    pub const UNLIMITED: Self = Self {
+        untyped: usize::MAX,
+        pages: usize::MAX,
+        channels: usize::MAX,
+        slots: usize::MAX,
+    };
+
+    /// `apps.toml` 中没有写出配额时使用的配额
+    pub const DEFAULT_QUOTA: Self = Self {
+        untyped: 0x100_0000,
+        pages: 256,
+        channels: 8,
+        slots: 1024,
+    };
+
+    /// 加上 `other` 之后的使用量，溢出时返回 [Option::None]
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(Self {
+            untyped: self.untyped.checked_add(other.untyped)?,
+            pages: self.pages.checked_add(other.pages)?,
+            channels: self.channels.checked_add(other.channels)?,
+            slots: self.slots.checked_add(other.slots)?,
+        })
+    }
+
+    /// 使用量是否都没有超过配额 `quota`
+    pub fn within(&self, quota: &Self) -> bool {
+        self.untyped <= quota.untyped
+            && self.pages <= quota.pages
+            && self.channels <= quota.channels
+            && self.slots <= quota.slots
+    }
+
+    /// 按照 untyped、pages、channels、slots 的顺序转换为数组
+    pub const fn to_array(&self) -> [usize; 4] {
+        [self.untyped, self.pages, self.channels, self.slots]
+    }
+
+    /// 从 [ResourceUsage::to_array] 的数组中恢复
+    pub const fn from_array(array: [usize; 4]) -> Self {
+        Self {
+            untyped: array[0],
+            pages: array[1],
+            channels: array[2],
+            slots: array[3],
+        }
+    }
+}
+
+/// 查询当前服务的资源使用量时使用的任务编号
+pub const QUERY_SELF: usize = usize::MAX;
+
+/// 设备类型，小于 `0x100` 的值和 virtio 的设备编号相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(usize)]
+pub enum DeviceKind {
+    VirtIONet = 1,
+    VirtIOBlock = 2,
+    VirtIOConsole = 3,
+    Pl011 = 0x100,
+}
+
+/// root-task 从设备树中找到的设备
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceInfo {
+    /// 设备寄存器的物理地址
+    pub paddr: usize,
+    /// 设备的中断号
+    pub irq: usize,
+}
+
+impl DeviceInfo {
+    /// 设备所在的大页映射到 `window` 时设备寄存器的虚拟地址
+    pub const fn vaddr(&self, window: usize) -> usize {
+        window + self.paddr % crate::config::LARGE_PAGE_SIZE
+    }
 }
 
 macro_rules! call_ep {
@@ -28,9 +153,12 @@ macro_rules! call_ep {
     };
 }
 
+/// 查找服务，root-task 把服务端口放到新申请的 slot 中
 pub fn find_service(name: &str) -> Result<LeafSlot, sel4::Error> {
+    let dst_slot = alloc_slot();
     let wlen = &mut 0;
     with_ipc_buffer_mut(|ib| {
+        dst_slot.raw().write_buffer(ib, wlen);
         name.write_buffer(ib, wlen);
     });
     let msg = MessageInfoBuilder::default()
@@ -39,14 +167,206 @@ pub fn find_service(name: &str) -> Result<LeafSlot, sel4::Error> {
         .build();
 
     let msg = call_ep!(msg);
-    if msg.extra_caps() == 0 {
+    if msg.label() != 0 {
+        recycle_slot(dst_slot);
         return Err(sel4::Error::FailedLookup);
     }
-    let dst_slot = alloc_slot();
-    LeafSlot::new(0).move_to(dst_slot)?;
     Ok(dst_slot)
 }
 
+/// 查询第 `index` 个 `kind` 类型的设备，同类设备按照 QEMU 命令行中的顺序编号
+pub fn find_device(kind: DeviceKind, index: usize) -> Option<DeviceInfo> {
+    with_ipc_buffer_mut(|ib| {
+        ib.msg_regs_mut()[0] = usize::from(kind) as _;
+        ib.msg_regs_mut()[1] = index as _;
+    });
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::FindDevice.into())
+        .length(2)
+        .build();
+
+    let msg = call_ep!(msg);
+    if msg.label() != 0 {
+        return None;
+    }
+    with_ipc_buffer(|ib| {
+        Some(DeviceInfo {
+            paddr: ib.msg_regs()[0] as _,
+            irq: ib.msg_regs()[1] as _,
+        })
+    })
+}
+
+/// 查询设备映射到 `window` 之后的 (虚拟地址, 中断号)
+///
+/// root-task 没有找到设备时 (例如没有设备树) 使用 `default` 中的固定配置
+pub fn find_device_or(
+    kind: DeviceKind,
+    index: usize,
+    window: usize,
+    default: (usize, usize),
+) -> (usize, usize) {
+    match find_device(kind, index) {
+        Some(device) => (device.vaddr(window), device.irq),
+        None => default,
+    }
+}
+
+/// 把物理地址 `paddr` 开始 `size` 字节的设备内存映射到 `window`，返回设备寄存器的虚拟地址
+///
+/// `paddr` 所在的 2MiB 内存映射到 `window`，`window` 需要按照 2MiB 对齐。
+/// `shared` 为 `true` 时可以和其他允许共享的任务同时映射。设备内存不存在、被其他任务独占
+/// 或者超过配额时返回 [sel4::Error::InvalidArgument]
+pub fn map_device(
+    window: usize,
+    paddr: usize,
+    size: usize,
+    shared: bool,
+) -> Result<usize, sel4::Error> {
+    with_ipc_buffer_mut(|ib| {
+        let regs = ib.msg_regs_mut();
+        regs[0] = window as _;
+        regs[1] = paddr as _;
+        regs[2] = size as _;
+        regs[3] = shared as _;
+    });
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::MapDevice.into())
+        .length(4)
+        .build();
+
+    let msg = call_ep!(msg);
+    if msg.label() != 0 {
+        return Err(sel4::Error::InvalidArgument);
+    }
+    Ok(window + paddr % crate::config::LARGE_PAGE_SIZE)
+}
+
+/// 申请当前服务创建的 Linux 应用使用的调度参数，返回 (应用的优先级, 调度上下文)
+///
+/// MCS 内核上 root-task 同时创建按照 apps.toml 中的 `app-sched` 配置的调度上下文，
+/// 非 MCS 内核上没有调度上下文。超过配额时返回 [sel4::Error::NotEnoughMemory]
+pub fn alloc_sched_context() -> Result<(usize, Option<LeafSlot>), sel4::Error> {
+    let recv_slot = with_ipc_buffer_mut(|ib| LeafSlot::new(ib.recv_slot().path().bits() as _));
+
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::AllocSchedContext.into())
+        .build();
+
+    let recv_msg = call_ep!(msg);
+    if recv_msg.label() != 0 {
+        return Err(sel4::Error::NotEnoughMemory);
+    }
+    let priority = with_ipc_buffer(|ib| ib.msg_regs()[0] as _);
+    if recv_msg.extra_caps() == 0 {
+        return Ok((priority, None));
+    }
+    let sched_context = alloc_slot();
+    recv_slot.move_to(sched_context)?;
+    Ok((priority, Some(sched_context)))
+}
+
+/// 动态启动的服务名称的最大长度
+pub const SPAWN_NAME_LEN: usize = 32;
+
+/// 动态启动的服务最多申请的设备内存和 DMA 内存的数量
+pub const SPAWN_MAX_REGIONS: usize = 4;
+
+/// 动态启动的服务最多可以查找的服务数量
+pub const SPAWN_MAX_SERVICES: usize = 8;
+
+/// 共享内存通道中 ELF 文件开始的位置，前面是 [SpawnRequest]
+pub const SPAWN_ELF_OFFSET: usize = crate::config::PAGE_SIZE;
+
+/// 动态启动服务的请求
+///
+/// 放在共享内存通道的开头，ELF 文件从 [SPAWN_ELF_OFFSET] 开始，通过 [spawn_service]
+/// 交给 root-task。名称以 0 结尾，大小为 0 的内存区域和空的服务名称会被忽略。
+/// 只有 `allow` 中写出 `spawn` 的任务可以启动服务
+#[repr(C)]
+pub struct SpawnRequest {
+    /// 服务名称
+    pub name: [u8; SPAWN_NAME_LEN],
+    /// ELF 文件的大小
+    pub elf_len: usize,
+    /// 设备内存 (虚拟地址, 物理地址, 大小, 是否可以和其他任务共享)，共享时为 1，否则为 0
+    pub mem: [(usize, usize, usize, u8); SPAWN_MAX_REGIONS],
+    /// DMA 内存 (虚拟地址, 大小)
+    pub dma: [(usize, usize); SPAWN_MAX_REGIONS],
+    /// 服务可以通过 [find_service] 查找的服务，其他服务都无法查找
+    pub services: [[u8; SPAWN_NAME_LEN]; SPAWN_MAX_SERVICES],
+}
+
+/// 定长数组中以 0 结尾的字符串
+fn spawn_str(name: &[u8; SPAWN_NAME_LEN]) -> &str {
+    let len = name.iter().position(|x| *x == 0).unwrap_or(SPAWN_NAME_LEN);
+    core::str::from_utf8(&name[..len]).unwrap_or_default()
+}
+
+/// 字符串复制到定长数组中，超过 [SPAWN_NAME_LEN] 时返回 `false`
+fn set_spawn_str(dst: &mut [u8; SPAWN_NAME_LEN], name: &str) -> bool {
+    if name.len() > SPAWN_NAME_LEN {
+        return false;
+    }
+    dst.fill(0);
+    dst[..name.len()].copy_from_slice(name.as_bytes());
+    true
+}
+
+impl SpawnRequest {
+    /// 清空请求
+    pub fn clear(&mut self) {
+        self.name.fill(0);
+        self.elf_len = 0;
+        self.mem.fill((0, 0, 0, 0));
+        self.dma.fill((0, 0));
+        self.services.iter_mut().for_each(|x| x.fill(0));
+    }
+
+    /// 服务名称
+    pub fn name(&self) -> &str {
+        spawn_str(&self.name)
+    }
+
+    /// 设置服务名称，名称太长时返回 `false`
+    pub fn set_name(&mut self, name: &str) -> bool {
+        set_spawn_str(&mut self.name, name)
+    }
+
+    /// 添加一个可以查找的服务，名称太长或者数量超过 [SPAWN_MAX_SERVICES] 时返回 `false`
+    pub fn allow_service(&mut self, name: &str) -> bool {
+        match self.services.iter_mut().find(|x| x[0] == 0) {
+            Some(slot) => set_spawn_str(slot, name),
+            None => false,
+        }
+    }
+
+    /// 可以查找的服务
+    pub fn services(&self) -> impl Iterator<Item = &str> {
+        self.services
+            .iter()
+            .map(spawn_str)
+            .filter(|x| !x.is_empty())
+    }
+}
+
+/// 启动共享内存通道 `channel_id` 中的服务，成功时返回服务的编号
+///
+/// 通道中需要放入 [SpawnRequest] 和 ELF 文件，启动之后可以通过 [find_service] 找到这个服务
+pub fn spawn_service(channel_id: usize) -> Result<usize, sel4::Error> {
+    with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = channel_id as _);
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::SpawnService.into())
+        .length(1)
+        .build();
+
+    let msg = call_ep!(msg);
+    if msg.label() != 0 {
+        return Err(sel4::Error::InvalidArgument);
+    }
+    Ok(with_ipc_buffer(|ib| ib.msg_regs()[0] as _))
+}
+
 // #[generate_ipc_send(label = RootEvent::TranslateAddr)]
 pub fn translate_addr(vaddr: usize) -> usize {
     let addr = &mut (vaddr as u64);
@@ -90,6 +410,9 @@ pub fn register_notify(target_slot: LeafSlot, badge: usize) -> Result<(), sel4::
         .build();
 
     let recv_msg = call_ep!(msg);
+    if recv_msg.label() != 0 {
+        return Err(sel4::Error::NotEnoughMemory);
+    }
     assert!(recv_msg.extra_caps() == 1);
     recv_slot.mint_to(target_slot, CapRights::all(), badge)?;
     recv_slot.delete()?;
@@ -109,6 +432,9 @@ pub fn alloc_page(target_slot: LeafSlot, addr: usize) -> Result<LeafSlot, sel4::
         .build();
 
     let recv_msg = call_ep!(msg);
+    if recv_msg.label() != 0 {
+        return Err(sel4::Error::NotEnoughMemory);
+    }
     assert!(recv_msg.extra_caps() == 1);
     recv_slot.move_to(target_slot)?;
 
@@ -123,18 +449,156 @@ pub fn alloc_untyped(target_slot: LeafSlot) -> Result<LeafSlot, sel4::Error> {
         .build();
 
     let recv_msg = call_ep!(msg);
+    if recv_msg.label() != 0 {
+        return Err(sel4::Error::NotEnoughMemory);
+    }
     assert!(recv_msg.extra_caps() == 1);
     recv_slot.move_to(target_slot)?;
 
     Ok(target_slot)
 }
 
+/// 创建一个共享内存通道并映射到 `addr`，返回通道编号
+///
+/// 超过配额时 root-task 返回 `usize::MAX`
 #[generate_ipc_send(label = RootEvent::CreateChannel)]
 pub fn create_channel(addr: usize, page_count: usize) -> usize {}
 
+/// 加入共享内存通道并映射到 `addr`，返回通道的大小
+///
+/// 通道不存在或者超过配额时 root-task 返回 0
 #[generate_ipc_send(label = RootEvent::JoinChannel)]
 pub fn join_channel(channel_id: usize, addr: usize) -> usize {}
 
+/// 发送只有一个参数的请求，root-task 回复的 label 不为 0 时返回 [sel4::Error::InvalidArgument]
+fn call_with_arg(event: RootEvent, arg: usize) -> Result<(), sel4::Error> {
+    with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = arg as _);
+    let msg = MessageInfoBuilder::default()
+        .label(event.into())
+        .length(1)
+        .build();
+    match call_ep!(msg).label() {
+        0 => Ok(()),
+        _ => Err(sel4::Error::InvalidArgument),
+    }
+}
+
+/// 离开共享内存通道并取消映射，最后一个任务离开之后通道会被释放
+pub fn leave_channel(channel_id: usize) -> Result<(), sel4::Error> {
+    call_with_arg(RootEvent::LeaveChannel, channel_id)
+}
+
+/// 删除自己创建的共享内存通道，所有加入通道的任务都会取消映射
+pub fn destroy_channel(channel_id: usize) -> Result<(), sel4::Error> {
+    call_with_arg(RootEvent::DestroyChannel, channel_id)
+}
+
+/// 为自己创建的共享内存通道登记一个 Notification
+///
+/// 加入通道的服务通过 [channel_notify] 获取这个 Notification，通道中有新的数据时通知创建者，
+/// 创建者不需要轮询。不是通道的创建者时返回 [sel4::Error::InvalidArgument]
+///
+/// 日志通道 ([crate::config::LOG_CHANNEL_ID]) 没有创建者，第一个登记的参与者成为创建者，
+/// Notification 会被复制到所有加入日志环的任务的 [crate::config::LOG_NOTIFY_SLOT]
+pub fn set_channel_notify(channel_id: usize, notify: LeafSlot) -> Result<(), sel4::Error> {
+    with_ipc_buffer_mut(|ib| {
+        ib.msg_regs_mut()[0] = channel_id as _;
+        ib.caps_or_badges_mut()[0] = notify.raw() as _;
+    });
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::SetChannelNotify.into())
+        .length(1)
+        .extra_caps(1)
+        .build();
+    match call_ep!(msg).label() {
+        0 => Ok(()),
+        _ => Err(sel4::Error::InvalidArgument),
+    }
+}
+
+/// 获取共享内存通道的创建者登记的 Notification，放入 `target_slot`
+///
+/// 没有加入通道或者创建者没有登记时返回 [sel4::Error::InvalidArgument]
+pub fn channel_notify(channel_id: usize, target_slot: LeafSlot) -> Result<(), sel4::Error> {
+    let recv_slot = with_ipc_buffer_mut(|ib| {
+        ib.msg_regs_mut()[0] = channel_id as _;
+        LeafSlot::new(ib.recv_slot().path().bits() as _)
+    });
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::GetChannelNotify.into())
+        .length(1)
+        .build();
+    let recv_msg = call_ep!(msg);
+    if recv_msg.label() != 0 || recv_msg.extra_caps() == 0 {
+        return Err(sel4::Error::InvalidArgument);
+    }
+    recv_slot.move_to(target_slot)
+}
+
+/// 申请 `size` 字节物理地址连续并且按照 `align` 对齐的 DMA 内存，映射到 `addr`，返回物理地址
+///
+/// 大小超过限制或者超过配额时返回 [sel4::Error::NotEnoughMemory]
+pub fn alloc_dma(addr: usize, size: usize, align: usize) -> Result<usize, sel4::Error> {
+    with_ipc_buffer_mut(|ib| {
+        let regs = ib.msg_regs_mut();
+        regs[0] = addr as _;
+        regs[1] = size as _;
+        regs[2] = align as _;
+    });
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::AllocDma.into())
+        .length(3)
+        .build();
+
+    let msg = call_ep!(msg);
+    if msg.label() != 0 {
+        return Err(sel4::Error::NotEnoughMemory);
+    }
+    Ok(with_ipc_buffer(|ib| ib.msg_regs()[0] as _))
+}
+
+/// 释放通过 [alloc_dma] 映射到 `addr` 的 DMA 内存
+pub fn free_dma(addr: usize) -> Result<(), sel4::Error> {
+    call_with_arg(RootEvent::FreeDma, addr)
+}
+
+/// 查询任务 `id` 的资源使用量和配额，[QUERY_SELF] 表示当前服务
+///
+/// 只能查询当前服务和当前服务通过 [spawn_service] 启动的服务。
+/// 返回 (使用量, 配额)，任务不存在或者无法查询时返回 [sel4::Error::InvalidArgument]
+pub fn query_usage(id: usize) -> Result<(ResourceUsage, ResourceUsage), sel4::Error> {
+    with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = id as _);
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::QueryUsage.into())
+        .length(1)
+        .build();
+
+    let msg = call_ep!(msg);
+    if msg.label() != 0 {
+        return Err(sel4::Error::InvalidArgument);
+    }
+    Ok(with_ipc_buffer(|ib| {
+        let regs = ib.msg_regs();
+        (
+            ResourceUsage::from_array(core::array::from_fn(|i| regs[i] as _)),
+            ResourceUsage::from_array(core::array::from_fn(|i| regs[4 + i] as _)),
+        )
+    }))
+}
+
+/// 退出当前服务，`code` 不为 0 时表示服务出错
+///
+/// root-task 会根据 `apps.toml` 中服务的重启策略决定是否重新启动服务
+pub fn exit(code: usize) -> ! {
+    with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = code as _);
+    let msg = MessageInfoBuilder::default()
+        .label(RootEvent::Exit.into())
+        .length(1)
+        .build();
+    call_ep!(msg);
+    unreachable!()
+}
+
 /// 向 ROOT_EP 发送关机
 pub fn shutdown() -> ! {
     sel4::sys::seL4_CallWithMRsWithoutIPCBuffer(
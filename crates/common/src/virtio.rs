@@ -1,44 +1,51 @@
+//! virtio 驱动使用的 [Hal] 实现
+//!
+//! DMA 内存从 [DMA_POOL] 中申请，共享给设备的缓冲区通过 root-task 查询物理地址。
 use alloc::collections::BTreeMap;
-use common::{config::DMA_ADDR_START, root::translate_addr};
-use core::{
-    ptr::NonNull,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use core::ptr::NonNull;
 use spin::Mutex;
 use virtio_drivers::{BufferDirection, Hal, PAGE_SIZE, PhysAddr};
 
-static DMA_ADDR: AtomicUsize = AtomicUsize::new(DMA_ADDR_START);
+use crate::{dma::DMA_POOL, root::translate_addr};
+
+/// 已经查询过的虚拟页号和物理页号
 static ADDR_MAP: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
 
+/// 查询虚拟地址对应的物理地址
+///
+/// DMA 内存池中的地址直接从内存池中查询，内存池的虚拟地址会被重新使用，不能缓存
 pub fn translate_address(vaddr: usize) -> usize {
+    if let Some(paddr) = DMA_POOL.lock().translate(vaddr) {
+        return paddr;
+    }
     let vp_index = vaddr / PAGE_SIZE;
     let offset = vaddr % PAGE_SIZE;
 
     let mut map = ADDR_MAP.lock();
-    let paddr = match map.get(&vp_index) {
+    match map.get(&vp_index) {
         Some(v) => v * PAGE_SIZE + offset,
         None => {
             let paddr = translate_addr(vaddr);
             map.insert(vp_index, paddr / PAGE_SIZE);
             paddr
         }
-    };
-    paddr
+    }
 }
 
+/// virtio 驱动使用的 [Hal]
 pub struct HalImpl;
 
 unsafe impl Hal for HalImpl {
     fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
-        let vaddr = DMA_ADDR.load(Ordering::Acquire);
-        DMA_ADDR.store(vaddr + pages * PAGE_SIZE, Ordering::Release);
-        (
-            translate_address(vaddr),
-            NonNull::new(vaddr as *mut u8).unwrap(),
-        )
+        let (vaddr, paddr) = DMA_POOL
+            .lock()
+            .alloc(pages)
+            .expect("can't allocate dma memory");
+        (paddr, NonNull::new(vaddr as *mut u8).unwrap())
     }
 
-    unsafe fn dma_dealloc(_paddr: PhysAddr, _vaddr: NonNull<u8>, _pages: usize) -> i32 {
+    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
+        DMA_POOL.lock().dealloc(vaddr.as_ptr() as usize, pages);
         0
     }
 
@@ -4,7 +4,7 @@
 extern crate sel4_panicking;
 
 use common::config::DEFAULT_EMPTY_SLOT_INDEX;
-use core::{hint::spin_loop, ptr};
+use core::ptr;
 use sel4_ctors_dtors::run_ctors;
 use sel4_kit::ipc_buffer::init_ipc_buffer;
 use sel4_panicking::catch_unwind;
@@ -32,9 +32,8 @@ unsafe extern "C" fn main_entry() -> ! {
 
         match catch_unwind(|| unsafe {
             _impl_main();
-            loop {
-                spin_loop();
-            }
+            // main() 返回时通知 root-task 服务已经退出
+            common::root::exit(0)
         }) {
             Ok(never) => never,
             Err(_) => {
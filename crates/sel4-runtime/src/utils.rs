@@ -22,6 +22,7 @@ use sel4_runtime_common::ContArg;
 /// - `tcb`   [Tcb] 创建线程使用的 Capability
 /// - `ipc_addr` IpcBuffer 使用的物理地址，需要 4k 对齐
 /// - `ipc_cap`  IpcBuffer 使用的物理页
+/// - `priority` 新线程的优先级，不能超过当前服务的优先级
 /// - `args`  参数列表
 ///
 /// TODO: 使用参数列表传递参数
@@ -31,6 +32,7 @@ pub fn create_thread(
     tcb: Tcb,
     ipc_addr: usize,
     ipc_cap: SmallPage,
+    priority: usize,
     _args: &[&str],
 ) -> Result<(), sel4::Error> {
     let mut ctx = UserContext::default();
@@ -48,7 +50,7 @@ pub fn create_thread(
         ipc_addr as _,
         ipc_cap,
     )?;
-    tcb.tcb_set_sched_params(slot::TCB.cap(), 0, 255)?;
+    tcb.tcb_set_sched_params(slot::TCB.cap(), 0, priority as _)?;
     tcb.tcb_write_all_registers(true, &mut ctx)
 }
 
@@ -1,12 +1,16 @@
 use crate::__prelude::*;
 use common::ipc_trait;
+use syscalls::Errno;
 
+/// 块设备服务接口
+///
+/// 服务已经退出时读写和获取容量返回 `ECONNRESET`
 #[ipc_trait(event = BLOCK_EVENT)]
 pub trait BlockIface: Sync + Send {
     fn init(&mut self, channel_id: usize);
-    fn read_block(&mut self, block_id: usize, block_num: usize);
-    fn write_block(&mut self, block_id: usize, block_num: usize);
-    fn capacity(&self) -> u64;
+    fn read_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno>;
+    fn write_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno>;
+    fn capacity(&self) -> Result<u64, Errno>;
 }
 
 #[cfg(blk_ipc)]
@@ -15,6 +19,7 @@ mod _impl {
     use crate::def_blk_impl;
     use common::{generate_ipc_send, root::find_service};
     use sel4::cap::Endpoint;
+    use syscalls::Errno;
 
     def_blk_impl!(BLK_IPC, BlockIfaceIPCImpl {
         ep: find_service("block-thread").unwrap().into(),
@@ -32,17 +37,17 @@ mod _impl {
         }
 
         #[generate_ipc_send(label = BlockIfaceEvent::read_block)]
-        fn read_block(&mut self, block_id: usize, block_num: usize) {
+        fn read_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno> {
             todo!()
         }
 
         #[generate_ipc_send(label = BlockIfaceEvent::write_block)]
-        fn write_block(&mut self, block_id: usize, block_num: usize) {
+        fn write_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno> {
             todo!()
         }
 
         #[generate_ipc_send(label = BlockIfaceEvent::capacity)]
-        fn capacity(&self) -> u64 {
+        fn capacity(&self) -> Result<u64, Errno> {
             todo!()
         }
     }
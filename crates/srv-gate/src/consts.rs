@@ -1,3 +1,4 @@
 pub const UART_EVENT: u64 = 0x20;
 pub const BLOCK_EVENT: u64 = 0x21;
 pub const FS_EVENT: u64 = 0x22;
+pub const NET_EVENT: u64 = 0x23;
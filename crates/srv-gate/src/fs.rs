@@ -3,17 +3,33 @@ use common::ipc_trait;
 use libc_core::types::Stat;
 use syscalls::Errno;
 
+/// 文件系统服务接口
+///
+/// 服务已经退出时返回 `Result` 的请求返回 `ECONNRESET`
 #[ipc_trait(event = FS_EVENT)]
 pub trait FSIface: Sync + Send {
     fn init(&mut self, channel_id: usize, addr: usize, size: usize);
-    fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> usize;
-    fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> usize;
+    fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> Result<usize, Errno>;
+    fn copy_range(
+        &mut self,
+        src: u64,
+        src_off: usize,
+        dst: u64,
+        dst_off: usize,
+        len: usize,
+    ) -> Result<usize, Errno>;
     fn open(&mut self, path: &str, flags: u32) -> Result<(usize, usize), Errno>;
     fn mkdir(&self, path: &str);
     fn unlink(&self, path: &str);
     fn close(&mut self, inode: usize);
-    fn stat(&mut self, inode: usize) -> Stat;
-    fn getdents64(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> (usize, usize);
+    fn stat(&mut self, inode: usize) -> Result<Stat, Errno>;
+    fn getdents64(
+        &mut self,
+        inode: u64,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(usize, usize), Errno>;
 }
 
 #[cfg(fs_ipc)]
@@ -25,7 +41,7 @@ mod _impl {
     use common::{
         config::{IPC_DATA_LEN, REG_LEN},
         generate_ipc_send,
-        root::find_service,
+        root::{check_reply, find_service},
     };
     use libc_core::types::Stat;
     use sel4::{MessageInfoBuilder, cap::Endpoint, with_ipc_buffer, with_ipc_buffer_mut};
@@ -57,7 +73,7 @@ mod _impl {
         }
 
         #[inline]
-        fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> usize {
+        fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
             let trans_len = cmp::min(buf.len(), self.share_size);
             with_ipc_buffer_mut(|ib| {
                 let regs = ib.msg_regs_mut();
@@ -69,18 +85,17 @@ mod _impl {
                 .label(FSIfaceEvent::read_at.into())
                 .length(3)
                 .build();
-            let ret = self.ep.call(msg);
-            assert_eq!(ret.label(), 0);
+            check_reply(self.ep.call(msg))?;
             let rlen = with_ipc_buffer(|ib| ib.msg_regs()[0] as usize);
             let ptr = self.share_addr as *mut u8;
             unsafe {
                 ptr.copy_to_nonoverlapping(buf.as_mut_ptr(), rlen);
             }
-            rlen
+            Ok(rlen)
         }
 
         #[inline]
-        fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> usize {
+        fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> Result<usize, Errno> {
             let trans_len = cmp::min(data.len(), IPC_DATA_LEN - 3 * REG_LEN);
             with_ipc_buffer_mut(|ib| {
                 let regs = ib.msg_regs_mut();
@@ -94,9 +109,32 @@ mod _impl {
                 .label(FSIfaceEvent::write_at.into())
                 .length(3 + data.len().div_ceil(REG_LEN))
                 .build();
-            let ret = self.ep.call(msg);
-            assert_eq!(ret.label(), 0);
-            with_ipc_buffer(|ib| ib.msg_regs()[0] as usize)
+            check_reply(self.ep.call(msg))?;
+            Ok(with_ipc_buffer(|ib| ib.msg_regs()[0] as usize))
+        }
+
+        fn copy_range(
+            &mut self,
+            src: u64,
+            src_off: usize,
+            dst: u64,
+            dst_off: usize,
+            len: usize,
+        ) -> Result<usize, Errno> {
+            with_ipc_buffer_mut(|ib| {
+                let regs = ib.msg_regs_mut();
+                regs[0] = src;
+                regs[1] = src_off as _;
+                regs[2] = dst;
+                regs[3] = dst_off as _;
+                regs[4] = len as _;
+            });
+            let msg = MessageInfoBuilder::default()
+                .label(FSIfaceEvent::copy_range.into())
+                .length(5)
+                .build();
+            check_reply(self.ep.call(msg))?;
+            Ok(with_ipc_buffer(|ib| ib.msg_regs()[0] as usize))
         }
 
         fn open(&mut self, path: &str, flags: u32) -> Result<(usize, usize), Errno> {
@@ -112,12 +150,8 @@ mod _impl {
                 .label(FSIfaceEvent::open.into())
                 .length(len)
                 .build();
-            let ret = self.ep.call(msg);
-            if ret.label() == 0 {
-                with_ipc_buffer(|ib| Ok((ib.msg_regs()[0] as _, ib.msg_regs()[1] as _)))
-            } else {
-                Err(Errno::new(ret.label() as _))
-            }
+            check_reply(self.ep.call(msg))?;
+            with_ipc_buffer(|ib| Ok((ib.msg_regs()[0] as _, ib.msg_regs()[1] as _)))
         }
 
         #[generate_ipc_send(label = FSIfaceEvent::mkdir)]
@@ -129,22 +163,26 @@ mod _impl {
         #[generate_ipc_send(label = FSIfaceEvent::close)]
         fn close(&mut self, inode: usize) {}
 
-        fn stat(&mut self, inode: usize) -> Stat {
+        fn stat(&mut self, inode: usize) -> Result<Stat, Errno> {
             with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = inode as _);
             let msg = MessageInfoBuilder::default()
                 .label(FSIfaceEvent::stat.into())
                 .length(1)
                 .build();
-            let ret = self.ep.call(msg);
-            assert_eq!(ret.label(), 0);
+            check_reply(self.ep.call(msg))?;
             let stat = with_ipc_buffer(|ib| {
                 let ptr = ib.msg_bytes().as_ptr() as *const Stat;
                 unsafe { ptr.as_ref().unwrap().clone() }
             });
-            stat.clone()
+            Ok(stat)
         }
 
-        fn getdents64(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> (usize, usize) {
+        fn getdents64(
+            &mut self,
+            inode: u64,
+            offset: usize,
+            buf: &mut [u8],
+        ) -> Result<(usize, usize), Errno> {
             with_ipc_buffer_mut(|ib| {
                 ib.msg_regs_mut()[0] = inode;
                 ib.msg_regs_mut()[1] = offset as _;
@@ -154,14 +192,13 @@ mod _impl {
                 .label(FSIfaceEvent::getdents64.into())
                 .length(3)
                 .build();
-            let ret = self.ep.call(msg);
-            assert_eq!(ret.label(), 0);
-            with_ipc_buffer(|ib| {
+            check_reply(self.ep.call(msg))?;
+            Ok(with_ipc_buffer(|ib| {
                 let rlen = ib.msg_regs()[0] as usize;
                 let num = ib.msg_regs()[1] as usize;
                 buf[..rlen].copy_from_slice(&ib.msg_bytes()[2 * REG_LEN..2 * REG_LEN + rlen]);
                 (rlen, num)
-            })
+            }))
         }
     }
 }
@@ -0,0 +1,662 @@
+//! 日志环
+//!
+//! root-task 启动时创建一个所有服务共享的日志环 ([LOG_CHANNEL_ID])，服务通过 [init] 加入日志环
+//! 并设置全局的 logger，日志连同时间、服务名称和级别一起放入日志环。
+//! kernel-thread 通过 `/dev/kmsg` 和 `syslog(2)` 读取日志环。
+//!
+//! 服务的日志不直接输出到控制台，串口服务通过 [LogCursor] 读取新的日志并输出到服务日志的
+//! 虚拟控制台。串口服务为日志环登记 Notification 并调用 [LogRing::enable_doorbell] 之后，
+//! 放入日志时通过 [LOG_NOTIFY_SLOT] 中的 Notification 通知串口服务。
+//!
+//! 每个服务的日志级别也保存在日志环中，修改之后立即对这个服务生效。
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use common::{
+    config::{LOG_CHANNEL_ID, LOG_CHANNEL_PAGES, LOG_NOTIFY_SLOT, PAGE_SIZE},
+    root::join_channel,
+};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering, fence},
+    time::Duration,
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use sel4::cap::Notification;
+use spin::Once;
+
+/// kernel-thread 在日志环中的服务名称，它的日志由 kernel-thread 输出到内核日志的虚拟控制台
+pub const KERNEL_LOG_SERVICE: &str = "kernel-thread";
+
+/// 服务名称的最大长度
+pub const LOG_SERVICE_NAME_LEN: usize = 16;
+
+/// 日志环中最多记录的服务数量
+pub const LOG_MAX_SERVICES: usize = 16;
+
+/// 一条日志的最大长度，超出的部分会被截断
+pub const LOG_MSG_LEN: usize = 216;
+
+/// 日志环中日志的数量，除去日志环开头的序号和服务列表
+pub const LOG_RECORDS: usize = LOG_CHANNEL_PAGES * PAGE_SIZE / size_of::<LogSlot>() - 3;
+
+const _: () = assert!(size_of::<LogRing>() <= LOG_CHANNEL_PAGES * PAGE_SIZE);
+
+/// 服务列表中没有使用的项
+const SERVICE_FREE: usize = 0;
+
+/// 服务列表中正在写入名称的项
+const SERVICE_CLAIMED: usize = 1;
+
+/// 服务列表中已经注册的项
+const SERVICE_READY: usize = 2;
+
+/// 日志环中的一项正在被写入，和 `seq + 1` 一起保存在 [LogSlot::state] 中
+const RECORD_BUSY: u64 = 1 << 63;
+
+/// 日志环中记录的服务
+#[repr(C)]
+struct LogService {
+    /// 服务名称，注册之后不再修改
+    name: UnsafeCell<[u8; LOG_SERVICE_NAME_LEN]>,
+    /// 服务的日志级别，[LevelFilter] 的值
+    level: AtomicUsize,
+    /// [SERVICE_FREE]、[SERVICE_CLAIMED] 或者 [SERVICE_READY]
+    state: AtomicUsize,
+}
+
+/// 日志环中的一项
+#[repr(C)]
+struct LogSlot {
+    /// 0 表示没有日志，`seq + 1` 表示序号为 `seq` 的日志已经提交，
+    /// 加上 [RECORD_BUSY] 表示序号为 `seq` 的日志正在写入
+    state: AtomicU64,
+    /// 日志的内容
+    record: UnsafeCell<LogRecord>,
+}
+
+/// 日志环中的一条日志
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LogRecord {
+    /// 日志的时间 (微秒)
+    timestamp: u64,
+    /// 服务名称
+    service: [u8; LOG_SERVICE_NAME_LEN],
+    /// 日志级别，[Level] 的值
+    level: u8,
+    _pad: u8,
+    /// 日志的长度
+    len: u16,
+    _pad2: u32,
+    /// 日志的内容
+    msg: [u8; LOG_MSG_LEN],
+}
+
+impl LogSlot {
+    /// 为序号为 `seq` 的日志占用这一项，这一项已经被更新的日志占用时返回 `false`
+    ///
+    /// 正在写入的更旧的日志会被覆盖，写入旧日志的服务可能已经退出
+    fn claim(&self, seq: u64) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & !RECORD_BUSY > seq {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                (seq + 1) | RECORD_BUSY,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(x) => state = x,
+            }
+        }
+        // 读者看到新的状态之前不能看到新写入的内容
+        fence(Ordering::Release);
+        true
+    }
+
+    /// 提交序号为 `seq` 的日志，写入的过程中被更新的日志占用时不会提交
+    fn commit(&self, seq: u64) {
+        let _ = self.state.compare_exchange(
+            (seq + 1) | RECORD_BUSY,
+            seq + 1,
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// 从日志环中读取的一条日志
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// 日志的序号
+    pub seq: u64,
+    /// 日志的时间
+    pub timestamp: Duration,
+    /// 日志级别
+    pub level: Level,
+    /// 服务名称
+    pub service: String,
+    /// 日志的内容
+    pub message: String,
+}
+
+impl LogEntry {
+    /// 输出到控制台的带颜色的一行日志，格式和 [common::init_log] 一致
+    pub fn colored(&self) -> String {
+        format!(
+            "\u{1B}[{}m[{} {}] {}\u{1B}[0m\n",
+            color_code(self.level),
+            self.service,
+            self.level,
+            self.message
+        )
+    }
+}
+
+/// 日志级别在控制台中的颜色
+const fn color_code(level: Level) -> u8 {
+    match level {
+        Level::Error => 31, // Red
+        Level::Warn => 93,  // BrightYellow
+        Level::Info => 34,  // Blue
+        Level::Debug => 32, // Green
+        Level::Trace => 90, // BrightBlack
+    }
+}
+
+/// 共享内存中的多生产者日志环
+///
+/// 日志的序号只增不减，日志环满了之后覆盖最旧的日志。日志环中没有锁，服务在持有锁的时候
+/// 退出或者被抢占不会阻塞其他服务：写者先取得序号，占用对应的项之后写入日志再提交，
+/// 读者只读取已经提交的日志，读取之后再次检查这一项没有被覆盖
+#[repr(C)]
+pub struct LogRing {
+    /// 下一条日志的序号
+    next_seq: AtomicU64,
+    /// 串口服务是否已经登记了日志环的 Notification
+    doorbell: AtomicBool,
+    /// 记录的服务
+    services: [LogService; LOG_MAX_SERVICES],
+    /// 日志
+    records: [LogSlot; LOG_RECORDS],
+}
+
+unsafe impl Sync for LogRing {}
+
+/// 把字符串截断后复制到定长的数组中，剩余部分填 0
+fn copy_name(name: &str) -> [u8; LOG_SERVICE_NAME_LEN] {
+    let mut buf = [0u8; LOG_SERVICE_NAME_LEN];
+    let len = name.len().min(LOG_SERVICE_NAME_LEN);
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf
+}
+
+/// 定长数组中的字符串
+fn name_str(name: &[u8]) -> String {
+    let len = name.iter().position(|x| *x == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).to_string()
+}
+
+/// [LevelFilter] 转换为保存在日志环中的值
+const fn filter_to_usize(level: LevelFilter) -> usize {
+    level as usize
+}
+
+/// 日志环中保存的值转换为 [LevelFilter]
+fn filter_from_usize(level: usize) -> LevelFilter {
+    LevelFilter::iter()
+        .find(|x| *x as usize == level)
+        .unwrap_or(LevelFilter::Trace)
+}
+
+impl LogRing {
+    /// 从共享内存地址获取日志环
+    ///
+    /// # Safety
+    ///
+    /// `addr` 必须指向已经映射的日志环通道，大小至少为 [LOG_CHANNEL_PAGES] 页，
+    /// 并且在程序运行期间一直有效
+    pub unsafe fn from_addr(addr: usize) -> &'static Self {
+        unsafe { &*(addr as *const Self) }
+    }
+
+    /// 已经注册的名称为 `name` 的服务的编号
+    fn find(&self, name: &[u8; LOG_SERVICE_NAME_LEN]) -> Option<usize> {
+        self.services.iter().position(|x| {
+            x.state.load(Ordering::Acquire) == SERVICE_READY && unsafe { *x.name.get() } == *name
+        })
+    }
+
+    /// 注册一个服务，返回服务的编号
+    ///
+    /// 服务已经注册过时 (例如服务重启) 返回原来的编号并保留原来的日志级别，
+    /// 服务列表已满时返回 [Option::None]
+    pub fn register(&self, name: &str, level: LevelFilter) -> Option<usize> {
+        let name = copy_name(name);
+        loop {
+            if let Some(index) = self.find(&name) {
+                return Some(index);
+            }
+            let index = self
+                .services
+                .iter()
+                .position(|x| x.state.load(Ordering::Relaxed) == SERVICE_FREE)?;
+            let service = &self.services[index];
+            // 其他服务同时占用了这一项时重新查找
+            if service
+                .state
+                .compare_exchange(
+                    SERVICE_FREE,
+                    SERVICE_CLAIMED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                continue;
+            }
+            unsafe { *service.name.get() = name };
+            service
+                .level
+                .store(filter_to_usize(level), Ordering::Relaxed);
+            service.state.store(SERVICE_READY, Ordering::Release);
+            return Some(index);
+        }
+    }
+
+    /// 获取服务的日志级别
+    pub fn level(&self, index: usize) -> LevelFilter {
+        self.services.get(index).map_or(LevelFilter::Off, |x| {
+            filter_from_usize(x.level.load(Ordering::Acquire))
+        })
+    }
+
+    /// 修改服务的日志级别，服务不存在时返回 `false`
+    pub fn set_level(&self, name: &str, level: LevelFilter) -> bool {
+        self.find(&copy_name(name))
+            .inspect(|x| {
+                self.services[*x]
+                    .level
+                    .store(filter_to_usize(level), Ordering::Release)
+            })
+            .is_some()
+    }
+
+    /// 所有注册的服务和它们的日志级别
+    pub fn services(&self) -> Vec<(String, LevelFilter)> {
+        self.services
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.state.load(Ordering::Acquire) == SERVICE_READY)
+            .map(|(index, x)| (name_str(unsafe { &*x.name.get() }), self.level(index)))
+            .collect()
+    }
+
+    /// 放入一条日志，返回日志的序号
+    ///
+    /// 日志环回绕到这一项时更新的日志已经占用了这一项，这条日志会被丢弃
+    pub fn push(&self, service: &str, level: Level, timestamp: Duration, msg: &[u8]) -> u64 {
+        let len = msg.len().min(LOG_MSG_LEN);
+        let mut record = LogRecord {
+            timestamp: timestamp.as_micros() as _,
+            service: copy_name(service),
+            level: level as _,
+            _pad: 0,
+            len: len as _,
+            _pad2: 0,
+            msg: [0; LOG_MSG_LEN],
+        };
+        record.msg[..len].copy_from_slice(&msg[..len]);
+
+        let seq = self.next_seq.fetch_add(1, Ordering::AcqRel);
+        let slot = &self.records[seq as usize % LOG_RECORDS];
+        if slot.claim(seq) {
+            unsafe { slot.record.get().write_volatile(record) };
+            slot.commit(seq);
+        }
+        if self.doorbell.load(Ordering::Acquire) {
+            Notification::from_bits(LOG_NOTIFY_SLOT).signal();
+        }
+        seq
+    }
+
+    /// 串口服务为日志环登记 Notification 之后调用，之后放入日志时通知串口服务
+    pub fn enable_doorbell(&self) {
+        self.doorbell.store(true, Ordering::Release);
+    }
+
+    /// 下一条日志的序号，序号小于它的日志可能还没有提交
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq.load(Ordering::Acquire)
+    }
+
+    /// 日志环中最旧的日志的序号
+    pub fn first_seq(&self) -> u64 {
+        self.next_seq().saturating_sub(LOG_RECORDS as _)
+    }
+
+    /// 序号为 `seq` 的日志已经取得了序号，但是还没有提交
+    pub fn pending(&self, seq: u64) -> bool {
+        if seq >= self.next_seq() {
+            return false;
+        }
+        let state = self.records[seq as usize % LOG_RECORDS]
+            .state
+            .load(Ordering::Acquire);
+        // 写者还没有占用这一项时，这一项中还是更旧的日志
+        state != seq + 1 && state & !RECORD_BUSY <= seq + 1
+    }
+
+    /// 读取序号为 `seq` 的日志
+    ///
+    /// 日志还没有提交或者已经被覆盖时返回 [Option::None]，使用 [LogRing::pending] 区分
+    pub fn read(&self, seq: u64) -> Option<LogEntry> {
+        let next = self.next_seq();
+        if seq >= next || next - seq > LOG_RECORDS as u64 {
+            return None;
+        }
+        let slot = &self.records[seq as usize % LOG_RECORDS];
+        let state = slot.state.load(Ordering::Acquire);
+        if state != seq + 1 {
+            return None;
+        }
+        let record = unsafe { slot.record.get().read_volatile() };
+        // 读取的过程中被新的日志覆盖时丢弃读到的内容
+        fence(Ordering::Acquire);
+        if slot.state.load(Ordering::Relaxed) != state {
+            return None;
+        }
+        let len = (record.len as usize).min(LOG_MSG_LEN);
+        Some(LogEntry {
+            seq,
+            timestamp: Duration::from_micros(record.timestamp),
+            level: Level::iter()
+                .find(|x| *x as u8 == record.level)
+                .unwrap_or(Level::Info),
+            service: name_str(&record.service),
+            message: String::from_utf8_lossy(&record.msg[..len]).to_string(),
+        })
+    }
+}
+
+/// 把日志放入日志环的 logger，设置了控制台时同时输出到控制台
+struct RingLogger {
+    /// 日志环
+    ring: Once<&'static LogRing>,
+    /// 服务名称
+    name: Once<&'static str>,
+    /// 服务在日志环中的编号
+    index: AtomicUsize,
+    /// 输出到控制台的函数
+    console: Once<fn(&str, &Record)>,
+}
+
+static LOGGER: RingLogger = RingLogger {
+    ring: Once::new(),
+    name: Once::new(),
+    index: AtomicUsize::new(0),
+    console: Once::new(),
+};
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.ring
+            .get()
+            .is_some_and(|ring| metadata.level() <= ring.level(self.index.load(Ordering::Relaxed)))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let (Some(ring), Some(name)) = (self.ring.get(), self.name.get()) else {
+            return;
+        };
+        let msg = format!("{}", record.args());
+        ring.push(
+            name,
+            record.level(),
+            sel4_kit::arch::current_time(),
+            msg.as_bytes(),
+        );
+        if let Some(console) = self.console.get() {
+            console(name, record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// 写者取得序号之后超过这个时间还没有提交时，读者跳过这条日志
+pub const LOG_COMMIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// 按顺序读取日志环中新放入的日志
+///
+/// 遇到还没有提交的日志时停下来等待，写者可能在写入的过程中退出，
+/// 等待超过 [LOG_COMMIT_TIMEOUT] 之后跳过这条日志
+#[derive(Debug, Clone)]
+pub struct LogCursor {
+    /// 下一条要读取的日志的序号
+    next: u64,
+    /// 正在等待提交的日志的序号和开始等待的时间
+    stalled: Option<(u64, Duration)>,
+}
+
+impl LogCursor {
+    /// 从日志环中最旧的日志开始读取
+    pub const fn new() -> Self {
+        Self::at(0)
+    }
+
+    /// 从序号为 `seq` 的日志开始读取，日志已经被覆盖时从最旧的日志开始
+    pub const fn at(seq: u64) -> Self {
+        Self {
+            next: seq,
+            stalled: None,
+        }
+    }
+
+    /// 下一条要读取的日志的序号
+    pub const fn seq(&self) -> u64 {
+        self.next
+    }
+
+    /// 读取下一条日志，已经被覆盖的日志会被跳过
+    ///
+    /// 没有新的日志或者下一条日志还没有提交时返回 [Option::None]
+    pub fn next(&mut self, ring: &LogRing) -> Option<LogEntry> {
+        loop {
+            self.next = self.next.max(ring.first_seq());
+            if self.next >= ring.next_seq() {
+                return None;
+            }
+            let seq = self.next;
+            if let Some(entry) = ring.read(seq) {
+                self.next += 1;
+                return Some(entry);
+            }
+            if ring.pending(seq) {
+                let now = sel4_kit::arch::current_time();
+                match self.stalled {
+                    Some((x, since)) if x == seq && now - since >= LOG_COMMIT_TIMEOUT => {}
+                    Some((x, _)) if x == seq => return None,
+                    _ => {
+                        self.stalled = Some((seq, now));
+                        return None;
+                    }
+                }
+            }
+            self.next += 1;
+        }
+    }
+
+    /// 读取上次读取之后放入的所有已经提交的日志
+    pub fn poll(&mut self, ring: &LogRing) -> Vec<LogEntry> {
+        core::iter::from_fn(|| self.next(ring)).collect()
+    }
+}
+
+impl Default for LogCursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 加入日志环并设置全局的 logger
+///
+/// ## 参数
+/// - `name`    服务名称，超过 [LOG_SERVICE_NAME_LEN] 的部分会被截断
+/// - `addr`    映射日志环的地址
+/// - `level`   服务第一次注册时的日志级别
+/// - `console` 日志同时输出到控制台的函数，服务的日志由串口服务输出，不需要设置
+///
+/// 返回日志环映射的大小
+pub fn init(
+    name: &'static str,
+    addr: usize,
+    level: LevelFilter,
+    console: Option<fn(&str, &Record)>,
+) -> usize {
+    let size = join_channel(LOG_CHANNEL_ID, addr);
+    let ring = unsafe { LogRing::from_addr(addr) };
+    let index = ring.register(name, level).unwrap_or(LOG_MAX_SERVICES);
+    LOGGER.index.store(index, Ordering::Relaxed);
+    LOGGER.name.call_once(|| name);
+    if let Some(console) = console {
+        LOGGER.console.call_once(|| console);
+    }
+    LOGGER.ring.call_once(|| ring);
+    // 日志级别在 logger 中判断，这样修改日志环中的级别之后可以立即生效
+    log::set_max_level(LevelFilter::Trace);
+    log::set_logger(&LOGGER).unwrap();
+    size
+}
+
+/// 当前服务使用的日志环，还没有调用 [init] 时返回 [Option::None]
+pub fn ring() -> Option<&'static LogRing> {
+    LOGGER.ring.get().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{
+        alloc::{Layout, alloc_zeroed},
+        boxed::Box,
+    };
+
+    /// 在堆上创建一个全 0 的日志环，和 root-task 新创建的通道相同
+    fn zeroed_ring() -> Box<LogRing> {
+        unsafe { Box::from_raw(alloc_zeroed(Layout::new::<LogRing>()) as *mut LogRing) }
+    }
+
+    fn push(ring: &LogRing, msg: &str) -> u64 {
+        ring.push(
+            "test",
+            Level::Info,
+            Duration::from_micros(7),
+            msg.as_bytes(),
+        )
+    }
+
+    #[test]
+    fn slot_claim_commit() {
+        let slot = LogSlot {
+            state: AtomicU64::new(0),
+            record: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+        };
+        assert!(slot.claim(0));
+        assert_eq!(slot.state.load(Ordering::Relaxed), 1 | RECORD_BUSY);
+        slot.commit(0);
+        assert_eq!(slot.state.load(Ordering::Relaxed), 1);
+
+        // 更新的日志覆盖旧的日志，更旧的日志不能再占用
+        assert!(slot.claim(5));
+        slot.commit(5);
+        assert!(!slot.claim(3));
+        assert_eq!(slot.state.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn slot_overtaken_writer() {
+        let slot = LogSlot {
+            state: AtomicU64::new(0),
+            record: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+        };
+        // 写者在写入的过程中被更新的日志占用，提交不生效
+        assert!(slot.claim(10));
+        assert!(slot.claim(20));
+        slot.commit(10);
+        assert_eq!(slot.state.load(Ordering::Relaxed), 21 | RECORD_BUSY);
+        slot.commit(20);
+        assert_eq!(slot.state.load(Ordering::Relaxed), 21);
+    }
+
+    #[test]
+    fn ring_push_read() {
+        let ring = zeroed_ring();
+        assert_eq!(push(&ring, "hello"), 0);
+        assert_eq!(push(&ring, "world"), 1);
+        assert_eq!(ring.next_seq(), 2);
+
+        let entry = ring.read(0).unwrap();
+        assert_eq!(entry.seq, 0);
+        assert_eq!(entry.service, "test");
+        assert_eq!(entry.level, Level::Info);
+        assert_eq!(entry.timestamp, Duration::from_micros(7));
+        assert_eq!(entry.message, "hello");
+        assert_eq!(ring.read(1).unwrap().message, "world");
+        assert!(ring.read(2).is_none());
+        assert!(!ring.pending(0));
+
+        // 过长的日志被截断
+        let long = "x".repeat(LOG_MSG_LEN + 10);
+        let seq = push(&ring, &long);
+        assert_eq!(ring.read(seq).unwrap().message.len(), LOG_MSG_LEN);
+    }
+
+    #[test]
+    fn ring_pending() {
+        let ring = zeroed_ring();
+        // 写者取得了序号但是还没有占用和提交
+        let seq = ring.next_seq.fetch_add(1, Ordering::AcqRel);
+        assert!(ring.pending(seq));
+        assert!(ring.read(seq).is_none());
+        // 写者占用之后仍然没有提交
+        assert!(ring.records[seq as usize].claim(seq));
+        assert!(ring.pending(seq));
+        ring.records[seq as usize].commit(seq);
+        assert!(!ring.pending(seq));
+        assert!(ring.read(seq).is_some());
+    }
+
+    #[test]
+    fn ring_wraps() {
+        let ring = zeroed_ring();
+        for i in 0..=LOG_RECORDS {
+            push(&ring, &format!("{}", i));
+        }
+        // 最旧的日志被覆盖
+        assert_eq!(ring.first_seq(), 1);
+        assert!(ring.read(0).is_none());
+        assert!(!ring.pending(0));
+        assert_eq!(ring.read(1).unwrap().message, "1");
+        assert_eq!(
+            ring.read(LOG_RECORDS as u64).unwrap().message,
+            format!("{}", LOG_RECORDS)
+        );
+
+        // 读者跳过被覆盖的日志
+        let mut cursor = LogCursor::new();
+        let entries = cursor.poll(&ring);
+        assert_eq!(entries.len(), LOG_RECORDS);
+        assert_eq!(entries[0].seq, 1);
+        assert_eq!(cursor.seq(), LOG_RECORDS as u64 + 1);
+    }
+}
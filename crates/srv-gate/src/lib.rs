@@ -8,12 +8,16 @@ pub mod blk;
 pub mod consts;
 pub mod event;
 pub mod fs;
+pub mod klog;
+pub mod net;
 pub mod uart;
 
 use blk::BlockIface;
 use fs::FSIface;
 pub use linkme;
+use net::NetIface;
 pub use paste::paste;
+pub use syscalls::Errno;
 
 use core::fmt::Write;
 
@@ -28,6 +32,8 @@ pub static UART_IMPLS: [Lazy<Arc<Mutex<dyn UartIface>>>];
 pub static BLK_IMPLS: [Lazy<Arc<Mutex<dyn BlockIface>>>];
 #[distributed_slice]
 pub static FS_IMPLS: [Lazy<Arc<Mutex<dyn FSIface>>>];
+#[distributed_slice]
+pub static NET_IMPLS: [Lazy<Arc<Mutex<dyn NetIface>>>];
 
 /// 定义一个事件处理器，利用 paste! 将 中断处理号加入到名称中用于做标识，防止 irq 冲突
 #[macro_export]
@@ -65,6 +71,18 @@ macro_rules! def_fs_impl {
     };
 }
 
+/// 定义一个事件处理器，利用 paste! 将 中断处理号加入到名称中用于做标识，防止 irq 冲突
+#[macro_export]
+macro_rules! def_net_impl {
+    ($name:ident, $f:expr) => {
+        #[$crate::linkme::distributed_slice($crate::NET_IMPLS)]
+        #[linkme(crate = $crate::linkme)]
+        #[unsafe(no_mangle)]
+        pub static $name: spin::Lazy<alloc::sync::Arc<spin::Mutex<dyn $crate::net::NetIface>>> =
+            spin::Lazy::new(|| alloc::sync::Arc::new(spin::Mutex::new($f)));
+    };
+}
+
 pub struct Console;
 
 impl Write for Console {
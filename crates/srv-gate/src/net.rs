@@ -0,0 +1,195 @@
+//! 网络设备服务接口
+//!
+//! 客户端创建一个共享内存通道并通过 [NetIface::init] 交给网络服务，通道的前半部分是发送环，
+//! 后半部分是接收环。客户端把需要发送的帧放入发送环后调用 [NetIface::transmit]，调用
+//! [NetIface::receive] 让服务把网卡收到的帧放入接收环，帧的数据不经过 IPC 传递。
+//!
+//! 网络服务重启之后没有通道，[NetIface::transmit] 和 [NetIface::receive] 返回 [NET_NOT_READY]，
+//! 客户端需要重新调用 [NetIface::init]。网络服务不再重启时返回 [NET_UNAVAILABLE]，
+//! [NetIface::mac_address] 返回 `ECONNRESET`。
+use crate::__prelude::*;
+use common::ipc_trait;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use syscalls::Errno;
+
+/// 每个帧槽的大小
+pub const NET_FRAME_SIZE: usize = 2048;
+
+/// 帧的最大长度
+pub const NET_MAX_FRAME_LEN: usize = NET_FRAME_SIZE - size_of::<usize>();
+
+/// 每个环中帧槽的数量
+pub const NET_RING_SLOTS: usize = 15;
+
+/// 共享内存通道需要的页数量
+pub const NET_CHANNEL_PAGES: usize = 2 * size_of::<FrameRing>() / common::config::PAGE_SIZE;
+
+/// 网络服务还没有通道时 [NetIface::transmit] 和 [NetIface::receive] 的返回值
+pub const NET_NOT_READY: usize = usize::MAX - 1;
+
+/// 网络服务已经不可用时 [NetIface::transmit] 和 [NetIface::receive] 的返回值
+pub const NET_UNAVAILABLE: usize = usize::MAX;
+
+#[ipc_trait(event = NET_EVENT)]
+pub trait NetIface: Sync + Send {
+    fn init(&mut self, channel_id: usize);
+    fn mac_address(&self) -> Result<u64, Errno>;
+    fn transmit(&mut self) -> usize;
+    fn receive(&mut self) -> usize;
+}
+
+/// 帧槽
+#[repr(C)]
+struct FrameSlot {
+    /// 帧的长度
+    len: usize,
+    /// 帧的数据
+    data: [u8; NET_MAX_FRAME_LEN],
+}
+
+/// 共享内存中的单生产者单消费者帧环
+///
+/// `head` 和 `tail` 只增不减，两者的差就是环中帧的数量
+#[repr(C)]
+pub struct FrameRing {
+    /// 下一个被消费的位置，只由消费者修改
+    head: AtomicUsize,
+    /// 下一个被生产的位置，只由生产者修改
+    tail: AtomicUsize,
+    _pad: [u8; NET_FRAME_SIZE - 2 * size_of::<usize>()],
+    /// 帧槽
+    slots: [UnsafeCell<FrameSlot>; NET_RING_SLOTS],
+}
+
+unsafe impl Sync for FrameRing {}
+
+impl FrameRing {
+    /// 从共享内存地址获取帧环
+    ///
+    /// # Safety
+    ///
+    /// `addr` 必须指向一个已经映射的共享内存通道，并且在程序运行期间一直有效
+    pub unsafe fn from_addr(addr: usize) -> &'static Self {
+        unsafe { &*(addr as *const Self) }
+    }
+
+    /// 从共享内存通道中获取 (发送环, 接收环)
+    ///
+    /// # Safety
+    ///
+    /// 同 [FrameRing::from_addr]，通道的大小至少为 [NET_CHANNEL_PAGES] 页
+    pub unsafe fn channel(addr: usize) -> (&'static Self, &'static Self) {
+        unsafe {
+            (
+                Self::from_addr(addr),
+                Self::from_addr(addr + size_of::<Self>()),
+            )
+        }
+    }
+
+    /// 清空帧环，只在通道初始化时调用
+    pub fn reset(&self) {
+        self.head.store(0, Ordering::Release);
+        self.tail.store(0, Ordering::Release);
+    }
+
+    /// 环中帧的数量
+    pub fn len(&self) -> usize {
+        self.tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// 环是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 环是否已满
+    pub fn is_full(&self) -> bool {
+        self.len() >= NET_RING_SLOTS
+    }
+
+    /// 放入一个帧，环已满或者帧太长时返回 `false`
+    pub fn push(&self, frame: &[u8]) -> bool {
+        if self.is_full() || frame.len() > NET_MAX_FRAME_LEN {
+            return false;
+        }
+        let tail = self.tail.load(Ordering::Relaxed);
+        let slot = unsafe { &mut *self.slots[tail % NET_RING_SLOTS].get() };
+        slot.data[..frame.len()].copy_from_slice(frame);
+        slot.len = frame.len();
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// 取出一个帧并交给 `f` 处理，环为空时返回 [Option::None]
+    pub fn pop<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.head.load(Ordering::Relaxed);
+        let slot = unsafe { &*self.slots[head % NET_RING_SLOTS].get() };
+        let res = f(&slot.data[..slot.len.min(NET_MAX_FRAME_LEN)]);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(res)
+    }
+}
+
+#[cfg(net_ipc)]
+mod _impl {
+    use super::{NET_UNAVAILABLE, NetIface, NetIfaceEvent};
+    use crate::def_net_impl;
+    use common::{
+        generate_ipc_send,
+        root::{check_reply, find_service},
+    };
+    use sel4::{MessageInfoBuilder, cap::Endpoint, with_ipc_buffer};
+    use syscalls::Errno;
+
+    def_net_impl!(
+        NET_IPC,
+        NetIfaceIPCImpl {
+            ep: find_service("net-thread").unwrap().into(),
+        }
+    );
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct NetIfaceIPCImpl {
+        ep: Endpoint,
+    }
+
+    impl NetIface for NetIfaceIPCImpl {
+        #[generate_ipc_send(label = NetIfaceEvent::init)]
+        fn init(&mut self, channel_id: usize) {
+            todo!()
+        }
+
+        #[generate_ipc_send(label = NetIfaceEvent::mac_address)]
+        fn mac_address(&self) -> Result<u64, Errno> {
+            todo!()
+        }
+
+        fn transmit(&mut self) -> usize {
+            self.call(NetIfaceEvent::transmit)
+        }
+
+        fn receive(&mut self) -> usize {
+            self.call(NetIfaceEvent::receive)
+        }
+    }
+
+    impl NetIfaceIPCImpl {
+        /// 发送没有参数的请求，网络服务已经退出时返回 [NET_UNAVAILABLE]
+        fn call(&self, event: NetIfaceEvent) -> usize {
+            let msg = MessageInfoBuilder::default().label(event.into()).build();
+            match check_reply(self.ep.call(msg)) {
+                Ok(_) => with_ipc_buffer(|ib| ib.msg_regs()[0] as _),
+                Err(_) => NET_UNAVAILABLE,
+            }
+        }
+    }
+}
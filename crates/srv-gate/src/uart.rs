@@ -1,26 +1,168 @@
+//! 串口服务接口
+//!
+//! 串口服务在中断中把收到的字符放入接收缓冲区，[UartIface::getchar] 在没有输入时会
+//! 一直等待，[UartIface::try_getchar] 和 [UartIface::read] 只读取缓冲区中已有的字符。
+//!
+//! 输出通过共享内存中的发送环 [ByteRing] 传递：客户端创建共享内存通道并通过
+//! [UartIface::init_tx] 交给串口服务，把数据放入发送环后调用 [UartIface::flush_tx]
+//! 通知串口服务发送，发送环已满时客户端需要等待串口服务取走数据。
+//!
+//! 串口上复用了 [VC_COUNT] 个虚拟控制台，每个发送环属于一个虚拟控制台，只有正在显示的
+//! 虚拟控制台会输出到串口。输入 [VC_HOTKEY] 和控制台编号可以切换显示的控制台。
+//! 服务的日志不经过发送环，串口服务直接从日志环 ([crate::klog]) 中读取。
+//!
+//! 串口服务有两种实现，在 `apps.toml` 中选择其中一个 ([UART_SERVICES])：`uart-thread`
+//! 使用 PL011 串口，`virtio-console` 使用 virtio-console 设备，每个虚拟控制台输出到
+//! 独立的串口，不需要切换。
+//!
+//! 串口服务已经退出时 [UartIface::getchar] 和 [UartIface::read] 返回 `ECONNRESET`，
+//! [UartIface::try_getchar] 返回 [Option::None]。
 use crate::__prelude::*;
-use common::ipc_trait;
+use common::{config::PAGE_SIZE, ipc_trait};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use syscalls::Errno;
+
+/// 一次 [UartIface::read] 通过 IPC 最多读取的字符数量
+pub const UART_READ_MAX: usize = 512;
+
+/// 虚拟控制台的数量
+pub const VC_COUNT: usize = 4;
+
+/// 应用程序的虚拟控制台，串口的输入只交给这个控制台
+pub const VC_APP: usize = 0;
+
+/// 内核日志的虚拟控制台
+pub const VC_KERNEL_LOG: usize = 1;
+
+/// 服务日志的虚拟控制台，串口服务从日志环中读取除了 kernel-thread 之外的日志输出到这里
+pub const VC_SERVICE_LOG: usize = 2;
+
+/// 服务的警告和错误的虚拟控制台，这些日志同时也输出到 [VC_SERVICE_LOG]
+pub const VC_SERVICE_ERROR: usize = 3;
+
+/// 切换虚拟控制台的热键 (`Ctrl-A`)，之后输入控制台编号 (`1` 开始) 切换
+pub const VC_HOTKEY: u8 = 0x01;
+
+/// 提供串口服务的服务名称，客户端使用第一个存在的服务
+pub const UART_SERVICES: &[&str] = &["uart-thread", "virtio-console"];
+
+/// 发送环共享内存通道需要的页数量
+pub const UART_TX_CHANNEL_PAGES: usize = 2;
+
+/// 发送环能够容纳的字节数量
+pub const UART_TX_RING_SIZE: usize = UART_TX_CHANNEL_PAGES * PAGE_SIZE - 2 * size_of::<usize>();
 
 #[ipc_trait(event = UART_EVENT)]
 pub trait UartIface: Sync + Send {
     fn init(&mut self);
     fn putchar(&mut self, c: u8);
-    fn getchar(&mut self) -> u8;
+    fn getchar(&mut self) -> Result<u8, Errno>;
     fn puts(&mut self, bytes: &[u8]);
+    fn try_getchar(&mut self) -> Option<u8>;
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn init_tx(&mut self, channel_id: usize, vc: usize);
+    fn flush_tx(&mut self, vc: usize);
+}
+
+/// 共享内存中的单生产者单消费者字节环
+///
+/// `head` 和 `tail` 只增不减，两者的差就是环中字节的数量
+#[repr(C)]
+pub struct ByteRing {
+    /// 下一个被消费的位置，只由消费者修改
+    head: AtomicUsize,
+    /// 下一个被生产的位置，只由生产者修改
+    tail: AtomicUsize,
+    /// 环中的数据
+    data: UnsafeCell<[u8; UART_TX_RING_SIZE]>,
+}
+
+unsafe impl Sync for ByteRing {}
+
+impl ByteRing {
+    /// 从共享内存地址获取字节环
+    ///
+    /// # Safety
+    ///
+    /// `addr` 必须指向一个已经映射的共享内存通道，大小至少为 [UART_TX_CHANNEL_PAGES] 页，
+    /// 并且在程序运行期间一直有效
+    pub unsafe fn from_addr(addr: usize) -> &'static Self {
+        unsafe { &*(addr as *const Self) }
+    }
+
+    /// 清空字节环，只在通道初始化时调用
+    pub fn reset(&self) {
+        self.head.store(0, Ordering::Release);
+        self.tail.store(0, Ordering::Release);
+    }
+
+    /// 环中字节的数量
+    pub fn len(&self) -> usize {
+        self.tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// 环是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 环中剩余的空间
+    pub fn room(&self) -> usize {
+        UART_TX_RING_SIZE.saturating_sub(self.len())
+    }
+
+    /// 放入数据，返回放入的字节数量，环已满时只放入一部分
+    pub fn push(&self, data: &[u8]) -> usize {
+        let wlen = data.len().min(self.room());
+        let tail = self.tail.load(Ordering::Relaxed);
+        let ring = self.data.get() as *mut u8;
+        for (i, c) in data[..wlen].iter().enumerate() {
+            unsafe {
+                ring.add(tail.wrapping_add(i) % UART_TX_RING_SIZE).write(*c);
+            }
+        }
+        self.tail.store(tail.wrapping_add(wlen), Ordering::Release);
+        wlen
+    }
+
+    /// 取出数据，返回取出的字节数量，环为空时返回 0
+    pub fn pop(&self, buf: &mut [u8]) -> usize {
+        let rlen = buf.len().min(self.len());
+        let head = self.head.load(Ordering::Relaxed);
+        let ring = self.data.get() as *const u8;
+        for (i, c) in buf[..rlen].iter_mut().enumerate() {
+            *c = unsafe { ring.add(head.wrapping_add(i) % UART_TX_RING_SIZE).read() };
+        }
+        self.head.store(head.wrapping_add(rlen), Ordering::Release);
+        rlen
+    }
 }
 
 #[cfg(uart_ipc)]
 mod _impl {
 
-    use common::{generate_ipc_send, root::find_service};
-    use sel4::cap::Endpoint;
+    use common::{
+        generate_ipc_send,
+        root::{check_reply, find_service},
+    };
+    use sel4::{MessageInfoBuilder, cap::Endpoint, with_ipc_buffer, with_ipc_buffer_mut};
+    use syscalls::Errno;
 
     use crate::def_uart_impl;
 
-    use super::{UartIface, UartIfaceEvent};
+    use super::{UART_READ_MAX, UART_SERVICES, UartIface, UartIfaceEvent};
 
     def_uart_impl!(UART_IPC, UartIfaceIPCImpl {
-        ep: find_service("uart-thread").unwrap().into(),
+        ep: UART_SERVICES
+            .iter()
+            .find_map(|name| find_service(name).ok())
+            .expect("Can't find uart service")
+            .into(),
     });
 
     pub struct UartIfaceIPCImpl {
@@ -40,7 +182,7 @@ mod _impl {
         }
 
         #[generate_ipc_send(label = UartIfaceEvent::getchar)]
-        fn getchar(&mut self) -> u8 {
+        fn getchar(&mut self) -> Result<u8, Errno> {
             todo!()
         }
 
@@ -48,5 +190,41 @@ mod _impl {
         fn puts(&mut self, bytes: &[u8]) {
             todo!()
         }
+
+        fn try_getchar(&mut self) -> Option<u8> {
+            let msg = MessageInfoBuilder::default()
+                .label(UartIfaceEvent::try_getchar.into())
+                .build();
+            check_reply(self.ep.call(msg)).ok()?;
+            // 第一个寄存器表示是否读取到字符，第二个寄存器是读取到的字符
+            with_ipc_buffer(|ib| (ib.msg_regs()[0] != 0).then_some(ib.msg_regs()[1] as u8))
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+            let len = buf.len().min(UART_READ_MAX);
+            with_ipc_buffer_mut(|ib| ib.msg_regs_mut()[0] = len as _);
+            let msg = MessageInfoBuilder::default()
+                .label(UartIfaceEvent::read.into())
+                .length(1)
+                .build();
+            check_reply(self.ep.call(msg))?;
+            // 第一个寄存器是读取的长度，之后是读取到的字符
+            Ok(with_ipc_buffer(|ib| {
+                let rlen = (ib.msg_regs()[0] as usize).min(len);
+                let offset = size_of::<sel4::Word>();
+                buf[..rlen].copy_from_slice(&ib.msg_bytes()[offset..offset + rlen]);
+                rlen
+            }))
+        }
+
+        #[generate_ipc_send(label = UartIfaceEvent::init_tx)]
+        fn init_tx(&mut self, channel_id: usize, vc: usize) {
+            todo!()
+        }
+
+        #[generate_ipc_send(label = UartIfaceEvent::flush_tx)]
+        fn flush_tx(&mut self, vc: usize) {
+            todo!()
+        }
     }
 }
@@ -0,0 +1,289 @@
+//! 共享内存通道
+//!
+//! 通道记录创建者和所有映射了通道的任务。任务离开通道时取消映射，最后一个任务离开之后
+//! 通道的页会被清零并放回 root-task 的空闲页中，之后创建通道时优先使用。
+//! 任务退出时会离开所有的通道，日志通道 ([LOG_CHANNEL_ID]) 不会被释放。
+//!
+//! 创建者可以为通道登记一个 Notification，加入通道的服务获取之后在通道中有新数据时通知创建者。
+//! 日志通道没有创建者，第一个登记 Notification 的参与者 (串口服务) 成为日志通道的创建者，
+//! root-task 把它的 Notification 复制到每个加入日志环的任务的 [LOG_NOTIFY_SLOT] 中。
+use alloc::vec::Vec;
+use common::{
+    config::{LOG_CHANNEL_ID, LOG_NOTIFY_SLOT, PAGE_SIZE},
+    page::PhysPage,
+    root::ResourceUsage,
+};
+use sel4::{CPtr, CapRights, cap::SmallPage};
+use sel4_kit::slot_manager::LeafSlot;
+
+use crate::{OBJ_ALLOCATOR, RootTaskHandler, utils::map_root_frame};
+
+/// root-task 清零空闲页时映射的地址
+const ZERO_VADDR: usize = 0x2_8000_0000;
+
+/// 共享内存通道
+pub struct Channel {
+    /// 通道编号
+    pub id: usize,
+    /// 创建通道的任务，root-task 创建的通道和创建者已经退出的通道为 [Option::None]
+    pub owner: Option<usize>,
+    /// 通道的页
+    pub pages: Vec<SmallPage>,
+    /// 映射了通道的任务 (任务编号, 映射的地址)
+    pub participants: Vec<(usize, usize)>,
+    /// 创建者登记的 Notification
+    pub notify: Option<LeafSlot>,
+}
+
+impl Channel {
+    /// 创建一个没有任务映射的通道
+    pub const fn new(id: usize, owner: Option<usize>, pages: Vec<SmallPage>) -> Self {
+        Self {
+            id,
+            owner,
+            pages,
+            participants: Vec::new(),
+            notify: None,
+        }
+    }
+
+    /// 通道的大小
+    pub fn size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    /// 通道在任务中的 Capability 数量，用于计算配额
+    fn slot_usage(&self) -> ResourceUsage {
+        ResourceUsage {
+            slots: self.pages.len(),
+            ..Default::default()
+        }
+    }
+}
+
+impl RootTaskHandler {
+    /// 查找通道
+    pub fn channel(&self, id: usize) -> Option<&Channel> {
+        self.channels.iter().find(|x| x.id == id)
+    }
+
+    /// 为任务 `id` 创建 `page_count` 个页的通道并映射到 `addr`，超过配额时返回 [Option::None]
+    pub fn create_channel(&mut self, id: usize, addr: usize, page_count: usize) -> Option<usize> {
+        let request = ResourceUsage {
+            pages: page_count,
+            channels: 1,
+            slots: page_count,
+            ..Default::default()
+        };
+        if !self.charge(id, request) {
+            return None;
+        }
+        let reused = self.free_pages.len().min(page_count);
+        let mut pages = self.free_pages.split_off(self.free_pages.len() - reused);
+        pages.extend(OBJ_ALLOCATOR.alloc_pages(page_count - reused));
+
+        let channel_id = self.next_channel_id;
+        self.next_channel_id += 1;
+        self.channels
+            .push(Channel::new(channel_id, Some(id), pages));
+        self.map_channel(channel_id, id, addr);
+        Some(channel_id)
+    }
+
+    /// 任务 `id` 加入通道并映射到 `addr`，返回通道的大小
+    ///
+    /// 通道不存在、已经加入或者超过配额时返回 [Option::None]
+    pub fn join_channel(&mut self, id: usize, channel_id: usize, addr: usize) -> Option<usize> {
+        let channel = self.channel(channel_id)?;
+        if channel.participants.iter().any(|x| x.0 == id) {
+            return None;
+        }
+        let (size, request) = (channel.size(), channel.slot_usage());
+        if !self.charge(id, request) {
+            return None;
+        }
+        self.map_channel(channel_id, id, addr);
+        if channel_id == LOG_CHANNEL_ID {
+            self.copy_log_notify(id);
+        }
+        Some(size)
+    }
+
+    /// 任务 `id` 离开通道，最后一个任务离开之后释放通道。没有加入通道时返回 [Option::None]
+    pub fn leave_channel(&mut self, id: usize, channel_id: usize) -> Option<()> {
+        let channel = self.channels.iter_mut().find(|x| x.id == channel_id)?;
+        let idx = channel.participants.iter().position(|x| x.0 == id)?;
+        let (_, addr) = channel.participants.remove(idx);
+        let (page_count, request) = (channel.pages.len(), channel.slot_usage());
+
+        // 退出的任务的页已经在删除任务时删除
+        if self.tasks[id].running {
+            (0..page_count).for_each(|i| self.tasks[id].unmap_page(addr + i * PAGE_SIZE));
+            self.refund(id, request);
+            if channel_id == LOG_CHANNEL_ID {
+                self.copy_log_notify(id);
+            }
+        }
+        self.free_unused_channels();
+        Some(())
+    }
+
+    /// 创建者删除通道，所有任务都会取消映射。不是通道的创建者时返回 [Option::None]
+    ///
+    /// 日志通道不能被删除
+    pub fn destroy_channel(&mut self, id: usize, channel_id: usize) -> Option<()> {
+        let channel = self.channel(channel_id)?;
+        if channel.owner != Some(id) || channel_id == LOG_CHANNEL_ID {
+            return None;
+        }
+        let participants = channel.participants.iter().map(|x| x.0).collect::<Vec<_>>();
+        participants.into_iter().for_each(|task| {
+            self.leave_channel(task, channel_id);
+        });
+        Some(())
+    }
+
+    /// 通道的创建者 `id` 登记 Notification，Notification 已经放在 `recv_slot` 中
+    ///
+    /// 不是通道的创建者时返回 [Option::None]，之前登记的 Notification 会被替换。
+    /// 日志通道没有创建者时，加入了日志通道的任务登记之后成为日志通道的创建者
+    pub fn set_channel_notify(
+        &mut self,
+        id: usize,
+        channel_id: usize,
+        recv_slot: LeafSlot,
+    ) -> Option<()> {
+        let channel = self.channels.iter_mut().find(|x| x.id == channel_id)?;
+        if channel_id == LOG_CHANNEL_ID
+            && channel.owner.is_none()
+            && channel.participants.iter().any(|x| x.0 == id)
+        {
+            channel.owner = Some(id);
+        }
+        if channel.owner != Some(id) {
+            return None;
+        }
+        let slot = match channel.notify {
+            Some(slot) => {
+                slot.delete().unwrap();
+                slot
+            }
+            None => OBJ_ALLOCATOR.allocate_slot(),
+        };
+        recv_slot.move_to(slot).ok()?;
+        channel.notify = Some(slot);
+        if channel_id == LOG_CHANNEL_ID {
+            self.share_log_notify();
+        }
+        Some(())
+    }
+
+    /// 加入了通道的任务 `id` 获取创建者登记的 Notification
+    pub fn channel_notify(&self, id: usize, channel_id: usize) -> Option<LeafSlot> {
+        let channel = self.channel(channel_id)?;
+        if !channel.participants.iter().any(|x| x.0 == id) {
+            return None;
+        }
+        channel.notify
+    }
+
+    /// 任务 `id` 退出时离开所有的通道，不再记录为通道的创建者
+    pub fn leave_all_channels(&mut self, id: usize) {
+        let log_owner = self.channel(LOG_CHANNEL_ID).and_then(|x| x.owner) == Some(id);
+        self.channels
+            .iter_mut()
+            .filter(|x| x.owner == Some(id))
+            .for_each(|x| {
+                x.owner = None;
+                // 创建者已经退出，不再需要通知
+                if let Some(slot) = x.notify.take() {
+                    slot.delete().unwrap();
+                }
+            });
+        let joined = self
+            .channels
+            .iter()
+            .filter(|x| x.participants.iter().any(|x| x.0 == id))
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        joined.into_iter().for_each(|channel_id| {
+            self.leave_channel(id, channel_id);
+        });
+        // 串口服务退出之后其他任务不再通知它，重启之后重新登记
+        if log_owner {
+            self.share_log_notify();
+        }
+    }
+
+    /// 把日志通道的 Notification 复制到所有加入日志环的任务
+    fn share_log_notify(&self) {
+        self.channel(LOG_CHANNEL_ID)
+            .into_iter()
+            .flat_map(|x| x.participants.iter())
+            .for_each(|(task, _)| self.copy_log_notify(*task));
+    }
+
+    /// 把日志通道的 Notification 复制到任务 `id` 的 [LOG_NOTIFY_SLOT]
+    ///
+    /// 没有登记 Notification 或者任务已经离开日志通道时清空这个 SLOT
+    fn copy_log_notify(&self, id: usize) {
+        let task = &self.tasks[id];
+        if !task.running {
+            return;
+        }
+        let dst = task.abs_cptr(CPtr::from_bits(LOG_NOTIFY_SLOT));
+        let _ = dst.delete();
+        let Some(channel) = self.channel(LOG_CHANNEL_ID) else {
+            return;
+        };
+        let joined = channel.participants.iter().any(|x| x.0 == id);
+        if let Some(slot) = channel.notify.filter(|_| joined) {
+            dst.copy(&slot.abs_cptr(), CapRights::all()).unwrap();
+        }
+    }
+
+    /// 把通道的页复制一份映射到任务 `id` 的 `addr`
+    fn map_channel(&mut self, channel_id: usize, id: usize, addr: usize) {
+        let channel = self
+            .channels
+            .iter_mut()
+            .find(|x| x.id == channel_id)
+            .unwrap();
+        channel.participants.push((id, addr));
+        for (idx, page) in channel.pages.iter().enumerate() {
+            let slot = OBJ_ALLOCATOR.allocate_slot();
+            slot.copy_from(&LeafSlot::from_cap(*page), CapRights::all())
+                .unwrap();
+            self.tasks[id].map_page(addr + idx * PAGE_SIZE, PhysPage::new(slot.cap()));
+        }
+    }
+
+    /// 释放没有任务映射的通道，页清零之后放入空闲页
+    fn free_unused_channels(&mut self) {
+        let unused = self
+            .channels
+            .extract_if(.., |x| x.participants.is_empty() && x.id != LOG_CHANNEL_ID)
+            .collect::<Vec<_>>();
+        for channel in unused {
+            if let Some(slot) = channel.notify {
+                slot.delete().unwrap();
+            }
+            if let Some(owner) = channel.owner {
+                let request = ResourceUsage {
+                    pages: channel.pages.len(),
+                    channels: 1,
+                    ..Default::default()
+                };
+                self.refund(owner, request);
+            }
+            for page in channel.pages {
+                // 删除所有的复制，保证页不再被任何任务映射
+                LeafSlot::from_cap(page).revoke().unwrap();
+                map_root_frame(ZERO_VADDR, page);
+                unsafe { core::ptr::write_bytes(ZERO_VADDR as *mut u8, 0, PAGE_SIZE) };
+                page.frame_unmap().unwrap();
+                self.free_pages.push(page);
+            }
+        }
+    }
+}
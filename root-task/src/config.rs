@@ -2,9 +2,11 @@
 
 use core::fmt::Debug;
 
-use common::config::*;
+use common::{config::*, root::ResourceUsage};
 
-use crate::include_bytes_aligned;
+use crate::{
+    include_bytes_aligned, policy::ServiceRights, sched::SchedParams, supervisor::RestartPolicy,
+};
 
 /// 内核服务名称
 pub struct KernelServices {
@@ -12,10 +14,22 @@ pub struct KernelServices {
     pub name: &'static str,
     /// 文件数据
     pub file: &'static [u8],
-    /// 格式： (虚拟地址，物理地址，内存大小)
-    pub mem: &'static [(usize, usize, usize)],
+    /// 格式： (虚拟地址，物理地址，内存大小，是否可以和其他任务共享)
+    pub mem: &'static [(usize, usize, usize, bool)],
     /// 格式： (虚拟地址, 内存大小)
     pub dma: &'static [(usize, usize)],
+    /// 服务退出之后的重启策略
+    pub restart: RestartPolicy,
+    /// 服务启动之后需要运行的时间 (ms)，运行的时间不到这个时间就退出时记为连续失败，需要运行的时间翻倍
+    pub backoff: u64,
+    /// 可以查找的服务和服务端口的权限，没有列出的服务都无法查找
+    pub allow: &'static [(&'static str, ServiceRights)],
+    /// 资源配额
+    pub quota: ResourceUsage,
+    /// 服务的调度参数
+    pub sched: SchedParams,
+    /// 服务创建的 Linux 应用的调度参数
+    pub app_sched: SchedParams,
 }
 
 impl Debug for KernelServices {
@@ -23,6 +37,11 @@ impl Debug for KernelServices {
         f.debug_struct(self.name)
             .field("mem", &format_args!("{:X?}", self.mem))
             .field("dma", &format_args!("{:X?}", self.dma))
+            .field("restart", &self.restart)
+            .field("allow", &self.allow)
+            .field("quota", &self.quota)
+            .field("sched", &self.sched)
+            .field("app_sched", &self.app_sched)
             .finish()
     }
 }
@@ -32,21 +51,52 @@ macro_rules! service {
     (
         name: $name:expr,
         file: $file:expr,
-        mem: &[$(($mem_virt:expr, $mem_phys:expr, $mem_size:expr)),*],
-        dma: &[$(($dma_addr:expr, $dma_size:expr)),*]$(,)?
+        mem: &[$(($mem_virt:expr, $mem_phys:expr, $mem_size:expr, $mem_shared:expr)),*],
+        dma: &[$(($dma_addr:expr, $dma_size:expr)),*],
+        restart: $restart:expr,
+        backoff: $backoff:expr,
+        allow: $allow:expr,
+        quota: $quota:expr,
+        sched: $sched:expr,
+        app_sched: $app_sched:expr$(,)?
     ) => {
         KernelServices {
             name: $name,
             file: include_bytes_aligned!(16, concat!("../../target/", $file)),
-            mem: &[$(($mem_virt, $mem_phys, $mem_size)),*],
+            mem: &[$(($mem_virt, $mem_phys, $mem_size, $mem_shared)),*],
             dma: &[$(($dma_addr, $dma_size)),*],
+            restart: $restart,
+            backoff: $backoff,
+            allow: $allow,
+            quota: $quota,
+            sched: $sched,
+            app_sched: $app_sched,
         }
     };
+    (
+        name: $name:expr,
+        file: $file:expr,
+        mem: &[$(($mem_virt:expr, $mem_phys:expr, $mem_size:expr, $mem_shared:expr)),*],
+        dma: &[$(($dma_addr:expr, $dma_size:expr)),*]$(,)?
+    ) => {
+        service!(
+            name: $name,
+            file: $file,
+            mem: &[$(($mem_virt, $mem_phys, $mem_size, $mem_shared)),*],
+            dma: &[$(($dma_addr, $dma_size)),*],
+            restart: RestartPolicy::Never,
+            backoff: 0,
+            allow: &[],
+            quota: ResourceUsage::DEFAULT_QUOTA,
+            sched: SchedParams::SERVICE,
+            app_sched: SchedParams::APP,
+        )
+    };
     (name: $name:expr,file: $file:expr $(,)?) => {
         service!(name: $name, file:$file, mem: &[], dma: &[])
     };
-    (name: $name:expr,file: $file:expr, mem: &[$(($mem_virt:expr, $mem_phys:expr, $mem_size:expr)),*] $(,)?) => {
-        service!(name: $name, file:$file, mem: &[$(($mem_virt, $mem_phys, $mem_size)),*], dma: &[])
+    (name: $name:expr,file: $file:expr, mem: &[$(($mem_virt:expr, $mem_phys:expr, $mem_size:expr, $mem_shared:expr)),*] $(,)?) => {
+        service!(name: $name, file:$file, mem: &[$(($mem_virt, $mem_phys, $mem_size, $mem_shared)),*], dma: &[])
     };
 }
 
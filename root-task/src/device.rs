@@ -0,0 +1,325 @@
+//! 设备发现
+//!
+//! root-task 启动时解析 bootinfo 中的设备树，找到所有的 virtio-mmio 设备和 PL011 串口。
+//! virtio-mmio 设备通过读取寄存器中的设备编号确定类型，没有设备的 virtio-mmio 槽会被忽略。
+//! 服务启动时通过 [common::root::find_device] 查询设备的物理地址和中断号，
+//! 改变 QEMU 中 `-device` 的顺序不会影响服务找到设备。
+//!
+//! 设备内存在 `apps.toml` 的 `mem` 中声明，动态启动的驱动可以通过 [common::root::map_device] 申请。
+use alloc::vec::Vec;
+use common::{
+    config::{LARGE_PAGE_SIZE, PAGE_SIZE, VIRTIO_MMIO_SLOT_SIZE},
+    root::{DeviceInfo, DeviceKind, ResourceUsage},
+};
+use core::{cmp::Reverse, ops::Range};
+use sel4::{
+    BootInfoExtraId, Cap, CapRights, CapTypeForFrameObject, ObjectBlueprintArm, UntypedDesc,
+    cap::{LargePage, SmallPage, Untyped},
+};
+use sel4_kit::slot_manager::LeafSlot;
+
+use crate::{OBJ_ALLOCATOR, RootTaskHandler, dtb, utils::map_root_frame};
+
+/// root-task 探测设备时映射设备内存的地址
+const PROBE_VADDR: usize = 0x2_0000_0000;
+
+/// virtio-mmio 寄存器中的魔数 ("virt")
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+
+/// virtio-mmio 设备编号寄存器的偏移
+const VIRTIO_MMIO_DEVICE_ID: usize = 0x8;
+
+/// 发现的设备
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    /// 设备类型
+    pub kind: DeviceKind,
+    /// 设备的物理地址和中断号
+    pub info: DeviceInfo,
+}
+
+/// 从设备 untyped 中申请的页
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceFrame {
+    /// 4KiB 的页
+    Small(SmallPage),
+    /// 2MiB 的大页
+    Large(LargePage),
+}
+
+impl DeviceFrame {
+    /// 页的大小
+    pub const fn size(&self) -> usize {
+        match self {
+            DeviceFrame::Small(_) => PAGE_SIZE,
+            DeviceFrame::Large(_) => LARGE_PAGE_SIZE,
+        }
+    }
+
+    /// 复制一个新的 Capability
+    fn copy(&self) -> Self {
+        let leaf_slot = OBJ_ALLOCATOR.allocate_slot();
+        let src = match self {
+            DeviceFrame::Small(page) => LeafSlot::from_cap(*page),
+            DeviceFrame::Large(page) => LeafSlot::from_cap(*page),
+        };
+        leaf_slot.copy_from(&src, CapRights::all()).unwrap();
+        match self {
+            DeviceFrame::Small(_) => DeviceFrame::Small(leaf_slot.cap()),
+            DeviceFrame::Large(_) => DeviceFrame::Large(leaf_slot.cap()),
+        }
+    }
+
+    /// 删除 Capability
+    pub fn delete(&self) {
+        match self {
+            DeviceFrame::Small(page) => LeafSlot::from_cap(*page).delete().unwrap(),
+            DeviceFrame::Large(page) => LeafSlot::from_cap(*page).delete().unwrap(),
+        }
+    }
+}
+
+/// 设备 untyped 和已经从中申请的页
+///
+/// untyped 只能从低地址向高地址 retype，`watermark` 之前的内存都已经申请为页
+struct DeviceUntyped {
+    cap: Untyped,
+    desc: UntypedDesc,
+    watermark: usize,
+    /// (物理地址, 页)，按照物理地址排列
+    frames: Vec<(usize, DeviceFrame)>,
+}
+
+impl DeviceUntyped {
+    /// untyped 结束的物理地址
+    fn end(&self) -> usize {
+        self.desc.paddr() + (1 << self.desc.size_bits())
+    }
+
+    /// 申请 `watermark` 开始的下一个页，`target` 是需要映射的内存
+    ///
+    /// 对齐的 2MiB 内存整个在 `target` 之前或者之中时申请大页，其他情况申请 4KiB 的页
+    fn retype_next(&mut self, target: &Range<usize>) {
+        let addr = self.watermark;
+        let large_end = addr + LARGE_PAGE_SIZE;
+        let large = addr % LARGE_PAGE_SIZE == 0
+            && large_end <= self.end()
+            && (large_end <= target.start || (target.start <= addr && large_end <= target.end));
+        let blueprint = if large {
+            ObjectBlueprintArm::LargePage
+        } else {
+            ObjectBlueprintArm::SmallPage
+        };
+        let leaf_slot = OBJ_ALLOCATOR.allocate_slot();
+        self.cap
+            .untyped_retype(
+                &blueprint.into(),
+                &leaf_slot.cnode_abs_cptr(),
+                leaf_slot.offset_of_cnode(),
+                1,
+            )
+            .unwrap();
+        let frame = if large {
+            DeviceFrame::Large(leaf_slot.cap())
+        } else {
+            DeviceFrame::Small(leaf_slot.cap())
+        };
+        self.frames.push((addr, frame));
+        self.watermark += frame.size();
+    }
+}
+
+/// 任务映射的设备内存
+struct DeviceClaim {
+    /// 任务编号
+    task: usize,
+    /// 映射的页覆盖的物理地址
+    range: Range<usize>,
+    /// 是否可以和其他任务共享
+    shared: bool,
+}
+
+/// 设备内存的页
+///
+/// 设备内存按照需要映射的大小申请 4KiB 的页或者 2MiB 的大页，多个任务使用同一个设备页时复制页的 Capability，
+/// 不能重复 retype。只有所有映射的任务都允许共享时同一段设备内存才可以被多个任务映射
+pub struct DeviceFrames {
+    /// 设备 untyped
+    untypes: Vec<DeviceUntyped>,
+    /// 任务映射的设备内存
+    claims: Vec<DeviceClaim>,
+}
+
+impl DeviceFrames {
+    /// 使用 bootinfo 中的设备 untyped 创建
+    pub fn new(untypes: Vec<(Untyped, UntypedDesc)>) -> Self {
+        Self {
+            untypes: untypes
+                .into_iter()
+                .map(|(cap, desc)| DeviceUntyped {
+                    cap,
+                    watermark: desc.paddr(),
+                    desc,
+                    frames: Vec::new(),
+                })
+                .collect(),
+            claims: Vec::new(),
+        }
+    }
+
+    /// 获取覆盖物理地址 `paddr` 开始 `size` 字节设备内存的所有页 (物理地址, 页)，每次返回新的 Capability 复制
+    ///
+    /// 内存不在同一个设备 untyped 中时返回 [Option::None]
+    pub fn frames(&mut self, paddr: usize, size: usize) -> Option<Vec<(usize, DeviceFrame)>> {
+        let start = paddr / PAGE_SIZE * PAGE_SIZE;
+        let end = (paddr + size.max(1)).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let untyped = self
+            .untypes
+            .iter_mut()
+            .find(|x| x.desc.paddr() <= start && end <= x.end())?;
+        while untyped.watermark < end {
+            untyped.retype_next(&(start..end));
+        }
+        let frames = untyped
+            .frames
+            .iter()
+            .filter(|(addr, frame)| *addr < end && start < addr + frame.size())
+            .map(|(addr, frame)| (*addr, frame.copy()))
+            .collect();
+        Some(frames)
+    }
+
+    /// 任务 `task` 映射 `frames` 覆盖的设备内存，和其他任务冲突时返回 `false`
+    ///
+    /// 其他任务已经映射并且自己或者其他任务不允许共享时冲突
+    pub fn claim(&mut self, task: usize, frames: &[(usize, DeviceFrame)], shared: bool) -> bool {
+        let (Some(first), Some(last)) = (frames.first(), frames.last()) else {
+            return true;
+        };
+        let range = first.0..last.0 + last.1.size();
+        let conflict = self.claims.iter().find(|x| {
+            x.task != task
+                && x.range.start < range.end
+                && range.start < x.range.end
+                && !(x.shared && shared)
+        });
+        if let Some(claim) = conflict {
+            log::warn!(
+                "[RootTask] Device memory {:#x?} of task {} is used by task {}",
+                range,
+                task,
+                claim.task
+            );
+            return false;
+        }
+        self.claims.push(DeviceClaim {
+            task,
+            range,
+            shared,
+        });
+        true
+    }
+
+    /// 任务 `task` 退出之后其他任务可以映射它使用的设备内存
+    pub fn release(&mut self, task: usize) {
+        self.claims.retain(|x| x.task != task);
+    }
+}
+
+impl RootTaskHandler {
+    /// 为任务 `id` 把 `paddr` 开始 `size` 字节的设备内存映射到 `window`，每个页计入配额的 `slots`
+    ///
+    /// `window` 没有按照 2MiB 对齐、设备内存不存在、和其他任务冲突或者超过配额时返回 [Option::None]
+    pub fn map_device(
+        &mut self,
+        id: usize,
+        window: usize,
+        paddr: usize,
+        size: usize,
+        shared: bool,
+    ) -> Option<()> {
+        if window % LARGE_PAGE_SIZE != 0 || size == 0 {
+            return None;
+        }
+        let frames = self.device_frames.frames(paddr, size)?;
+        let request = ResourceUsage {
+            slots: frames.len(),
+            ..Default::default()
+        };
+        if !self.charge(id, request) {
+            frames.iter().for_each(|x| x.1.delete());
+            return None;
+        }
+        if !self.device_frames.claim(id, &frames, shared) {
+            frames.iter().for_each(|x| x.1.delete());
+            self.refund(id, request);
+            return None;
+        }
+        self.tasks[id].map_device(window, paddr, frames);
+        Some(())
+    }
+}
+
+/// 读取 virtio-mmio 设备的设备编号，槽中没有设备或者设备类型不支持时返回 [Option::None]
+fn probe_virtio(frames: &mut DeviceFrames, paddr: usize) -> Option<DeviceKind> {
+    let (frame_addr, frame) = frames.frames(paddr, VIRTIO_MMIO_SLOT_SIZE)?.pop()?;
+    let base = (PROBE_VADDR + paddr - frame_addr) as *const u32;
+    let (magic, device_id) = match frame {
+        DeviceFrame::Small(page) => probe_read(page, base),
+        DeviceFrame::Large(page) => probe_read(page, base),
+    };
+    frame.delete();
+    (magic == VIRTIO_MMIO_MAGIC)
+        .then_some(device_id)
+        .and_then(|x| DeviceKind::try_from(x as usize).ok())
+}
+
+/// 把页映射到 [PROBE_VADDR]，读取 virtio-mmio 的魔数和设备编号
+fn probe_read<T: CapTypeForFrameObject>(frame: Cap<T>, base: *const u32) -> (u32, u32) {
+    map_root_frame(PROBE_VADDR, frame);
+    let values = unsafe {
+        (
+            base.read_volatile(),
+            base.byte_add(VIRTIO_MMIO_DEVICE_ID).read_volatile(),
+        )
+    };
+    frame.frame_unmap().unwrap();
+    values
+}
+
+/// 从 bootinfo 中的设备树发现设备
+///
+/// 同类设备按照地址从高到低排列，和 QEMU 命令行中 `-device` 的顺序相同。
+/// 没有设备树时返回空列表，服务使用 [common::config] 中的固定配置
+pub fn discover(bootinfo: &sel4::BootInfoPtr, frames: &mut DeviceFrames) -> Vec<Device> {
+    let Some(fdt) = bootinfo.extra().find(|x| x.id == BootInfoExtraId::Fdt) else {
+        log::warn!("[RootTask] No device tree in bootinfo");
+        return Vec::new();
+    };
+    let Some(nodes) = dtb::parse(fdt.content()) else {
+        log::warn!("[RootTask] Invalid device tree");
+        return Vec::new();
+    };
+    let mut devices = Vec::new();
+    for node in nodes {
+        let (Some((paddr, _)), Some(irq), true) = (node.reg, node.irq, node.is_enabled()) else {
+            continue;
+        };
+        let kind = if node.is_compatible("arm,pl011") {
+            Some(DeviceKind::Pl011)
+        } else if node.is_compatible("virtio,mmio") {
+            probe_virtio(frames, paddr)
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            log::debug!("[RootTask] Found {:?} {} irq {}", kind, node.name, irq);
+            devices.push(Device {
+                kind,
+                info: DeviceInfo { paddr, irq },
+            });
+        }
+    }
+    devices.sort_by_key(|x| Reverse(x.info.paddr));
+    devices
+}
@@ -0,0 +1,146 @@
+//! 物理地址连续的 DMA 内存
+//!
+//! seL4 中一个 untyped 是一段物理地址连续并且按照自身大小对齐的内存，root-task 申请一个足够大的 untyped，
+//! 再把它全部 retype 为页，这些页的物理地址是连续的。驱动通过 AllocDma 申请的内存记录在 root-task 中，
+//! 驱动释放内存或者退出之后 untyped 会被回收，之后申请相同大小的 DMA 内存时重新使用。
+use alloc::vec::Vec;
+use common::{config::PAGE_SIZE, page::PhysPage, root::ResourceUsage, slot::recycle_slot};
+use sel4::{
+    CapTypeForObjectOfFixedSize,
+    cap::{SmallPage, Untyped},
+    cap_type,
+};
+use sel4_kit::slot_manager::LeafSlot;
+
+use crate::{OBJ_ALLOCATOR, RootTaskHandler};
+
+/// 一次 retype 最多可以创建的对象数量，和内核的 `CONFIG_RETYPE_FAN_OUT_LIMIT` 相同
+const RETYPE_FAN_OUT_LIMIT: usize = 256;
+
+/// 一次可以申请的最大的 DMA 内存 (4MiB)
+const MAX_DMA_SIZE_BITS: usize = 22;
+
+/// 任务通过 AllocDma 申请的 DMA 内存
+pub struct DmaRegion {
+    /// 申请内存的任务
+    pub task: usize,
+    /// 映射的虚拟地址
+    pub vaddr: usize,
+    /// 映射的页数
+    pub pages: usize,
+    /// 内存所在的 untyped 和 untyped 的 size_bits
+    pub untyped: (Untyped, usize),
+}
+
+/// 满足大小和对齐要求的 untyped 的 size_bits，超过 [MAX_DMA_SIZE_BITS] 时返回 [Option::None]
+pub fn dma_size_bits(size: usize, align: usize) -> Option<usize> {
+    let size = size.max(align).max(PAGE_SIZE).checked_next_power_of_two()?;
+    let size_bits = size.trailing_zeros() as usize;
+    (size_bits <= MAX_DMA_SIZE_BITS).then_some(size_bits)
+}
+
+/// 把 `untyped` 全部 retype 为页，返回的页的物理地址是连续的
+///
+/// 只保留前 `pages` 个页，剩下的页会被删除并回收 slot
+pub fn retype_pages(untyped: Untyped, size_bits: usize, pages: usize) -> Vec<SmallPage> {
+    let count = 1 << (size_bits - PAGE_SIZE.trailing_zeros() as usize);
+    let mut frames = Vec::with_capacity(count);
+    while frames.len() < count {
+        let batch = (count - frames.len()).min(RETYPE_FAN_OUT_LIMIT);
+        let leaf_slot = common::slot::alloc_slots(batch);
+        untyped
+            .untyped_retype(
+                &cap_type::Granule::object_blueprint(),
+                &leaf_slot.cnode_abs_cptr(),
+                leaf_slot.offset_of_cnode(),
+                batch,
+            )
+            .unwrap();
+        frames.extend((0..batch).map(|x| leaf_slot.next_nth_slot(x).cap()));
+    }
+    frames.split_off(pages).into_iter().for_each(|page| {
+        let slot = LeafSlot::from_cap(page);
+        slot.delete().unwrap();
+        recycle_slot(slot);
+    });
+    frames
+}
+
+impl RootTaskHandler {
+    /// 为任务 `id` 申请 `size` 字节按照 `align` 对齐的 DMA 内存并映射到 `addr`，返回物理地址
+    ///
+    /// 参数不合法、大小超过限制或者超过配额时返回 [Option::None]
+    pub fn alloc_dma(
+        &mut self,
+        id: usize,
+        addr: usize,
+        size: usize,
+        align: usize,
+    ) -> Option<usize> {
+        if size == 0 || addr % PAGE_SIZE != 0 || (align != 0 && !align.is_power_of_two()) {
+            return None;
+        }
+        let size_bits = dma_size_bits(size, align)?;
+        let pages = size.div_ceil(PAGE_SIZE);
+        if !self.charge(id, dma_usage(pages)) {
+            return None;
+        }
+
+        // 优先使用已经回收的大小相同的 untyped
+        let untyped = match self.free_dma.iter().position(|x| x.1 == size_bits) {
+            Some(idx) => self.free_dma.swap_remove(idx).0,
+            None => OBJ_ALLOCATOR.alloc_untyped(size_bits),
+        };
+        let frames = retype_pages(untyped, size_bits, pages);
+        let paddr = frames[0].frame_get_address().unwrap();
+        frames.into_iter().enumerate().for_each(|(i, page)| {
+            self.tasks[id].map_page(addr + i * PAGE_SIZE, PhysPage::new(page));
+        });
+        self.dma_regions.push(DmaRegion {
+            task: id,
+            vaddr: addr,
+            pages,
+            untyped: (untyped, size_bits),
+        });
+        Some(paddr)
+    }
+
+    /// 释放任务 `id` 映射在 `addr` 的 DMA 内存，没有这块内存时返回 [Option::None]
+    pub fn free_dma(&mut self, id: usize, addr: usize) -> Option<()> {
+        let idx = self
+            .dma_regions
+            .iter()
+            .position(|x| x.task == id && x.vaddr == addr)?;
+        let region = self.dma_regions.swap_remove(idx);
+        (0..region.pages).for_each(|i| self.tasks[id].unmap_page(addr + i * PAGE_SIZE));
+        self.refund(id, dma_usage(region.pages));
+        self.recycle_dma(region);
+        Some(())
+    }
+
+    /// 任务 `id` 退出之后回收任务申请的所有 DMA 内存，页已经在删除任务时删除
+    pub fn release_dma(&mut self, id: usize) {
+        let regions = self
+            .dma_regions
+            .extract_if(.., |x| x.task == id)
+            .collect::<Vec<_>>();
+        regions
+            .into_iter()
+            .for_each(|region| self.recycle_dma(region));
+    }
+
+    /// 删除 untyped 中所有的页，放入空闲列表
+    fn recycle_dma(&mut self, region: DmaRegion) {
+        LeafSlot::from_cap(region.untyped.0).revoke().unwrap();
+        self.free_dma.push(region.untyped);
+    }
+}
+
+/// 映射 `pages` 个 DMA 页使用的资源
+fn dma_usage(pages: usize) -> ResourceUsage {
+    ResourceUsage {
+        pages,
+        slots: pages,
+        ..Default::default()
+    }
+}
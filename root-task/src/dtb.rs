@@ -0,0 +1,303 @@
+//! 设备树解析
+//!
+//! 只实现了发现设备需要的部分：遍历根节点下的设备节点，读取 `compatible`、`reg` 和
+//! `interrupts` 属性。中断按照 GIC 的三个 cell 格式解析。
+use alloc::{string::String, vec::Vec};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// GIC 中 SPI 中断的起始中断号
+const GIC_SPI_BASE: usize = 32;
+/// GIC 中 PPI 中断的起始中断号
+const GIC_PPI_BASE: usize = 16;
+
+/// 根节点下的设备节点
+#[derive(Debug, Default)]
+pub struct DtbNode {
+    /// 节点名称，例如 `virtio_mmio@a003e00`
+    pub name: String,
+    /// `compatible` 属性中的所有字符串
+    pub compatible: Vec<String>,
+    /// 第一段寄存器的 (地址, 大小)
+    pub reg: Option<(usize, usize)>,
+    /// 第一个中断的中断号
+    pub irq: Option<usize>,
+    /// `status` 属性，没有时为 [Option::None]
+    pub status: Option<String>,
+}
+
+impl DtbNode {
+    /// 节点是否兼容 `compatible`
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible.iter().any(|x| x == compatible)
+    }
+
+    /// 节点是否可用，`status` 为 `okay` 或者没有 `status` 属性时可用
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.status.as_deref(), None | Some("okay" | "ok"))
+    }
+
+    /// 设置节点的属性，`cells` 是根节点的 (`#address-cells`, `#size-cells`)
+    fn set_prop(&mut self, name: &str, value: &[u8], cells: (usize, usize)) {
+        match name {
+            "compatible" => {
+                self.compatible = value
+                    .split(|x| *x == 0)
+                    .filter(|x| !x.is_empty())
+                    .filter_map(|x| core::str::from_utf8(x).ok())
+                    .map(String::from)
+                    .collect()
+            }
+            "reg" => {
+                let addr = read_cells(value, cells.0);
+                let size = value
+                    .get(cells.0 * 4..)
+                    .and_then(|x| read_cells(x, cells.1));
+                self.reg = addr.zip(size);
+            }
+            "status" => self.status = read_str(value, 0).map(String::from),
+            "interrupts" => {
+                self.irq = match (be32(value, 0), be32(value, 4)) {
+                    (Some(0), Some(irq)) => Some(irq as usize + GIC_SPI_BASE),
+                    (Some(1), Some(irq)) => Some(irq as usize + GIC_PPI_BASE),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 读取 `offset` 处大端序的 u32
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+}
+
+/// 读取 `cells` 个 cell 组成的数
+fn read_cells(data: &[u8], cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0, |acc, i| Some((acc << 32) | be32(data, i * 4)? as usize))
+}
+
+/// 读取 `offset` 处以 0 结尾的字符串
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let data = data.get(offset..)?;
+    let len = data.iter().position(|x| *x == 0)?;
+    core::str::from_utf8(&data[..len]).ok()
+}
+
+/// 解析设备树，返回根节点下的所有设备节点，设备树格式错误时返回 [Option::None]
+pub fn parse(fdt: &[u8]) -> Option<Vec<DtbNode>> {
+    if be32(fdt, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let off_struct = be32(fdt, 8)? as usize;
+    let off_strings = be32(fdt, 12)? as usize;
+    let size_struct = be32(fdt, 36)? as usize;
+    let structs = fdt.get(off_struct..off_struct + size_struct)?;
+    let strings = fdt.get(off_strings..)?;
+
+    let mut nodes = Vec::new();
+    let mut current: Option<DtbNode> = None;
+    let mut depth = 0usize;
+    // 根节点的 #address-cells 和 #size-cells，没有指定时使用规范中的默认值
+    let mut cells = (2, 1);
+    let mut offset = 0;
+    loop {
+        let token = be32(structs, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = read_str(structs, offset)?;
+                offset = (offset + name.len() + 1).next_multiple_of(4);
+                depth += 1;
+                if depth == 2 {
+                    current = Some(DtbNode {
+                        name: String::from(name),
+                        ..Default::default()
+                    });
+                }
+            }
+            FDT_END_NODE => {
+                if depth == 2 {
+                    nodes.extend(current.take());
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = be32(structs, offset)? as usize;
+                let name = read_str(strings, be32(structs, offset + 4)? as usize)?;
+                let value = structs.get(offset + 8..offset + 8 + len)?;
+                offset = (offset + 8 + len).next_multiple_of(4);
+                match (depth, name) {
+                    (1, "#address-cells") => cells.0 = be32(value, 0)? as usize,
+                    (1, "#size-cells") => cells.1 = be32(value, 0)? as usize,
+                    (2, _) => {
+                        if let Some(node) = &mut current {
+                            node.set_prop(name, value, cells);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+    Some(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// 构造测试用的设备树
+    #[derive(Default)]
+    struct FdtBuilder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl FdtBuilder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structs
+                .resize(self.structs.len().next_multiple_of(4), 0);
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_off = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP);
+            self.token(value.len() as u32);
+            self.token(name_off);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let value: Vec<u8> = cells.iter().flat_map(|x| x.to_be_bytes()).collect();
+            self.prop(name, &value)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+            let off_struct = 40u32;
+            let off_strings = off_struct + self.structs.len() as u32;
+            let mut fdt = vec![0u8; 40];
+            fdt[0..4].copy_from_slice(&FDT_MAGIC.to_be_bytes());
+            fdt[8..12].copy_from_slice(&off_struct.to_be_bytes());
+            fdt[12..16].copy_from_slice(&off_strings.to_be_bytes());
+            fdt[36..40].copy_from_slice(&(self.structs.len() as u32).to_be_bytes());
+            fdt.extend_from_slice(&self.structs);
+            fdt.extend_from_slice(&self.strings);
+            fdt
+        }
+    }
+
+    #[test]
+    fn parse_device_nodes() {
+        let fdt = FdtBuilder::default()
+            .begin("")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[2])
+            .begin("virtio_mmio@a003e00")
+            .prop("compatible", b"virtio,mmio\0")
+            .prop_cells("reg", &[0, 0xa003e00, 0, 0x200])
+            .prop_cells("interrupts", &[0, 0x2f, 1])
+            .end()
+            .begin("timer")
+            .token(FDT_NOP)
+            .prop("compatible", b"arm,armv8-timer\0arm,armv7-timer\0")
+            .prop_cells("interrupts", &[1, 0xe, 4])
+            .prop("status", b"disabled\0")
+            // 更深层的节点不会被收集
+            .begin("child")
+            .prop("compatible", b"nested\0")
+            .end()
+            .end()
+            .end()
+            .finish();
+
+        let nodes = parse(&fdt).unwrap();
+        assert_eq!(nodes.len(), 2);
+
+        let virtio = &nodes[0];
+        assert_eq!(virtio.name, "virtio_mmio@a003e00");
+        assert!(virtio.is_compatible("virtio,mmio"));
+        assert_eq!(virtio.reg, Some((0xa003e00, 0x200)));
+        assert_eq!(virtio.irq, Some(0x2f + GIC_SPI_BASE));
+        assert!(virtio.is_enabled());
+
+        let timer = &nodes[1];
+        assert!(timer.is_compatible("arm,armv7-timer"));
+        assert!(!timer.is_compatible("nested"));
+        assert_eq!(timer.reg, None);
+        assert_eq!(timer.irq, Some(0xe + GIC_PPI_BASE));
+        assert!(!timer.is_enabled());
+    }
+
+    #[test]
+    fn parse_default_cells() {
+        // 根节点没有 #address-cells 和 #size-cells 时使用 (2, 1)
+        let fdt = FdtBuilder::default()
+            .begin("")
+            .begin("pl011@9000000")
+            .prop_cells("reg", &[0, 0x900_0000, 0x1000])
+            .end()
+            .end()
+            .finish();
+        let nodes = parse(&fdt).unwrap();
+        assert_eq!(nodes[0].reg, Some((0x900_0000, 0x1000)));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut fdt = FdtBuilder::default().begin("").end().finish();
+        assert!(parse(&fdt).is_some());
+        // 错误的 magic
+        fdt[0] = 0;
+        assert!(parse(&fdt).is_none());
+        // 被截断的设备树
+        assert!(parse(&FdtBuilder::default().begin("").end().finish()[..44]).is_none());
+        // 未知的 token
+        assert!(parse(&FdtBuilder::default().begin("").token(7).finish()).is_none());
+    }
+
+    #[test]
+    fn node_status() {
+        let mut node = DtbNode::default();
+        assert!(node.is_enabled());
+        node.status = Some(String::from("okay"));
+        assert!(node.is_enabled());
+        node.status = Some(String::from("ok"));
+        assert!(node.is_enabled());
+        node.status = Some(String::from("disabled"));
+        assert!(!node.is_enabled());
+    }
+}
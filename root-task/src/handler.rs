@@ -1,16 +1,31 @@
-use core::sync::atomic::AtomicUsize;
-
-use common::{config::PAGE_SIZE, page::PhysPage, read_types, reply_with, root::RootEvent};
-use sel4::{CapRights, Fault, IpcBuffer, MessageInfoBuilder, init_thread::slot, with_ipc_buffer};
+use common::{
+    page::PhysPage,
+    read_types, reply_with,
+    root::{DeviceKind, ResourceUsage, RootEvent},
+};
+use sel4::{
+    CPtr, CapRights, Fault, IpcBuffer, MessageInfoBuilder, init_thread::slot, with_ipc_buffer,
+};
 use sel4_kit::slot_manager::LeafSlot;
 
-use crate::{OBJ_ALLOCATOR, RootTaskHandler};
+use crate::{OBJ_ALLOCATOR, RootTaskHandler, task::cnode_relative};
 
 impl RootTaskHandler {
     /// 处理 sel4 任务触发的异常，例如 VMFault
-    pub fn handle_fault(&mut self, fault: Fault) {
+    ///
+    /// 调用已经删除的服务端口产生的 CapFault 会让调用返回错误，其他异常会停止任务，
+    /// 根据任务的重启策略决定是否重新启动
+    pub fn handle_fault(&mut self, ib: &mut IpcBuffer, fault: Fault) {
+        if let Fault::CapFault(_) = fault {
+            log::warn!(
+                "[RootTask] Task {} called an invalid capability",
+                self.badge
+            );
+            self.fail_call(ib);
+            return;
+        }
         log::error!("[RootTask] Received {} Fault: {:#x?}", self.badge, fault);
-        sel4_kit::arch::shutdown();
+        self.handle_exit(self.badge as usize, true);
     }
     /// 等待任务传递的消息，并进行处理
     ///
@@ -18,8 +33,12 @@ impl RootTaskHandler {
     /// 如果有返回 Capability 如何处理？
     pub fn waiting_and_handle(&mut self, ib: &mut IpcBuffer) -> ! {
         let rev_msg = MessageInfoBuilder::default();
-        let swap_slot = OBJ_ALLOCATOR.allocate_slot();
+        // 任务传递的 Capability 放在这里，例如 [RootEvent::SetChannelNotify]
+        let recv_slot = OBJ_ALLOCATOR.allocate_slot();
+        ib.set_recv_slot(&recv_slot.abs_cptr());
         loop {
+            // 清除上一个请求中没有使用的 Capability，否则之后无法接收
+            let _ = recv_slot.delete();
             let (message, badge) = self.fault_ep.recv(());
             self.badge = badge;
             let msg_label = match RootEvent::try_from(message.label()) {
@@ -29,53 +48,81 @@ impl RootTaskHandler {
                         log::error!("Unknown root messaage label: {}", message.label())
                     }
                     let fault = with_ipc_buffer(|buffer| Fault::new(buffer, &message));
-                    self.handle_fault(fault);
+                    self.handle_fault(ib, fault);
                     continue;
                 }
             };
 
             match msg_label {
+                // 超过配额时回复错误，通道编号为 usize::MAX
                 RootEvent::CreateChannel => {
-                    static CHANNEL_ID: AtomicUsize = AtomicUsize::new(1);
                     let (addr, page_count) = read_types!(ib, usize, usize);
-
-                    let pages = OBJ_ALLOCATOR.alloc_pages(page_count);
-                    pages
-                        .iter()
-                        .map(|x| {
-                            let slot = OBJ_ALLOCATOR.allocate_slot();
-                            slot.copy_from(&LeafSlot::from_cap(*x), CapRights::all())
-                                .unwrap();
-                            slot
-                        })
-                        .enumerate()
-                        .for_each(|(idx, x)| {
-                            self.tasks[badge as usize]
-                                .map_page(addr + idx * PAGE_SIZE, PhysPage::new(x.cap()));
-                        });
-                    let channel_id = CHANNEL_ID.fetch_add(1, core::sync::atomic::Ordering::SeqCst);
-                    self.channels.push((channel_id, pages));
-
-                    reply_with!(ib, channel_id);
+                    let msg = match self.create_channel(badge as usize, addr, page_count) {
+                        Some(channel_id) => {
+                            ib.msg_regs_mut()[0] = channel_id as _;
+                            rev_msg.length(1).build()
+                        }
+                        None => {
+                            ib.msg_regs_mut()[0] = usize::MAX as _;
+                            rev_msg.label(1).length(1).build()
+                        }
+                    };
+                    sel4::reply(ib, msg);
                 }
+                // 通道不存在或者超过配额时回复错误，通道大小为 0
                 RootEvent::JoinChannel => {
                     let (channel_id, addr) = read_types!(ib, usize, usize);
-                    if let Some((_, pages)) = self.channels.iter().find(|x| x.0 == channel_id) {
-                        pages
-                            .iter()
-                            .map(|x| {
-                                let slot = OBJ_ALLOCATOR.allocate_slot();
-                                slot.copy_from(&LeafSlot::from_cap(*x), CapRights::all())
-                                    .unwrap();
-                                slot
-                            })
-                            .enumerate()
-                            .for_each(|(idx, x)| {
-                                self.tasks[badge as usize]
-                                    .map_page(addr + idx * PAGE_SIZE, PhysPage::new(x.cap()));
-                            });
-                        reply_with!(ib, pages.len() * PAGE_SIZE);
-                    }
+                    let msg = match self.join_channel(badge as usize, channel_id, addr) {
+                        Some(size) => {
+                            ib.msg_regs_mut()[0] = size as _;
+                            rev_msg.length(1).build()
+                        }
+                        None => {
+                            ib.msg_regs_mut()[0] = 0;
+                            rev_msg.label(1).length(1).build()
+                        }
+                    };
+                    sel4::reply(ib, msg);
+                }
+                RootEvent::LeaveChannel => {
+                    let channel_id = read_types!(ib, usize);
+                    let msg = match self.leave_channel(badge as usize, channel_id) {
+                        Some(()) => rev_msg.build(),
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                RootEvent::DestroyChannel => {
+                    let channel_id = read_types!(ib, usize);
+                    let msg = match self.destroy_channel(badge as usize, channel_id) {
+                        Some(()) => rev_msg.build(),
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 通道的创建者登记 Notification，消息中需要传递一个 Capability
+                RootEvent::SetChannelNotify => {
+                    let channel_id = read_types!(ib, usize);
+                    let res = match message.extra_caps() {
+                        1 => self.set_channel_notify(badge as usize, channel_id, recv_slot),
+                        _ => None,
+                    };
+                    let msg = match res {
+                        Some(()) => rev_msg.build(),
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                RootEvent::GetChannelNotify => {
+                    let channel_id = read_types!(ib, usize);
+                    let msg = match self.channel_notify(badge as usize, channel_id) {
+                        Some(slot) => {
+                            ib.caps_or_badges_mut()[0] = slot.raw() as _;
+                            rev_msg.extra_caps(1).build()
+                        }
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
                 }
                 RootEvent::TranslateAddr => {
                     let addr = read_types!(ib, usize);
@@ -88,23 +135,33 @@ impl RootTaskHandler {
 
                     reply_with!(ib, phys_addr + addr % 0x1000);
                 }
+                // 服务端口直接放到任务指定的 slot 中，服务退出时通过这个位置找到正在等待服务的任务
                 RootEvent::FindService => {
-                    let name = read_types!(ib, &str);
+                    let (dst, name) = read_types!(ib, u64, &str);
 
-                    let task = self.tasks.iter().find(|task| task.name == name);
-                    let msg = match task {
-                        Some(task) => {
-                            LeafSlot::from(task.srv_ep)
-                                .mint_to(swap_slot, CapRights::all(), badge as _)
-                                .unwrap();
-                            ib.caps_or_badges_mut()[0] = swap_slot.raw() as _;
-                            rev_msg.extra_caps(1).build()
-                        }
+                    // 按照 apps.toml 中的策略检查是否可以查找服务
+                    let rights = self.lookup_rights(badge as usize, &name);
+                    let server = self
+                        .tasks
+                        .iter()
+                        .position(|task| task.running && task.name == name)
+                        .zip(rights);
+                    let res = server.and_then(|(server, rights)| {
+                        let srv_ep = self.tasks[server].srv_ep;
+                        let client = &mut self.tasks[badge as usize];
+                        client
+                            .abs_cptr(CPtr::from_bits(dst))
+                            .mint(&cnode_relative(srv_ep), rights.cap_rights(), badge as _)
+                            .ok()?;
+                        client.found_services.push((server, dst));
+                        Some(())
+                    });
+                    let msg = match res {
+                        Some(()) => rev_msg.build(),
                         // 发生错误时返回值 不为 -1
                         None => rev_msg.label(1).build(),
                     };
                     sel4::reply(ib, msg);
-                    let _ = swap_slot.delete();
                 }
                 // Allocate a irq handler capability
                 // Transfer it to the requested service
@@ -123,6 +180,14 @@ impl RootTaskHandler {
                 }
                 // 申请一个 Notification Capability
                 RootEvent::AllocNotification => {
+                    let request = ResourceUsage {
+                        slots: 1,
+                        ..Default::default()
+                    };
+                    if !self.charge(badge as usize, request) {
+                        sel4::reply(ib, rev_msg.label(1).build());
+                        continue;
+                    }
                     // 在 0 的 slot 处创建一个 Capability
                     OBJ_ALLOCATOR.retype_to_first(sel4::ObjectBlueprint::Notification);
 
@@ -133,6 +198,16 @@ impl RootTaskHandler {
                 }
                 // 申请一个 Untyped Memory
                 RootEvent::AllocUntyped => {
+                    let request = self.untyped.last().map(|(_, desc)| ResourceUsage {
+                        untyped: 1 << desc.size_bits(),
+                        slots: 1,
+                        ..Default::default()
+                    });
+                    // 没有剩余的 untyped 或者超过配额时回复错误
+                    if !request.is_some_and(|request| self.charge(badge as usize, request)) {
+                        sel4::reply(ib, rev_msg.label(1).build());
+                        continue;
+                    }
                     let (cap, _) = self.untyped.pop().unwrap();
                     ib.caps_or_badges_mut()[0] = cap.bits();
                     sel4::reply(ib, rev_msg.extra_caps(1).build());
@@ -141,6 +216,15 @@ impl RootTaskHandler {
                     assert_eq!(message.length(), 1);
                     let addr = read_types!(ib, usize);
 
+                    let request = ResourceUsage {
+                        pages: 1,
+                        slots: 1,
+                        ..Default::default()
+                    };
+                    if !self.charge(badge as usize, request) {
+                        sel4::reply(ib, rev_msg.label(1).build());
+                        continue;
+                    }
                     let page = OBJ_ALLOCATOR.alloc_page();
                     self.tasks[badge as usize].map_page(addr, PhysPage::new(page));
                     LeafSlot::new(0)
@@ -150,6 +234,117 @@ impl RootTaskHandler {
                     sel4::reply(ib, rev_msg.extra_caps(1).build());
                     LeafSlot::new(0).delete().unwrap();
                 }
+                // 申请物理地址连续的 DMA 内存，回复物理地址
+                RootEvent::AllocDma => {
+                    let (addr, size, align) = read_types!(ib, usize, usize, usize);
+                    let msg = match self.alloc_dma(badge as usize, addr, size, align) {
+                        Some(paddr) => {
+                            ib.msg_regs_mut()[0] = paddr as _;
+                            rev_msg.length(1).build()
+                        }
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 映射设备内存，动态启动的驱动使用
+                RootEvent::MapDevice => {
+                    let (window, paddr, size, shared) = read_types!(ib, usize, usize, usize, usize);
+                    let msg =
+                        match self.map_device(badge as usize, window, paddr, size, shared != 0) {
+                            Some(()) => rev_msg.build(),
+                            None => rev_msg.label(1).build(),
+                        };
+                    sel4::reply(ib, msg);
+                }
+                RootEvent::FreeDma => {
+                    let addr = read_types!(ib, usize);
+                    let msg = match self.free_dma(badge as usize, addr) {
+                        Some(()) => rev_msg.build(),
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 申请 Linux 应用使用的调度参数，回复优先级，MCS 内核上同时传递调度上下文
+                RootEvent::AllocSchedContext => {
+                    ib.msg_regs_mut()[0] = self.app_priority(badge as usize) as _;
+                    #[cfg(not(feature = "mcs"))]
+                    sel4::reply(ib, rev_msg.length(1).build());
+
+                    #[cfg(feature = "mcs")]
+                    {
+                        let request = ResourceUsage {
+                            slots: 1,
+                            ..Default::default()
+                        };
+                        if !self.charge(badge as usize, request) {
+                            sel4::reply(ib, rev_msg.label(1).build());
+                            continue;
+                        }
+                        // 在 0 的 slot 处创建调度上下文
+                        let sched_context = OBJ_ALLOCATOR
+                            .retype_to_first(sel4::ObjectBlueprint::SchedContext {
+                                size_bits: sel4::sys::seL4_MinSchedContextBits as _,
+                            })
+                            .cast();
+                        self.tasks[badge as usize]
+                            .app_sched
+                            .configure(sched_context, 0);
+
+                        ib.caps_or_badges_mut()[0] = 0;
+                        sel4::reply(ib, rev_msg.length(1).extra_caps(1).build());
+
+                        LeafSlot::new(0).delete().unwrap();
+                    }
+                }
+                // 查询从设备树中发现的设备，回复设备的物理地址和中断号
+                RootEvent::FindDevice => {
+                    let (kind, index) = read_types!(ib, usize, usize);
+                    let device = DeviceKind::try_from(kind)
+                        .ok()
+                        .and_then(|kind| self.devices.iter().filter(|x| x.kind == kind).nth(index));
+                    let msg = match device {
+                        Some(device) => {
+                            ib.msg_regs_mut()[0] = device.info.paddr as _;
+                            ib.msg_regs_mut()[1] = device.info.irq as _;
+                            rev_msg.length(2).build()
+                        }
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 启动共享内存通道中的服务，回复服务的编号
+                RootEvent::SpawnService => {
+                    let channel_id = read_types!(ib, usize);
+                    let msg = match self.spawn_service(badge as usize, channel_id) {
+                        Some(id) => {
+                            ib.msg_regs_mut()[0] = id as _;
+                            rev_msg.length(1).build()
+                        }
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 查询任务的资源使用量和配额
+                RootEvent::QueryUsage => {
+                    let id = read_types!(ib, usize);
+                    let msg = match self.usage_of(id, badge as usize) {
+                        Some((usage, quota)) => {
+                            let regs = usage.to_array().into_iter().chain(quota.to_array());
+                            ib.msg_regs_mut()
+                                .iter_mut()
+                                .zip(regs)
+                                .for_each(|(reg, x)| *reg = x as _);
+                            rev_msg.length(8).build()
+                        }
+                        None => rev_msg.label(1).build(),
+                    };
+                    sel4::reply(ib, msg);
+                }
+                // 任务退出，不需要回复
+                RootEvent::Exit => {
+                    let code = read_types!(ib, usize);
+                    self.handle_exit(badge as usize, code != 0);
+                }
                 RootEvent::Shutdown => sel4_kit::arch::shutdown(),
             }
         }
@@ -1,26 +1,38 @@
 #![no_std]
 #![no_main]
 #![feature(never_type)]
+#![feature(extract_if)]
 
 extern crate alloc;
 
+mod channel;
 mod config;
 mod cspace;
+mod device;
+mod dma;
+mod dtb;
 mod handler;
+mod policy;
+mod quota;
+mod sched;
+mod spawn;
+mod supervisor;
 mod task;
 mod utils;
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
+use channel::Channel;
 use common::{
     ObjectAllocator,
-    config::{DEFAULT_CUSTOM_SLOT, PAGE_SIZE, VIRTIO_MMIO_ADDR},
-    page::PhysPage,
+    config::{DEFAULT_CUSTOM_SLOT, LOG_CHANNEL_ID, LOG_CHANNEL_PAGES},
 };
 use config::TASK_FILES;
+use device::{Device, DeviceFrames};
+use dma::DmaRegion;
 use include_bytes_aligned::include_bytes_aligned;
 use sel4::{
-    Cap, CapRights, ObjectBlueprintArm, UntypedDesc,
-    cap::{LargePage, SmallPage, Untyped},
+    Cap, CapRights, UntypedDesc,
+    cap::{SmallPage, Untyped},
     cap_type::Endpoint,
     debug_println,
     init_thread::slot,
@@ -28,6 +40,7 @@ use sel4::{
 };
 use sel4_kit::slot_manager::LeafSlot;
 use sel4_root_task::{Never, root_task};
+use supervisor::{RestartPolicy, Supervised};
 use task::*;
 
 /// Object 分配器，可以用来申请 Capability
@@ -78,6 +91,10 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
         OBJ_ALLOCATOR.extend_slot(slot);
     });
 
+    // MCS 内核上创建任务的调度上下文需要使用 SchedControl
+    #[cfg(feature = "mcs")]
+    sched::SCHED_CONTROL.call_once(|| bootinfo.sched_control().index(0).cap());
+
     // Used for fault and normal IPC ( Reuse )
     let fault_ep = OBJ_ALLOCATOR.alloc_endpoint();
 
@@ -87,62 +104,30 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
         tasks.push(build_kernel_thread(
             id,
             (fault_ep, tasks.len() as _),
+            OBJ_ALLOCATOR.alloc_endpoint(),
             task.name,
             task.file,
+            task.sched,
         )?);
         debug_println!("service {:#x?}", task)
     }
 
+    // 设备内存的大页，多个任务使用同一个设备页时复制大页的 Capability
+    let mut device_frames = DeviceFrames::new(
+        device_untypes
+            .into_iter()
+            .map(|(cap, desc)| (cap, desc.clone()))
+            .collect(),
+    );
+
     // 处理所有定义的任务
     TASK_FILES.iter().enumerate().for_each(|(t_idx, t)| {
-        // 映射设备内存，应该保证映射到指定的物理地址上
-        // 一般情况下映射的大小不会超过一个页
-        // NOTICE: 目前不支持多个人物共享一个物理页表的情况
-        // TODO: 实现申请指定物理内存到映射到特定物理地址的操作
-        for (vaddr, paddr, _size) in t.mem {
-            let (blk_device_untyped_cap, _) = device_untypes
-                .iter()
-                .find(|(_, desc)| {
-                    (desc.paddr()..(desc.paddr() + (1 << desc.size_bits()))).contains(paddr)
-                })
-                .expect("[RootTask] can't find device memory");
-            let leaf_slot = OBJ_ALLOCATOR.allocate_slot();
-            let blk_device_frame_cap = LargePage::from_bits(leaf_slot.raw() as _);
-
-            blk_device_untyped_cap
-                .untyped_retype(
-                    &ObjectBlueprintArm::LargePage.into(),
-                    &leaf_slot.cnode_abs_cptr(),
-                    leaf_slot.offset_of_cnode(),
-                    1,
-                )
-                .unwrap();
-
-            assert!(blk_device_frame_cap.frame_get_address().unwrap() < VIRTIO_MMIO_ADDR);
-
-            tasks[t_idx].map_large_page(*vaddr, blk_device_frame_cap);
-        }
-
-        // 映射 DMA 内存，应该随机分配任意内存即可
-        for (start, size) in t.dma {
-            // 申请多个页表
-            // TODO: 检查页表是否连续
-            let pages_cap = OBJ_ALLOCATOR.alloc_pages(size / PAGE_SIZE);
-
-            // 映射多个页表
-            pages_cap.into_iter().enumerate().for_each(|(i, page)| {
-                debug_println!(
-                    "[RootTask] Mapping DMA {:#x} -> {:#x}",
-                    start + i * PAGE_SIZE,
-                    page.frame_get_address().unwrap()
-                );
-                tasks[t_idx].map_page(start + i * PAGE_SIZE, PhysPage::new(page));
-            });
-            // for i in 0..size / PAGE_SIZE {
-            //     let page_cap = OBJ_ALLOCATOR.lock().alloc_page();
-            //     tasks[t_idx].map_page(start + i * PAGE_SIZE, PhysPage::new(page_cap));
-            // }
-        }
+        tasks[t_idx].allowed_services = t.allowed_services();
+        tasks[t_idx].quota = t.quota;
+        tasks[t_idx].app_sched = t.app_sched;
+        tasks[t_idx]
+            .map_resources(&mut device_frames, t_idx, t.mem, t.dma)
+            .expect("[RootTask] can't find device memory");
 
         // FIXME: 将分配内存的逻辑写成一个通用的逻辑
         if t.name == "kernel-thread" {
@@ -155,13 +140,34 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
         }
     });
 
-    run_tasks(&tasks);
+    // 从设备树中发现设备，服务启动时查询设备的地址和中断号
+    let devices = device::discover(bootinfo, &mut device_frames);
+
+    // 所有服务共享的日志环，需要在任务运行之前创建
+    let log_pages = OBJ_ALLOCATOR.alloc_pages(LOG_CHANNEL_PAGES);
+
+    // 有重启策略的服务需要记录服务的配置
+    let supervised = TASK_FILES
+        .iter()
+        .enumerate()
+        .filter(|(_, service)| service.restart != RestartPolicy::Never)
+        .map(|(id, service)| Supervised::new(id, service))
+        .collect();
+
+    run_tasks(&mut tasks);
     let mut root_task_handler = RootTaskHandler {
         tasks,
         fault_ep,
         badge: 0,
-        channels: Vec::new(),
+        channels: vec![Channel::new(LOG_CHANNEL_ID, None, log_pages)],
+        next_channel_id: LOG_CHANNEL_ID + 1,
+        free_pages: Vec::new(),
+        dma_regions: Vec::new(),
+        free_dma: Vec::new(),
         untyped: mem_untypes,
+        devices,
+        device_frames,
+        supervised,
     };
     with_ipc_buffer_mut(|ib| root_task_handler.waiting_and_handle(ib))
 }
@@ -170,6 +176,13 @@ pub struct RootTaskHandler {
     tasks: Vec<Sel4Task>,
     fault_ep: Cap<Endpoint>,
     badge: u64,
-    channels: Vec<(usize, Vec<SmallPage>)>,
+    channels: Vec<Channel>,
+    next_channel_id: usize,
+    free_pages: Vec<SmallPage>,
+    dma_regions: Vec<DmaRegion>,
+    free_dma: Vec<(Untyped, usize)>,
     untyped: Vec<(Cap<sel4::cap_type::Untyped>, UntypedDesc)>,
+    devices: Vec<Device>,
+    device_frames: DeviceFrames,
+    supervised: Vec<Supervised>,
 }
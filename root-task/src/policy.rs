@@ -0,0 +1,120 @@
+//! 服务查找策略
+//!
+//! `apps.toml` 中任务的 `allow` 列出任务可以通过 FindService 查找的服务和得到的服务端口的权限，
+//! 没有列出的服务都无法查找，没有 `allow` 的任务不能查找任何服务。root-task 在查找服务时检查策略，
+//! 拒绝的查找会记录到日志中。
+//!
+//! `allow` 中的 [SPAWN_PERMISSION] 不是服务，写出它的任务可以通过 SpawnService 启动服务。
+use alloc::{string::String, vec::Vec};
+use sel4::CapRights;
+
+use crate::{RootTaskHandler, config::KernelServices};
+
+/// `allow` 中允许任务在运行时启动服务的项
+pub const SPAWN_PERMISSION: &str = "spawn";
+
+/// 服务端口的权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceRights {
+    /// 可以从端口接收消息
+    pub read: bool,
+    /// 可以向端口发送消息
+    pub write: bool,
+    /// 可以通过端口传递 Capability
+    pub grant: bool,
+    /// 可以使用 Call 等待服务的回复
+    pub grant_reply: bool,
+}
+
+impl ServiceRights {
+    /// 转换为 seL4 的 [CapRights]
+    pub fn cap_rights(&self) -> CapRights {
+        CapRights::new(self.grant_reply, self.grant, self.read, self.write)
+    }
+}
+
+impl KernelServices {
+    /// 服务可以查找的服务
+    pub fn allowed_services(&self) -> Vec<(String, ServiceRights)> {
+        self.allow
+            .iter()
+            .map(|(name, rights)| (String::from(*name), *rights))
+            .collect()
+    }
+}
+
+impl RootTaskHandler {
+    /// 任务 `id` 查找服务 `name` 时得到的权限，不允许查找时返回 [Option::None]
+    pub fn lookup_rights(&self, id: usize, name: &str) -> Option<ServiceRights> {
+        let task = &self.tasks[id];
+        let rights = find_rights(&task.allowed_services, name);
+        if rights.is_none() {
+            log::warn!("[RootTask] {} is not allowed to find {}", task.name, name);
+        }
+        rights
+    }
+
+    /// 任务 `id` 是否可以启动服务，`allow` 中需要写出 [SPAWN_PERMISSION]
+    pub fn may_spawn(&self, id: usize) -> bool {
+        let task = &self.tasks[id];
+        let allowed = allows_spawn(&task.allowed_services);
+        if !allowed {
+            log::warn!("[RootTask] {} is not allowed to spawn services", task.name);
+        }
+        allowed
+    }
+}
+
+/// 在 `allow` 列表中查找服务 `name` 的权限，没有列出的服务返回 [Option::None]
+fn find_rights(allow: &[(String, ServiceRights)], name: &str) -> Option<ServiceRights> {
+    allow.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// `allow` 列表中是否写出了 [SPAWN_PERMISSION]
+fn allows_spawn(allow: &[(String, ServiceRights)]) -> bool {
+    allow.iter().any(|x| x.0 == SPAWN_PERMISSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// 只能调用服务的权限
+    const CALL: ServiceRights = ServiceRights {
+        read: false,
+        write: true,
+        grant: false,
+        grant_reply: true,
+    };
+
+    #[test]
+    fn lookup_default_deny() {
+        assert_eq!(find_rights(&[], "fs"), None);
+        let allow = vec![(String::from("fs"), CALL)];
+        assert_eq!(find_rights(&allow, "blk"), None);
+        // 名称需要完全相同
+        assert_eq!(find_rights(&allow, "fs2"), None);
+        assert_eq!(find_rights(&allow, ""), None);
+    }
+
+    #[test]
+    fn lookup_returns_rights() {
+        let grant = ServiceRights {
+            grant: true,
+            ..CALL
+        };
+        let allow = vec![(String::from("fs"), CALL), (String::from("blk"), grant)];
+        assert_eq!(find_rights(&allow, "fs"), Some(CALL));
+        assert_eq!(find_rights(&allow, "blk"), Some(grant));
+    }
+
+    #[test]
+    fn spawn_permission() {
+        assert!(!allows_spawn(&[]));
+        let mut allow = vec![(String::from("fs"), CALL)];
+        assert!(!allows_spawn(&allow));
+        allow.push((String::from(SPAWN_PERMISSION), CALL));
+        assert!(allows_spawn(&allow));
+    }
+}
@@ -0,0 +1,154 @@
+//! 服务的资源配额
+//!
+//! root-task 记录每个任务申请的 untyped 内存、页、共享内存通道和 Capability 的数量，
+//! 超过 `apps.toml` 中 `quota` 的申请会得到错误回复，没有写出的资源使用 [ResourceUsage::DEFAULT_QUOTA]。
+//! 任务重启之后重新开始计算。
+use common::root::{QUERY_SELF, ResourceUsage};
+
+use crate::RootTaskHandler;
+
+impl RootTaskHandler {
+    /// 为任务 `id` 记录申请的资源，超过配额时不记录并返回 `false`
+    pub fn charge(&mut self, id: usize, request: ResourceUsage) -> bool {
+        let task = &mut self.tasks[id];
+        if charge_usage(&mut task.usage, &task.quota, &request) {
+            return true;
+        }
+        log::warn!(
+            "[RootTask] {} is out of quota: {:?} + {:?} > {:?}",
+            task.name,
+            task.usage,
+            request,
+            task.quota
+        );
+        false
+    }
+
+    /// 任务 `id` 释放资源之后减少记录的使用量
+    pub fn refund(&mut self, id: usize, release: ResourceUsage) {
+        refund_usage(&mut self.tasks[id].usage, &release);
+    }
+
+    /// 任务 `id` 的资源使用量和配额，`id` 为 [QUERY_SELF] 时查询 `badge` 对应的任务
+    ///
+    /// 任务 `badge` 只能查询自己和自己启动的任务
+    pub fn usage_of(&self, id: usize, badge: usize) -> Option<(ResourceUsage, ResourceUsage)> {
+        let id = if id == QUERY_SELF { badge } else { id };
+        let task = self.tasks.get(id)?;
+        if id != badge && task.parent != Some(badge) {
+            log::warn!(
+                "[RootTask] {} is not allowed to query the usage of {}",
+                self.tasks[badge].name,
+                task.name
+            );
+            return None;
+        }
+        Some((task.usage, task.quota))
+    }
+}
+
+/// 在 `usage` 上记录申请的资源 `request`，超过配额 `quota` 时不修改 `usage` 并返回 `false`
+fn charge_usage(usage: &mut ResourceUsage, quota: &ResourceUsage, request: &ResourceUsage) -> bool {
+    match usage.checked_add(request) {
+        Some(new) if new.within(quota) => {
+            *usage = new;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 从 `usage` 中减去释放的资源 `release`，最少减到 0
+fn refund_usage(usage: &mut ResourceUsage, release: &ResourceUsage) {
+    usage.untyped = usage.untyped.saturating_sub(release.untyped);
+    usage.pages = usage.pages.saturating_sub(release.pages);
+    usage.channels = usage.channels.saturating_sub(release.channels);
+    usage.slots = usage.slots.saturating_sub(release.slots);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: ResourceUsage = ResourceUsage {
+        untyped: 0x1000,
+        pages: 4,
+        channels: 1,
+        slots: 8,
+    };
+
+    #[test]
+    fn charge_within_quota() {
+        let mut usage = ResourceUsage::default();
+        let request = ResourceUsage {
+            pages: 2,
+            slots: 2,
+            ..Default::default()
+        };
+        assert!(charge_usage(&mut usage, &QUOTA, &request));
+        assert!(charge_usage(&mut usage, &QUOTA, &request));
+        assert_eq!(usage.pages, 4);
+        assert_eq!(usage.slots, 4);
+        // 刚好用完配额之后不能再申请
+        assert!(!charge_usage(&mut usage, &QUOTA, &request));
+        assert_eq!(usage.pages, 4);
+    }
+
+    #[test]
+    fn charge_over_quota() {
+        let mut usage = ResourceUsage {
+            channels: 1,
+            ..Default::default()
+        };
+        let before = usage;
+        // 任意一项超过配额都会拒绝整个申请
+        let request = ResourceUsage {
+            pages: 1,
+            channels: 1,
+            ..Default::default()
+        };
+        assert!(!charge_usage(&mut usage, &QUOTA, &request));
+        assert_eq!(usage, before);
+        // 溢出同样被拒绝
+        let request = ResourceUsage {
+            untyped: usize::MAX,
+            ..Default::default()
+        };
+        usage.untyped = 1;
+        assert!(!charge_usage(
+            &mut usage,
+            &ResourceUsage::UNLIMITED,
+            &request
+        ));
+        assert_eq!(usage.untyped, 1);
+    }
+
+    #[test]
+    fn refund_saturates() {
+        let mut usage = ResourceUsage {
+            untyped: 0x1000,
+            pages: 2,
+            channels: 1,
+            slots: 3,
+        };
+        refund_usage(
+            &mut usage,
+            &ResourceUsage {
+                pages: 1,
+                slots: 5,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            usage,
+            ResourceUsage {
+                untyped: 0x1000,
+                pages: 1,
+                channels: 1,
+                slots: 0,
+            }
+        );
+        refund_usage(&mut usage, &ResourceUsage::UNLIMITED);
+        assert_eq!(usage, ResourceUsage::default());
+    }
+}
@@ -0,0 +1,119 @@
+//! 服务的调度参数
+//!
+//! `apps.toml` 中的 `sched` 设置服务的优先级、预算和周期，`app-sched` 设置 kernel-thread 创建的
+//! Linux 应用使用的调度参数。服务的最大控制优先级 (MCP) 和优先级相同，服务无法把自己或者创建的线程
+//! 设置为更高的优先级，Linux 应用的优先级也不会超过 kernel-thread 的优先级。
+//!
+//! 在 MCS 内核上 (`mcs` feature) root-task 为每个服务和 Linux 应用的线程创建调度上下文，
+//! 线程在每个周期中最多运行预算的时间，失控的 Linux 应用无法占用驱动的 CPU 时间。非 MCS 内核上只有优先级生效。
+use sel4::init_thread::slot;
+#[cfg(feature = "mcs")]
+use sel4::{cap::SchedContext, cap_type};
+#[cfg(feature = "mcs")]
+use sel4_kit::slot_manager::LeafSlot;
+
+#[cfg(feature = "mcs")]
+use crate::OBJ_ALLOCATOR;
+use crate::{RootTaskHandler, task::Sel4Task};
+
+/// 默认的调度周期 (us)
+const DEFAULT_PERIOD: u64 = 10_000;
+
+/// 调度参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedParams {
+    /// 优先级，0 到 255
+    pub priority: usize,
+    /// 每个周期中可以运行的时间 (us)，和周期相同时不限制
+    pub budget: u64,
+    /// 周期 (us)
+    pub period: u64,
+}
+
+impl SchedParams {
+    /// 服务默认的调度参数，和 Linux 应用的优先级相同，低于驱动、kernel-thread 和文件系统
+    pub const SERVICE: Self = Self {
+        priority: 100,
+        budget: DEFAULT_PERIOD,
+        period: DEFAULT_PERIOD,
+    };
+
+    /// Linux 应用默认的调度参数
+    pub const APP: Self = Self {
+        priority: 0,
+        budget: DEFAULT_PERIOD,
+        period: DEFAULT_PERIOD,
+    };
+
+    /// 创建按照预算和周期配置的调度上下文，`badge` 为超时异常消息中的标记
+    #[cfg(feature = "mcs")]
+    pub fn sched_context(&self, badge: u64) -> SchedContext {
+        let sched_context = OBJ_ALLOCATOR
+            .allocate_and_retyped_variable_sized::<cap_type::SchedContext>(
+                sel4::sys::seL4_MinSchedContextBits as _,
+            );
+        self.configure(sched_context, badge);
+        sched_context
+    }
+
+    /// 按照预算和周期配置调度上下文
+    #[cfg(feature = "mcs")]
+    pub fn configure(&self, sched_context: SchedContext, badge: u64) {
+        SCHED_CONTROL
+            .get()
+            .expect("[RootTask] SchedControl is not initialized")
+            .sched_control_configure_flags(sched_context, self.budget, self.period, 0, badge, 0)
+            .unwrap();
+    }
+}
+
+/// 当前 CPU 的 SchedControl，用来配置调度上下文
+#[cfg(feature = "mcs")]
+pub static SCHED_CONTROL: spin::Once<sel4::cap::SchedControl> = spin::Once::new();
+
+impl Sel4Task {
+    /// 设置任务的优先级和最大控制优先级，MCS 内核上同时绑定调度上下文
+    ///
+    /// `fault_ep` 为 (异常端口, 标记)，MCS 内核在设置调度参数时指定异常端口
+    pub fn set_sched(
+        &mut self,
+        sched: SchedParams,
+        fault_ep: (sel4::cap::Endpoint, u64),
+    ) -> sel4::Result<()> {
+        self.sched = sched;
+        let priority = sched.priority as _;
+        #[cfg(not(feature = "mcs"))]
+        {
+            let _ = fault_ep;
+            self.tcb
+                .tcb_set_sched_params(slot::TCB.cap(), priority, priority)
+        }
+        #[cfg(feature = "mcs")]
+        {
+            let sched_context = sched.sched_context(fault_ep.1);
+            let ep_slot = OBJ_ALLOCATOR.allocate_slot();
+            LeafSlot::from_cap(fault_ep.0).mint_to(
+                ep_slot,
+                sel4::CapRights::all(),
+                fault_ep.1 as _,
+            )?;
+            self.sched_slots
+                .extend([LeafSlot::from_cap(sched_context), ep_slot]);
+            self.tcb.tcb_set_sched_params(
+                slot::TCB.cap(),
+                priority,
+                priority,
+                sched_context,
+                ep_slot.cap(),
+            )
+        }
+    }
+}
+
+impl RootTaskHandler {
+    /// 任务 `id` 创建的 Linux 应用的优先级，不超过任务自己的优先级
+    pub fn app_priority(&self, id: usize) -> usize {
+        let task = &self.tasks[id];
+        task.app_sched.priority.min(task.sched.priority)
+    }
+}
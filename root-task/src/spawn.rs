@@ -0,0 +1,158 @@
+//! 运行时启动服务
+//!
+//! kernel-thread 把 [SpawnRequest] 和从文件系统中读取的 ELF 文件放在共享内存通道中，
+//! root-task 映射这个通道，检查请求之后像启动时的服务一样创建任务。
+//! 只有 `allow` 中写出 [SPAWN_PERMISSION] 的任务可以启动服务。动态启动的服务只能查找请求中列出的、
+//! 启动它的任务也可以查找的服务，得到的权限和启动它的任务相同。服务的配额为 [SPAWN_QUOTA]，
+//! 从启动它的任务的配额中扣除，服务退出之后归还。
+use alloc::vec::Vec;
+use common::{
+    config::PAGE_SIZE,
+    root::{ResourceUsage, SPAWN_ELF_OFFSET, SpawnRequest},
+};
+use object::File;
+use sel4::{CapRights, cap::SmallPage};
+use sel4_kit::slot_manager::LeafSlot;
+use xmas_elf::ElfFile;
+
+use crate::{
+    OBJ_ALLOCATOR, RootTaskHandler, policy::SPAWN_PERMISSION, task::build_kernel_thread,
+    utils::map_root_frame,
+};
+
+/// root-task 映射启动请求的地址
+const SPAWN_VADDR: usize = 0x2_4000_0000;
+
+/// 动态启动的服务的资源配额
+const SPAWN_QUOTA: ResourceUsage = ResourceUsage {
+    untyped: 0x10_0000,
+    pages: 64,
+    channels: 4,
+    slots: 256,
+};
+
+impl RootTaskHandler {
+    /// 任务 `parent` 启动共享内存通道 `channel_id` 中的服务，成功时返回服务的编号
+    ///
+    /// 新的服务使用 `parent` 的调度参数。`parent` 不能启动服务、配额不足、通道不存在、
+    /// 请求不合法或者 ELF 文件无法解析时返回 [Option::None]
+    pub fn spawn_service(&mut self, parent: usize, channel_id: usize) -> Option<usize> {
+        if !self.may_spawn(parent) {
+            return None;
+        }
+        let pages = &self.channel(channel_id)?.pages;
+        if pages.len() * PAGE_SIZE <= SPAWN_ELF_OFFSET {
+            return None;
+        }
+
+        // 映射通道中所有的页，每个页使用一个新的 Capability 复制
+        let frames = pages
+            .iter()
+            .enumerate()
+            .map(|(idx, page)| {
+                let slot = OBJ_ALLOCATOR.allocate_slot();
+                slot.copy_from(&LeafSlot::from_cap(*page), CapRights::all())
+                    .unwrap();
+                let frame: SmallPage = slot.cap();
+                map_root_frame(SPAWN_VADDR + idx * PAGE_SIZE, frame);
+                frame
+            })
+            .collect::<Vec<_>>();
+        let size = frames.len() * PAGE_SIZE;
+
+        let request = unsafe { &*(SPAWN_VADDR as *const SpawnRequest) };
+        let id = self.spawn_from_request(parent, request, size);
+
+        frames.into_iter().for_each(|frame| {
+            frame.frame_unmap().unwrap();
+            LeafSlot::from_cap(frame).delete().unwrap();
+        });
+        id
+    }
+
+    /// 检查映射在 [SPAWN_VADDR] 的请求并创建任务，`parent` 为启动服务的任务，`size` 为通道的大小
+    fn spawn_from_request(
+        &mut self,
+        parent: usize,
+        request: &SpawnRequest,
+        size: usize,
+    ) -> Option<usize> {
+        let name = request.name();
+        // 共享内存中的值由其他任务写入，是否共享按照整数读取，只能是 0 或者 1
+        let mem = request
+            .mem
+            .iter()
+            .filter(|x| x.2 != 0)
+            .map(|&(vaddr, paddr, len, shared)| match shared {
+                0 | 1 => Some((vaddr, paddr, len, shared == 1)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        let valid = !name.is_empty() && request.elf_len <= size - SPAWN_ELF_OFFSET;
+        let Some(mem) = mem.filter(|_| valid) else {
+            log::warn!("[RootTask] Invalid spawn request for {:?}", name);
+            return None;
+        };
+        if self
+            .tasks
+            .iter()
+            .any(|task| task.name == name && task.running)
+        {
+            log::warn!("[RootTask] Service {} already exists", name);
+            return None;
+        }
+
+        let elf = unsafe {
+            core::slice::from_raw_parts(
+                (SPAWN_VADDR + SPAWN_ELF_OFFSET) as *const u8,
+                request.elf_len,
+            )
+        };
+        // build_kernel_thread 无法解析 ELF 文件时会直接 panic，需要提前检查
+        if ElfFile::new(elf).is_err() || File::parse(elf).is_err() {
+            log::warn!("[RootTask] Invalid ELF file for {}", name);
+            return None;
+        }
+
+        // 只能查找启动它的任务也可以查找的服务，权限不超过启动它的任务
+        let services = request
+            .services()
+            .filter_map(|name| Some((name.into(), self.lookup_rights(parent, name)?)))
+            .collect();
+        if !self.charge(parent, SPAWN_QUOTA) {
+            return None;
+        }
+
+        let id = self.tasks.len();
+        let srv_ep = OBJ_ALLOCATOR.alloc_endpoint();
+        let sched = self.tasks[parent].sched;
+        let Ok(mut task) =
+            build_kernel_thread(id, (self.fault_ep, id as _), srv_ep, name, elf, sched)
+        else {
+            self.refund(parent, SPAWN_QUOTA);
+            LeafSlot::from_cap(srv_ep).delete().unwrap();
+            return None;
+        };
+
+        let dma = request.dma.iter().filter(|x| x.1 != 0).copied();
+        if task
+            .map_resources(&mut self.device_frames, id, &mem, &dma.collect::<Vec<_>>())
+            .is_none()
+        {
+            task.destroy();
+            self.device_frames.release(id);
+            self.refund(parent, SPAWN_QUOTA);
+            LeafSlot::from_cap(srv_ep).delete().unwrap();
+            log::warn!("[RootTask] Can't find device memory for {}", name);
+            return None;
+        }
+        task.allowed_services = services;
+        task.quota = SPAWN_QUOTA;
+        task.parent = Some(parent);
+
+        log::info!("[RootTask] Spawn service {} as {}", name, id);
+        task.run();
+        self.tasks.push(task);
+        Some(id)
+    }
+}
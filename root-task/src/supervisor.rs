@@ -0,0 +1,244 @@
+//! 服务监督
+//!
+//! 服务出现异常或者退出时 root-task 会停止服务并删除服务的 TCB、地址空间、共享内存通道和 DMA 内存，释放服务独占的设备内存，
+//! 然后根据 `apps.toml` 中的重启策略决定是否重新启动服务。重启时重新加载 ELF 文件并继续使用原来的服务端口，
+//! 客户端拿到的服务端口仍然有效。不再重启的服务的端口会被删除，之后调用这个服务的客户端会得到
+//! [SERVICE_UNAVAILABLE] 错误，而不是一直等待。
+//!
+//! 服务退出时已经调用服务、正在等待回复的客户端不会再收到回复。root-task 记录了每个任务通过 FindService
+//! 得到的服务端口的位置，服务退出时停止正在调用这个端口的客户端，让调用直接返回 [SERVICE_UNAVAILABLE]。
+//!
+//! root-task 没有定时器，服务退出时立即重启。服务启动之后运行的时间不到 [Supervised::backoff] 就再次退出时
+//! 记为连续失败，需要运行的时间翻倍，连续失败 [MAX_RESTARTS] 次或者无法重新创建的服务不再重启。
+//!
+//! 服务的 TCB、CSpace、地址空间和页都从 [OBJ_ALLOCATOR](crate::OBJ_ALLOCATOR) 的 untyped 中创建，
+//! 删除之后内存不会回到 untyped 中，每次重启都会多占用一个服务的内存。所以一个服务一共只能重启
+//! [MAX_TOTAL_RESTARTS] 次，之后和连续失败的服务一样不再重启。
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use common::root::SERVICE_UNAVAILABLE;
+use sel4::{IpcBuffer, MessageInfoBuilder, UserContext};
+use sel4_kit::{arch::current_time, slot_manager::LeafSlot};
+
+use crate::{RootTaskHandler, config::KernelServices, task::build_kernel_thread};
+
+/// 服务的重启策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// 不重启
+    Never,
+    /// 服务出现异常或者以非 0 的退出码退出时重启
+    OnFailure,
+    /// 服务退出时总是重启
+    Always,
+}
+
+impl RestartPolicy {
+    /// 服务退出之后是否需要重启，`failed` 表示服务出现异常或者以非 0 的退出码退出
+    pub const fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// 连续失败之后不再重启的次数
+const MAX_RESTARTS: u32 = 6;
+
+/// 一个服务一共可以重启的次数，限制重启服务泄漏的内存
+const MAX_TOTAL_RESTARTS: u32 = 32;
+
+/// 启动时创建的服务的监督状态
+pub struct Supervised {
+    /// 任务编号
+    pub id: usize,
+    /// 服务的配置
+    pub service: &'static KernelServices,
+    /// 连续失败的次数
+    pub restarts: u32,
+    /// 一共重启的次数
+    pub total_restarts: u32,
+    /// 服务最近一次启动的时间
+    pub started_at: Duration,
+}
+
+impl Supervised {
+    /// 创建一个服务的监督状态，服务在创建时启动
+    pub fn new(id: usize, service: &'static KernelServices) -> Self {
+        Self {
+            id,
+            service,
+            restarts: 0,
+            total_restarts: 0,
+            started_at: current_time(),
+        }
+    }
+
+    /// 服务启动之后需要运行的时间，运行的时间不到这个时间就退出时记为连续失败
+    fn backoff(&self) -> Duration {
+        Duration::from_millis(self.service.backoff << self.restarts)
+    }
+}
+
+/// `seL4_Call` 的系统调用号，保存在 x7 中
+const SYS_CALL: u64 = -1i64 as u64;
+
+/// seL4 中 MessageInfo 的编码：label 在第 12 位之后，低 7 位是消息长度
+const fn message_info(label: u64, length: u64) -> u64 {
+    (label << 12) | length
+}
+
+impl RootTaskHandler {
+    /// 处理任务退出，`failed` 表示任务出现异常或者以非 0 的退出码退出
+    pub fn handle_exit(&mut self, id: usize, failed: bool) {
+        let task = &mut self.tasks[id];
+        if !task.running {
+            return;
+        }
+        log::warn!(
+            "[RootTask] Service {} {}",
+            task.name,
+            if failed { "failed" } else { "exited" }
+        );
+        task.destroy();
+        // 动态启动的服务退出之后把配额还给启动它的任务
+        if let Some(parent) = task.parent.take() {
+            let quota = task.quota;
+            self.refund(parent, quota);
+        }
+        self.fail_waiting_callers(id);
+        self.leave_all_channels(id);
+        self.release_dma(id);
+        self.device_frames.release(id);
+
+        let now = current_time();
+        let supervised = self
+            .supervised
+            .iter()
+            .position(|x| x.id == id && x.service.restart.should_restart(failed));
+        let restarted = supervised.is_some_and(|idx| {
+            let supervised = &mut self.supervised[idx];
+            // 运行了足够长时间的服务重新开始计算连续失败的次数
+            match now - supervised.started_at >= supervised.backoff() {
+                true => supervised.restarts = 0,
+                false => supervised.restarts += 1,
+            }
+            if supervised.restarts >= MAX_RESTARTS {
+                log::error!(
+                    "[RootTask] {} failed {} times in a row, give up",
+                    supervised.service.name,
+                    supervised.restarts
+                );
+                return false;
+            }
+            if supervised.total_restarts >= MAX_TOTAL_RESTARTS {
+                log::error!(
+                    "[RootTask] {} has been restarted {} times, give up",
+                    supervised.service.name,
+                    supervised.total_restarts
+                );
+                return false;
+            }
+            supervised.total_restarts += 1;
+            supervised.started_at = now;
+            self.restart(idx)
+        });
+        if !restarted {
+            // 删除服务端口和所有客户端的端口，之后的调用会得到错误
+            let srv_ep = LeafSlot::from_cap(self.tasks[id].srv_ep);
+            srv_ep.revoke().unwrap();
+            srv_ep.delete().unwrap();
+        }
+    }
+
+    /// 重新创建第 `idx` 个被监督的服务，无法创建时返回 `false`
+    fn restart(&mut self, idx: usize) -> bool {
+        let Supervised { id, service, .. } = self.supervised[idx];
+        log::info!("[RootTask] Restart {}", service.name);
+
+        let srv_ep = self.tasks[id].srv_ep;
+        let task = build_kernel_thread(
+            id,
+            (self.fault_ep, id as _),
+            srv_ep,
+            service.name,
+            service.file,
+            service.sched,
+        );
+        let mut task = match task {
+            Ok(task) => task,
+            Err(err) => {
+                log::error!("[RootTask] Can't rebuild {}: {:?}", service.name, err);
+                return false;
+            }
+        };
+        task.allowed_services = service.allowed_services();
+        task.quota = service.quota;
+        task.app_sched = service.app_sched;
+        if task
+            .map_resources(&mut self.device_frames, id, service.mem, service.dma)
+            .is_none()
+        {
+            task.destroy();
+            self.device_frames.release(id);
+            log::error!("[RootTask] Can't find device memory for {}", service.name);
+            return false;
+        }
+        task.run();
+        self.tasks[id] = task;
+        true
+    }
+
+    /// 让正在调用第 `id` 个任务的服务端口的客户端直接返回 [SERVICE_UNAVAILABLE]
+    ///
+    /// 服务退出之后排队等待的调用和等待回复的调用都不会再得到回复。客户端的 x7 为 [SYS_CALL]
+    /// 并且 x0 是得到的服务端口时正在调用这个服务，停止客户端之后取消调用并设置返回值，再恢复运行。
+    fn fail_waiting_callers(&mut self, id: usize) {
+        for client in self.tasks.iter().filter(|task| task.running) {
+            let slots = client
+                .found_services
+                .iter()
+                .filter(|(server, _)| *server == id)
+                .map(|(_, slot)| *slot)
+                .collect::<Vec<_>>();
+            if slots.is_empty() {
+                continue;
+            }
+            let ctx = client.tcb.tcb_read_all_registers(false).unwrap();
+            if *ctx.gpr(7) != SYS_CALL || !slots.contains(ctx.gpr(0)) {
+                continue;
+            }
+            log::warn!(
+                "[RootTask] Cancel the call from {} to {}",
+                client.name,
+                self.tasks[id].name
+            );
+            client.tcb.tcb_suspend().unwrap();
+            let mut ctx = client.tcb.tcb_read_all_registers(false).unwrap();
+            set_unavailable(&mut ctx);
+            client.tcb.tcb_write_all_registers(true, &mut ctx).unwrap();
+        }
+    }
+
+    /// 让调用已经删除的服务端口的任务直接返回 [SERVICE_UNAVAILABLE]
+    ///
+    /// 任务调用无效的 Capability 时会产生 CapFault，跳过系统调用指令并设置返回的 MessageInfo，
+    /// 第一个消息寄存器设置为 `-1`。
+    pub fn fail_call(&mut self, ib: &mut IpcBuffer) {
+        let tcb = self.tasks[self.badge as usize].tcb;
+        let mut ctx = tcb.tcb_read_all_registers(false).unwrap();
+        set_unavailable(&mut ctx);
+        tcb.tcb_write_all_registers(false, &mut ctx).unwrap();
+        sel4::reply(ib, MessageInfoBuilder::default().build());
+    }
+}
+
+/// 跳过系统调用指令，让调用返回 [SERVICE_UNAVAILABLE]，第一个消息寄存器为 `-1`
+fn set_unavailable(ctx: &mut UserContext) {
+    *ctx.pc_mut() += 4;
+    *ctx.gpr_mut(1) = message_info(SERVICE_UNAVAILABLE, 1);
+    *ctx.gpr_mut(2) = u64::MAX;
+}
@@ -1,5 +1,9 @@
 use crate::{
     OBJ_ALLOCATOR,
+    device::{DeviceFrame, DeviceFrames},
+    dma::{dma_size_bits, retype_pages},
+    policy::ServiceRights,
+    sched::SchedParams,
     utils::{footprint, map_image, map_intermediate_translation_tables},
 };
 use alloc::{
@@ -11,9 +15,11 @@ use alloc::{
 use common::{
     config::{
         self, CNODE_RADIX_BITS, DEFAULT_PARENT_EP, DEFAULT_SERVE_EP, DEFAULT_THREAD_NOTIFICATION,
-        PAGE_SIZE, STACK_ALIGN_SIZE,
+        LARGE_PAGE_SIZE, PAGE_SIZE, STACK_ALIGN_SIZE,
     },
     page::PhysPage,
+    root::ResourceUsage,
+    slot::recycle_slot,
 };
 use object::{File, Object};
 use sel4::{
@@ -61,6 +67,24 @@ pub struct Sel4Task {
     pub mapped_page: BTreeMap<usize, PhysPage>,
     /// 栈底
     pub stack_bottom: usize,
+    /// 可以通过 FindService 查找的服务和服务端口的权限，没有列出的服务都无法查找
+    pub allowed_services: Vec<(String, ServiceRights)>,
+    /// 通过 FindService 得到的服务端口：服务的任务编号和端口在任务 CSpace 中的位置
+    pub found_services: Vec<(usize, u64)>,
+    /// 任务是否正在运行，退出之后为 `false`
+    pub running: bool,
+    /// 任务已经申请的资源
+    pub usage: ResourceUsage,
+    /// 任务的资源配额
+    pub quota: ResourceUsage,
+    /// 启动这个任务的任务，任务的配额从启动它的任务的配额中扣除
+    pub parent: Option<usize>,
+    /// 任务的调度参数
+    pub sched: SchedParams,
+    /// 任务创建的 Linux 应用的调度参数
+    pub app_sched: SchedParams,
+    /// 为任务创建的调度上下文和异常端口，删除任务时一起删除
+    pub sched_slots: Vec<LeafSlot>,
 }
 
 impl Sel4Task {
@@ -83,6 +107,15 @@ impl Sel4Task {
             mapped_pt: Arc::new(Mutex::new(Vec::new())),
             mapped_page,
             stack_bottom: config::SERVICE_BOOT_STACK_TOP,
+            allowed_services: Vec::new(),
+            found_services: Vec::new(),
+            running: false,
+            usage: ResourceUsage::default(),
+            quota: ResourceUsage::DEFAULT_QUOTA,
+            parent: None,
+            sched: SchedParams::SERVICE,
+            app_sched: SchedParams::APP,
+            sched_slots: Vec::new(),
         };
 
         // Move Fault EP to child process
@@ -138,6 +171,33 @@ impl Sel4Task {
         unreachable!("Failed to map page!")
     }
 
+    /// 取消映射 `vaddr` 上的页，删除页的 Capability 并回收 slot
+    pub fn unmap_page(&mut self, vaddr: usize) {
+        if let Some(page) = self.mapped_page.remove(&vaddr) {
+            let slot = LeafSlot::from_cap(page.cap());
+            slot.delete().unwrap();
+            recycle_slot(slot);
+        }
+    }
+
+    /// 映射设备内存的页，`paddr` 所在的 2MiB 内存映射到 `vaddr`
+    ///
+    /// 设备寄存器的虚拟地址为 `vaddr + paddr % LARGE_PAGE_SIZE`，和 [common::root::DeviceInfo::vaddr] 相同。
+    /// 已经映射的页会被跳过
+    pub fn map_device(&mut self, vaddr: usize, paddr: usize, frames: Vec<(usize, DeviceFrame)>) {
+        let base = paddr / LARGE_PAGE_SIZE * LARGE_PAGE_SIZE;
+        for (frame_addr, frame) in frames {
+            let vaddr = vaddr + frame_addr - base;
+            match frame {
+                DeviceFrame::Small(page) if !self.mapped_page.contains_key(&vaddr) => {
+                    self.map_page(vaddr, PhysPage::new(page))
+                }
+                DeviceFrame::Large(page) => self.map_large_page(vaddr, page),
+                DeviceFrame::Small(_) => frame.delete(),
+            }
+        }
+    }
+
     /// 映射一个大页 [sel4::cap::LargePage] 到指定的虚拟地址
     pub fn map_large_page(&mut self, vaddr: usize, page: sel4::cap::LargePage) {
         assert_eq!(vaddr % PAGE_SIZE, 0);
@@ -195,7 +255,9 @@ impl Sel4Task {
             .copy(&cnode_relative(self.vspace), CapRights::all())
             .unwrap();
 
+        // MCS 内核在设置调度参数时指定异常端口
         self.tcb.tcb_configure(
+            #[cfg(not(feature = "mcs"))]
             DEFAULT_PARENT_EP.cptr(),
             self.cnode,
             CNodeCapData::skip_high_bits(radix_bits),
@@ -231,6 +293,15 @@ impl Sel4Task {
             mapped_pt: self.mapped_pt.clone(),
             mapped_page: self.mapped_page.clone(),
             stack_bottom: self.stack_bottom,
+            allowed_services: self.allowed_services.clone(),
+            found_services: self.found_services.clone(),
+            running: self.running,
+            usage: ResourceUsage::default(),
+            quota: self.quota,
+            parent: None,
+            sched: self.sched,
+            app_sched: self.app_sched,
+            sched_slots: Vec::new(),
         }
     }
 
@@ -256,9 +327,88 @@ impl Sel4Task {
         self.name = name.to_string();
     }
 
+    /// 映射任务的设备内存和 DMA 内存
+    ///
+    /// ## 参数
+    /// - `frames` 设备内存的页
+    /// - `id`     任务编号
+    /// - `mem`    设备内存 (虚拟地址, 物理地址, 大小, 是否共享)
+    /// - `dma`    DMA 内存 (虚拟地址, 大小)
+    ///
+    /// 找不到设备内存、设备内存被其他任务独占或者 DMA 内存太大时返回 [Option::None]
+    pub fn map_resources(
+        &mut self,
+        frames: &mut DeviceFrames,
+        id: usize,
+        mem: &[(usize, usize, usize, bool)],
+        dma: &[(usize, usize)],
+    ) -> Option<()> {
+        for (vaddr, paddr, size, shared) in mem {
+            let device_frames = frames.frames(*paddr, *size)?;
+            if !frames.claim(id, &device_frames, *shared) {
+                device_frames.iter().for_each(|x| x.1.delete());
+                return None;
+            }
+            self.map_device(*vaddr, *paddr, device_frames);
+        }
+
+        // 映射 DMA 内存，从一个 untyped 中申请物理地址连续的页
+        for (start, size) in dma {
+            let size_bits = dma_size_bits(*size, PAGE_SIZE)?;
+            let untyped = OBJ_ALLOCATOR.alloc_untyped(size_bits);
+            let pages_cap = retype_pages(untyped, size_bits, size.div_ceil(PAGE_SIZE));
+
+            // 映射多个页表
+            pages_cap.into_iter().enumerate().for_each(|(i, page)| {
+                debug_println!(
+                    "[RootTask] Mapping DMA {:#x} -> {:#x}",
+                    start + i * PAGE_SIZE,
+                    page.frame_get_address().unwrap()
+                );
+                self.map_page(start + i * PAGE_SIZE, PhysPage::new(page));
+            });
+        }
+        Some(())
+    }
+
     /// 将任务设置为运行状态
-    pub fn run(&self) {
+    pub fn run(&mut self) {
         self.tcb.tcb_resume().unwrap();
+        self.running = true;
+    }
+
+    /// 停止任务并删除任务的 TCB、CSpace、地址空间和映射的页
+    ///
+    /// 服务端口由调用者处理，重启服务时继续使用原来的服务端口。
+    /// 删除 Capability 之后任务使用的内存不会还给 [OBJ_ALLOCATOR]，所以监督的服务重启的次数有上限，
+    /// 见 [crate::supervisor]
+    pub fn destroy(&mut self) {
+        self.tcb.tcb_suspend().unwrap();
+        self.running = false;
+
+        // 删除页的 Capability 时会取消映射，删除之后回收 slot
+        let pages = core::mem::take(&mut self.mapped_page)
+            .into_values()
+            .map(|page| LeafSlot::from_cap(page.cap()));
+        let pts = self.mapped_pt.lock().drain(..).collect::<Vec<_>>();
+        for slot in pages.chain(pts.into_iter().map(LeafSlot::from_cap)) {
+            slot.delete().unwrap();
+            recycle_slot(slot);
+        }
+
+        // 先删除复制到任务 CSpace 中的 Capability，再删除最后一个 Capability 回收对象
+        let caps = [
+            LeafSlot::from_cap(self.tcb),
+            LeafSlot::from_cap(self.cnode),
+            LeafSlot::from_cap(self.vspace),
+        ];
+        for slot in caps {
+            slot.revoke().unwrap();
+            slot.delete().unwrap();
+        }
+        self.sched_slots
+            .drain(..)
+            .for_each(|slot| slot.delete().unwrap());
     }
 }
 
@@ -270,8 +420,10 @@ pub fn cnode_relative<T: HasCPtrWithDepth>(path: T) -> AbsoluteCPtr {
 pub fn build_kernel_thread(
     id: usize,
     fault_ep: (Endpoint, u64),
+    srv_ep: Endpoint,
     thread_name: &str,
     file_data: &[u8],
+    sched: SchedParams,
 ) -> sel4::Result<Sel4Task> {
     // make 新线程的虚拟地址空间
     let cnode = OBJ_ALLOCATOR.alloc_cnode(CNODE_RADIX_BITS);
@@ -284,7 +436,6 @@ pub fn build_kernel_thread(
     );
 
     let tcb = OBJ_ALLOCATOR.alloc_tcb();
-    let srv_ep = OBJ_ALLOCATOR.alloc_endpoint();
 
     let mut task = Sel4Task::new(
         tcb,
@@ -303,7 +454,7 @@ pub fn build_kernel_thread(
     task.map_stack(config::SERVICE_BOOT_STACK_SIZE.div_ceil(PAGE_SIZE));
 
     // set task priority and max control priority
-    task.tcb.tcb_set_sched_params(slot::TCB.cap(), 255, 255)?;
+    task.set_sched(sched, fault_ep)?;
 
     task.tcb.debug_name(thread_name.as_bytes());
     task.set_name(thread_name);
@@ -321,8 +472,8 @@ pub fn build_kernel_thread(
     Ok(task)
 }
 
-pub fn run_tasks(tasks: &[Sel4Task]) {
-    tasks.iter().for_each(Sel4Task::run)
+pub fn run_tasks(tasks: &mut [Sel4Task]) {
+    tasks.iter_mut().for_each(Sel4Task::run)
 }
 
 /// 创建一个新的虚拟地址空间
@@ -6,7 +6,12 @@ use object::{
     Object, ObjectSegment, SegmentFlags,
     elf::{PF_R, PF_W, PF_X},
 };
-use sel4::{UntypedDesc, cap::Untyped};
+use sel4::{
+    CapRights, CapTypeForFrameObject, Error, UntypedDesc, VmAttributes, cap::Untyped,
+    init_thread::slot,
+};
+
+use crate::OBJ_ALLOCATOR;
 
 /// Display the boot information in the console.
 pub fn display_bootinfo(
@@ -184,3 +189,24 @@ fn coarsen_footprint(footprint: &Range<usize>, granularity: usize) -> Range<usiz
 const fn round_down(n: usize, b: usize) -> usize {
     n - n % b
 }
+
+/// 把页映射到 root-task 自己的地址空间，缺少页表时先映射页表
+pub fn map_root_frame<T: CapTypeForFrameObject>(vaddr: usize, frame: sel4::Cap<T>) {
+    for _ in 0..sel4::vspace_levels::NUM_LEVELS {
+        let res = frame.frame_map(
+            slot::VSPACE.cap(),
+            vaddr,
+            CapRights::read_write(),
+            VmAttributes::DEFAULT,
+        );
+        match res {
+            Ok(_) => return,
+            Err(Error::FailedLookup) => OBJ_ALLOCATOR
+                .alloc_pt()
+                .pt_map(slot::VSPACE.cap(), vaddr, VmAttributes::DEFAULT)
+                .unwrap(),
+            _ => res.unwrap(),
+        }
+    }
+    unreachable!("Failed to map page!")
+}
@@ -7,23 +7,30 @@ extern crate alloc;
 use core::ptr::NonNull;
 
 use common::{
-    config::{VIRTIO_MMIO_BLK_VIRT_ADDR, VIRTIO_NET_IRQ},
-    root::{join_channel, register_irq, register_notify},
+    config::{VIRTIO_BLK_IRQ, VIRTIO_MMIO_BLK_VIRT_ADDR, VIRTIO_MMIO_VIRT_ADDR},
+    root::{DeviceKind, find_device_or, join_channel, register_irq, register_notify},
     slot::alloc_slot,
+    virtio::HalImpl,
 };
 use flatten_objects::FlattenObjects;
 use sel4::cap::{IrqHandler, Notification};
 use sel4_runtime::utils::alloc_free_addr;
-use srv_gate::{blk::BlockIface, def_blk_impl};
-use virtio::HalImpl;
+use srv_gate::{Errno, blk::BlockIface, def_blk_impl};
 use virtio_drivers::{
     device::blk::{BlkReq, BlkResp, VirtIOBlk},
     transport::mmio::{MmioTransport, VirtIOHeader},
 };
 
-mod virtio;
-
-def_blk_impl!(VIRTIOBLK, VirtIOBlkImpl::new(VIRTIO_MMIO_BLK_VIRT_ADDR));
+def_blk_impl!(VIRTIOBLK, {
+    // 使用 root-task 从设备树中找到的第一个块设备
+    let (addr, irq) = find_device_or(
+        DeviceKind::VirtIOBlock,
+        0,
+        VIRTIO_MMIO_VIRT_ADDR,
+        (VIRTIO_MMIO_BLK_VIRT_ADDR, VIRTIO_BLK_IRQ),
+    );
+    VirtIOBlkImpl::new(addr, irq)
+});
 
 pub struct VirtIOBlkImpl {
     device: VirtIOBlk<HalImpl, MmioTransport>,
@@ -36,7 +43,7 @@ unsafe impl Sync for VirtIOBlkImpl {}
 unsafe impl Send for VirtIOBlkImpl {}
 
 impl VirtIOBlkImpl {
-    pub fn new(addr: usize) -> Self {
+    pub fn new(addr: usize, irq: usize) -> Self {
         let ptr = addr as *mut VirtIOHeader;
         let stores = FlattenObjects::<(usize, usize), 32>::new();
         let device = VirtIOBlk::<HalImpl, MmioTransport>::new(unsafe {
@@ -46,7 +53,7 @@ impl VirtIOBlkImpl {
 
         // 向 root-task 申请一个中断
         let irq_handler = alloc_slot().cap();
-        register_irq(VIRTIO_NET_IRQ as _, irq_handler.into());
+        register_irq(irq as _, irq_handler.into());
 
         // 向 root-task 申请一个通知
         let ntfn = alloc_slot().cap();
@@ -81,7 +88,7 @@ impl BlockIface for VirtIOBlkImpl {
         alloc_free_addr(size);
     }
 
-    fn read_block(&mut self, block_id: usize, block_num: usize) {
+    fn read_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno> {
         let mut request = BlkReq::default();
         let mut resp = BlkResp::default();
         // TODO: 根据 badge 分辨多个任务
@@ -91,7 +98,7 @@ impl BlockIface for VirtIOBlkImpl {
         let token = unsafe {
             self.device
                 .read_blocks_nb(block_id, &mut request, buffer, &mut resp)
-                .unwrap()
+                .map_err(|_| Errno::EIO)?
         };
         // 顺序不能变，先等待中断，然后处理 virtio_blk 的中断
         // 最后 ACK 中断
@@ -102,11 +109,12 @@ impl BlockIface for VirtIOBlkImpl {
         unsafe {
             self.device
                 .complete_read_blocks(token, &request, buffer, &mut resp)
-                .unwrap();
+                .map_err(|_| Errno::EIO)?;
         }
+        Ok(())
     }
 
-    fn write_block(&mut self, block_id: usize, block_num: usize) {
+    fn write_block(&mut self, block_id: usize, block_num: usize) -> Result<(), Errno> {
         let mut request = BlkReq::default();
         let mut resp = BlkResp::default();
         // TODO: 根据 badge 分辨多个任务
@@ -116,7 +124,7 @@ impl BlockIface for VirtIOBlkImpl {
         let token = unsafe {
             self.device
                 .write_blocks_nb(block_id, &mut request, buffer, &mut resp)
-                .unwrap()
+                .map_err(|_| Errno::EIO)?
         };
         // 顺序不能变，先等待中断，然后处理 virtio_blk 的中断
         // 最后 ACK 中断
@@ -127,11 +135,12 @@ impl BlockIface for VirtIOBlkImpl {
         unsafe {
             self.device
                 .complete_write_blocks(token, &request, buffer, &mut resp)
-                .unwrap();
+                .map_err(|_| Errno::EIO)?;
         }
+        Ok(())
     }
 
-    fn capacity(&self) -> u64 {
-        self.device.capacity() * 0x200
+    fn capacity(&self) -> Result<u64, Errno> {
+        Ok(self.device.capacity() * 0x200)
     }
 }
@@ -7,16 +7,25 @@ extern crate blk_thread;
 use blk_thread::VIRTIOBLK;
 use common::{config::DEFAULT_SERVE_EP, read_types, reply_with};
 use sel4::{MessageInfoBuilder, with_ipc_buffer_mut};
-use sel4_runtime::main;
-use srv_gate::blk::BlockIfaceEvent;
+use sel4_runtime::{main, utils::alloc_free_addr};
+use srv_gate::{blk::BlockIfaceEvent, klog};
 
 sel4_runtime::define_heap!(common::config::SERVICE_HEAP_SIZE);
 
 #[main]
 fn main() {
+    // 加入日志环，日志由串口服务输出到服务日志的虚拟控制台，也可以通过 dmesg 查看
+    let log_addr = alloc_free_addr(0);
+    alloc_free_addr(klog::init(
+        "block-thread",
+        log_addr,
+        log::LevelFilter::Debug,
+        None,
+    ));
+
     let mut virtio_blk = VIRTIOBLK.lock();
 
-    log::debug!("Block device capacity: {:#x}", virtio_blk.capacity());
+    log::debug!("Block device capacity: {:#x?}", virtio_blk.capacity());
 
     let rev_msg = MessageInfoBuilder::default();
 
@@ -36,17 +45,22 @@ fn main() {
                 }
                 BlockIfaceEvent::read_block => {
                     let (block_id, block_num) = read_types!(ib, usize, usize);
-                    virtio_blk.read_block(block_id, block_num);
-
-                    sel4::reply(ib, rev_msg.build());
+                    let label = match virtio_blk.read_block(block_id, block_num) {
+                        Ok(()) => 0,
+                        Err(errno) => errno.into_raw() as _,
+                    };
+                    sel4::reply(ib, rev_msg.label(label).build());
                 }
                 BlockIfaceEvent::write_block => {
                     let (block_id, block_num) = read_types!(ib, usize, usize);
-                    virtio_blk.write_block(block_id, block_num);
-                    sel4::reply(ib, rev_msg.build());
+                    let label = match virtio_blk.write_block(block_id, block_num) {
+                        Ok(()) => 0,
+                        Err(errno) => errno.into_raw() as _,
+                    };
+                    sel4::reply(ib, rev_msg.label(label).build());
                 }
                 BlockIfaceEvent::capacity => {
-                    reply_with!(ib, virtio_blk.capacity())
+                    reply_with!(ib, virtio_blk.capacity().unwrap_or(0))
                 }
             }
         }
@@ -0,0 +1,290 @@
+//! virtio-console 串口服务
+//!
+//! 使用 QEMU 的 virtio-serial 提供 [UartIface]，和 `uart-thread` 二选一。
+//! 一个多端口的 virtio-console 设备为每个虚拟控制台提供一个端口 ([multiport])：
+//! 端口 0 是应用程序的控制台，端口 1 输出内核日志，端口 2 输出服务日志，端口 3 输出服务的
+//! 警告和错误。虚拟控制台直接输出到对应的端口，不需要热键切换，只有应用程序的控制台接收输入。
+//! 服务日志从日志环中读取，服务放入日志之后通过中断使用的通知唤醒服务。
+#![no_std]
+#![no_main]
+#![feature(used_with_arg)]
+
+extern crate alloc;
+
+mod multiport;
+mod queue;
+
+use alloc::collections::vec_deque::VecDeque;
+use core::ptr::NonNull;
+
+use common::{
+    config::{
+        LOG_CHANNEL_ID, VIRTIO_CONSOLE_IRQ, VIRTIO_CONSOLE_PORTS, VIRTIO_MMIO_CONSOLE_VIRT_ADDR,
+        VIRTIO_MMIO_VIRT_ADDR,
+    },
+    root::{
+        DeviceKind, channel_notify, find_device_or, join_channel, register_irq, register_notify,
+        set_channel_notify,
+    },
+    slot::{alloc_slot, recycle_slot},
+};
+use log::Level;
+use sel4::{
+    cap::{IrqHandler, Notification},
+    init_thread,
+};
+use sel4_kit::slot_manager::LeafSlot;
+use sel4_runtime::utils::alloc_free_addr;
+use spin::Mutex;
+use srv_gate::{
+    Errno, def_uart_impl,
+    klog::{self, KERNEL_LOG_SERVICE, LogCursor},
+    uart::{ByteRing, UartIface, VC_APP, VC_COUNT, VC_SERVICE_ERROR, VC_SERVICE_LOG},
+};
+use virtio_drivers::transport::{
+    DeviceType, Transport,
+    mmio::{MmioTransport, VirtIOHeader},
+};
+
+use multiport::MultiportConsole;
+
+// 每个虚拟控制台都需要一个端口
+const _: () = assert!(VIRTIO_CONSOLE_PORTS >= VC_COUNT);
+
+/// 接收缓冲区的大小，缓冲区满时丢弃新收到的字符
+pub const RX_BUFFER_SIZE: usize = 4096;
+
+def_uart_impl!(VIRTIOCONSOLE, VirtIOConsoleImpl::new());
+
+/// 客户端为应用程序控制台的发送环登记的通知
+///
+/// 收到输入或者发送环中的数据发送完成之后通知客户端，客户端不需要定时轮询
+static CLIENT_NOTIFY: Mutex<Option<Notification>> = Mutex::new(None);
+
+/// 通知客户端有新的输入或者发送环有了空间
+pub fn notify_client() {
+    if let Some(notify) = *CLIENT_NOTIFY.lock() {
+        notify.signal();
+    }
+}
+
+pub struct VirtIOConsoleImpl {
+    /// virtio-console 设备，设备不存在时为 [Option::None]
+    device: Option<MultiportConsole>,
+    /// 设备的中断
+    irq_handler: Option<IrqHandler>,
+    /// 已经收到但还没有被读取的字符
+    rx: VecDeque<u8>,
+    /// 中断是否已经绑定到通知上
+    irq_enabled: bool,
+    /// 每个虚拟控制台和客户端共享的发送环
+    tx: [Option<&'static ByteRing>; VC_COUNT],
+    /// 读取服务日志的位置
+    logs: LogCursor,
+}
+
+unsafe impl Sync for VirtIOConsoleImpl {}
+unsafe impl Send for VirtIOConsoleImpl {}
+
+impl VirtIOConsoleImpl {
+    pub fn new() -> Self {
+        // 使用 root-task 从设备树中找到的第一个控制台设备
+        let (addr, irq) = find_device_or(
+            DeviceKind::VirtIOConsole,
+            0,
+            VIRTIO_MMIO_VIRT_ADDR,
+            (VIRTIO_MMIO_CONSOLE_VIRT_ADDR, VIRTIO_CONSOLE_IRQ),
+        );
+        let device = Self::probe(addr);
+        let irq_handler = device.as_ref().map(|_| {
+            // 向 root-task 申请一个中断
+            let irq_handler: IrqHandler = alloc_slot().cap();
+            register_irq(irq as _, irq_handler.into());
+            irq_handler
+        });
+        if device.is_none() {
+            log::warn!("virtio-console not found");
+        }
+        Self {
+            device,
+            irq_handler,
+            rx: VecDeque::with_capacity(RX_BUFFER_SIZE),
+            irq_enabled: false,
+            tx: [None; VC_COUNT],
+            logs: LogCursor::new(),
+        }
+    }
+
+    /// 探测 `addr` 上的 virtio-console 设备并初始化，设备不存在时返回 [Option::None]
+    fn probe(addr: usize) -> Option<MultiportConsole> {
+        let ptr = addr as *mut VirtIOHeader;
+        let transport = unsafe { MmioTransport::new(NonNull::new(ptr).unwrap()) }.ok()?;
+        if transport.device_type() != DeviceType::Console {
+            return None;
+        }
+        let device = MultiportConsole::new(transport)
+            .inspect_err(|err| log::warn!("Failed to init virtio-console: {:?}", err))
+            .ok()?;
+        // 没有对应端口的虚拟控制台的输出会被丢弃
+        (0..VC_COUNT)
+            .filter(|vc| !device.has_port(*vc))
+            .for_each(|vc| log::warn!("virtio-console port {} not found", vc));
+        Some(device)
+    }
+
+    /// 向虚拟控制台对应的端口输出，端口不存在时丢弃
+    fn output(&mut self, vc: usize, data: &[u8]) {
+        if let Some(device) = &mut self.device {
+            device.send(vc, data);
+        }
+    }
+
+    /// 处理控制消息，取出应用程序控制台收到的字符并响应中断
+    fn drain(&mut self) {
+        if let Some(device) = &mut self.device {
+            device.ack_interrupt();
+            device.handle_control();
+            let rx = &mut self.rx;
+            device.recv(VC_APP, |data| {
+                let len = data.len().min(RX_BUFFER_SIZE - rx.len());
+                rx.extend(&data[..len]);
+            });
+        }
+        if let Some(irq_handler) = self.irq_handler.filter(|_| self.irq_enabled) {
+            irq_handler.irq_handler_ack().unwrap();
+        }
+        self.drain_logs();
+    }
+
+    /// 把日志环中新的服务日志输出到服务日志的端口，警告和错误同时输出到 [VC_SERVICE_ERROR]
+    fn drain_logs(&mut self) {
+        let Some(ring) = klog::ring() else {
+            return;
+        };
+        for entry in self.logs.poll(ring) {
+            // kernel-thread 的日志已经通过发送环输出到内核日志的端口
+            if entry.service == KERNEL_LOG_SERVICE {
+                continue;
+            }
+            let line = entry.colored();
+            self.output(VC_SERVICE_LOG, line.as_bytes());
+            if entry.level <= Level::Warn {
+                self.output(VC_SERVICE_ERROR, line.as_bytes());
+            }
+        }
+    }
+}
+
+impl Default for VirtIOConsoleImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UartIface for VirtIOConsoleImpl {
+    fn init(&mut self) {
+        if self.irq_enabled {
+            return;
+        }
+        // 向 root-task 申请一个通知，设备的中断和日志环的通知都发送到这个通知
+        let notify: Notification = alloc_slot().cap();
+        register_notify(notify.into(), usize::MAX).expect("Can't register interrupt handler");
+        // 将 Notification 绑定在 TCB 上,以便在接受 IPC 的时候也可以接受 notify
+        init_thread::slot::TCB
+            .cap()
+            .tcb_bind_notification(notify)
+            .unwrap();
+        if let Some(irq_handler) = self.irq_handler {
+            irq_handler.irq_handler_set_notification(notify).unwrap();
+        }
+        self.irq_enabled = true;
+
+        // 服务放入日志之后通过同一个通知唤醒服务
+        if let Some(ring) = klog::ring() {
+            match set_channel_notify(LOG_CHANNEL_ID, notify.into()) {
+                Ok(()) => ring.enable_doorbell(),
+                Err(_) => log::warn!("can't register the notification of the log ring"),
+            }
+        }
+        self.drain();
+    }
+
+    fn putchar(&mut self, c: u8) {
+        self.output(VC_APP, &[c]);
+    }
+
+    /// 阻塞读取一个字符
+    ///
+    /// 会占用当前线程直到收到输入，服务中应该使用 [UartIface::try_getchar] 并保存调用者
+    fn getchar(&mut self) -> Result<u8, Errno> {
+        loop {
+            if let Some(c) = self.try_getchar() {
+                return Ok(c);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn puts(&mut self, bytes: &[u8]) {
+        self.output(VC_APP, bytes);
+    }
+
+    fn try_getchar(&mut self) -> Option<u8> {
+        self.drain();
+        self.rx.pop_front()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.drain();
+        let rlen = buf.len().min(self.rx.len());
+        self.rx
+            .drain(..rlen)
+            .zip(buf.iter_mut())
+            .for_each(|(c, x)| *x = c);
+        Ok(rlen)
+    }
+
+    fn init_tx(&mut self, channel_id: usize, vc: usize) {
+        if vc >= VC_COUNT {
+            return;
+        }
+        // TODO: 支持多个程序的 channel 初始化，根据 badge 区分不同的程序
+        let ptr = alloc_free_addr(0) as *mut u8;
+        let size = join_channel(channel_id, ptr as usize);
+        alloc_free_addr(size);
+        let tx = unsafe { ByteRing::from_addr(ptr as usize) };
+        tx.reset();
+        self.tx[vc] = Some(tx);
+
+        // 应用程序控制台的客户端通过通道登记接收输入的通知
+        if vc != VC_APP {
+            return;
+        }
+        let mut client_notify = CLIENT_NOTIFY.lock();
+        if let Some(notify) = client_notify.take() {
+            let slot = LeafSlot::from_cap(notify);
+            slot.delete().unwrap();
+            recycle_slot(slot);
+        }
+        let slot = alloc_slot();
+        match channel_notify(channel_id, slot) {
+            Ok(()) => *client_notify = Some(slot.cap()),
+            Err(_) => recycle_slot(slot),
+        }
+    }
+
+    /// 把发送环中的数据全部写入虚拟控制台对应的端口
+    fn flush_tx(&mut self, vc: usize) {
+        let Some(tx) = self.tx.get(vc).copied().flatten() else {
+            return;
+        };
+        let mut buffer = [0u8; 64];
+        loop {
+            let len = tx.pop(&mut buffer);
+            if len == 0 {
+                break;
+            }
+            self.output(vc, &buffer[..len]);
+        }
+    }
+}
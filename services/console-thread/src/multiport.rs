@@ -0,0 +1,237 @@
+//! 多端口的 virtio-console
+//!
+//! 设备支持 `VIRTIO_CONSOLE_F_MULTIPORT` 时，除了端口 0 的接收和发送队列之外还有一对控制队列，
+//! 端口 `n` (`n > 0`) 使用第 `2n + 2` 和 `2n + 3` 个队列。驱动初始化之后通过控制队列发送
+//! `DEVICE_READY`，设备为每个端口发送 `DEVICE_ADD`，驱动回复 `PORT_READY`；
+//! 控制台端口还会收到 `CONSOLE_PORT`，驱动回复 `PORT_OPEN` 之后开始使用这个端口。
+//!
+//! 设备不支持多端口时只有端口 0。
+use alloc::vec::Vec;
+use common::config::{PAGE_SIZE, VIRTIO_CONSOLE_PORTS};
+use virtio_drivers::transport::{DeviceStatus, Transport, mmio::MmioTransport};
+
+use crate::queue::{BUFFER_SIZE, QueueError, VirtQueue};
+
+/// 设备支持多个端口
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+
+/// 设备支持 virtio 1.0，modern virtio-mmio 需要协商
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// 驱动已经准备好 (驱动发送)
+const DEVICE_READY: u16 = 0;
+/// 设备添加了一个端口 (设备发送)
+const DEVICE_ADD: u16 = 1;
+/// 驱动已经准备好使用端口 (驱动发送)
+const PORT_READY: u16 = 3;
+/// 端口是一个控制台 (设备发送)
+const CONSOLE_PORT: u16 = 4;
+/// 端口被打开或者关闭 (双方发送)
+const PORT_OPEN: u16 = 6;
+
+/// 控制队列中的消息
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ControlMessage {
+    /// 端口编号
+    id: u32,
+    /// 事件
+    event: u16,
+    /// 事件的参数
+    value: u16,
+}
+
+impl ControlMessage {
+    fn to_bytes(self) -> [u8; size_of::<Self>()] {
+        let mut buf = [0u8; size_of::<Self>()];
+        buf[..4].copy_from_slice(&self.id.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.event.to_le_bytes());
+        buf[6..].copy_from_slice(&self.value.to_le_bytes());
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let buf: &[u8; size_of::<Self>()] = buf.get(..size_of::<Self>())?.try_into().ok()?;
+        Some(Self {
+            id: u32::from_le_bytes(buf[..4].try_into().unwrap()),
+            event: u16::from_le_bytes(buf[4..6].try_into().unwrap()),
+            value: u16::from_le_bytes(buf[6..].try_into().unwrap()),
+        })
+    }
+}
+
+/// 一个端口的接收和发送队列
+struct Port {
+    rx: VirtQueue,
+    tx: VirtQueue,
+}
+
+/// 端口 `port` 的 (接收队列, 发送队列) 的编号
+const fn port_queues(port: usize) -> (u16, u16) {
+    match port {
+        0 => (0, 1),
+        n => (2 * n as u16 + 2, 2 * n as u16 + 3),
+    }
+}
+
+/// 多端口的 virtio-console 设备
+pub struct MultiportConsole {
+    transport: MmioTransport,
+    /// 控制队列，设备不支持多端口时为 [Option::None]
+    control: Option<Port>,
+    /// 每个端口的队列，设备没有这个端口时为 [Option::None]
+    ports: [Option<Port>; VIRTIO_CONSOLE_PORTS],
+}
+
+unsafe impl Send for MultiportConsole {}
+
+impl MultiportConsole {
+    /// 初始化设备，创建控制队列和每个端口的队列
+    pub fn new(mut transport: MmioTransport) -> Result<Self, QueueError> {
+        transport.set_status(DeviceStatus::empty());
+        transport.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+        let features =
+            transport.read_device_features() & (VIRTIO_CONSOLE_F_MULTIPORT | VIRTIO_F_VERSION_1);
+        transport.write_driver_features(features);
+        transport.set_status(
+            DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK,
+        );
+        transport.set_guest_page_size(PAGE_SIZE as _);
+
+        let control = match features & VIRTIO_CONSOLE_F_MULTIPORT {
+            0 => None,
+            _ => Some(Port {
+                rx: VirtQueue::new(&mut transport, 2)?,
+                tx: VirtQueue::new(&mut transport, 3)?,
+            }),
+        };
+        let ports = core::array::from_fn(|port| {
+            if port > 0 && control.is_none() {
+                return None;
+            }
+            let (rx, tx) = port_queues(port);
+            Some(Port {
+                rx: VirtQueue::new(&mut transport, rx).ok()?,
+                tx: VirtQueue::new(&mut transport, tx).ok()?,
+            })
+        });
+        transport.set_status(
+            DeviceStatus::ACKNOWLEDGE
+                | DeviceStatus::DRIVER
+                | DeviceStatus::FEATURES_OK
+                | DeviceStatus::DRIVER_OK,
+        );
+
+        let mut console = Self {
+            transport,
+            control,
+            ports,
+        };
+        // 所有的接收队列放满缓冲区
+        let rx_queues = console
+            .control
+            .iter_mut()
+            .chain(console.ports.iter_mut().flatten())
+            .map(|port| {
+                while port.rx.add_rx() {}
+                port.rx.index()
+            })
+            .collect::<Vec<_>>();
+        rx_queues
+            .into_iter()
+            .for_each(|queue| console.transport.notify(queue));
+        console.send_control(0, DEVICE_READY, 1);
+        Ok(console)
+    }
+
+    /// 端口 `port` 是否存在
+    pub fn has_port(&self, port: usize) -> bool {
+        self.ports.get(port).is_some_and(|x| x.is_some())
+    }
+
+    /// 响应设备的中断
+    pub fn ack_interrupt(&mut self) {
+        let _ = self.transport.ack_interrupt();
+    }
+
+    /// 通过控制队列发送消息，设备不支持多端口时忽略
+    fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let Some(control) = &mut self.control else {
+            return;
+        };
+        let msg = ControlMessage { id, event, value };
+        send(&mut self.transport, &mut control.tx, &msg.to_bytes());
+    }
+
+    /// 处理设备通过控制队列发送的消息
+    pub fn handle_control(&mut self) {
+        loop {
+            let Some(control) = &mut self.control else {
+                return;
+            };
+            let Some((id, len)) = control.rx.pop_used() else {
+                break;
+            };
+            let msg = ControlMessage::from_bytes(control.rx.buffer(id, len));
+            control.rx.readd(id);
+            let queue = control.rx.index();
+            self.transport.notify(queue);
+            let Some(msg) = msg else {
+                continue;
+            };
+            match msg.event {
+                // 只使用前 VIRTIO_CONSOLE_PORTS 个端口
+                DEVICE_ADD => {
+                    let ready = self.has_port(msg.id as usize);
+                    self.send_control(msg.id, PORT_READY, ready as _);
+                }
+                CONSOLE_PORT if self.has_port(msg.id as usize) => {
+                    self.send_control(msg.id, PORT_OPEN, 1);
+                }
+                PORT_OPEN => log::debug!("virtio-console port {} open: {}", msg.id, msg.value),
+                _ => {}
+            }
+        }
+    }
+
+    /// 向端口 `port` 发送数据，端口不存在时丢弃
+    pub fn send(&mut self, port: usize, data: &[u8]) {
+        let Some(Some(port)) = self.ports.get_mut(port) else {
+            return;
+        };
+        send(&mut self.transport, &mut port.tx, data);
+    }
+
+    /// 读取端口 `port` 收到的数据，交给 `f` 处理
+    pub fn recv(&mut self, port: usize, mut f: impl FnMut(&[u8])) {
+        let Some(Some(port)) = self.ports.get_mut(port) else {
+            return;
+        };
+        let mut received = false;
+        while let Some((id, len)) = port.rx.pop_used() {
+            f(port.rx.buffer(id, len));
+            port.rx.readd(id);
+            received = true;
+        }
+        if received {
+            self.transport.notify(port.rx.index());
+        }
+    }
+}
+
+/// 通过发送队列发送数据，队列满时等待设备取走数据
+fn send(transport: &mut MmioTransport, tx: &mut VirtQueue, mut data: &[u8]) {
+    while !data.is_empty() {
+        // 回收已经发送完成的描述符
+        while let Some((id, _)) = tx.pop_used() {
+            tx.recycle(id);
+        }
+        if !tx.has_free() {
+            core::hint::spin_loop();
+            continue;
+        }
+        let len = tx.add_tx(&data[..data.len().min(BUFFER_SIZE)]);
+        data = &data[len..];
+        transport.notify(tx.index());
+    }
+}
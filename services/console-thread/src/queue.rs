@@ -0,0 +1,211 @@
+//! virtio 的 split virtqueue
+//!
+//! virtio-drivers 没有公开 virtqueue，多端口的 virtio-console 需要自己管理控制队列和每个端口的
+//! 队列。队列按照 legacy 布局放在物理地址连续的 DMA 内存中，legacy 和 modern 的 virtio-mmio
+//! 都可以使用。每个描述符只对应一个缓冲区，缓冲区放在队列之后的 DMA 内存中。
+use alloc::vec::Vec;
+use common::{config::PAGE_SIZE, virtio::HalImpl};
+use core::{
+    ptr::{NonNull, addr_of, addr_of_mut},
+    sync::atomic::{Ordering, fence},
+};
+use virtio_drivers::{BufferDirection, Hal, transport::Transport};
+
+/// 队列中描述符的数量
+pub const QUEUE_SIZE: usize = 16;
+
+/// 每个描述符对应的缓冲区的大小
+pub const BUFFER_SIZE: usize = 128;
+
+/// 描述符表和 avail 环占用一页，used 环占用一页，缓冲区占用一页
+const QUEUE_PAGES: usize = 3;
+
+const _: () = assert!(QUEUE_SIZE * BUFFER_SIZE <= PAGE_SIZE);
+
+/// 设备向缓冲区写入数据
+const DESC_F_WRITE: u16 = 2;
+
+/// 描述符
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// 驱动放入的描述符
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+/// 设备使用完成的描述符
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// 设备使用完成的描述符环
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+/// 队列创建失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// 设备没有这个队列或者队列太小
+    Unavailable,
+    /// 队列已经被使用
+    AlreadyUsed,
+}
+
+/// split virtqueue
+pub struct VirtQueue {
+    /// 队列的编号
+    index: u16,
+    /// 描述符表
+    desc: NonNull<[Descriptor; QUEUE_SIZE]>,
+    /// avail 环
+    avail: NonNull<AvailRing>,
+    /// used 环
+    used: NonNull<UsedRing>,
+    /// 每个描述符对应的缓冲区
+    buffers: NonNull<[[u8; BUFFER_SIZE]; QUEUE_SIZE]>,
+    /// 缓冲区的物理地址
+    buffers_paddr: usize,
+    /// 空闲的描述符
+    free: Vec<u16>,
+    /// 下一个放入 avail 环的位置
+    avail_idx: u16,
+    /// 下一个读取的 used 环的位置
+    last_used: u16,
+}
+
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    /// 创建第 `index` 个队列并交给设备
+    pub fn new(transport: &mut impl Transport, index: u16) -> Result<Self, QueueError> {
+        if transport.queue_used(index) {
+            return Err(QueueError::AlreadyUsed);
+        }
+        if (transport.max_queue_size(index) as usize) < QUEUE_SIZE {
+            return Err(QueueError::Unavailable);
+        }
+        let (paddr, vaddr) = HalImpl::dma_alloc(QUEUE_PAGES, BufferDirection::Both);
+        unsafe { core::ptr::write_bytes(vaddr.as_ptr(), 0, QUEUE_PAGES * PAGE_SIZE) };
+
+        // legacy 布局：描述符表之后紧跟 avail 环，used 环按页对齐
+        let avail_offset = size_of::<[Descriptor; QUEUE_SIZE]>();
+        transport.queue_set(
+            index,
+            QUEUE_SIZE as _,
+            paddr,
+            paddr + avail_offset,
+            paddr + PAGE_SIZE,
+        );
+        let free = (0..QUEUE_SIZE as u16).rev().collect();
+        unsafe {
+            Ok(Self {
+                index,
+                desc: vaddr.cast(),
+                avail: vaddr.byte_add(avail_offset).cast(),
+                used: vaddr.byte_add(PAGE_SIZE).cast(),
+                buffers: vaddr.byte_add(2 * PAGE_SIZE).cast(),
+                buffers_paddr: paddr + 2 * PAGE_SIZE,
+                free,
+                avail_idx: 0,
+                last_used: 0,
+            })
+        }
+    }
+
+    /// 队列的编号
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// 是否还有空闲的描述符
+    pub fn has_free(&self) -> bool {
+        !self.free.is_empty()
+    }
+
+    /// 放入一个设备可以写入的缓冲区，没有空闲的描述符时返回 `false`
+    pub fn add_rx(&mut self) -> bool {
+        let Some(id) = self.free.pop() else {
+            return false;
+        };
+        self.add(id, BUFFER_SIZE, DESC_F_WRITE);
+        true
+    }
+
+    /// 放入一个设备读取的缓冲区，返回放入的字节数量，没有空闲的描述符时返回 0
+    pub fn add_tx(&mut self, data: &[u8]) -> usize {
+        let Some(id) = self.free.pop() else {
+            return 0;
+        };
+        let len = data.len().min(BUFFER_SIZE);
+        unsafe { self.buffers.as_mut()[id as usize][..len].copy_from_slice(&data[..len]) };
+        self.add(id, len, 0);
+        len
+    }
+
+    /// 把描述符 `id` 放入 avail 环
+    fn add(&mut self, id: u16, len: usize, flags: u16) {
+        let desc = Descriptor {
+            addr: (self.buffers_paddr + id as usize * BUFFER_SIZE) as _,
+            len: len as _,
+            flags,
+            next: 0,
+        };
+        unsafe {
+            addr_of_mut!((*self.desc.as_ptr())[id as usize]).write_volatile(desc);
+            let avail = self.avail.as_ptr();
+            addr_of_mut!((*avail).ring[self.avail_idx as usize % QUEUE_SIZE]).write_volatile(id);
+            // 设备看到新的 idx 之前需要看到描述符
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            addr_of_mut!((*avail).idx).write_volatile(self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// 取出一个设备使用完成的描述符，返回描述符和设备写入的长度
+    ///
+    /// 描述符需要通过 [VirtQueue::recycle] 或者 [VirtQueue::readd] 放回
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        fence(Ordering::SeqCst);
+        let used = self.used.as_ptr();
+        if self.last_used == unsafe { addr_of!((*used).idx).read_volatile() } {
+            return None;
+        }
+        let elem =
+            unsafe { addr_of!((*used).ring[self.last_used as usize % QUEUE_SIZE]).read_volatile() };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((elem.id as _, (elem.len as usize).min(BUFFER_SIZE)))
+    }
+
+    /// 描述符 `id` 对应的缓冲区中的前 `len` 个字节
+    pub fn buffer(&self, id: u16, len: usize) -> &[u8] {
+        unsafe { &self.buffers.as_ref()[id as usize][..len.min(BUFFER_SIZE)] }
+    }
+
+    /// 放回一个发送完成的描述符
+    pub fn recycle(&mut self, id: u16) {
+        self.free.push(id);
+    }
+
+    /// 把读取完成的接收缓冲区重新交给设备
+    pub fn readd(&mut self, id: u16) {
+        self.add(id, BUFFER_SIZE, DESC_F_WRITE);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     consts::task::DEF_STACK_TOP,
+    fs::tty::CONSOLE,
     task::{PollWakeEvent, Sel4Task},
 };
 use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
@@ -44,6 +45,10 @@ pub fn add_test_child(elf_file: &[u8], args: &[&str]) -> Result<(), sel4::Error>
     }
     drop(file_table);
 
+    // 测试任务是新会话的首进程，控制台是它的控制终端
+    CONSOLE.set_session(task.pcb.sid());
+    CONSOLE.set_foreground(task.pcb.pgid());
+
     // 写入线程的寄存器信息
     {
         let mut user_context = sel4::UserContext::default();
@@ -65,10 +70,25 @@ pub fn add_test_child(elf_file: &[u8], args: &[&str]) -> Result<(), sel4::Error>
 /// 等待队列 (父进程 id, 子进程 id)
 pub static WAITING_PID: Mutex<Vec<(u64, u64, Waker)>> = Mutex::new(Vec::new());
 
+/// 子进程退出时返回退出状态，`options` 包含 `WUNTRACED` 或者 `WCONTINUED` 时
+/// 还会返回进程组控制产生的停止或者继续事件
+fn child_status(tid: u64, target: &Sel4Task, options: u32) -> Option<(u64, u32)> {
+    if let Some(code) = *target.exit.lock() {
+        return Some((tid, code));
+    }
+    match target.pid == target.tid {
+        true => target
+            .pcb
+            .take_job_event(options)
+            .map(|status| (tid, status)),
+        false => None,
+    }
+}
+
 /// 等待程序结束
 ///
-/// (父进程 pid, 等待的子进程 pid, Blocking)
-pub struct WaitPid(pub u64, pub u64, pub bool);
+/// (父进程 pid, 等待的子进程 pid, Blocking, wait 的选项)
+pub struct WaitPid(pub u64, pub u64, pub bool, pub u32);
 
 impl Future for WaitPid {
     type Output = Option<Result<(u64, u32), Errno>>;
@@ -80,12 +100,8 @@ impl Future for WaitPid {
         let task_map = TASK_MAP.lock();
         let finded = task_map
             .iter()
-            .find(|(_, target)| {
-                target.exit.lock().is_some()
-                    && target.ppid == self.0 as _
-                    && target.pid == self.1 as _
-            })
-            .map(|(&tid, task)| (tid, task.exit.lock().unwrap()));
+            .filter(|(_, target)| target.ppid == self.0 as _ && target.pid == self.1 as _)
+            .find_map(|(&tid, target)| child_status(tid, target, self.3));
 
         match finded {
             Some(res) => Poll::Ready(Some(Ok(res))),
@@ -116,8 +132,8 @@ impl Future for WaitPid {
 
 /// 等待程序结束
 ///
-/// (父进程 pid, poll once)
-pub struct WaitAnyChild(pub u64, pub bool);
+/// (父进程 pid, poll once, wait 的选项)
+pub struct WaitAnyChild(pub u64, pub bool, pub u32);
 
 impl Future for WaitAnyChild {
     type Output = Option<Result<(u64, u32), Errno>>;
@@ -129,8 +145,8 @@ impl Future for WaitAnyChild {
         let task_map = TASK_MAP.lock();
         let finded = task_map
             .iter()
-            .find(|(_, target)| target.exit.lock().is_some() && target.ppid == self.0 as _)
-            .map(|(&tid, task)| (tid, task.exit.lock().unwrap()));
+            .filter(|(_, target)| target.ppid == self.0 as _)
+            .find_map(|(&tid, target)| child_status(tid, target, self.2));
 
         match finded {
             Some(res) => Poll::Ready(Some(Ok(res))),
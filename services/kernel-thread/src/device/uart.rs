@@ -1,14 +1,132 @@
 //! 串口模块初始化，查找串口服务
-use srv_gate::UART_IMPLS;
+//!
+//! 串口收到的字符交给控制台终端 [CONSOLE] 处理。串口驱动合并在 kernel-thread 中时，
+//! 串口中断发送到 [crate::exception::GLOBAL_NOTIFY]，badge 为 [crate::exception::NOTIFY_UART]；
+//! 使用独立的串口服务时，在控制台发送环的通道上登记 badge 为 [crate::exception::NOTIFY_UART]
+//! 的通知，串口服务收到输入或者清空发送环之后通知 kernel-thread。
+//!
+//! 控制台的输出和内核日志分别放入和串口服务共享的发送环，发送环由空变为非空时通知
+//! 串口服务发送。两者属于不同的虚拟控制台，日志不会和程序的输出混在一起。
+#[cfg(uart_ipc)]
+use common::root::set_channel_notify;
+use common::{config::PAGE_SIZE, root::create_channel};
+use spin::Once;
+use srv_gate::{
+    UART_IMPLS,
+    uart::{ByteRing, UART_TX_CHANNEL_PAGES, VC_APP, VC_KERNEL_LOG},
+};
+
+use crate::fs::tty::CONSOLE;
+
+/// 和串口服务共享的发送环的地址，每个虚拟控制台使用一个通道
+const UART_TX_CHANNEL_ADDR: usize = 0x3_2000_0000;
+
+/// 控制台输出使用的发送环
+static TX_RING: Once<&'static ByteRing> = Once::new();
+
+/// 内核日志使用的发送环
+static LOG_RING: Once<&'static ByteRing> = Once::new();
 
 pub(super) fn init() {
+    #[cfg(not(uart_ipc))]
+    {
+        use crate::exception::{NOTIFY_UART, badged_notify};
+
+        uart_thread::set_irq_notification(badged_notify(NOTIFY_UART).cap());
+    }
     UART_IMPLS[0].lock().init();
+
+    TX_RING.call_once(|| init_tx(VC_APP));
+    LOG_RING.call_once(|| init_tx(VC_KERNEL_LOG));
+    // 通知之前已经收到的输入
+    #[cfg(uart_ipc)]
+    handle_input();
+}
+
+/// 创建虚拟控制台 `vc` 的发送环并交给串口服务
+fn init_tx(vc: usize) -> &'static ByteRing {
+    let addr = UART_TX_CHANNEL_ADDR + vc * UART_TX_CHANNEL_PAGES * PAGE_SIZE;
+    let channel_id = create_channel(addr, UART_TX_CHANNEL_PAGES);
+    // 串口服务加入应用程序控制台的通道时获取这个通知
+    #[cfg(uart_ipc)]
+    if vc == VC_APP {
+        use crate::exception::{NOTIFY_UART, badged_notify};
+
+        if set_channel_notify(channel_id, badged_notify(NOTIFY_UART)).is_err() {
+            log::warn!(
+                "can't register the notification of uart channel {}",
+                channel_id
+            );
+        }
+    }
+    UART_IMPLS[0].lock().init_tx(channel_id, vc);
+    unsafe { ByteRing::from_addr(addr) }
+}
+
+/// 把数据放入虚拟控制台 `vc` 的发送环，返回放入的字节数量
+fn push(tx: &ByteRing, vc: usize, data: &[u8]) -> usize {
+    // 发送环不为空时串口服务正在发送，会一直发送到发送环为空
+    let idle = tx.is_empty();
+    let wlen = tx.push(data);
+    if idle && wlen > 0 {
+        // 日志可能在使用串口服务的过程中输出，获取不到锁时等待 [handle_output] 通知
+        if let Some(mut uart) = UART_IMPLS[0].try_lock() {
+            uart.flush_tx(vc);
+        }
+    }
+    wlen
+}
+
+/// 发送环中剩余的空间，发送环还没有初始化时返回 0
+pub fn write_room() -> usize {
+    TX_RING.get().map_or(0, |tx| tx.room())
+}
+
+/// 把数据放入发送环，返回放入的字节数量
+///
+/// 发送环已满时只放入一部分，剩余的数据需要等待串口服务取走之后再写入
+pub fn write(data: &[u8]) -> usize {
+    TX_RING.get().map_or(0, |tx| push(tx, VC_APP, data))
+}
+
+/// 输出内核日志，发送环还没有初始化时返回 `false`
+///
+/// 发送环已满时丢弃放不下的日志
+pub fn write_log(data: &[u8]) -> bool {
+    LOG_RING
+        .get()
+        .map(|tx| push(tx, VC_KERNEL_LOG, data))
+        .is_some()
+}
+
+/// 轮询发送环
+///
+/// 通知串口服务发送还没有发送的数据，发送环有空间时唤醒等待写入控制台的任务
+pub fn handle_output() {
+    let rings = [(VC_APP, &TX_RING), (VC_KERNEL_LOG, &LOG_RING)];
+    for (vc, tx) in rings {
+        if tx.get().is_some_and(|tx| !tx.is_empty()) {
+            UART_IMPLS[0].lock().flush_tx(vc);
+        }
+    }
+    if write_room() > 0 {
+        CONSOLE.write_wakeup();
+    }
 }
 
-/// 从 UartService 中读取一个字符 (u8)
+/// 读取串口收到的字符并交给控制台终端
 ///
-/// 如果没有读取到任何的数，直接返回 [Option::None]
-#[inline]
-pub fn get_char() -> Option<u8> {
-    Some(UART_IMPLS[0].lock().getchar())
+/// 收到串口中断或者串口服务的通知时调用，读取时不会阻塞
+pub fn handle_input() {
+    let mut buffer = [0u8; 64];
+    loop {
+        // 串口服务已经退出时当作没有输入
+        let rlen = UART_IMPLS[0].lock().read(&mut buffer).unwrap_or(0);
+        if rlen == 0 {
+            break;
+        }
+        buffer[..rlen].iter().for_each(|c| {
+            CONSOLE.receive(*c);
+        });
+    }
 }
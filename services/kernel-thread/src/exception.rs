@@ -4,10 +4,14 @@
 //! 为传统宏内核应用。目前传统宏内核应用的 syscall 需要预处理，将 syscall 指令
 //! 更换为 `0xdeadbeef` 指令，这样在异常处理时可以区分用户异常和系统调用。且不用
 //! 为宏内核支持引入多余的部件。
-use common::config::PAGE_SIZE;
+use core::sync::atomic::Ordering;
+
+use common::{config::PAGE_SIZE, slot::alloc_slot};
 use sel4::{
-    Fault, MessageInfo, UserException, VmFault, cap::Notification, init_thread, with_ipc_buffer,
+    CapRights, Fault, MessageInfo, UserException, VmFault, cap::Notification, init_thread,
+    with_ipc_buffer,
 };
+use sel4_kit::slot_manager::LeafSlot;
 use spin::Lazy;
 
 use crate::{child_test::TASK_MAP, syscall::handle_syscall, utils::obj::alloc_notification};
@@ -17,6 +21,43 @@ use crate::{child_test::TASK_MAP, syscall::handle_syscall, utils::obj::alloc_not
 /// 在各种结构上绑定的 [Notification]
 pub static GLOBAL_NOTIFY: Lazy<Notification> = Lazy::new(alloc_notification);
 
+/// 通知的 badge 最高位为 1，和任务编号区分，其余的位表示通知的来源。
+/// 多个来源同时通知时 badge 是它们的按位或
+pub const NOTIFY_BADGE: u64 = 1 << 63;
+
+/// 时钟中断
+pub const NOTIFY_TIMER: u64 = NOTIFY_BADGE | 1 << 0;
+
+/// 串口中断
+pub const NOTIFY_UART: u64 = NOTIFY_BADGE | 1 << 1;
+
+/// 网络服务在接收环中放入了新的帧
+pub const NOTIFY_NET: u64 = NOTIFY_BADGE | 1 << 2;
+
+/// 从 [GLOBAL_NOTIFY] 复制一个 badge 为 `badge` 的 [Notification]
+pub fn badged_notify(badge: u64) -> LeafSlot {
+    let slot = alloc_slot();
+    LeafSlot::from_cap(*GLOBAL_NOTIFY)
+        .mint_to(slot, CapRights::all(), badge as _)
+        .unwrap();
+    slot
+}
+
+/// 处理 [GLOBAL_NOTIFY] 收到的通知，`badge` 中可能包含多个来源
+pub fn handle_notify(badge: u64) {
+    let has = |source: u64| badge & source == source;
+    if has(NOTIFY_TIMER) {
+        crate::timer::handle_timer();
+    }
+    if has(NOTIFY_UART) {
+        crate::device::uart::handle_input();
+        crate::device::uart::handle_output();
+    }
+    if has(NOTIFY_NET) {
+        crate::net::poll();
+    }
+}
+
 /// 处理用户异常
 ///
 /// - `tid` 是用户进程绑定的任务 ID
@@ -32,6 +73,7 @@ pub async fn handle_user_exception(tid: u64, exception: UserException) {
 
     // 如果是某个特定的指令，则说明此次调用是系统调用
     if Some(0xdeadbeef) == ins {
+        task.in_syscall.store(true, Ordering::SeqCst);
         let mut user_ctx = task
             .tcb
             .tcb_read_all_registers(true)
@@ -55,15 +97,16 @@ pub async fn handle_user_exception(tid: u64, exception: UserException) {
             .tcb_write_all_registers(false, &mut user_ctx)
             .unwrap();
 
-        // 检查信号
+        // 检查信号，之后恢复系统调用临时替换的信号屏蔽位
         task.check_signal(&mut user_ctx);
+        task.restore_sigmask();
 
         if task.exit.lock().is_some() {
             return;
         }
 
-        // 恢复任务运行状态
-        task.tcb.tcb_resume().unwrap();
+        // 恢复任务运行状态，进程被停止时等到继续运行时恢复
+        task.resume_user();
     } else {
         log::debug!("trigger fault: {:#x?}", exception);
     }
@@ -80,7 +123,7 @@ pub fn handle_vmfault(tid: u64, vmfault: VmFault) {
     let task = task_map.get_mut(&tid).unwrap();
     task.map_blank_page(vaddr);
 
-    task.tcb.tcb_resume().unwrap();
+    task.resume_user();
     drop(task_map);
 }
 
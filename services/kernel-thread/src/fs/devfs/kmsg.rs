@@ -0,0 +1,211 @@
+//! 内核日志
+//!
+//! 所有服务共享的日志环 ([srv_gate::klog]) 通过 `/dev/kmsg` 读取。每次打开 `/dev/kmsg`
+//! 都会创建一个新的 [KmsgFile]，记录自己读取到的位置，每次读取返回一条日志。
+//! 写入 `/dev/kmsg` 的数据作为 `user` 服务的日志放入日志环。
+//!
+//! `/dev/loglevel` 列出所有服务的日志级别，写入 `<服务名称> <日志级别>` 修改服务的日志级别。
+use alloc::{format, string::String, sync::Arc};
+use core::cmp;
+use fs::INodeInterface;
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use log::{Level, LevelFilter};
+use sel4_kit::arch::current_time;
+use spin::Mutex;
+use srv_gate::klog::{self, LogCursor, LogEntry};
+use syscalls::Errno;
+use vfscore::VfsResult;
+
+use super::makedev;
+
+/// `/dev/kmsg` 的设备号
+pub const KMSG_RDEV: (u32, u32) = (1, 11);
+
+/// 日志级别对应的 syslog 优先级
+pub const fn syslog_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// syslog 优先级对应的日志级别
+pub const fn syslog_level(priority: u8) -> Level {
+    match priority & 7 {
+        0..=3 => Level::Error,
+        4 => Level::Warn,
+        5 | 6 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// 按照 `/dev/kmsg` 的格式输出一条日志: `<优先级>,<序号>,<时间>,-;<日志>`
+pub fn format_kmsg(entry: &LogEntry) -> String {
+    format!(
+        "{},{},{},-;[{}] {}\n",
+        syslog_priority(entry.level),
+        entry.seq,
+        entry.timestamp.as_micros(),
+        entry.service,
+        entry.message
+    )
+}
+
+/// 按照 `syslog(2)` 的格式输出一条日志: `<优先级>[时间] <日志>`
+pub fn format_syslog(entry: &LogEntry) -> String {
+    format!(
+        "<{}>[{:5}.{:06}] [{}] {}\n",
+        syslog_priority(entry.level),
+        entry.timestamp.as_secs(),
+        entry.timestamp.subsec_micros(),
+        entry.service,
+        entry.message
+    )
+}
+
+/// 把用户写入的日志放入日志环，可以使用 `<优先级>` 前缀指定日志级别
+pub fn write_user_log(buffer: &[u8]) -> VfsResult<usize> {
+    let ring = klog::ring().ok_or(Errno::EIO)?;
+    let mut level = Level::Info;
+    let mut msg = buffer;
+    if let [b'<', rest @ ..] = buffer {
+        if let Some(end) = rest.iter().position(|x| *x == b'>') {
+            let priority = core::str::from_utf8(&rest[..end])
+                .ok()
+                .and_then(|x| x.parse::<u32>().ok());
+            if let Some(priority) = priority {
+                level = syslog_level(priority as u8);
+                msg = &rest[end + 1..];
+            }
+        }
+    }
+    let msg = msg.strip_suffix(b"\n").unwrap_or(msg);
+    ring.push("user", level, current_time(), msg);
+    Ok(buffer.len())
+}
+
+/// `/dev/kmsg`，打开时会创建新的 [KmsgFile]，见 [super::open_cloned]
+pub struct Kmsg;
+
+impl INodeInterface for Kmsg {
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = 0;
+        stat.ino = 1;
+        stat.mode = StatMode::CHAR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = makedev(KMSG_RDEV.0, KMSG_RDEV.1) as _;
+        Ok(())
+    }
+}
+
+/// 打开的 `/dev/kmsg`
+pub struct KmsgFile {
+    /// 读取日志的位置
+    cursor: Mutex<LogCursor>,
+}
+
+impl KmsgFile {
+    /// 打开 `/dev/kmsg`，从日志环中最旧的日志开始读取
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            cursor: Mutex::new(LogCursor::new()),
+        })
+    }
+}
+
+impl INodeInterface for KmsgFile {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let ring = klog::ring().ok_or(Errno::EIO)?;
+        let mut cursor = self.cursor.lock();
+        // 缓冲区放不下时不读取这条日志
+        let mut next = cursor.clone();
+        let entry = next.next(ring).ok_or(Errno::EAGAIN)?;
+        let line = format_kmsg(&entry);
+        if line.len() > buffer.len() {
+            return Err(Errno::EINVAL);
+        }
+        buffer[..line.len()].copy_from_slice(line.as_bytes());
+        *cursor = next;
+        Ok(line.len())
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        write_user_log(buffer)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        let readable = klog::ring().is_some_and(|x| self.cursor.lock().seq() < x.next_seq());
+        if events.contains(PollEvent::IN) && readable {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) {
+            res |= PollEvent::OUT;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        Kmsg.stat(stat)
+    }
+}
+
+/// `/dev/loglevel`，查看和修改每个服务的日志级别
+pub struct LogLevel;
+
+impl LogLevel {
+    /// 所有服务的日志级别，每行一个服务
+    fn content() -> String {
+        klog::ring()
+            .map(|ring| ring.services())
+            .unwrap_or_default()
+            .iter()
+            .map(|(name, level)| format!("{} {}\n", name, level.as_str().to_lowercase()))
+            .collect()
+    }
+}
+
+impl INodeInterface for LogLevel {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let content = Self::content();
+        let content = content.as_bytes().get(offset..).unwrap_or_default();
+        let rlen = cmp::min(buffer.len(), content.len());
+        buffer[..rlen].copy_from_slice(&content[..rlen]);
+        Ok(rlen)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        let ring = klog::ring().ok_or(Errno::EIO)?;
+        let content = core::str::from_utf8(buffer).map_err(|_| Errno::EINVAL)?;
+        for line in content.lines().filter(|x| !x.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(level), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(Errno::EINVAL);
+            };
+            let level: LevelFilter = level.parse().map_err(|_| Errno::EINVAL)?;
+            if !ring.set_level(name, level) {
+                return Err(Errno::ENOENT);
+            }
+        }
+        Ok(buffer.len())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = 0;
+        stat.ino = 1;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        stat.size = Self::content().len() as _;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
@@ -1,17 +1,97 @@
 //! 设备文件系统
 //!
 //!
+pub mod kmsg;
 mod null;
+pub mod pty;
+mod spawn;
 mod stdio;
 mod zero;
 
 use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
-use fs::{FileType, INodeInterface};
+use fs::{FileType, INodeInterface, file::File};
 use libc_core::types::{Stat, StatMode};
 use syscalls::Errno;
 use vfscore::{DirEntry, FileSystem, VfsResult};
 
-use crate::fs::devfs::stdio::StdConsole;
+use crate::fs::devfs::{
+    kmsg::{KMSG_RDEV, KmsgFile},
+    pty::{PTS_MAJOR, PtyMaster},
+    spawn::SPAWN_RDEV,
+    stdio::StdConsole,
+};
+
+use super::registry::ANON_DEV;
+
+/// 组合主设备号和次设备号，编码方式和 glibc 的 `makedev` 一致
+pub const fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xfffff000) << 32)
+        | ((major & 0xfff) << 8)
+        | ((minor & 0xffffff00) << 12)
+        | (minor & 0xff)
+}
+
+/// 获取设备号中的主设备号
+pub const fn major(dev: u64) -> u32 {
+    (((dev >> 32) & 0xfffff000) | ((dev >> 8) & 0xfff)) as u32
+}
+
+/// 获取设备号中的次设备号
+pub const fn minor(dev: u64) -> u32 {
+    (((dev >> 12) & 0xffffff00) | (dev & 0xff)) as u32
+}
+
+/// 根据设备号获取设备驱动，用于打开 `mknod` 创建的设备文件
+///
+/// ## 参数
+/// - `mode` 设备类型，[StatMode::CHAR] 或者 [StatMode::BLOCK]
+/// - `rdev` 设备号
+///
+/// 没有对应的驱动时返回 [Option::None]，目前还没有块设备驱动
+pub fn open_device(mode: StatMode, rdev: u64) -> Option<Arc<dyn INodeInterface>> {
+    if mode != StatMode::CHAR {
+        return None;
+    }
+    match (major(rdev), minor(rdev)) {
+        (1, 3) => Some(Arc::new(null::Null)),
+        (1, 5) => Some(Arc::new(zero::Zero)),
+        KMSG_RDEV => Some(KmsgFile::new()),
+        (5, 1) => Some(Arc::new(StdConsole::new(3))),
+        // 每次打开 ptmx 都会创建一对新的伪终端
+        (5, 2) => PtyMaster::new().ok().map(|x| x as Arc<dyn INodeInterface>),
+        (PTS_MAJOR, index) => pty::open_slave(index).ok(),
+        _ => None,
+    }
+}
+
+/// 打开 devfs 中每次打开都会创建新设备的文件，例如 `/dev/ptmx`、`/dev/kmsg` 和 `/dev/pts/N`
+///
+/// `file` 是通过路径打开的文件，如果它是这样的设备，返回新创建的设备，
+/// 否则返回 [Option::None]，继续使用原来的文件。进程 `pid` 不能打开的设备返回错误
+pub fn open_cloned(file: &File, pid: usize) -> Option<Result<Arc<dyn INodeInterface>, Errno>> {
+    let mut stat = Stat::default();
+    file.stat(&mut stat).ok()?;
+    if stat.mode != StatMode::CHAR {
+        return None;
+    }
+    match (
+        stat.dev as u64,
+        major(stat.rdev as _),
+        minor(stat.rdev as _),
+    ) {
+        (0, 5, 2) => Some(PtyMaster::new().map(|x| x as Arc<dyn INodeInterface>)),
+        // 每次打开 kmsg 都会从最旧的日志开始读取
+        (0, major, minor) if (major, minor) == KMSG_RDEV => Some(Ok(KmsgFile::new())),
+        // 只有 init 进程可以启动服务
+        (0, major, minor) if (major, minor) == SPAWN_RDEV => {
+            (!spawn::may_open(pid)).then_some(Err(Errno::EPERM))
+        }
+        // devpts 中查找到的从设备在打开时才计入打开次数
+        (ANON_DEV, PTS_MAJOR, index) => Some(pty::open_slave(index)),
+        _ => None,
+    }
+}
 
 /// 设备文件系统
 pub struct DevFS {
@@ -65,6 +145,10 @@ impl DevDir {
         map.insert("stderr", Arc::new(StdConsole::new(2)));
         map.insert("stdin", Arc::new(StdConsole::new(0)));
         map.insert("ttyv0", Arc::new(StdConsole::new(3)));
+        map.insert("ptmx", Arc::new(pty::Ptmx));
+        map.insert("kmsg", Arc::new(kmsg::Kmsg));
+        map.insert("loglevel", Arc::new(kmsg::LogLevel));
+        map.insert("spawn", Arc::new(spawn::Spawn));
         map.insert("null", Arc::new(null::Null));
         map.insert("zero", Arc::new(zero::Zero));
 
@@ -2,6 +2,8 @@ use libc_core::types::{Stat, StatMode};
 use syscalls::Errno;
 use vfscore::{INodeInterface, VfsResult};
 
+use super::makedev;
+
 pub struct Null;
 
 impl INodeInterface for Null {
@@ -24,7 +26,7 @@ impl INodeInterface for Null {
         stat.size = 0;
         stat.blksize = 512;
         stat.blocks = 0;
-        stat.rdev = 0; // TODO: add device id
+        stat.rdev = makedev(1, 3) as _;
         Ok(())
     }
 
@@ -0,0 +1,362 @@
+//! 伪终端
+//!
+//! 每次打开 `/dev/ptmx` 都会创建一对新的伪终端：打开 `/dev/ptmx` 得到的文件是主设备
+//! [PtyMaster]，从设备挂载在 devpts 文件系统中 (`/dev/pts/N`)。从设备是一个普通的
+//! [Tty]，和控制台使用同一个行规程：写入主设备的数据作为从设备的输入，从设备的输出
+//! （包括回显）可以从主设备读取。
+//!
+//! 在 devpts 中查找 `/dev/pts/N` 只返回描述从设备的 [PtsNode]，真正打开文件时
+//! 才通过 [open_slave] 打开从设备，`stat` 等操作不会计入从设备的打开次数。
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    format,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use fs::{FileType, INodeInterface};
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{DirEntry, FileSystem, VfsResult};
+
+use super::makedev;
+use crate::fs::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    tty::{Tty, TtyDriver},
+    wait_queue::WaitQueue,
+};
+
+/// 伪终端的最大数量
+const PTY_MAX: u32 = 4096;
+
+/// 伪终端从设备的主设备号
+pub const PTS_MAJOR: u32 = 136;
+
+/// 从设备的输出最多缓存的字节数量，主设备没有及时读取时从设备写入返回 `EAGAIN`
+const PTY_OUTPUT_MAX: usize = 4096;
+
+/// 所有存活的伪终端主设备
+static PTY_MASTER_INODES: InodeRegistry<PtyMaster> = InodeRegistry::new();
+
+/// 已经分配的伪终端 (编号, 主设备)
+static PTYS: Mutex<BTreeMap<u32, Weak<PtyMaster>>> = Mutex::new(BTreeMap::new());
+
+/// 伪终端主设备
+pub struct PtyMaster {
+    /// 伪终端编号
+    index: u32,
+    /// 从设备使用的终端
+    tty: Arc<Tty>,
+    /// 从设备的输出，等待主设备读取
+    output: Mutex<VecDeque<u8>>,
+    /// 从设备是否被锁定，锁定时不能打开从设备
+    locked: AtomicBool,
+    /// 打开的从设备数量
+    slaves: AtomicUsize,
+    /// 从设备是否被打开过
+    opened: AtomicBool,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+/// 伪终端从设备的驱动，将从设备的输出交给主设备
+struct PtsDriver(Weak<PtyMaster>);
+
+impl TtyDriver for PtsDriver {
+    fn write(&self, data: &[u8]) -> usize {
+        let Some(master) = self.0.upgrade() else {
+            return data.len();
+        };
+        let mut output = master.output.lock();
+        let wlen = data.len().min(PTY_OUTPUT_MAX.saturating_sub(output.len()));
+        output.extend(&data[..wlen]);
+        drop(output);
+        master.wait.wake_all();
+        wlen
+    }
+
+    fn write_room(&self) -> usize {
+        match self.0.upgrade() {
+            Some(master) => PTY_OUTPUT_MAX.saturating_sub(master.output.lock().len()),
+            None => usize::MAX,
+        }
+    }
+}
+
+/// 打开编号为 `index` 的伪终端从设备
+///
+/// 伪终端不存在时返回 `ENOENT`，从设备被锁定时返回 `EIO`
+pub fn open_slave(index: u32) -> Result<Arc<dyn INodeInterface>, Errno> {
+    let master = PTYS
+        .lock()
+        .get(&index)
+        .and_then(Weak::upgrade)
+        .ok_or(Errno::ENOENT)?;
+    Ok(master.open_slave()? as Arc<dyn INodeInterface>)
+}
+
+impl PtyMaster {
+    /// 创建一对新的伪终端，返回主设备
+    ///
+    /// 伪终端的数量达到上限时返回 `ENOSPC`
+    pub fn new() -> Result<Arc<Self>, Errno> {
+        let mut ptys = PTYS.lock();
+        let index = (0..PTY_MAX)
+            .find(|x| ptys.get(x).is_none_or(|x| x.strong_count() == 0))
+            .ok_or(Errno::ENOSPC)?;
+        let ino = alloc_ino();
+        let master = Arc::new_cyclic(|this| Self {
+            index,
+            tty: Tty::new(PtsDriver(this.clone()), makedev(PTS_MAJOR, index)),
+            output: Mutex::new(VecDeque::new()),
+            locked: AtomicBool::new(true),
+            slaves: AtomicUsize::new(0),
+            opened: AtomicBool::new(false),
+            wait: WaitQueue::new(ino),
+        });
+        ptys.insert(index, Arc::downgrade(&master));
+        PTY_MASTER_INODES.insert(ino, &master);
+        Ok(master)
+    }
+
+    /// 从打开的 [fs::file::File] 中获取 [PtyMaster]
+    ///
+    /// 如果文件不是伪终端主设备，返回 [Option::None]
+    pub fn from_file(file: &fs::file::File) -> Option<Arc<Self>> {
+        PTY_MASTER_INODES.from_file(file)
+    }
+
+    /// 伪终端编号，从设备的路径为 `/dev/pts/<index>`
+    pub const fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// 从设备使用的终端
+    pub fn tty(&self) -> &Arc<Tty> {
+        &self.tty
+    }
+
+    /// 从设备是否被锁定
+    pub fn locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    /// 锁定或者解锁从设备，`unlockpt` 使用
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::SeqCst);
+    }
+
+    /// 打开从设备，从设备被锁定时返回 `EIO`
+    pub fn open_slave(self: &Arc<Self>) -> Result<Arc<PtySlave>, Errno> {
+        if self.locked() {
+            return Err(Errno::EIO);
+        }
+        self.slaves.fetch_add(1, Ordering::SeqCst);
+        self.opened.store(true, Ordering::SeqCst);
+        Ok(Arc::new(PtySlave {
+            tty: self.tty.clone(),
+            master: Arc::downgrade(self),
+        }))
+    }
+
+    /// 所有打开的从设备都已经关闭
+    fn slave_closed(&self) -> bool {
+        self.opened.load(Ordering::SeqCst) && self.slaves.load(Ordering::SeqCst) == 0
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        // 主设备关闭后从设备挂断
+        self.tty.hangup();
+        PTY_MASTER_INODES.remove(self.wait.ino());
+        let mut ptys = PTYS.lock();
+        if ptys.get(&self.index).is_some_and(|x| x.strong_count() == 0) {
+            ptys.remove(&self.index);
+        }
+    }
+}
+
+impl INodeInterface for PtyMaster {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let mut output = self.output.lock();
+        if output.is_empty() {
+            // 从设备全部关闭之后读取主设备返回 EIO
+            return match self.slave_closed() {
+                true => Err(Errno::EIO),
+                false => Err(Errno::EAGAIN),
+            };
+        }
+        let rlen = buffer.len().min(output.len());
+        output
+            .drain(..rlen)
+            .zip(buffer.iter_mut())
+            .for_each(|(c, x)| *x = c);
+        drop(output);
+        // 输出缓冲区有了空间，唤醒等待写入从设备的任务
+        self.tty.write_wakeup();
+        Ok(rlen)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        // 写入主设备的数据作为从设备的输入
+        buffer.iter().for_each(|c| {
+            self.tty.receive(*c);
+        });
+        Ok(buffer.len())
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && !self.output.lock().is_empty() {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) {
+            res |= PollEvent::OUT;
+        }
+        if self.slave_closed() {
+            res |= PollEvent::HUP;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::CHAR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = makedev(5, 2) as _;
+        Ok(())
+    }
+}
+
+/// 打开的伪终端从设备
+///
+/// 读写操作都交给从设备的 [Tty]，关闭时通知主设备
+pub struct PtySlave {
+    /// 从设备使用的终端
+    tty: Arc<Tty>,
+    /// 主设备
+    master: Weak<PtyMaster>,
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        if let Some(master) = self.master.upgrade() {
+            master.slaves.fetch_sub(1, Ordering::SeqCst);
+            master.wait.wake_all();
+        }
+    }
+}
+
+impl INodeInterface for PtySlave {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        self.tty.readat(offset, buffer)
+    }
+
+    fn writeat(&self, offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        self.tty.writeat(offset, buffer)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        self.tty.poll(events)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        // 使用终端的 inode 编号，这样可以通过文件找到对应的 [Tty]
+        self.tty.stat(stat)
+    }
+}
+
+/// devpts 中的 `/dev/pts/N`，打开时通过 [open_slave] 打开从设备，见 [super::open_cloned]
+pub struct PtsNode(Weak<PtyMaster>);
+
+impl INodeInterface for PtsNode {
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        // 和打开的从设备相同，这样 ttyname 可以通过比较 inode 找到路径
+        self.0.upgrade().ok_or(Errno::ENOENT)?.tty.stat(stat)
+    }
+}
+
+/// `/dev/ptmx`，打开时会创建新的伪终端，见 [super::open_cloned]
+pub struct Ptmx;
+
+impl INodeInterface for Ptmx {
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = 0;
+        stat.ino = 1;
+        stat.mode = StatMode::CHAR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = makedev(5, 2) as _;
+        Ok(())
+    }
+}
+
+/// devpts 文件系统，挂载在 `/dev/pts`
+pub struct DevPtsFS;
+
+impl DevPtsFS {
+    /// 创建一个新的 [DevPtsFS]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl FileSystem for DevPtsFS {
+    fn root_dir(&self) -> Arc<dyn INodeInterface> {
+        Arc::new(DevPtsDir)
+    }
+
+    fn name(&self) -> &str {
+        "devpts"
+    }
+}
+
+/// devpts 文件系统的根目录，列出所有已经分配的伪终端从设备
+pub struct DevPtsDir;
+
+impl INodeInterface for DevPtsDir {
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn INodeInterface>> {
+        let index: u32 = name.parse().map_err(|_| Errno::ENOENT)?;
+        let master = PTYS
+            .lock()
+            .get(&index)
+            .and_then(Weak::upgrade)
+            .ok_or(Errno::ENOENT)?;
+        Ok(Arc::new(PtsNode(Arc::downgrade(&master))))
+    }
+
+    fn read_dir(&self) -> VfsResult<Vec<DirEntry>> {
+        Ok(PTYS
+            .lock()
+            .iter()
+            .filter(|(_, master)| master.strong_count() > 0)
+            .map(|(index, _)| DirEntry {
+                filename: format!("{}", index),
+                len: 0,
+                file_type: FileType::Device,
+            })
+            .collect())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = 0;
+        stat.mode = StatMode::DIR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
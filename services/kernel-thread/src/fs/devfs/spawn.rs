@@ -0,0 +1,102 @@
+//! 运行时启动服务
+//!
+//! 向 `/dev/spawn` 写入 `<服务名称> <ELF 路径> [可以查找的服务 ...]`，每行一个服务。
+//! kernel-thread 从文件系统中读取 ELF 文件，和 [SpawnRequest] 一起放入一个新的共享内存通道，
+//! 然后请求 root-task 启动服务。启动后其他服务可以通过名称找到这个服务。
+//!
+//! 只有 init 进程 (运行 `/init.sh` 的进程) 可以打开 `/dev/spawn`，其他进程打开时得到 `EPERM`。
+//! root-task 还会检查 kernel-thread 的 `allow`，服务只能查找 kernel-thread 可以查找的服务。
+use alloc::vec::Vec;
+use common::{
+    config::PAGE_SIZE,
+    root::{SPAWN_ELF_OFFSET, SpawnRequest, create_channel, destroy_channel, spawn_service},
+};
+use fs::{INodeInterface, file::File};
+use libc_core::{
+    fcntl::OpenFlags,
+    types::{Stat, StatMode},
+};
+use sel4_runtime::utils::alloc_free_addr;
+use syscalls::Errno;
+use vfscore::VfsResult;
+
+use super::makedev;
+
+/// `/dev/spawn` 的设备号
+pub const SPAWN_RDEV: (u32, u32) = (10, 240);
+
+/// 可以打开 `/dev/spawn` 的进程
+const INIT_PID: usize = 1;
+
+/// 进程 `pid` 是否可以打开 `/dev/spawn`
+pub const fn may_open(pid: usize) -> bool {
+    pid == INIT_PID
+}
+
+/// `/dev/spawn`，写入时启动服务，见 [super::open_cloned]
+pub struct Spawn;
+
+impl Spawn {
+    /// 读取 `path` 中的 ELF 文件，启动名称为 `name` 的服务，成功时返回服务的编号
+    fn spawn(name: &str, path: &str, services: &[&str]) -> VfsResult<usize> {
+        let file = File::open(path, OpenFlags::RDONLY)?;
+        let elf_len = file.file_size()?;
+        let pages = (SPAWN_ELF_OFFSET + elf_len).div_ceil(PAGE_SIZE);
+
+        let addr = alloc_free_addr(pages * PAGE_SIZE);
+        let channel_id = create_channel(addr, pages);
+        if channel_id == usize::MAX {
+            return Err(Errno::ENOMEM);
+        }
+
+        // 服务启动之后 root-task 不再需要通道，无论是否成功都删除通道
+        let result = Self::fill_request(&file, addr, name, services)
+            .and_then(|_| spawn_service(channel_id).map_err(|_| Errno::EINVAL));
+        let _ = destroy_channel(channel_id);
+        result
+    }
+
+    /// 在映射到 `addr` 的通道中写入启动请求和 ELF 文件
+    fn fill_request(file: &File, addr: usize, name: &str, services: &[&str]) -> VfsResult<()> {
+        let elf_len = file.file_size()?;
+        let request = unsafe { &mut *(addr as *mut SpawnRequest) };
+        request.clear();
+        request.elf_len = elf_len;
+        if !request.set_name(name) || !services.iter().all(|x| request.allow_service(x)) {
+            return Err(Errno::EINVAL);
+        }
+        let elf = unsafe {
+            core::slice::from_raw_parts_mut((addr + SPAWN_ELF_OFFSET) as *mut u8, elf_len)
+        };
+        file.read(elf)?;
+        Ok(())
+    }
+}
+
+impl INodeInterface for Spawn {
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        let content = core::str::from_utf8(buffer).map_err(|_| Errno::EINVAL)?;
+        for line in content.lines().filter(|x| !x.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(path)) = (parts.next(), parts.next()) else {
+                return Err(Errno::EINVAL);
+            };
+            let services = parts.collect::<Vec<_>>();
+            let id = Self::spawn(name, path, &services)?;
+            log::info!("spawn service {} from {} as {}", name, path, id);
+        }
+        Ok(buffer.len())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = 0;
+        stat.ino = 1;
+        stat.mode = StatMode::CHAR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = makedev(SPAWN_RDEV.0, SPAWN_RDEV.1) as _;
+        Ok(())
+    }
+}
@@ -1,12 +1,12 @@
 //! 标准输入输出使用的接口
 //!
-//! 目前标准输入输出等都使用一个结构体，通过设置不同的位置来确保只读，只写
+//! 目前标准输入输出等都使用一个结构体，通过设置不同的位置来确保只读，只写，
+//! 读写操作都交给系统控制台终端 [CONSOLE] 处理
 use fs::INodeInterface;
-use libc_core::types::StatMode;
-use sel4::debug_print;
+use libc_core::poll::PollEvent;
 use syscalls::Errno;
 
-use crate::device::uart::get_char;
+use crate::fs::tty::CONSOLE;
 
 /// 标准输入输出接口
 pub struct StdConsole(u8);
@@ -24,40 +24,26 @@ impl StdConsole {
 }
 
 impl INodeInterface for StdConsole {
-    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> vfscore::VfsResult<usize> {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> vfscore::VfsResult<usize> {
         if self.0 != 0 && self.0 <= 2 {
             return Err(Errno::EPERM);
         }
-        match get_char() {
-            Some(c) => {
-                buffer[0] = c;
-                Ok(1)
-            }
-            None => Ok(0),
-        }
+        CONSOLE.readat(offset, buffer)
     }
 
-    fn writeat(&self, _offset: usize, buffer: &[u8]) -> vfscore::VfsResult<usize> {
+    fn writeat(&self, offset: usize, buffer: &[u8]) -> vfscore::VfsResult<usize> {
         if self.0 == 0 {
             return Err(Errno::EPERM);
         }
-        // srv_gate::UART_IMPLS[0].lock().puts(data);
-        // debug_println!("{}", );
-        buffer.iter().for_each(|x| debug_print!("{}", *x as char));
-        Ok(buffer.len())
+        CONSOLE.writeat(offset, buffer)
+    }
+
+    fn poll(&self, events: PollEvent) -> vfscore::VfsResult<PollEvent> {
+        CONSOLE.poll(events)
     }
 
     fn stat(&self, stat: &mut libc_core::types::Stat) -> vfscore::VfsResult<()> {
-        stat.dev = 0;
-        stat.ino = 1; // TODO: convert path to number(ino)
-        stat.mode = StatMode::CHAR; // TODO: add access mode
-        stat.nlink = 1;
-        stat.uid = 1000;
-        stat.gid = 1000;
-        stat.size = 0;
-        stat.blksize = 512;
-        stat.blocks = 0;
-        stat.rdev = 0; // TODO: add device id
-        Ok(())
+        // 使用控制台终端的 inode 编号，这样可以通过文件找到对应的 [crate::fs::tty::Tty]
+        CONSOLE.stat(stat)
     }
 }
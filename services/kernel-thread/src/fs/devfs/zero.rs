@@ -1,6 +1,8 @@
 use libc_core::types::{Stat, StatMode};
 use vfscore::{INodeInterface, VfsResult};
 
+use super::makedev;
+
 pub struct Zero;
 
 impl INodeInterface for Zero {
@@ -23,7 +25,7 @@ impl INodeInterface for Zero {
         stat.size = 0;
         stat.blksize = 512;
         stat.blocks = 0;
-        stat.rdev = 0; // TODO: add device id
+        stat.rdev = makedev(1, 5) as _;
         Ok(())
     }
 }
@@ -0,0 +1,425 @@
+//! epoll 文件
+//!
+//! epoll 维护一个兴趣列表和一个就绪列表。添加文件时会订阅文件的 [WaitQueue]，
+//! 文件状态变化时直接将文件加入就绪列表并唤醒等待的任务，`epoll_pwait` 只需要检查
+//! 就绪列表中的文件。没有等待队列的文件（例如普通文件）每次都会被检查。
+use core::task::Waker;
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use bitflags::bitflags;
+use fs::file::File;
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+
+/// 所有存活的 epoll
+static EPOLL_INODES: InodeRegistry<EpollFile> = InodeRegistry::new();
+
+bitflags! {
+    /// epoll 事件标志
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EpollEventFlags: u32 {
+        /// 可读
+        const IN = 0x001;
+        /// 有紧急数据可读
+        const PRI = 0x002;
+        /// 可写
+        const OUT = 0x004;
+        /// 发生错误
+        const ERR = 0x008;
+        /// 挂断
+        const HUP = 0x010;
+        /// 普通数据可读
+        const RDNORM = 0x040;
+        /// 优先数据可读
+        const RDBAND = 0x080;
+        /// 普通数据可写
+        const WRNORM = 0x100;
+        /// 优先数据可写
+        const WRBAND = 0x200;
+        /// 消息
+        const MSG = 0x400;
+        /// 对端关闭连接
+        const RDHUP = 0x2000;
+        /// 独占唤醒
+        const EXCLUSIVE = 1 << 28;
+        /// 防止系统休眠
+        const WAKEUP = 1 << 29;
+        /// 只通知一次
+        const ONESHOT = 1 << 30;
+        /// 边缘触发
+        const ET = 1 << 31;
+    }
+}
+
+/// `struct epoll_event`，aarch64 下不是 packed 结构
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct EpollEvent {
+    /// 事件标志 [EpollEventFlags]
+    pub events: u32,
+    _pad: u32,
+    /// 用户数据
+    pub data: u64,
+}
+
+/// `epoll_ctl` 操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum::TryFromPrimitive)]
+#[repr(usize)]
+pub enum EpollCtl {
+    /// 添加文件
+    Add = 1,
+    /// 删除文件
+    Del = 2,
+    /// 修改文件的事件
+    Mod = 3,
+}
+
+/// epoll 就绪列表
+struct EpollReady {
+    /// 就绪（可能就绪）的文件描述符
+    fds: Mutex<BTreeSet<usize>>,
+    /// epoll 自身的等待队列，`epoll_pwait`、嵌套的 epoll 和 poll 都在这里等待
+    wait: Arc<WaitQueue>,
+}
+
+impl EpollReady {
+    /// 将文件描述符加入就绪列表并唤醒等待的任务
+    fn push(&self, fd: usize) {
+        self.fds.lock().insert(fd);
+        self.wait.wake_all();
+    }
+}
+
+/// 订阅文件等待队列使用的 [Waker]
+struct EpollNotifier {
+    ready: Weak<EpollReady>,
+    fd: usize,
+}
+
+impl Wake for EpollNotifier {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(ready) = self.ready.upgrade() {
+            ready.push(self.fd);
+        }
+    }
+}
+
+/// epoll 兴趣列表中的一项
+struct EpollItem {
+    /// 监听的文件，文件关闭后自动从兴趣列表中移除
+    file: Weak<File>,
+    /// 监听的事件
+    events: EpollEventFlags,
+    /// 用户数据
+    data: u64,
+    /// 订阅的等待队列 (等待队列, 订阅 id)，没有等待队列的文件需要轮询
+    subscribed: Option<(Arc<WaitQueue>, usize)>,
+    /// `EPOLLONESHOT` 触发之后禁用，直到 `EPOLL_CTL_MOD`
+    disabled: bool,
+}
+
+impl Drop for EpollItem {
+    fn drop(&mut self) {
+        if let Some((wait, id)) = &self.subscribed {
+            wait.unsubscribe(*id);
+        }
+    }
+}
+
+/// 文件报告事件之后对兴趣列表中这一项的处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportAction {
+    /// `EPOLLONESHOT`，禁用这一项直到 `EPOLL_CTL_MOD`
+    Disable,
+    /// 水平触发，留在就绪列表中下次继续检查
+    Requeue,
+    /// 边缘触发或者需要轮询的文件，等待文件状态变化或者下次轮询
+    Wait,
+}
+
+/// 检查文件时关心的事件，`EPOLLERR` 和 `EPOLLHUP` 总是会被报告
+fn interest(events: EpollEventFlags) -> EpollEventFlags {
+    events | EpollEventFlags::ERR | EpollEventFlags::HUP
+}
+
+/// 监听 `events` 的文件返回 `polled` 时报告的事件，检查文件失败时报告 `EPOLLERR`
+fn reported_events(events: EpollEventFlags, polled: Option<EpollEventFlags>) -> EpollEventFlags {
+    polled.map_or(EpollEventFlags::ERR, |x| x & interest(events))
+}
+
+/// 监听 `events` 的文件报告事件之后的处理，`subscribed` 表示文件有等待队列
+fn after_report(events: EpollEventFlags, subscribed: bool) -> ReportAction {
+    if events.contains(EpollEventFlags::ONESHOT) {
+        ReportAction::Disable
+    } else if !events.contains(EpollEventFlags::ET) && subscribed {
+        ReportAction::Requeue
+    } else {
+        ReportAction::Wait
+    }
+}
+
+/// epoll 文件
+pub struct EpollFile {
+    /// inode 编号
+    ino: u64,
+    /// 兴趣列表 (文件描述符, 监听项)
+    items: Mutex<BTreeMap<usize, EpollItem>>,
+    /// 就绪列表
+    ready: Arc<EpollReady>,
+}
+
+impl EpollFile {
+    /// 创建一个新的 epoll 文件
+    pub fn new() -> Arc<Self> {
+        let ino = alloc_ino();
+        let epoll = Arc::new(Self {
+            ino,
+            items: Mutex::new(BTreeMap::new()),
+            ready: Arc::new(EpollReady {
+                fds: Mutex::new(BTreeSet::new()),
+                wait: WaitQueue::new(ino),
+            }),
+        });
+        EPOLL_INODES.insert(ino, &epoll);
+        epoll
+    }
+
+    /// 从打开的 [File] 中获取 [EpollFile]
+    ///
+    /// 如果文件不是 epoll，返回 [Option::None]
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        EPOLL_INODES.from_file(file)
+    }
+
+    /// 修改兴趣列表
+    ///
+    /// ## 参数
+    /// - `op`    操作类型
+    /// - `fd`    需要操作的文件描述符
+    /// - `file`  文件描述符对应的文件
+    /// - `event` 监听的事件，`EPOLL_CTL_DEL` 时忽略
+    pub fn ctl(
+        &self,
+        op: EpollCtl,
+        fd: usize,
+        file: &Arc<File>,
+        event: EpollEvent,
+    ) -> Result<(), Errno> {
+        let mut items = self.items.lock();
+        // 文件描述符被重新使用，之前的文件已经关闭
+        if items
+            .get(&fd)
+            .is_some_and(|item| !Weak::ptr_eq(&item.file, &Arc::downgrade(file)))
+        {
+            items.remove(&fd);
+        }
+        let events = EpollEventFlags::from_bits_truncate(event.events);
+        match op {
+            EpollCtl::Add => {
+                if items.contains_key(&fd) {
+                    return Err(Errno::EEXIST);
+                }
+                let subscribed = WaitQueue::from_file(file).map(|wait| {
+                    let notifier = Arc::new(EpollNotifier {
+                        ready: Arc::downgrade(&self.ready),
+                        fd,
+                    });
+                    let id = wait.subscribe(Waker::from(notifier));
+                    (wait, id)
+                });
+                items.insert(
+                    fd,
+                    EpollItem {
+                        file: Arc::downgrade(file),
+                        events,
+                        data: event.data,
+                        subscribed,
+                        disabled: false,
+                    },
+                );
+            }
+            EpollCtl::Mod => {
+                let item = items.get_mut(&fd).ok_or(Errno::ENOENT)?;
+                item.events = events;
+                item.data = event.data;
+                item.disabled = false;
+            }
+            EpollCtl::Del => {
+                items.remove(&fd).ok_or(Errno::ENOENT)?;
+                self.ready.fds.lock().remove(&fd);
+                return Ok(());
+            }
+        }
+        drop(items);
+        // 添加或修改之后需要重新检查一次文件状态
+        self.ready.push(fd);
+        Ok(())
+    }
+
+    /// 是否存在需要轮询的文件
+    pub fn has_polling_items(&self) -> bool {
+        self.items
+            .lock()
+            .values()
+            .any(|item| item.subscribed.is_none() && !item.disabled)
+    }
+
+    /// 收集就绪的事件
+    ///
+    /// ## 参数
+    /// - `max` 最多返回的事件数量
+    ///
+    /// 水平触发的文件在返回之后仍然保留在就绪列表中，下次继续检查；
+    /// 边缘触发的文件只有在文件状态再次变化后才会被检查。
+    pub fn collect(&self, max: usize) -> Vec<EpollEvent> {
+        let mut items = self.items.lock();
+        let mut candidates = core::mem::take(&mut *self.ready.fds.lock());
+        items
+            .iter()
+            .filter(|(_, item)| item.subscribed.is_none())
+            .for_each(|(fd, _)| {
+                candidates.insert(*fd);
+            });
+
+        let mut events = Vec::new();
+        let mut requeue = Vec::new();
+        for fd in candidates {
+            let Some(item) = items.get_mut(&fd) else {
+                continue;
+            };
+            if item.disabled {
+                continue;
+            }
+            let Some(file) = item.file.upgrade() else {
+                items.remove(&fd);
+                continue;
+            };
+            if events.len() >= max {
+                requeue.push(fd);
+                continue;
+            }
+            let interest = interest(item.events);
+            let polled = file
+                .poll(PollEvent::from_bits_truncate(interest.bits() as _))
+                .map(|x| EpollEventFlags::from_bits_truncate(x.bits() as _));
+            let revents = reported_events(item.events, polled.ok());
+            if revents.is_empty() {
+                continue;
+            }
+            events.push(EpollEvent {
+                events: revents.bits(),
+                _pad: 0,
+                data: item.data,
+            });
+            match after_report(item.events, item.subscribed.is_some()) {
+                ReportAction::Disable => item.disabled = true,
+                ReportAction::Requeue => requeue.push(fd),
+                ReportAction::Wait => {}
+            }
+        }
+        self.ready.fds.lock().extend(requeue);
+        events
+    }
+
+    /// 等待就绪列表中出现文件
+    ///
+    /// ## 参数
+    /// - `tid` 等待的任务 id，被信号打断时返回 `EINTR`
+    ///
+    /// 多个任务可以同时等待同一个 epoll，就绪列表变化时全部唤醒
+    pub async fn wait(&self, tid: usize) -> Result<(), Errno> {
+        if !self.ready.fds.lock().is_empty() {
+            return Ok(());
+        }
+        self.ready.wait.wait(tid).await
+    }
+}
+
+impl Drop for EpollFile {
+    fn drop(&mut self) {
+        EPOLL_INODES.remove(self.ino);
+    }
+}
+
+impl INodeInterface for EpollFile {
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && !self.ready.fds.lock().is_empty() {
+            res |= PollEvent::IN;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.ino as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_triggered() {
+        let events = EpollEventFlags::IN;
+        assert_eq!(after_report(events, true), ReportAction::Requeue);
+        // 没有等待队列的文件每次都会被轮询，不需要留在就绪列表中
+        assert_eq!(after_report(events, false), ReportAction::Wait);
+    }
+
+    #[test]
+    fn edge_triggered() {
+        let events = EpollEventFlags::IN | EpollEventFlags::ET;
+        assert_eq!(after_report(events, true), ReportAction::Wait);
+        assert_eq!(after_report(events, false), ReportAction::Wait);
+    }
+
+    #[test]
+    fn oneshot() {
+        for events in [
+            EpollEventFlags::IN | EpollEventFlags::ONESHOT,
+            EpollEventFlags::IN | EpollEventFlags::ONESHOT | EpollEventFlags::ET,
+        ] {
+            assert_eq!(after_report(events, true), ReportAction::Disable);
+            assert_eq!(after_report(events, false), ReportAction::Disable);
+        }
+    }
+
+    #[test]
+    fn reported() {
+        let events = EpollEventFlags::IN | EpollEventFlags::ET;
+        // 只报告监听的事件
+        let polled = EpollEventFlags::IN | EpollEventFlags::OUT;
+        assert_eq!(reported_events(events, Some(polled)), EpollEventFlags::IN);
+        assert!(reported_events(events, Some(EpollEventFlags::OUT)).is_empty());
+        // EPOLLERR 和 EPOLLHUP 不需要监听
+        let polled = EpollEventFlags::HUP | EpollEventFlags::ERR;
+        assert_eq!(reported_events(events, Some(polled)), polled);
+        // 检查文件失败
+        assert_eq!(reported_events(events, None), EpollEventFlags::ERR);
+    }
+}
@@ -0,0 +1,109 @@
+//! eventfd 文件
+//!
+//! eventfd 内部维护一个 64 位的计数器，写入时累加，读取时返回计数器的值并清零。
+//! 信号量模式 (`EFD_SEMAPHORE`) 下每次读取只返回 1 并将计数器减 1。
+use alloc::sync::Arc;
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+
+use super::{
+    registry::{ANON_DEV, alloc_ino},
+    wait_queue::WaitQueue,
+};
+
+/// 信号量模式
+pub const EFD_SEMAPHORE: usize = 1;
+
+/// 计数器的最大值
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+/// eventfd 文件
+pub struct EventFd {
+    /// 计数器
+    count: Mutex<u64>,
+    /// 是否为信号量模式
+    semaphore: bool,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+impl EventFd {
+    /// 创建一个新的 eventfd
+    ///
+    /// ## 参数
+    /// - `initval`   计数器的初始值
+    /// - `semaphore` 是否为信号量模式
+    pub fn new(initval: u64, semaphore: bool) -> Arc<Self> {
+        Arc::new(Self {
+            count: Mutex::new(initval),
+            semaphore,
+            wait: WaitQueue::new(alloc_ino()),
+        })
+    }
+}
+
+impl INodeInterface for EventFd {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(Errno::EINVAL);
+        }
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        let value = match self.semaphore {
+            true => 1,
+            false => *count,
+        };
+        *count -= value;
+        drop(count);
+        buffer[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+        self.wait.wake_all();
+        Ok(size_of::<u64>())
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(Errno::EINVAL);
+        }
+        let value = u64::from_ne_bytes(buffer[..size_of::<u64>()].try_into().unwrap());
+        if value == u64::MAX {
+            return Err(Errno::EINVAL);
+        }
+        let mut count = self.count.lock();
+        if EVENTFD_MAX - *count < value {
+            return Err(Errno::EAGAIN);
+        }
+        *count += value;
+        drop(count);
+        if value != 0 {
+            self.wait.wake_all();
+        }
+        Ok(size_of::<u64>())
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let count = *self.count.lock();
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && count > 0 {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) && count < EVENTFD_MAX {
+            res |= PollEvent::OUT;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
@@ -1,117 +1,160 @@
 //! 通过 IPC 关联文件系统
 //!
-//!
+//! 文件系统服务中的文件通过 [FS_IMPLS] 访问，打开的文件登记在 [IPC_FILES] 中。
+//! `copy_file_range` 的两端是同一个文件系统服务中的文件时，通过 [copy_range]
+//! 由服务直接复制数据，不需要经过 kernel-thread。
 
 use core::cmp;
 
-use alloc::{boxed::Box, string::String};
-use libc_core::types::Stat;
+use alloc::{format, string::String, sync::Arc};
+use fs::{INodeInterface, file::File};
+use libc_core::types::{Stat, StatMode};
 use srv_gate::FS_IMPLS;
+use syscalls::Errno;
+use vfscore::{FileSystem, VfsResult};
+
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    special::S_IFMT,
+};
 
-use super::vfs::{FileInterface, FileMetaData, FileResult, FileSystem};
+/// 所有打开的文件系统服务中的文件
+static IPC_FILES: InodeRegistry<IPCFile> = InodeRegistry::new();
 
 /// 通过 IPC 连接的文件系统
 pub struct IPCFileSystem {
-    /// 文件系统名称，需要在编译期间指定名称
+    /// 文件系统名称
     pub name: &'static str,
-    /// 传输端口
-    pub fs: usize,
-}
-
-/// 通过 IPC 连接的文件系统
-pub struct IPCFile {
-    /// 文件路径
-    pub path: String,
-    /// 文件标识节点
-    pub inode: u64,
-    /// 文件大小
-    pub fsize: u64,
-    /// 传输端口
+    /// 文件系统服务在 [FS_IMPLS] 中的序号
     pub fs: usize,
 }
 
 impl IPCFileSystem {
     /// 创建一个 IPC 文件系统
     ///
-    /// - `name` 文件系统名称，也将使用这个名称查找服务的存在, 目前限制必须是编译期间存在的字符
-    pub fn new(name: &'static str, id: usize) -> Result<Self, sel4::Error> {
-        Ok(Self { name, fs: id })
+    /// - `name` 文件系统名称
+    /// - `id`   文件系统服务在 [FS_IMPLS] 中的序号
+    pub fn new(name: &'static str, id: usize) -> Arc<Self> {
+        Arc::new(Self { name, fs: id })
     }
 }
 
 impl FileSystem for IPCFileSystem {
-    fn info(&self) -> super::vfs::FSInfo {
-        todo!()
+    fn root_dir(&self) -> Arc<dyn INodeInterface> {
+        Arc::new(IPCDir {
+            path: String::new(),
+            fs: self.fs,
+        })
     }
 
-    fn open(&self, path: &str, flags: u64) -> FileResult<Box<dyn FileInterface>> {
-        // let (inode, fsize) = self.fs.open(path, flags)?;
-        let (inode, fsize) = FS_IMPLS[self.fs].lock().open(path, flags as _)?;
-        Ok(Box::new(IPCFile {
-            path: String::from(path),
-            inode: inode as _,
-            fsize: fsize as _,
-            fs: self.fs,
-        }))
+    fn name(&self) -> &str {
+        self.name
     }
+}
 
-    fn mkdir(&self, path: &str) -> FileResult<()> {
-        // self.fs.mkdir(path).map_err(|_| Errno::EIO)
-        FS_IMPLS[self.fs].lock().mkdir(path);
-        Ok(())
+/// 文件系统服务中的目录
+pub struct IPCDir {
+    /// 目录的路径，根目录为空
+    path: String,
+    /// 文件系统服务在 [FS_IMPLS] 中的序号
+    fs: usize,
+}
+
+impl INodeInterface for IPCDir {
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn INodeInterface>> {
+        let path = format!("{}/{}", self.path, name);
+        let (inode, _) = FS_IMPLS[self.fs]
+            .lock()
+            .open(&path, 0)
+            .map_err(|_| Errno::ENOENT)?;
+        let file = IPCFile::new(self.fs, inode as _);
+        let mut stat = Stat::default();
+        file.stat(&mut stat)?;
+        if stat.mode.bits() as u32 & S_IFMT == StatMode::DIR.bits() as u32 {
+            return Ok(Arc::new(IPCDir { path, fs: self.fs }));
+        }
+        Ok(file)
     }
 
-    fn unlink(&self, path: &str) -> FileResult<()> {
-        // self.fs.unlink(path).map_err(|_| Errno::EBADF)
-        FS_IMPLS[self.fs].lock().unlink(path);
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = 0;
+        stat.mode = StatMode::DIR;
+        stat.nlink = 1;
+        stat.blksize = 512;
         Ok(())
     }
 }
 
-impl FileInterface for IPCFile {
-    fn readat(&mut self, off: usize, buf: &mut [u8]) -> FileResult<usize> {
-        // self.fs
-        //     .read_at(self.inode, off, buf)
-        //     .map_err(|_| Errno::EIO)
-        let buf_len = cmp::min(buf.len(), 0x4000);
-        Ok(FS_IMPLS[self.fs]
-            .lock()
-            .read_at(self.inode, off, &mut buf[..buf_len]))
-    }
+/// 文件系统服务中打开的文件
+pub struct IPCFile {
+    /// 登记在 [IPC_FILES] 中的 inode 编号
+    ino: u64,
+    /// 文件在服务中的编号
+    inode: u64,
+    /// 文件系统服务在 [FS_IMPLS] 中的序号
+    fs: usize,
+}
 
-    fn writeat(&mut self, off: usize, data: &[u8]) -> FileResult<usize> {
-        // let rsize = self
-        //     .fs
-        //     .write_at(self.inode, off, data)
-        //     .map_err(|_| Errno::EIO)?;
-        let rsize = FS_IMPLS[self.fs].lock().write_at(self.inode, off, data);
-        if off + rsize > self.fsize as _ {
-            self.fsize = (off + rsize) as _;
-        }
-        Ok(rsize)
+impl IPCFile {
+    /// 登记一个服务中打开的文件
+    fn new(fs: usize, inode: u64) -> Arc<Self> {
+        let ino = alloc_ino();
+        let file = Arc::new(Self { ino, inode, fs });
+        IPC_FILES.insert(ino, &file);
+        file
     }
+}
 
-    fn metadata(&self) -> FileResult<super::vfs::FileMetaData> {
-        Ok(FileMetaData {
-            size: self.fsize as _,
-        })
+impl INodeInterface for IPCFile {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let buf_len = cmp::min(buffer.len(), 0x4000);
+        Ok(FS_IMPLS[self.fs]
+            .lock()
+            .read_at(self.inode, offset, &mut buffer[..buf_len])?)
     }
 
-    fn getdents64(&self, offset: usize, buffer: &mut [u8]) -> FileResult<(usize, usize)> {
-        // self.fs.getdents64(self.inode, offset, buffer)
+    fn writeat(&self, offset: usize, buffer: &[u8]) -> VfsResult<usize> {
         Ok(FS_IMPLS[self.fs]
             .lock()
-            .getdents64(self.inode, offset, buffer))
+            .write_at(self.inode, offset, buffer)?)
     }
 
-    fn stat(&self) -> FileResult<Stat> {
-        // self.fs.stat(self.inode as _).map_err(|_| Errno::EBADFD)
-        Ok(FS_IMPLS[self.fs].lock().stat(self.inode as _))
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        *stat = FS_IMPLS[self.fs].lock().stat(self.inode as _)?;
+        // 使用登记的 inode 编号，通过 [IPC_FILES] 可以找到这个文件
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.ino as _;
+        Ok(())
     }
 }
 
 impl Drop for IPCFile {
     fn drop(&mut self) {
+        IPC_FILES.remove(self.ino);
         FS_IMPLS[self.fs].lock().close(self.inode as _);
     }
 }
+
+/// 两个文件是同一个文件系统服务中的文件时，由服务直接复制数据
+///
+/// 返回复制的字节数量或服务返回的错误，文件不在同一个文件系统服务中时返回
+/// [Option::None]，需要在 kernel-thread 中复制
+pub fn copy_range(
+    src: &File,
+    src_off: usize,
+    dst: &File,
+    dst_off: usize,
+    len: usize,
+) -> Option<Result<usize, Errno>> {
+    let src = IPC_FILES.from_file(src)?;
+    let dst = IPC_FILES.from_file(dst)?;
+    if src.fs != dst.fs {
+        return None;
+    }
+    Some(
+        FS_IMPLS[src.fs]
+            .lock()
+            .copy_range(src.inode, src_off, dst.inode, dst_off, len),
+    )
+}
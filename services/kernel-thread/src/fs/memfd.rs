@@ -0,0 +1,123 @@
+//! 匿名内存文件模块
+//!
+//! `memfd_create` 创建的文件，数据保存在 [SharedMemory] 中，
+//! 可以使用 `mmap(MAP_SHARED)` 映射，`fork` 之后父子进程共享相同的物理页。
+//! 映射之后仍然可以通过 `ftruncate` 扩展，已有的映射只能访问映射时的大小之内的部分。
+use core::cmp;
+
+use alloc::{string::String, sync::Arc};
+use common::config::PAGE_SIZE;
+use fs::file::File;
+use libc_core::types::{Stat, StatMode};
+use spin::Mutex;
+use vfscore::{INodeInterface, VfsResult};
+
+use super::registry::{ANON_DEV, InodeRegistry, alloc_ino};
+use crate::task::shm::SharedMemory;
+
+/// 所有存活的 memfd
+static MEMFD_INODES: InodeRegistry<MemFd> = InodeRegistry::new();
+
+/// 匿名内存文件
+pub struct MemFd {
+    /// inode 编号
+    ino: u64,
+    /// 创建时传入的名称，仅用于调试
+    name: String,
+    /// 存储数据的共享内存
+    mem: Mutex<Arc<SharedMemory>>,
+    /// 文件的大小
+    size: Mutex<usize>,
+}
+
+impl MemFd {
+    /// 创建一个新的 [MemFd]
+    ///
+    /// ## 参数
+    /// - `name` memfd 的名称
+    pub fn new(name: String) -> Arc<Self> {
+        let ino = alloc_ino();
+        let memfd = Arc::new(Self {
+            ino,
+            name,
+            mem: Mutex::new(Arc::new(SharedMemory::alloc(0))),
+            size: Mutex::new(0),
+        });
+        MEMFD_INODES.insert(ino, &memfd);
+        memfd
+    }
+
+    /// 从打开的 [File] 中获取 [MemFd]
+    ///
+    /// 如果文件不是 memfd，返回 [Option::None]
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        MEMFD_INODES.from_file(file)
+    }
+
+    /// 获取保存数据的共享内存
+    pub fn shared_memory(&self) -> Arc<SharedMemory> {
+        self.mem.lock().clone()
+    }
+
+    /// 获取 memfd 的名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for MemFd {
+    fn drop(&mut self) {
+        MEMFD_INODES.remove(self.ino);
+    }
+}
+
+impl INodeInterface for MemFd {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let size = *self.size.lock();
+        if offset >= size {
+            return Ok(0);
+        }
+        let rlen = cmp::min(buffer.len(), size - offset);
+        Ok(self.mem.lock().read_at(offset, &mut buffer[..rlen]))
+    }
+
+    fn writeat(&self, offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        if offset + buffer.len() > *self.size.lock() {
+            self.truncate(offset + buffer.len())?;
+        }
+        Ok(self.mem.lock().write_at(offset, buffer))
+    }
+
+    fn truncate(&self, size: usize) -> VfsResult<()> {
+        let mut mem = self.mem.lock();
+        if size > mem.size() && Arc::strong_count(&mem) > 1 {
+            // 已经被映射的共享内存不能替换物理页，扩展之后和已有的映射共用原来的物理页，
+            // 按倍数扩展避免反复扩展时层数过多
+            *mem = Arc::new(mem.grow(size.max(mem.size() * 2)));
+        } else if size > mem.size() {
+            let new_mem = SharedMemory::alloc(size.next_multiple_of(PAGE_SIZE));
+            let mut buffer = vec![0u8; *self.size.lock()];
+            mem.read_at(0, &mut buffer);
+            new_mem.write_at(0, &buffer);
+            *mem = Arc::new(new_mem);
+        } else if size < *self.size.lock() {
+            // 清空被截断的部分，重新扩展时读取到的数据为 0
+            let zeros = vec![0u8; *self.size.lock() - size];
+            mem.write_at(size, &zeros);
+        }
+        *self.size.lock() = size;
+        Ok(())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        let size = *self.size.lock();
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.ino as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        stat.size = size as _;
+        stat.blksize = PAGE_SIZE as _;
+        stat.blocks = size.div_ceil(512) as _;
+        Ok(())
+    }
+}
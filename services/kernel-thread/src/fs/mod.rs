@@ -2,7 +2,17 @@
 //!
 //! 查找 root-task 中存在的串口服务，并记录到全局变量中
 
-// pub mod ipc_fs;
 // pub mod pipe;
 pub mod devfs;
+pub mod epoll;
+pub mod eventfd;
+pub mod ipc_fs;
+pub mod memfd;
+pub mod mqueue;
 pub mod pipe;
+pub mod registry;
+pub mod signalfd;
+pub mod special;
+pub mod timerfd;
+pub mod tty;
+pub mod wait_queue;
@@ -0,0 +1,359 @@
+//! POSIX 消息队列文件系统
+//!
+//! 消息队列保存在 kernel-thread 中，通过 `mq_open` 创建或打开，
+//! 所有命名的消息队列挂载在 `/dev/mqueue` 下，可以通过文件系统查看。
+use alloc::{collections::btree_map::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use fs::{FileType, INodeInterface, file::File};
+use libc_core::{
+    poll::PollEvent,
+    signal::SignalNum,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{DirEntry, FileSystem, VfsResult};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+
+/// 默认的最大消息数量
+pub const MQ_DEF_MAXMSG: usize = 10;
+
+/// 默认的单条消息最大长度
+pub const MQ_DEF_MSGSIZE: usize = 8192;
+
+/// 允许设置的最大消息数量
+pub const MQ_MAX_MAXMSG: usize = 256;
+
+/// 允许设置的单条消息最大长度
+pub const MQ_MAX_MSGSIZE: usize = 0x10_0000;
+
+/// 已经命名的消息队列 (名称, 消息队列)
+static MQUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+/// 所有存活的消息队列 (ino, 消息队列)，包含已经 `mq_unlink` 但仍被打开的队列
+static MQUEUE_INODES: InodeRegistry<MessageQueue> = InodeRegistry::new();
+
+/// 消息队列属性，对应 `struct mq_attr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct MqAttr {
+    /// 消息队列标志，仅支持 `O_NONBLOCK`
+    pub flags: isize,
+    /// 最大消息数量
+    pub maxmsg: isize,
+    /// 单条消息最大长度
+    pub msgsize: isize,
+    /// 当前消息数量
+    pub curmsgs: isize,
+    __reserved: [isize; 4],
+}
+
+/// 通知方式，对应 `struct sigevent` 的前半部分
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct SigEvent {
+    /// 传递给处理函数的值
+    pub value: usize,
+    /// 通知使用的信号
+    pub signo: i32,
+    /// 通知方式 `SIGEV_*`
+    pub notify: i32,
+}
+
+/// 使用信号通知
+pub const SIGEV_SIGNAL: i32 = 0;
+/// 不进行通知
+pub const SIGEV_NONE: i32 = 1;
+
+/// 消息队列
+pub struct MessageQueue {
+    /// inode 编号
+    ino: u64,
+    /// 最大消息数量
+    maxmsg: usize,
+    /// 单条消息最大长度
+    msgsize: usize,
+    /// 消息列表 (优先级, 消息)，按照优先级从高到低排列，同优先级先进先出
+    messages: Mutex<Vec<(u32, Vec<u8>)>>,
+    /// 注册的通知 (进程 id, 信号)
+    notify: Mutex<Option<(usize, Option<SignalNum>)>>,
+    /// 等待队列，消息队列状态变化时唤醒
+    wait: Arc<WaitQueue>,
+}
+
+impl MessageQueue {
+    /// 创建一个新的消息队列
+    ///
+    /// ## 参数
+    /// - `maxmsg`  最大消息数量
+    /// - `msgsize` 单条消息最大长度
+    fn new(maxmsg: usize, msgsize: usize) -> Arc<Self> {
+        let ino = alloc_ino();
+        let queue = Arc::new(Self {
+            ino,
+            maxmsg,
+            msgsize,
+            messages: Mutex::new(Vec::new()),
+            notify: Mutex::new(None),
+            wait: WaitQueue::new(ino),
+        });
+        MQUEUE_INODES.insert(ino, &queue);
+        queue
+    }
+
+    /// 从打开的 [File] 中获取 [MessageQueue]
+    ///
+    /// 如果文件不是消息队列，返回 [Option::None]
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        MQUEUE_INODES.from_file(file)
+    }
+
+    /// 消息队列的等待队列，发送或者接收消息之后唤醒
+    pub fn wait_queue(&self) -> Arc<WaitQueue> {
+        self.wait.clone()
+    }
+
+    /// 获取消息队列的属性
+    pub fn attr(&self) -> MqAttr {
+        MqAttr {
+            maxmsg: self.maxmsg as _,
+            msgsize: self.msgsize as _,
+            curmsgs: self.messages.lock().len() as _,
+            ..Default::default()
+        }
+    }
+
+    /// 发送一条消息
+    ///
+    /// ## 参数
+    /// - `prio` 消息的优先级
+    /// - `data` 消息内容
+    ///
+    /// ## 返回值
+    /// 如果发送前消息队列为空且有注册的通知，返回需要通知的 (进程 id, 信号)，
+    /// 通知在触发后会被注销
+    pub fn send(&self, prio: u32, data: &[u8]) -> Result<Option<(usize, SignalNum)>, Errno> {
+        if data.len() > self.msgsize {
+            return Err(Errno::EMSGSIZE);
+        }
+        let mut messages = self.messages.lock();
+        if messages.len() >= self.maxmsg {
+            return Err(Errno::EAGAIN);
+        }
+        let was_empty = messages.is_empty();
+        let idx = messages
+            .iter()
+            .position(|(p, _)| *p < prio)
+            .unwrap_or(messages.len());
+        messages.insert(idx, (prio, data.to_vec()));
+        drop(messages);
+        self.wait.wake_all();
+
+        if !was_empty {
+            return Ok(None);
+        }
+        Ok(self
+            .notify
+            .lock()
+            .take()
+            .and_then(|(pid, signal)| signal.map(|signal| (pid, signal))))
+    }
+
+    /// 接收优先级最高的一条消息
+    ///
+    /// ## 参数
+    /// - `len` 接收缓冲区的大小，不能小于单条消息最大长度
+    pub fn receive(&self, len: usize) -> Result<(u32, Vec<u8>), Errno> {
+        if len < self.msgsize {
+            return Err(Errno::EMSGSIZE);
+        }
+        let mut messages = self.messages.lock();
+        if messages.is_empty() {
+            return Err(Errno::EAGAIN);
+        }
+        let message = messages.remove(0);
+        drop(messages);
+        self.wait.wake_all();
+        Ok(message)
+    }
+
+    /// 注册消息到达通知
+    ///
+    /// ## 参数
+    /// - `pid`    注册通知的进程
+    /// - `signal` 通知使用的信号，[Option::None] 表示仅注册不发送信号
+    pub fn set_notify(&self, pid: usize, signal: Option<SignalNum>) -> Result<(), Errno> {
+        let mut notify = self.notify.lock();
+        if notify.is_some() {
+            return Err(Errno::EBUSY);
+        }
+        *notify = Some((pid, signal));
+        Ok(())
+    }
+
+    /// 注销消息到达通知
+    ///
+    /// 只有注册通知的进程可以注销
+    pub fn clear_notify(&self, pid: usize) {
+        let mut notify = self.notify.lock();
+        if notify.is_some_and(|(owner, _)| owner == pid) {
+            *notify = None;
+        }
+    }
+}
+
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        MQUEUE_INODES.remove(self.ino);
+    }
+}
+
+impl INodeInterface for MessageQueue {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let qsize: usize = self.messages.lock().iter().map(|(_, x)| x.len()).sum();
+        let notify = self.notify.lock().map_or(0, |(pid, _)| pid);
+        let info = format!("QSIZE:{:<10} NOTIFY_PID:{:<6}\n", qsize, notify);
+        let info = info.as_bytes();
+        if offset >= info.len() {
+            return Ok(0);
+        }
+        let rlen = core::cmp::min(buffer.len(), info.len() - offset);
+        buffer[..rlen].copy_from_slice(&info[offset..offset + rlen]);
+        Ok(rlen)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        let len = self.messages.lock().len();
+        if events.contains(PollEvent::IN) && len > 0 {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) && len < self.maxmsg {
+            res |= PollEvent::OUT;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.ino as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
+
+/// 打开或创建一个命名的消息队列
+///
+/// ## 参数
+/// - `name`   消息队列的名称，不包含开头的 `/`
+/// - `create` 不存在时是否创建
+/// - `excl`   如果已经存在是否返回错误
+/// - `attr`   创建时使用的属性，[Option::None] 使用默认属性
+pub fn mq_open(
+    name: &str,
+    create: bool,
+    excl: bool,
+    attr: Option<MqAttr>,
+) -> Result<Arc<MessageQueue>, Errno> {
+    if name.is_empty() || name.contains('/') {
+        return Err(Errno::EINVAL);
+    }
+    let mut mqueues = MQUEUES.lock();
+    if let Some(queue) = mqueues.get(name) {
+        if create && excl {
+            return Err(Errno::EEXIST);
+        }
+        return Ok(queue.clone());
+    }
+    if !create {
+        return Err(Errno::ENOENT);
+    }
+    let (maxmsg, msgsize) = match attr {
+        Some(attr) => {
+            if attr.maxmsg <= 0
+                || attr.msgsize <= 0
+                || attr.maxmsg as usize > MQ_MAX_MAXMSG
+                || attr.msgsize as usize > MQ_MAX_MSGSIZE
+            {
+                return Err(Errno::EINVAL);
+            }
+            (attr.maxmsg as usize, attr.msgsize as usize)
+        }
+        None => (MQ_DEF_MAXMSG, MQ_DEF_MSGSIZE),
+    };
+    let queue = MessageQueue::new(maxmsg, msgsize);
+    mqueues.insert(String::from(name), queue.clone());
+    Ok(queue)
+}
+
+/// 删除一个命名的消息队列
+///
+/// 已经打开的消息队列在关闭之前仍然可以使用
+pub fn mq_unlink(name: &str) -> Result<(), Errno> {
+    MQUEUES.lock().remove(name).map(|_| ()).ok_or(Errno::ENOENT)
+}
+
+/// 消息队列文件系统
+pub struct MqueueFS;
+
+impl MqueueFS {
+    /// 创建一个新的 [MqueueFS]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl FileSystem for MqueueFS {
+    fn root_dir(&self) -> Arc<dyn INodeInterface> {
+        Arc::new(MqueueDir)
+    }
+
+    fn name(&self) -> &str {
+        "mqueue"
+    }
+}
+
+/// 消息队列文件系统的根目录
+pub struct MqueueDir;
+
+impl INodeInterface for MqueueDir {
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn INodeInterface>> {
+        MQUEUES
+            .lock()
+            .get(name)
+            .cloned()
+            .map(|x| x as Arc<dyn INodeInterface>)
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn read_dir(&self) -> VfsResult<Vec<DirEntry>> {
+        Ok(MQUEUES
+            .lock()
+            .keys()
+            .map(|name| DirEntry {
+                filename: name.clone(),
+                len: 0,
+                file_type: FileType::File,
+            })
+            .collect())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = 0;
+        stat.mode = StatMode::DIR;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
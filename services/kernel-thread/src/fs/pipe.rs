@@ -1,13 +1,16 @@
 //! 文件系统管道模块
 //!
-//!
-use core::cmp;
-
-use alloc::{
-    collections::VecDeque,
-    sync::{Arc, Weak},
+//! 读端和写端共享同一个 [Pipe]，管道为空或者已满时返回 `EAGAIN`，由系统调用层
+//! 在管道的 [WaitQueue] 上等待对端唤醒。
+use core::{
+    cmp,
+    sync::atomic::{AtomicUsize, Ordering},
 };
+
+use alloc::{collections::VecDeque, sync::Arc};
+use fs::file::File;
 use libc_core::{
+    fcntl::OpenFlags,
     poll::PollEvent,
     types::{Stat, StatMode},
 };
@@ -15,89 +18,431 @@ use spin::Mutex;
 use syscalls::Errno;
 use vfscore::{INodeInterface, VfsResult};
 
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+
+/// 所有存活的管道
+static PIPE_INODES: InodeRegistry<Pipe> = InodeRegistry::new();
+
+/// 不超过这个长度的写入是原子的，不会和其他写入交错
+pub const PIPE_BUF: usize = 4096;
+
+/// 管道默认的容量
+pub const PIPE_DEF_SIZE: usize = 0x10000;
+
+/// 非特权进程可以设置的最大容量，对应 `/proc/sys/fs/pipe-max-size`
+pub const PIPE_MAX_SIZE: usize = 0x100000;
+
+/// `fcntl` 设置管道容量
+pub const F_SETPIPE_SZ: u32 = 1031;
+
+/// `fcntl` 获取管道容量
+pub const F_GETPIPE_SZ: u32 = 1032;
+
+/// 管道缓冲区，读端和写端共享
+pub struct Pipe {
+    /// 管道中的数据
+    buffer: Mutex<VecDeque<u8>>,
+    /// 管道的容量
+    capacity: AtomicUsize,
+    /// 打开的读端数量
+    readers: AtomicUsize,
+    /// 打开的写端数量
+    writers: AtomicUsize,
+    /// 等待队列，读写两端共用
+    wait: Arc<WaitQueue>,
+}
+
+impl Pipe {
+    /// 创建一个新的管道缓冲区，创建时没有打开任何一端
+    pub fn new() -> Arc<Self> {
+        let ino = alloc_ino();
+        let pipe = Arc::new(Self {
+            buffer: Mutex::new(VecDeque::new()),
+            capacity: AtomicUsize::new(PIPE_DEF_SIZE),
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+            wait: WaitQueue::new(ino),
+        });
+        PIPE_INODES.insert(ino, &pipe);
+        pipe
+    }
+
+    /// 从打开的 [File] 中获取管道，读端和写端都可以
+    ///
+    /// 如果文件不是管道，返回 [Option::None]
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        PIPE_INODES.from_file(file)
+    }
+
+    /// 管道的容量
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::SeqCst)
+    }
+
+    /// 修改管道的容量，返回实际设置的容量
+    ///
+    /// 容量会向上取整到 2 的幂，并且不小于一页
+    pub fn set_capacity(&self, size: usize) -> Result<usize, Errno> {
+        if size > PIPE_MAX_SIZE {
+            return Err(Errno::EPERM);
+        }
+        let size = size.max(PIPE_BUF).next_power_of_two();
+        let buffer = self.buffer.lock();
+        if buffer.len() > size {
+            return Err(Errno::EBUSY);
+        }
+        self.capacity.store(size, Ordering::SeqCst);
+        drop(buffer);
+        self.wait.wake_all();
+        Ok(size)
+    }
+
+    /// 打开一个读端
+    pub fn open_reader(self: &Arc<Self>) -> Arc<PipeReceiver> {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        self.wait.wake_all();
+        Arc::new(PipeReceiver { pipe: self.clone() })
+    }
+
+    /// 打开一个写端
+    pub fn open_writer(self: &Arc<Self>) -> Arc<PipeSender> {
+        self.writers.fetch_add(1, Ordering::SeqCst);
+        self.wait.wake_all();
+        Arc::new(PipeSender { pipe: self.clone() })
+    }
+
+    /// 以命名管道的方式打开
+    ///
+    /// ## 参数
+    /// - `flags` 打开文件使用的标志
+    /// - `tid`   打开文件的任务 id，等待对端时被信号打断返回 `EINTR`
+    ///
+    /// ## 说明
+    /// 只读打开时等待写端打开，只写打开时等待读端打开，`O_NONBLOCK` 时不等待，
+    /// 但是没有读端时只写打开返回 `ENXIO`。读写打开不需要等待。
+    pub async fn open_fifo(
+        self: &Arc<Self>,
+        flags: OpenFlags,
+        tid: usize,
+    ) -> Result<Arc<dyn INodeInterface>, Errno> {
+        let nonblock = flags.contains(OpenFlags::NONBLOCK);
+        if flags.contains(OpenFlags::RDWR) {
+            return Ok(Arc::new(PipeDuplex {
+                rx: self.open_reader(),
+                tx: self.open_writer(),
+            }));
+        }
+        if flags.contains(OpenFlags::WRONLY) {
+            if nonblock && self.readers() == 0 {
+                return Err(Errno::ENXIO);
+            }
+            let tx = self.open_writer();
+            while self.readers() == 0 {
+                self.wait.wait(tid).await?;
+            }
+            return Ok(tx);
+        }
+        let rx = self.open_reader();
+        while !nonblock && self.writers() == 0 {
+            self.wait.wait(tid).await?;
+        }
+        Ok(rx)
+    }
+
+    /// 从文件中读取数据直接放入管道，不经过中间缓冲区，用于 `splice`
+    ///
+    /// ## 参数
+    /// - `file`   读取的文件
+    /// - `offset` 读取的位置，[Option::None] 时使用并更新文件的偏移
+    /// - `len`    最多读取的长度
+    ///
+    /// 管道已满时返回 `EAGAIN`，没有读端时返回 `EPIPE`
+    pub fn fill_from(&self, file: &File, offset: Option<usize>, len: usize) -> VfsResult<usize> {
+        if self.readers() == 0 {
+            return Err(Errno::EPIPE);
+        }
+        let mut queue = self.buffer.lock();
+        let old_len = queue.len();
+        let len = cmp::min(len, self.capacity().saturating_sub(old_len));
+        if len == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        // 先扩展管道，再把文件内容直接读到扩展出来的空间中
+        queue.resize(old_len + len, 0);
+        let (front, back) = queue.as_mut_slices();
+        let parts: [&mut [u8]; 2] = match old_len < front.len() {
+            true => [&mut front[old_len..], back],
+            false => [&mut back[old_len - front.len()..], Default::default()],
+        };
+        let mut rlen = 0;
+        let mut res = Ok(0);
+        for part in parts {
+            if part.is_empty() {
+                break;
+            }
+            res = match offset {
+                Some(offset) => file.readat(offset + rlen, part),
+                None => file.read(part),
+            };
+            match res {
+                Ok(n) if n == part.len() => rlen += n,
+                Ok(n) => {
+                    rlen += n;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        queue.truncate(old_len + rlen);
+        drop(queue);
+        // 已经读取了一部分数据时忽略错误
+        if rlen == 0 {
+            res?;
+        }
+        if rlen > 0 {
+            self.wait.wake_all();
+        }
+        Ok(rlen)
+    }
+
+    /// 将管道中的数据直接写入文件，不经过中间缓冲区，用于 `splice`
+    ///
+    /// ## 参数
+    /// - `file`   写入的文件
+    /// - `offset` 写入的位置，[Option::None] 时使用并更新文件的偏移
+    /// - `len`    最多写入的长度
+    ///
+    /// 管道为空时返回 `EAGAIN`，写端全部关闭时返回 0
+    pub fn drain_to(&self, file: &File, offset: Option<usize>, len: usize) -> VfsResult<usize> {
+        let mut queue = self.buffer.lock();
+        if queue.is_empty() {
+            return match self.writers() {
+                0 => Ok(0),
+                _ => Err(Errno::EAGAIN),
+            };
+        }
+        let len = cmp::min(len, queue.len());
+        let (front, back) = queue.as_slices();
+        let mut wlen = 0;
+        for part in [front, back] {
+            let part = &part[..cmp::min(part.len(), len - wlen)];
+            if part.is_empty() {
+                break;
+            }
+            let res = match offset {
+                Some(offset) => file.writeat(offset + wlen, part),
+                None => file.write(part),
+            };
+            match res {
+                Ok(n) => {
+                    wlen += n;
+                    if n < part.len() {
+                        break;
+                    }
+                }
+                Err(err) if wlen == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+        queue.drain(..wlen);
+        drop(queue);
+        self.wait.wake_all();
+        Ok(wlen)
+    }
+
+    /// 将数据移动到另一个管道，用于 `splice` 和 `tee`
+    ///
+    /// ## 参数
+    /// - `other` 目标管道
+    /// - `len`   最多移动的长度
+    /// - `keep`  是否保留当前管道中的数据，`tee` 只复制数据
+    pub fn transfer_to(&self, other: &Pipe, len: usize, keep: bool) -> VfsResult<usize> {
+        if core::ptr::eq(self, other) {
+            return Err(Errno::EINVAL);
+        }
+        if other.readers() == 0 {
+            return Err(Errno::EPIPE);
+        }
+        let mut queue = self.buffer.lock();
+        if queue.is_empty() {
+            return match self.writers() {
+                0 => Ok(0),
+                _ => Err(Errno::EAGAIN),
+            };
+        }
+        let mut out = other.buffer.lock();
+        let len = cmp::min(len, queue.len()).min(other.capacity().saturating_sub(out.len()));
+        if len == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        out.extend(queue.range(..len));
+        if !keep {
+            queue.drain(..len);
+        }
+        drop(out);
+        drop(queue);
+        other.wait.wake_all();
+        if !keep {
+            self.wait.wake_all();
+        }
+        Ok(len)
+    }
+
+    /// 打开的读端数量
+    pub fn readers(&self) -> usize {
+        self.readers.load(Ordering::SeqCst)
+    }
+
+    /// 打开的写端数量
+    pub fn writers(&self) -> usize {
+        self.writers.load(Ordering::SeqCst)
+    }
+
+    fn stat(&self, stat: &mut Stat) {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::FIFO;
+        stat.nlink = 1;
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        PIPE_INODES.remove(self.wait.ino());
+    }
+}
+
 /// Pipe 发送端
-pub struct PipeSender(Arc<Mutex<VecDeque<u8>>>);
+pub struct PipeSender {
+    pipe: Arc<Pipe>,
+}
 
 impl INodeInterface for PipeSender {
     fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
-        let mut queue = self.0.lock();
-        if queue.len() > 0x50000 {
-            Err(Errno::EWOULDBLOCK)
-        } else {
-            let wlen = buffer.len();
-            queue.extend(buffer.iter());
-            Ok(wlen)
+        // 读端全部关闭，由系统调用层发送 SIGPIPE
+        if self.pipe.readers() == 0 {
+            return Err(Errno::EPIPE);
         }
+        let mut queue = self.pipe.buffer.lock();
+        let free = self.pipe.capacity().saturating_sub(queue.len());
+        // 小于 PIPE_BUF 的写入要么全部写入，要么等待
+        if free == 0 || (buffer.len() <= PIPE_BUF && free < buffer.len()) {
+            return Err(Errno::EAGAIN);
+        }
+        let wlen = cmp::min(free, buffer.len());
+        queue.extend(&buffer[..wlen]);
+        drop(queue);
+        self.pipe.wait.wake_all();
+        Ok(wlen)
     }
 
     fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
         let mut res = PollEvent::NONE;
-        if events.contains(PollEvent::OUT) && self.0.lock().len() <= 0x50000 {
+        if self.pipe.readers() == 0 {
+            res |= PollEvent::ERR;
+        } else if events.contains(PollEvent::OUT)
+            && self
+                .pipe
+                .capacity()
+                .saturating_sub(self.pipe.buffer.lock().len())
+                >= PIPE_BUF
+        {
             res |= PollEvent::OUT;
         }
         Ok(res)
     }
 
     fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
-        stat.mode = StatMode::FIFO;
+        self.pipe.stat(stat);
         Ok(())
     }
 }
 
+impl Drop for PipeSender {
+    fn drop(&mut self) {
+        // 通知接收端管道已经关闭
+        self.pipe.writers.fetch_sub(1, Ordering::SeqCst);
+        self.pipe.wait.wake_all();
+    }
+}
+
 /// Pipe 接收端
 pub struct PipeReceiver {
-    queue: Arc<Mutex<VecDeque<u8>>>,
-    sender: Weak<PipeSender>,
+    pipe: Arc<Pipe>,
 }
 
 impl INodeInterface for PipeReceiver {
     fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
-        let mut queue = self.queue.lock();
+        let mut queue = self.pipe.buffer.lock();
         let rlen = cmp::min(queue.len(), buffer.len());
         queue
             .drain(..rlen)
             .zip(buffer.iter_mut())
             .for_each(|(src, dst)| *dst = src);
-        if rlen == 0 && Weak::strong_count(&self.sender) > 0 {
-            Err(Errno::EWOULDBLOCK)
+        drop(queue);
+        if rlen == 0 && self.pipe.writers() > 0 {
+            Err(Errno::EAGAIN)
         } else {
+            self.pipe.wait.wake_all();
             Ok(rlen)
         }
     }
 
     fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
         let mut res = PollEvent::NONE;
-        if events.contains(PollEvent::IN) {
-            if !self.queue.lock().is_empty() {
-                res |= PollEvent::IN;
-            } else if Weak::strong_count(&self.sender) == 0 {
-                res |= PollEvent::ERR;
-            }
+        if events.contains(PollEvent::IN) && !self.pipe.buffer.lock().is_empty() {
+            res |= PollEvent::IN;
         }
-        if events.contains(PollEvent::ERR)
-            && self.queue.lock().is_empty()
-            && Weak::strong_count(&self.sender) == 0
-        {
-            res |= PollEvent::ERR;
+        // 没有写端时读端挂断，和 Linux 一样不需要请求也会报告
+        if self.pipe.writers() == 0 {
+            res |= PollEvent::HUP;
         }
         Ok(res)
     }
 
     fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
-        stat.mode = StatMode::FIFO;
+        self.pipe.stat(stat);
         Ok(())
     }
 }
 
+impl Drop for PipeReceiver {
+    fn drop(&mut self) {
+        // 通知发送端管道已经关闭
+        self.pipe.readers.fetch_sub(1, Ordering::SeqCst);
+        self.pipe.wait.wake_all();
+    }
+}
+
+/// 以读写方式打开的命名管道，同时持有读端和写端
+pub struct PipeDuplex {
+    rx: Arc<PipeReceiver>,
+    tx: Arc<PipeSender>,
+}
+
+impl INodeInterface for PipeDuplex {
+    fn readat(&self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        self.rx.readat(offset, buffer)
+    }
+
+    fn writeat(&self, offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        self.tx.writeat(offset, buffer)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        Ok(self.rx.poll(events.clone())? | self.tx.poll(events)?)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        self.rx.stat(stat)
+    }
+}
+
 /// 创建一对可以互相通信的 Pipe
 pub fn create_pipe() -> (Arc<PipeReceiver>, Arc<PipeSender>) {
-    let queue = Arc::new(Mutex::new(VecDeque::new()));
-    let sender = Arc::new(PipeSender(queue.clone()));
-    (
-        Arc::new(PipeReceiver {
-            queue: queue.clone(),
-            sender: Arc::downgrade(&sender),
-        }),
-        sender,
-    )
+    let pipe = Pipe::new();
+    (pipe.open_reader(), pipe.open_writer())
 }
@@ -0,0 +1,89 @@
+//! 内核文件注册表
+//!
+//! [fs::file::File] 只保存了 `dyn INodeInterface`，无法直接获取具体的类型。
+//! kernel-thread 中实现的特殊文件（memfd、消息队列、epoll 等）都使用设备号 [ANON_DEV]，
+//! 在创建时通过 [alloc_ino] 分配一个 inode 编号并登记到 [InodeRegistry] 中，
+//! 之后可以通过 `stat` 返回的 `(dev, ino)` 找到对应的对象。同一个 inode 编号可以
+//! 登记在不同的注册表中，例如消息队列和它的等待队列。
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use fs::file::File;
+use libc_core::types::Stat;
+use spin::Mutex;
+
+/// kernel-thread 中特殊文件使用的设备号
+pub const ANON_DEV: u64 = 0x6b74;
+
+/// inode 编号分配器
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// 为特殊文件分配一个新的 inode 编号
+pub fn alloc_ino() -> u64 {
+    NEXT_INO.fetch_add(1, Ordering::SeqCst)
+}
+
+/// 特殊文件注册表
+pub struct InodeRegistry<T> {
+    /// 所有存活的文件 (ino, 文件)
+    inodes: Mutex<BTreeMap<u64, Weak<T>>>,
+}
+
+impl<T> InodeRegistry<T> {
+    /// 创建一个新的注册表
+    pub const fn new() -> Self {
+        Self {
+            inodes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 登记一个文件
+    ///
+    /// ## 参数
+    /// - `ino`   使用 [alloc_ino] 分配的 inode 编号
+    /// - `inode` 需要登记的文件
+    pub fn insert(&self, ino: u64, inode: &Arc<T>) {
+        self.inodes.lock().insert(ino, Arc::downgrade(inode));
+    }
+
+    /// 删除一个文件，一般在文件 `drop` 时调用
+    pub fn remove(&self, ino: u64) {
+        self.inodes.lock().remove(&ino);
+    }
+
+    /// 根据 inode 编号获取文件
+    pub fn get(&self, ino: u64) -> Option<Arc<T>> {
+        self.inodes.lock().get(&ino)?.upgrade()
+    }
+
+    /// 获取所有存活的文件
+    pub fn values(&self) -> Vec<Arc<T>> {
+        self.inodes
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+
+    /// 从打开的 [File] 中获取登记的文件
+    ///
+    /// 如果文件不属于这个注册表，返回 [Option::None]
+    pub fn from_file(&self, file: &File) -> Option<Arc<T>> {
+        let mut stat = Stat::default();
+        file.stat(&mut stat).ok()?;
+        if stat.dev as u64 != ANON_DEV {
+            return None;
+        }
+        self.get(stat.ino as u64)
+    }
+}
+
+impl<T> Default for InodeRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,145 @@
+//! signalfd 文件
+//!
+//! signalfd 从创建它的任务的待处理信号中读取属于监听集合的信号，
+//! 读取后信号不会再被投递给信号处理函数。
+use alloc::sync::Arc;
+use libc_core::{
+    poll::PollEvent,
+    types::{SigSet, Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes};
+
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+use crate::child_test::TASK_MAP;
+
+/// 所有存活的 signalfd
+static SIGNALFD_INODES: InodeRegistry<SignalFd> = InodeRegistry::new();
+
+/// `struct signalfd_siginfo`，大小固定为 128 字节
+#[repr(C)]
+#[derive(Debug, Clone, FromBytes, IntoBytes, Immutable)]
+pub struct SignalFdSigInfo {
+    /// 信号编号
+    pub signo: u32,
+    /// 错误码
+    pub errno: i32,
+    /// 信号来源
+    pub code: i32,
+    /// 发送信号的进程 id
+    pub pid: u32,
+    /// 发送信号的用户 id
+    pub uid: u32,
+    _pad: [u8; 108],
+}
+
+/// signalfd 文件
+pub struct SignalFd {
+    /// 创建 signalfd 的任务 id
+    tid: usize,
+    /// 监听的信号集合
+    mask: Mutex<SigSet>,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+impl SignalFd {
+    /// 创建一个新的 signalfd
+    ///
+    /// ## 参数
+    /// - `tid`  读取哪个任务的待处理信号
+    /// - `mask` 监听的信号集合
+    pub fn new(tid: usize, mask: SigSet) -> Arc<Self> {
+        let ino = alloc_ino();
+        let signalfd = Arc::new(Self {
+            tid,
+            mask: Mutex::new(mask),
+            wait: WaitQueue::new(ino),
+        });
+        SIGNALFD_INODES.insert(ino, &signalfd);
+        signalfd
+    }
+
+    /// 从打开的 [fs::file::File] 中获取 [SignalFd]
+    ///
+    /// 如果文件不是 signalfd，返回 [Option::None]
+    pub fn from_file(file: &fs::file::File) -> Option<Arc<Self>> {
+        SIGNALFD_INODES.from_file(file)
+    }
+
+    /// 修改监听的信号集合
+    pub fn set_mask(&self, mask: SigSet) {
+        *self.mask.lock() = mask;
+        self.wait.wake_all();
+    }
+
+    /// 任务收到新的信号，唤醒这个任务的 signalfd 上的等待者
+    pub fn notify(tid: usize) {
+        SIGNALFD_INODES
+            .values()
+            .iter()
+            .filter(|x| x.tid == tid)
+            .for_each(|x| x.wait.wake_all());
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        SIGNALFD_INODES.remove(self.wait.ino());
+    }
+}
+
+impl INodeInterface for SignalFd {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let info_size = size_of::<SignalFdSigInfo>();
+        if buffer.len() < info_size {
+            return Err(Errno::EINVAL);
+        }
+        let task = TASK_MAP
+            .lock()
+            .get(&(self.tid as _))
+            .cloned()
+            .ok_or(Errno::EAGAIN)?;
+        let mask = *self.mask.lock();
+        let mut rlen = 0;
+        while buffer.len() - rlen >= info_size {
+            let Some(signal) = task.take_signal_in(mask) else {
+                break;
+            };
+            let mut info = SignalFdSigInfo::new_zeroed();
+            info.signo = signal.num() as _;
+            buffer[rlen..rlen + info_size].copy_from_slice(info.as_bytes());
+            rlen += info_size;
+        }
+        match rlen {
+            0 => Err(Errno::EAGAIN),
+            _ => Ok(rlen),
+        }
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN)
+            && TASK_MAP
+                .lock()
+                .get(&(self.tid as _))
+                .is_some_and(|task| task.has_signal_in(*self.mask.lock()))
+        {
+            res |= PollEvent::IN;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
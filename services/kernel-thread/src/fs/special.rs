@@ -0,0 +1,216 @@
+//! `mknod` 创建的特殊文件
+//!
+//! 底层文件系统（ext4、tmpfs）的接口无法设置文件类型，`mknod` 会在底层文件系统中创建一个
+//! 空的普通文件占位，文件类型和设备号登记在 [SPECIAL_NODES] 中，使用占位文件的
+//! (设备号, inode 编号) 作为键。只有登记过的占位文件会被当作特殊文件，用户写入的文件内容
+//! 不能伪造特殊文件，重命名时登记跟随到新的文件。登记只在运行时存在，重新启动之后占位文件
+//! 是普通的空文件。
+//!
+//! 命名管道共享的 [Pipe] 和绑定到路径上的 Unix socket 也保存在登记中。
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+};
+use fs::{file::File, pathbuf::PathBuf};
+use libc_core::{
+    fcntl::OpenFlags,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::INodeInterface;
+
+use super::{devfs::open_device, pipe::Pipe};
+use crate::net::unix::UnixSocket;
+
+/// 文件类型掩码
+pub const S_IFMT: u32 = 0o170000;
+/// 命名管道
+pub const S_IFIFO: u32 = 0o010000;
+/// 字符设备
+pub const S_IFCHR: u32 = 0o020000;
+/// 块设备
+pub const S_IFBLK: u32 = 0o060000;
+/// 普通文件
+pub const S_IFREG: u32 = 0o100000;
+/// socket
+pub const S_IFSOCK: u32 = 0o140000;
+
+/// 特殊文件运行时的状态
+enum SpecialState {
+    /// 命名管道的读写两端共享的管道
+    Fifo(Arc<Pipe>),
+    /// 绑定到路径上的 Unix socket，socket 关闭后路径仍然存在
+    Socket(Weak<UnixSocket>),
+}
+
+/// 登记的特殊文件
+struct SpecialEntry {
+    /// `mknod` 传入的文件类型和权限
+    mode: u32,
+    /// 设备号，只有设备文件使用
+    rdev: u64,
+    /// 运行时的状态，第一次使用时创建
+    state: Option<SpecialState>,
+}
+
+/// `mknod` 创建的特殊文件，键为占位文件的 (设备号, inode 编号)
+static SPECIAL_NODES: Mutex<BTreeMap<(u64, u64), SpecialEntry>> = Mutex::new(BTreeMap::new());
+
+/// 登记过的特殊文件
+#[derive(Debug, Clone, Copy)]
+pub struct SpecialNode {
+    /// 文件类型和权限
+    mode: u32,
+    /// 设备号
+    rdev: u64,
+    /// 占位文件的 (设备号, inode 编号)
+    key: (u64, u64),
+}
+
+impl SpecialNode {
+    /// 文件类型，例如 [S_IFIFO]
+    pub const fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    /// 绑定在这个文件上的 Unix socket
+    pub fn socket(&self) -> Option<Arc<UnixSocket>> {
+        match SPECIAL_NODES.lock().get(&self.key)?.state {
+            Some(SpecialState::Socket(ref socket)) => socket.upgrade(),
+            _ => None,
+        }
+    }
+}
+
+/// 占位文件的 (设备号, inode 编号)，只有普通文件可以作为占位文件
+fn placeholder_key(file: &File) -> Option<((u64, u64), Stat)> {
+    let mut stat = Stat::default();
+    file.stat(&mut stat).ok()?;
+    if stat.mode.bits() as u32 & S_IFMT != S_IFREG {
+        return None;
+    }
+    Some(((stat.dev as _, stat.ino as _), stat))
+}
+
+/// 把新创建的占位文件登记为特殊文件
+///
+/// ## 参数
+/// - `file` 新创建的占位文件，需要可写
+/// - `mode` `mknod` 传入的文件类型和权限
+/// - `rdev` 设备号，只有设备文件使用
+pub fn mknod(file: &File, mode: u32, rdev: u64) -> Result<(), Errno> {
+    if !matches!(mode & S_IFMT, S_IFIFO | S_IFCHR | S_IFBLK | S_IFSOCK) {
+        return Err(Errno::EINVAL);
+    }
+    file.truncate(0)?;
+    let (key, _) = placeholder_key(file).ok_or(Errno::EIO)?;
+    SPECIAL_NODES.lock().insert(
+        key,
+        SpecialEntry {
+            mode,
+            rdev,
+            state: None,
+        },
+    );
+    Ok(())
+}
+
+/// 把 Unix socket 绑定到新创建的占位文件上
+pub fn bind_socket(file: &File, socket: Weak<UnixSocket>) -> Result<(), Errno> {
+    mknod(file, S_IFSOCK | 0o755, 0)?;
+    let node = lookup(file).ok_or(Errno::EIO)?;
+    if let Some(entry) = SPECIAL_NODES.lock().get_mut(&node.key) {
+        entry.state = Some(SpecialState::Socket(socket));
+    }
+    Ok(())
+}
+
+/// 检查打开的文件是否是登记过的特殊文件的占位文件
+pub fn lookup(file: &File) -> Option<SpecialNode> {
+    // 没有特殊文件时不需要访问文件系统
+    if SPECIAL_NODES.lock().is_empty() {
+        return None;
+    }
+    let (key, _) = placeholder_key(file)?;
+    let nodes = SPECIAL_NODES.lock();
+    let entry = nodes.get(&key)?;
+    Some(SpecialNode {
+        mode: entry.mode,
+        rdev: entry.rdev,
+        key,
+    })
+}
+
+/// 根据路径检查文件是否是登记过的特殊文件，没有特殊文件时不会打开文件
+pub fn lookup_path(path: &PathBuf) -> Option<SpecialNode> {
+    if SPECIAL_NODES.lock().is_empty() {
+        return None;
+    }
+    File::open(path.clone(), OpenFlags::RDONLY)
+        .ok()
+        .and_then(|file| lookup(&file))
+}
+
+/// 删除占位文件之前调用，最后一个链接被删除时取消登记
+pub fn remove(file: &File) {
+    if let Some((key, stat)) = placeholder_key(file) {
+        if stat.nlink <= 1 {
+            SPECIAL_NODES.lock().remove(&key);
+        }
+    }
+}
+
+/// 占位文件被复制到新的 inode 之后 (例如 `rename`)，登记跟随到新的文件
+pub fn moved(old: &SpecialNode, new: &File) {
+    let Some((key, _)) = placeholder_key(new) else {
+        return;
+    };
+    let mut nodes = SPECIAL_NODES.lock();
+    if let Some(entry) = nodes.remove(&old.key) {
+        nodes.insert(key, entry);
+    }
+}
+
+/// 使用特殊文件的类型和设备号覆盖占位文件的 [Stat]
+pub fn fill_stat(file: &File, stat: &mut Stat) {
+    if let Some(node) = lookup(file) {
+        stat.mode = StatMode::from_bits_truncate(node.mode as _);
+        stat.size = 0;
+        stat.rdev = node.rdev as _;
+    }
+}
+
+/// 打开特殊文件
+///
+/// ## 参数
+/// - `node`  需要打开的特殊文件
+/// - `flags` 打开文件使用的标志
+/// - `tid`   打开文件的任务 id，命名管道等待对端时使用
+pub async fn open(
+    node: SpecialNode,
+    flags: OpenFlags,
+    tid: usize,
+) -> Result<Arc<dyn INodeInterface>, Errno> {
+    match node.file_type() {
+        S_IFIFO => {
+            let pipe = {
+                let mut nodes = SPECIAL_NODES.lock();
+                let entry = nodes.get_mut(&node.key).ok_or(Errno::ENOENT)?;
+                match &entry.state {
+                    Some(SpecialState::Fifo(pipe)) => pipe.clone(),
+                    _ => {
+                        let pipe = Pipe::new();
+                        entry.state = Some(SpecialState::Fifo(pipe.clone()));
+                        pipe
+                    }
+                }
+            };
+            pipe.open_fifo(flags, tid).await
+        }
+        S_IFCHR => open_device(StatMode::CHAR, node.rdev).ok_or(Errno::ENXIO),
+        S_IFBLK => open_device(StatMode::BLOCK, node.rdev).ok_or(Errno::ENXIO),
+        // socket 文件只能通过 connect 使用
+        _ => Err(Errno::ENXIO),
+    }
+}
@@ -0,0 +1,186 @@
+//! timerfd 文件
+//!
+//! 定时器到期由 [crate::timer] 中的时间队列驱动，每次到期时累加到期次数并唤醒等待的任务，
+//! 读取时返回到期次数并清零。
+use core::time::Duration;
+
+use alloc::sync::{Arc, Weak};
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode, TimeSpec},
+};
+use sel4_kit::arch::current_time;
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use super::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+use crate::timer::set_timerfd_timer;
+
+/// 所有存活的 timerfd
+static TIMERFD_INODES: InodeRegistry<TimerFd> = InodeRegistry::new();
+
+/// `timerfd_settime` 使用绝对时间
+pub const TFD_TIMER_ABSTIME: usize = 1;
+
+/// `struct itimerspec`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, FromBytes, IntoBytes, Immutable)]
+pub struct ITimerSpec {
+    /// 定时器周期，为 0 时只触发一次
+    pub interval: TimeSpec,
+    /// 距离下一次到期的时间，为 0 时表示关闭定时器
+    pub value: TimeSpec,
+}
+
+/// 定时器状态
+#[derive(Default)]
+struct TimerFdState {
+    /// 下一次到期的时间，[Duration::ZERO] 表示定时器未启动
+    deadline: Duration,
+    /// 定时器周期
+    interval: Duration,
+    /// 尚未读取的到期次数
+    expirations: u64,
+    /// 每次设置定时器时递增，用于忽略时间队列中过期的项
+    generation: u64,
+}
+
+/// timerfd 文件
+pub struct TimerFd {
+    /// 定时器状态
+    state: Mutex<TimerFdState>,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+    /// 自身的弱引用，设置定时器时加入时间队列
+    this: Weak<TimerFd>,
+}
+
+impl TimerFd {
+    /// 创建一个新的 timerfd，创建后定时器处于关闭状态
+    pub fn new() -> Arc<Self> {
+        let ino = alloc_ino();
+        let timer = Arc::new_cyclic(|this| Self {
+            state: Mutex::new(TimerFdState::default()),
+            wait: WaitQueue::new(ino),
+            this: this.clone(),
+        });
+        TIMERFD_INODES.insert(ino, &timer);
+        timer
+    }
+
+    /// 从打开的 [fs::file::File] 中获取 [TimerFd]
+    ///
+    /// 如果文件不是 timerfd，返回 [Option::None]
+    pub fn from_file(file: &fs::file::File) -> Option<Arc<Self>> {
+        TIMERFD_INODES.from_file(file)
+    }
+
+    /// 获取定时器当前的设置
+    pub fn get(&self) -> ITimerSpec {
+        let state = self.state.lock();
+        let remain = match state.deadline.is_zero() {
+            true => Duration::ZERO,
+            // 剩余时间不能为 0，否则会被当作定时器已经关闭
+            false => state
+                .deadline
+                .saturating_sub(current_time())
+                .max(Duration::from_nanos(1)),
+        };
+        ITimerSpec {
+            interval: state.interval.into(),
+            value: remain.into(),
+        }
+    }
+
+    /// 设置定时器，返回之前的设置
+    ///
+    /// ## 参数
+    /// - `new`     新的定时器设置
+    /// - `abstime` `new.value` 是否为绝对时间
+    pub fn set(&self, new: ITimerSpec, abstime: bool) -> ITimerSpec {
+        let old = self.get();
+        let value: Duration = new.value.into();
+        let mut state = self.state.lock();
+        state.generation += 1;
+        state.interval = new.interval.into();
+        state.expirations = 0;
+        state.deadline = match value.is_zero() {
+            true => Duration::ZERO,
+            false if abstime => value.max(Duration::from_nanos(1)),
+            false => current_time() + value,
+        };
+        let (deadline, generation) = (state.deadline, state.generation);
+        drop(state);
+        if !deadline.is_zero() {
+            set_timerfd_timer(deadline, self.this.clone(), generation);
+        }
+        old
+    }
+
+    /// 定时器到期，由时间队列调用
+    ///
+    /// ## 参数
+    /// - `generation` 加入时间队列时的设置编号，和当前不一致时说明定时器已经被重新设置
+    /// - `curr_time`  当前时间
+    pub fn expire(&self, generation: u64, curr_time: Duration) {
+        let mut state = self.state.lock();
+        if state.generation != generation || state.deadline.is_zero() {
+            return;
+        }
+        if state.interval.is_zero() {
+            state.expirations += 1;
+            state.deadline = Duration::ZERO;
+        } else {
+            // 处理中断延迟导致错过的周期
+            let late = curr_time.saturating_sub(state.deadline).as_nanos();
+            let missed = (late / state.interval.as_nanos()) as u64;
+            state.expirations += missed + 1;
+            state.deadline +=
+                Duration::from_nanos((state.interval.as_nanos() * (missed as u128 + 1)) as u64);
+            set_timerfd_timer(state.deadline, self.this.clone(), generation);
+        }
+        drop(state);
+        self.wait.wake_all();
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        TIMERFD_INODES.remove(self.wait.ino());
+    }
+}
+
+impl INodeInterface for TimerFd {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(Errno::EINVAL);
+        }
+        let expirations = core::mem::take(&mut self.state.lock().expirations);
+        if expirations == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        buffer[..size_of::<u64>()].copy_from_slice(&expirations.to_ne_bytes());
+        Ok(size_of::<u64>())
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && self.state.lock().expirations > 0 {
+            res |= PollEvent::IN;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::FILE;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
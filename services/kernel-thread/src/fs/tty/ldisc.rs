@@ -0,0 +1,537 @@
+//! tty 行规程
+//!
+//! 行规程位于终端设备和读写终端的进程之间，负责处理 termios 中描述的输入输出转换。
+//! 规范模式 (`ICANON`) 下输入按行缓冲，并处理删除、删除单词、删除整行等编辑字符，
+//! 非规范模式下输入直接交给读者。`ISIG` 打开时中断、退出和挂起字符会转换为信号。
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+use bitflags::bitflags;
+use libc_core::signal::SignalNum;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+/// 控制字符的数量，和 Linux 内核中的 `struct termios` 一致
+pub const NCCS: usize = 19;
+
+/// 中断字符，默认为 `^C`
+pub const VINTR: usize = 0;
+/// 退出字符，默认为 `^\`
+pub const VQUIT: usize = 1;
+/// 删除字符，默认为 `DEL`
+pub const VERASE: usize = 2;
+/// 删除整行，默认为 `^U`
+pub const VKILL: usize = 3;
+/// 文件结束，默认为 `^D`
+pub const VEOF: usize = 4;
+/// 非规范模式下最少读取的字符数量
+pub const VMIN: usize = 6;
+/// 恢复输出，默认为 `^Q`
+pub const VSTART: usize = 8;
+/// 暂停输出，默认为 `^S`
+pub const VSTOP: usize = 9;
+/// 挂起字符，默认为 `^Z`
+pub const VSUSP: usize = 10;
+/// 额外的行结束字符
+pub const VEOL: usize = 11;
+/// 重新显示当前行，默认为 `^R`
+pub const VREPRINT: usize = 12;
+/// 删除单词，默认为 `^W`
+pub const VWERASE: usize = 14;
+/// 下一个字符按字面输入，默认为 `^V`
+pub const VLNEXT: usize = 15;
+/// 第二个额外的行结束字符
+pub const VEOL2: usize = 16;
+
+/// 规范模式下一行的最大长度，保留一个字节给换行符
+const MAX_CANON: usize = 4095;
+
+/// 输入缓冲区最多保存的字节数量，读者没有及时读取时丢弃之后的输入
+const MAX_INPUT: usize = 4096;
+
+bitflags! {
+    /// 输入模式 `c_iflag`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InputFlags: u32 {
+        /// 忽略 BREAK
+        const IGNBRK = 0o1;
+        /// BREAK 产生 SIGINT
+        const BRKINT = 0o2;
+        /// 忽略奇偶校验错误
+        const IGNPAR = 0o4;
+        /// 标记奇偶校验错误
+        const PARMRK = 0o10;
+        /// 开启奇偶校验
+        const INPCK = 0o20;
+        /// 去掉第 8 位
+        const ISTRIP = 0o40;
+        /// 将 NL 转换为 CR
+        const INLCR = 0o100;
+        /// 忽略 CR
+        const IGNCR = 0o200;
+        /// 将 CR 转换为 NL
+        const ICRNL = 0o400;
+        /// 将大写字母转换为小写
+        const IUCLC = 0o1000;
+        /// 开启输出流控
+        const IXON = 0o2000;
+        /// 任意字符恢复输出
+        const IXANY = 0o4000;
+        /// 开启输入流控
+        const IXOFF = 0o10000;
+        /// 输入缓冲区满时响铃
+        const IMAXBEL = 0o20000;
+        /// 输入为 UTF-8 编码
+        const IUTF8 = 0o40000;
+    }
+
+    /// 输出模式 `c_oflag`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OutputFlags: u32 {
+        /// 开启输出处理
+        const OPOST = 0o1;
+        /// 将小写字母转换为大写
+        const OLCUC = 0o2;
+        /// 将 NL 转换为 CR NL
+        const ONLCR = 0o4;
+        /// 将 CR 转换为 NL
+        const OCRNL = 0o10;
+        /// 第 0 列不输出 CR
+        const ONOCR = 0o20;
+        /// NL 同时完成 CR 的功能
+        const ONLRET = 0o40;
+    }
+
+    /// 本地模式 `c_lflag`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LocalFlags: u32 {
+        /// 中断、退出、挂起字符产生信号
+        const ISIG = 0o1;
+        /// 规范模式
+        const ICANON = 0o2;
+        /// 大小写转换
+        const XCASE = 0o4;
+        /// 回显输入
+        const ECHO = 0o10;
+        /// 删除字符擦除前一个字符
+        const ECHOE = 0o20;
+        /// 删除整行后输出换行
+        const ECHOK = 0o40;
+        /// 即使没有 ECHO 也回显换行
+        const ECHONL = 0o100;
+        /// 产生信号后不清空输入
+        const NOFLSH = 0o200;
+        /// 后台进程写终端时发送 SIGTTOU
+        const TOSTOP = 0o400;
+        /// 控制字符回显为 `^X`
+        const ECHOCTL = 0o1000;
+        /// 删除字符打印被删除的字符
+        const ECHOPRT = 0o2000;
+        /// 删除整行时逐个擦除字符
+        const ECHOKE = 0o4000;
+        /// 丢弃输出
+        const FLUSHO = 0o10000;
+        /// 重新显示未读取的输入
+        const PENDIN = 0o40000;
+        /// 开启扩展的输入处理
+        const IEXTEN = 0o100000;
+        /// 外部处理
+        const EXTPROC = 0o200000;
+    }
+}
+
+/// Linux 内核中的 `struct termios`，`TCGETS` 和 `TCSETS` 使用
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct Termios {
+    /// 输入模式
+    pub iflag: u32,
+    /// 输出模式
+    pub oflag: u32,
+    /// 控制模式
+    pub cflag: u32,
+    /// 本地模式
+    pub lflag: u32,
+    /// 行规程编号
+    pub line: u8,
+    /// 控制字符
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// 输入模式
+    pub const fn iflag(&self) -> InputFlags {
+        InputFlags::from_bits_truncate(self.iflag)
+    }
+
+    /// 输出模式
+    pub const fn oflag(&self) -> OutputFlags {
+        OutputFlags::from_bits_truncate(self.oflag)
+    }
+
+    /// 本地模式
+    pub const fn lflag(&self) -> LocalFlags {
+        LocalFlags::from_bits_truncate(self.lflag)
+    }
+}
+
+impl Default for Termios {
+    /// Linux 终端的默认设置，相当于 `stty sane`
+    fn default() -> Self {
+        let mut cc = [0u8; NCCS];
+        cc[..17].copy_from_slice(
+            b"\x03\x1c\x7f\x15\x04\x00\x01\x00\x11\x13\x1a\x00\x12\x0f\x17\x16\x00",
+        );
+        Self {
+            iflag: (InputFlags::ICRNL | InputFlags::IXON).bits(),
+            oflag: (OutputFlags::OPOST | OutputFlags::ONLCR).bits(),
+            // B38400 | CS8 | CREAD | HUPCL
+            cflag: 0o2277,
+            lflag: (LocalFlags::ISIG
+                | LocalFlags::ICANON
+                | LocalFlags::ECHO
+                | LocalFlags::ECHOE
+                | LocalFlags::ECHOK
+                | LocalFlags::ECHOCTL
+                | LocalFlags::ECHOKE
+                | LocalFlags::IEXTEN)
+                .bits(),
+            line: 0,
+            cc,
+        }
+    }
+}
+
+/// 终端窗口大小 `struct winsize`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct WinSize {
+    /// 行数
+    pub row: u16,
+    /// 列数
+    pub col: u16,
+    /// 宽度（像素）
+    pub xpixel: u16,
+    /// 高度（像素）
+    pub ypixel: u16,
+}
+
+impl Default for WinSize {
+    fn default() -> Self {
+        Self {
+            row: 24,
+            col: 80,
+            xpixel: 0,
+            ypixel: 0,
+        }
+    }
+}
+
+/// 行规程
+pub struct LineDiscipline {
+    /// 终端设置
+    termios: Termios,
+    /// 规范模式下正在编辑的行
+    line: Vec<u8>,
+    /// 规范模式下已经完成的行，空行表示文件结束
+    lines: VecDeque<Vec<u8>>,
+    /// 非规范模式下可以读取的数据
+    raw: VecDeque<u8>,
+    /// 下一个字符按字面输入 (`VLNEXT`)
+    lnext: bool,
+}
+
+impl LineDiscipline {
+    /// 创建一个使用默认设置的行规程
+    pub fn new() -> Self {
+        Self {
+            termios: Termios::default(),
+            line: Vec::new(),
+            lines: VecDeque::new(),
+            raw: VecDeque::new(),
+            lnext: false,
+        }
+    }
+
+    /// 当前的终端设置
+    pub const fn termios(&self) -> &Termios {
+        &self.termios
+    }
+
+    /// 修改终端设置
+    ///
+    /// 在规范模式和非规范模式之间切换时，已经输入的数据会移动到新的缓冲区中
+    pub fn set_termios(&mut self, termios: Termios) {
+        let canonical = termios.lflag().contains(LocalFlags::ICANON);
+        if canonical != self.canonical() {
+            if canonical {
+                // 已经输入的数据作为当前行继续编辑
+                self.line = self.raw.drain(..).collect();
+            } else {
+                let lines = core::mem::take(&mut self.lines);
+                self.raw.extend(lines.into_iter().flatten());
+                self.raw.extend(self.line.drain(..));
+            }
+        }
+        self.termios = termios;
+    }
+
+    /// 是否为规范模式
+    pub fn canonical(&self) -> bool {
+        self.termios.lflag().contains(LocalFlags::ICANON)
+    }
+
+    /// 非规范模式下 `VMIN` 为 0，没有数据时读取直接返回
+    pub fn nonblocking_raw(&self) -> bool {
+        !self.canonical() && self.termios.cc[VMIN] == 0
+    }
+
+    /// 是否有数据可以读取
+    pub fn readable(&self) -> bool {
+        match self.canonical() {
+            true => !self.lines.is_empty(),
+            false => !self.raw.is_empty(),
+        }
+    }
+
+    /// 可以读取的字节数量，用于 `FIONREAD`
+    pub fn available(&self) -> usize {
+        match self.canonical() {
+            true => self.lines.iter().map(Vec::len).sum(),
+            false => self.raw.len(),
+        }
+    }
+
+    /// 清空所有输入
+    pub fn flush_input(&mut self) {
+        self.line.clear();
+        self.lines.clear();
+        self.raw.clear();
+        self.lnext = false;
+    }
+
+    /// 读取数据
+    ///
+    /// 规范模式下一次最多读取一行，没有数据可以读取时返回 [Option::None]
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if !self.canonical() {
+            if self.raw.is_empty() {
+                return None;
+            }
+            let rlen = buffer.len().min(self.raw.len());
+            self.raw
+                .drain(..rlen)
+                .zip(buffer.iter_mut())
+                .for_each(|(c, x)| *x = c);
+            return Some(rlen);
+        }
+        let mut line = self.lines.pop_front()?;
+        let rlen = buffer.len().min(line.len());
+        buffer[..rlen].copy_from_slice(&line[..rlen]);
+        // 没有读取完的部分留到下次读取
+        if rlen < line.len() {
+            line.drain(..rlen);
+            self.lines.push_front(line);
+        }
+        Some(rlen)
+    }
+
+    /// 判断字符是否为某个控制字符，值为 0 的控制字符表示被禁用
+    fn is_cc(&self, c: u8, idx: usize) -> bool {
+        let cc = self.termios.cc[idx];
+        cc != 0 && cc == c
+    }
+
+    /// 回显一个字符
+    fn echo_char(&self, c: u8, echo: &mut Vec<u8>) {
+        let lflag = self.termios.lflag();
+        let is_ctrl = (c < 0x20 && c != b'\t' && c != b'\n') || c == 0x7f;
+        if is_ctrl && lflag.contains(LocalFlags::ECHOCTL) {
+            echo.extend_from_slice(&[b'^', c ^ 0x40]);
+        } else {
+            echo.push(c);
+        }
+    }
+
+    /// 回显字符所占的列数
+    fn echo_width(&self, c: u8) -> usize {
+        let is_ctrl = (c < 0x20 && c != b'\t') || c == 0x7f;
+        match is_ctrl && self.termios.lflag().contains(LocalFlags::ECHOCTL) {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    /// 删除当前行的最后一个字符，返回是否删除成功
+    fn erase_char(&mut self, echo: &mut Vec<u8>) -> bool {
+        let Some(mut c) = self.line.pop() else {
+            return false;
+        };
+        // UTF-8 编码的字符需要一起删除
+        if self.termios.iflag().contains(InputFlags::IUTF8) {
+            while c & 0xc0 == 0x80 {
+                match self.line.pop() {
+                    Some(prev) => c = prev,
+                    None => break,
+                }
+            }
+        }
+        let lflag = self.termios.lflag();
+        if lflag.contains(LocalFlags::ECHO | LocalFlags::ECHOE) {
+            (0..self.echo_width(c)).for_each(|_| echo.extend_from_slice(b"\x08 \x08"));
+        }
+        true
+    }
+
+    /// 处理从终端设备输入的一个字符
+    ///
+    /// ## 参数
+    /// - `c`    输入的字符
+    /// - `echo` 需要回显到终端的数据，由调用者进行输出处理后写入终端
+    ///
+    /// 字符需要产生信号时返回对应的信号，由调用者发送给前台进程组
+    pub fn receive(&mut self, c: u8, echo: &mut Vec<u8>) -> Option<SignalNum> {
+        let iflag = self.termios.iflag();
+        let lflag = self.termios.lflag();
+        let mut c = c;
+        if iflag.contains(InputFlags::ISTRIP) {
+            c &= 0x7f;
+        }
+        if self.lnext {
+            self.lnext = false;
+            self.push(c, echo);
+            return None;
+        }
+        if c == b'\r' {
+            if iflag.contains(InputFlags::IGNCR) {
+                return None;
+            }
+            if iflag.contains(InputFlags::ICRNL) {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag.contains(InputFlags::INLCR) {
+            c = b'\r';
+        }
+
+        if lflag.contains(LocalFlags::IEXTEN) && self.is_cc(c, VLNEXT) {
+            self.lnext = true;
+            if lflag.contains(LocalFlags::ECHO | LocalFlags::ECHOCTL) {
+                echo.extend_from_slice(b"^\x08");
+            }
+            return None;
+        }
+
+        if lflag.contains(LocalFlags::ISIG) {
+            let signal = if self.is_cc(c, VINTR) {
+                Some(SignalNum::INT)
+            } else if self.is_cc(c, VQUIT) {
+                Some(SignalNum::QUIT)
+            } else if self.is_cc(c, VSUSP) {
+                Some(SignalNum::TSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !lflag.contains(LocalFlags::NOFLSH) {
+                    self.flush_input();
+                }
+                if lflag.contains(LocalFlags::ECHO) {
+                    self.echo_char(c, echo);
+                }
+                return Some(signal);
+            }
+        }
+
+        // 没有实现输出暂停，流控字符直接丢弃
+        if iflag.contains(InputFlags::IXON) && (self.is_cc(c, VSTART) || self.is_cc(c, VSTOP)) {
+            return None;
+        }
+
+        if !self.canonical() {
+            self.push(c, echo);
+            return None;
+        }
+
+        if self.is_cc(c, VERASE) {
+            self.erase_char(echo);
+        } else if lflag.contains(LocalFlags::IEXTEN) && self.is_cc(c, VWERASE) {
+            while self.line.last().is_some_and(u8::is_ascii_whitespace) {
+                self.erase_char(echo);
+            }
+            while self.line.last().is_some_and(|x| !x.is_ascii_whitespace()) {
+                self.erase_char(echo);
+            }
+        } else if self.is_cc(c, VKILL) {
+            if lflag.contains(LocalFlags::ECHOKE | LocalFlags::ECHOE) {
+                while self.erase_char(echo) {}
+            } else {
+                self.line.clear();
+                if lflag.contains(LocalFlags::ECHO) {
+                    self.echo_char(c, echo);
+                }
+                if lflag.contains(LocalFlags::ECHOK) {
+                    echo.push(b'\n');
+                }
+            }
+        } else if lflag.contains(LocalFlags::IEXTEN) && self.is_cc(c, VREPRINT) {
+            if lflag.contains(LocalFlags::ECHO) {
+                self.echo_char(c, echo);
+                echo.push(b'\n');
+                self.line.iter().for_each(|x| self.echo_char(*x, echo));
+            }
+        } else if self.is_cc(c, VEOF) {
+            // 文件结束：提交当前行，但不包含结束字符
+            self.lines.push_back(core::mem::take(&mut self.line));
+        } else {
+            self.push(c, echo);
+        }
+        None
+    }
+
+    /// 将字符加入缓冲区，规范模式下遇到行结束字符时提交当前行
+    ///
+    /// 缓冲区中的数据达到 [MAX_INPUT] 时丢弃字符
+    fn push(&mut self, c: u8, echo: &mut Vec<u8>) {
+        let lflag = self.termios.lflag();
+        if self.available() + self.line.len() >= MAX_INPUT {
+            return;
+        }
+        if !self.canonical() {
+            self.raw.push_back(c);
+            if lflag.contains(LocalFlags::ECHO) {
+                self.echo_char(c, echo);
+            }
+            return;
+        }
+        let eol = c == b'\n' || self.is_cc(c, VEOL) || self.is_cc(c, VEOL2);
+        if !eol && self.line.len() >= MAX_CANON {
+            return;
+        }
+        if lflag.contains(LocalFlags::ECHO) || (c == b'\n' && lflag.contains(LocalFlags::ECHONL)) {
+            self.echo_char(c, echo);
+        }
+        self.line.push(c);
+        if eol {
+            self.lines.push_back(core::mem::take(&mut self.line));
+        }
+    }
+
+    /// 对输出数据进行处理 (`OPOST`)
+    pub fn output(&self, data: &[u8], out: &mut Vec<u8>) {
+        let oflag = self.termios.oflag();
+        if !oflag.contains(OutputFlags::OPOST) {
+            out.extend_from_slice(data);
+            return;
+        }
+        for &c in data {
+            match c {
+                b'\n' if oflag.contains(OutputFlags::ONLCR) => out.extend_from_slice(b"\r\n"),
+                b'\r' if oflag.contains(OutputFlags::OCRNL) => out.push(b'\n'),
+                _ => out.push(c),
+            }
+        }
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,333 @@
+//! 终端设备
+//!
+//! [Tty] 由终端驱动 [TtyDriver] 和行规程 [LineDiscipline] 组成，驱动负责和真正的设备
+//! （串口、伪终端等）交换数据，行规程负责 termios 描述的输入输出处理。
+//! 终端同时记录窗口大小、所属会话和前台进程组，中断字符产生的信号发送给前台进程组。
+//! 后台进程组读取控制终端时收到 `SIGTTIN`，`TOSTOP` 打开时写入控制终端收到 `SIGTTOU`。
+pub mod ldisc;
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use ldisc::{LineDiscipline, LocalFlags, Termios, WinSize};
+use libc_core::{
+    poll::PollEvent,
+    signal::SignalNum,
+    types::{Stat, StatMode},
+};
+use spin::{Lazy, Mutex};
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+
+use super::{
+    devfs::makedev,
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+use crate::{
+    device::uart,
+    task::{Sel4Task, kill_pgrp},
+};
+
+/// 所有存活的终端
+static TTY_INODES: InodeRegistry<Tty> = InodeRegistry::new();
+
+/// 系统控制台，`/dev/ttyv0`、`/dev/stdin` 等都指向这个终端
+pub static CONSOLE: Lazy<Arc<Tty>> = Lazy::new(|| Tty::new(ConsoleDriver, makedev(5, 1)));
+
+/// 终端驱动
+///
+/// 设备收到输入后由驱动调用 [Tty::receive]
+pub trait TtyDriver: Send + Sync {
+    /// 将经过行规程处理的数据输出到设备，返回设备接受的字节数量
+    fn write(&self, data: &[u8]) -> usize;
+
+    /// 设备还能接受的字节数量，为 0 时写入终端返回 `EAGAIN`
+    ///
+    /// 设备有空间之后驱动需要调用 [Tty::write_wakeup] 唤醒等待写入的任务
+    fn write_room(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// 控制台驱动，通过串口服务的发送环输出
+///
+/// 串口的输入由 [uart::handle_input] 交给控制台
+struct ConsoleDriver;
+
+impl TtyDriver for ConsoleDriver {
+    fn write(&self, data: &[u8]) -> usize {
+        uart::write(data)
+    }
+
+    fn write_room(&self) -> usize {
+        uart::write_room()
+    }
+}
+
+/// 终端设备
+pub struct Tty {
+    /// 终端驱动
+    driver: Box<dyn TtyDriver>,
+    /// 设备号
+    rdev: u64,
+    /// 行规程
+    ldisc: Mutex<LineDiscipline>,
+    /// 窗口大小
+    winsize: Mutex<WinSize>,
+    /// 前台进程组，0 表示没有前台进程组
+    foreground: AtomicUsize,
+    /// 以这个终端为控制终端的会话，0 表示不属于任何会话
+    session: AtomicUsize,
+    /// 终端已经挂断，例如伪终端的主设备已经关闭
+    hangup: AtomicBool,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+impl Tty {
+    /// 创建一个新的终端
+    ///
+    /// ## 参数
+    /// - `driver` 终端驱动
+    /// - `rdev`   终端的设备号，`stat` 时返回
+    pub fn new(driver: impl TtyDriver + 'static, rdev: u64) -> Arc<Self> {
+        let ino = alloc_ino();
+        let tty = Arc::new(Self {
+            driver: Box::new(driver),
+            rdev,
+            ldisc: Mutex::new(LineDiscipline::new()),
+            winsize: Mutex::new(WinSize::default()),
+            foreground: AtomicUsize::new(0),
+            session: AtomicUsize::new(0),
+            hangup: AtomicBool::new(false),
+            wait: WaitQueue::new(ino),
+        });
+        TTY_INODES.insert(ino, &tty);
+        tty
+    }
+
+    /// 从打开的 [fs::file::File] 中获取 [Tty]
+    ///
+    /// 如果文件不是终端，返回 [Option::None]
+    pub fn from_file(file: &fs::file::File) -> Option<Arc<Self>> {
+        TTY_INODES.from_file(file)
+    }
+
+    /// 获取终端设置
+    pub fn termios(&self) -> Termios {
+        *self.ldisc.lock().termios()
+    }
+
+    /// 修改终端设置
+    pub fn set_termios(&self, termios: Termios) {
+        self.ldisc.lock().set_termios(termios);
+        self.wait.wake_all();
+    }
+
+    /// 清空还没有读取的输入
+    pub fn flush_input(&self) {
+        self.ldisc.lock().flush_input();
+    }
+
+    /// 可以读取的字节数量
+    pub fn available(&self) -> usize {
+        self.ldisc.lock().available()
+    }
+
+    /// 获取窗口大小
+    pub fn winsize(&self) -> WinSize {
+        *self.winsize.lock()
+    }
+
+    /// 修改窗口大小，窗口大小发生变化时向前台进程组发送 `SIGWINCH`
+    pub fn set_winsize(&self, winsize: WinSize) {
+        let old = core::mem::replace(&mut *self.winsize.lock(), winsize);
+        if old != winsize {
+            self.send_signal(SignalNum::WINCH);
+        }
+    }
+
+    /// 前台进程组
+    pub fn foreground(&self) -> usize {
+        self.foreground.load(Ordering::SeqCst)
+    }
+
+    /// 设置前台进程组
+    pub fn set_foreground(&self, pgid: usize) {
+        self.foreground.store(pgid, Ordering::SeqCst);
+    }
+
+    /// 以这个终端为控制终端的会话
+    pub fn session(&self) -> usize {
+        self.session.load(Ordering::SeqCst)
+    }
+
+    /// 设置终端所属的会话，`sid` 为 0 时表示和会话断开
+    pub fn set_session(&self, sid: usize) {
+        self.session.store(sid, Ordering::SeqCst);
+        if sid == 0 {
+            self.set_foreground(0);
+        }
+    }
+
+    /// 挂断终端，向前台进程组发送 `SIGHUP`
+    ///
+    /// 挂断之后读取终端返回文件结束，写入终端返回 `EIO`
+    pub fn hangup(&self) {
+        if self.hangup.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.send_signal(SignalNum::HUP);
+        self.wait.wake_all();
+    }
+
+    /// 终端是否已经挂断
+    pub fn is_hangup(&self) -> bool {
+        self.hangup.load(Ordering::SeqCst)
+    }
+
+    /// 设备有空间继续写入，唤醒等待写入的任务
+    pub fn write_wakeup(&self) {
+        self.wait.wake_all();
+    }
+
+    /// 向前台进程组发送信号
+    fn send_signal(&self, signal: SignalNum) {
+        let pgid = self.foreground();
+        if pgid != 0 {
+            kill_pgrp(pgid, signal, 0);
+        }
+    }
+
+    /// 检查后台进程组能否读写这个控制终端
+    ///
+    /// 后台进程组读取时向进程组发送 `SIGTTIN`，`TOSTOP` 打开时写入向进程组发送 `SIGTTOU`，
+    /// 发送信号之后返回 `EINTR`，进程组在系统调用返回时停止。忽略或者屏蔽了 `SIGTTIN`
+    /// 的进程读取返回 `EIO`，忽略或者屏蔽了 `SIGTTOU` 的进程可以直接写入
+    ///
+    /// ## 参数
+    /// - `task`  读写终端的任务
+    /// - `write` 是否为写入
+    pub fn check_background(&self, task: &Sel4Task, write: bool) -> Result<(), Errno> {
+        let pgid = task.pcb.pgid();
+        let foreground = self.foreground();
+        if self.session() != task.pcb.sid() || foreground == 0 || foreground == pgid {
+            return Ok(());
+        }
+        let signal = match write {
+            true if !self.termios().lflag().contains(LocalFlags::TOSTOP) => return Ok(()),
+            true => SignalNum::TTOU,
+            false => SignalNum::TTIN,
+        };
+        if task.signal_blocked_or_ignored(signal) {
+            return match write {
+                true => Ok(()),
+                false => Err(Errno::EIO),
+            };
+        }
+        kill_pgrp(pgid, signal, task.tid);
+        Err(Errno::EINTR)
+    }
+
+    /// 终端设备收到一个输入字符
+    ///
+    /// 输入经过行规程处理后回显到设备，如果产生了信号返回 `true`
+    pub fn receive(&self, c: u8) -> bool {
+        let mut echo = Vec::new();
+        let mut out = Vec::new();
+        let mut ldisc = self.ldisc.lock();
+        let signal = ldisc.receive(c, &mut echo);
+        ldisc.output(&echo, &mut out);
+        let readable = ldisc.readable();
+        drop(ldisc);
+
+        // 设备没有空间时丢弃回显
+        if !out.is_empty() {
+            self.driver.write(&out);
+        }
+        if readable {
+            self.wait.wake_all();
+        }
+        match signal {
+            Some(signal) => {
+                self.send_signal(signal);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        TTY_INODES.remove(self.wait.ino());
+    }
+}
+
+impl INodeInterface for Tty {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        let mut ldisc = self.ldisc.lock();
+        if let Some(rlen) = ldisc.read(buffer) {
+            return Ok(rlen);
+        }
+        if self.is_hangup() || ldisc.nonblocking_raw() {
+            return Ok(0);
+        }
+        // 没有输入时等待驱动调用 [Tty::receive] 唤醒等待队列
+        Err(Errno::EAGAIN)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        if self.is_hangup() {
+            return Err(Errno::EIO);
+        }
+        let room = self.driver.write_room();
+        if room == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        // 只写入处理之后能够放入设备的部分
+        let mut out = Vec::with_capacity(buffer.len().min(room));
+        let ldisc = self.ldisc.lock();
+        let mut wlen = 0;
+        for c in buffer {
+            let len = out.len();
+            ldisc.output(core::slice::from_ref(c), &mut out);
+            if out.len() > room {
+                out.truncate(len);
+                break;
+            }
+            wlen += 1;
+        }
+        drop(ldisc);
+        self.driver.write(&out);
+        Ok(wlen)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && self.ldisc.lock().readable() {
+            res |= PollEvent::IN;
+        }
+        if self.is_hangup() {
+            return Ok(res | PollEvent::IN | PollEvent::HUP);
+        }
+        if events.contains(PollEvent::OUT) && self.driver.write_room() > 0 {
+            res |= PollEvent::OUT;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::CHAR;
+        stat.nlink = 1;
+        stat.uid = 1000;
+        stat.gid = 1000;
+        stat.size = 0;
+        stat.blksize = 512;
+        stat.blocks = 0;
+        stat.rdev = self.rdev as _;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! 文件等待队列
+//!
+//! 文件的状态发生变化（可读、可写、挂断等）时通过 [WaitQueue] 唤醒等待的协程，
+//! 同时通知订阅了这个文件的 epoll，避免反复轮询所有的文件。
+use core::task::{Poll, Waker};
+
+use alloc::{sync::Arc, vec::Vec};
+use fs::file::File;
+use spin::Mutex;
+use syscalls::Errno;
+
+use super::registry::InodeRegistry;
+use crate::{child_test::TASK_MAP, task::PollWakeEvent};
+
+/// 所有存活的等待队列
+static WAIT_QUEUES: InodeRegistry<WaitQueue> = InodeRegistry::new();
+
+/// 文件等待队列
+///
+/// 等待队列和所属的文件使用相同的 inode 编号，拥有等待队列的文件需要在 `stat` 中
+/// 返回 [super::registry::ANON_DEV] 和这个编号，这样才能通过 [WaitQueue::from_file]
+/// 找到文件对应的等待队列。
+pub struct WaitQueue {
+    /// inode 编号
+    ino: u64,
+    /// 一次性的 [Waker]，唤醒后移除
+    wakers: Mutex<Vec<Waker>>,
+    /// 持续订阅的 [Waker] (订阅 id, Waker)，例如 epoll
+    subscribers: Mutex<Vec<(usize, Waker)>>,
+}
+
+impl WaitQueue {
+    /// 创建一个新的等待队列
+    ///
+    /// ## 参数
+    /// - `ino` 所属文件的 inode 编号，使用 [super::registry::alloc_ino] 分配
+    pub fn new(ino: u64) -> Arc<Self> {
+        let queue = Arc::new(Self {
+            ino,
+            wakers: Mutex::new(Vec::new()),
+            subscribers: Mutex::new(Vec::new()),
+        });
+        WAIT_QUEUES.insert(ino, &queue);
+        queue
+    }
+
+    /// 从打开的 [File] 中获取等待队列
+    ///
+    /// 如果文件不支持等待队列，返回 [Option::None]，这时只能通过轮询获取文件状态
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        WAIT_QUEUES.from_file(file)
+    }
+
+    /// 等待队列所属文件的 inode 编号
+    pub const fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// 添加一个一次性的 [Waker]，下次文件状态变化时唤醒
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|x| x.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// 订阅文件状态变化，每次文件状态变化时都会唤醒
+    ///
+    /// 返回订阅的 id，可以通过 [Self::unsubscribe] 取消订阅
+    pub fn subscribe(&self, waker: Waker) -> usize {
+        let mut subscribers = self.subscribers.lock();
+        let id = subscribers.last().map_or(0, |x| x.0 + 1);
+        subscribers.push((id, waker));
+        id
+    }
+
+    /// 取消订阅
+    pub fn unsubscribe(&self, id: usize) {
+        self.subscribers.lock().retain(|x| x.0 != id);
+    }
+
+    /// 等待文件状态发生变化
+    ///
+    /// ## 参数
+    /// - `tid` 等待的任务 id，被信号打断时返回 `EINTR`
+    ///
+    /// ## 说明
+    /// 只等待一次状态变化，调用者需要重新检查文件状态
+    pub fn wait(self: &Arc<Self>, tid: usize) -> WaitForQueue {
+        Self::wait_any(alloc::vec![self.clone()], tid)
+    }
+
+    /// 等待任意一个等待队列被唤醒，用于 `ppoll` 和 `pselect`
+    ///
+    /// `queues` 为空时只能被信号打断
+    pub fn wait_any(queues: Vec<Arc<Self>>, tid: usize) -> WaitForQueue {
+        WaitForQueue {
+            queues,
+            tid,
+            registered: false,
+        }
+    }
+
+    /// 文件状态发生变化，唤醒所有等待者
+    pub fn wake_all(&self) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        wakers.into_iter().for_each(Waker::wake);
+        // 唤醒订阅者时可能会访问其他的等待队列，先复制再唤醒
+        let subscribers: Vec<Waker> = self
+            .subscribers
+            .lock()
+            .iter()
+            .map(|x| x.1.clone())
+            .collect();
+        subscribers.iter().for_each(Waker::wake_by_ref);
+    }
+}
+
+impl Drop for WaitQueue {
+    fn drop(&mut self) {
+        WAIT_QUEUES.remove(self.ino);
+    }
+}
+
+/// 等待文件状态变化，由 [WaitQueue::wait] 创建
+pub struct WaitForQueue {
+    queues: Vec<Arc<WaitQueue>>,
+    tid: usize,
+    registered: bool,
+}
+
+impl Future for WaitForQueue {
+    type Output = Result<(), Errno>;
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Self::Output> {
+        let curr_task = TASK_MAP.lock().get(&(self.tid as _)).unwrap().clone();
+
+        // 如果被 Signal 打断
+        if matches!(
+            curr_task.waker.lock().take(),
+            Some((PollWakeEvent::Signal(_), _))
+        ) {
+            return Poll::Ready(Err(Errno::EINTR));
+        }
+        // 注册之后再次被调度说明等待队列已经被唤醒
+        if self.registered {
+            return Poll::Ready(Ok(()));
+        }
+        *curr_task.waker.lock() = Some((PollWakeEvent::Blocking, cx.waker().clone()));
+        self.queues.iter().for_each(|x| x.register(cx.waker()));
+        self.registered = true;
+        Poll::Pending
+    }
+}
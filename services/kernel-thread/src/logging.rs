@@ -1,29 +1,36 @@
-use alloc::fmt;
+use alloc::format;
 use log::{Level, LevelFilter, Record};
-use sel4_logging::Logger;
+use sel4_runtime::utils::alloc_free_addr;
+use srv_gate::klog;
 
-static mut LOGGER: Logger = sel4_logging::LoggerBuilder::const_default()
-    .write(|s| sel4::debug_print!("{}", s))
-    .fmt(fmt_with_module)
-    .build();
-
-// TODO: remove `allow（static_mut_regs)`
-#[allow(static_mut_refs)]
 pub(super) fn init() {
-    unsafe {
-        LOGGER.level_filter = match option_env!("LOG") {
-            Some("error") => LevelFilter::Error,
-            Some("warn") => LevelFilter::Warn,
-            Some("info") => LevelFilter::Info,
-            Some("debug") => LevelFilter::Debug,
-            Some("trace") => LevelFilter::Trace,
-            _ => LevelFilter::Debug,
-        };
-        LOGGER.set().unwrap();
+    let level = match option_env!("LOG") {
+        Some("error") => LevelFilter::Error,
+        Some("warn") => LevelFilter::Warn,
+        Some("info") => LevelFilter::Info,
+        Some("debug") => LevelFilter::Debug,
+        Some("trace") => LevelFilter::Trace,
+        _ => LevelFilter::Debug,
+    };
+    // 日志同时放入日志环，通过 `/dev/kmsg` 和 `syslog` 读取
+    let log_addr = alloc_free_addr(0);
+    alloc_free_addr(klog::init(
+        klog::KERNEL_LOG_SERVICE,
+        log_addr,
+        level,
+        Some(console),
+    ));
+}
+
+/// 日志输出到单独的虚拟控制台，串口初始化之前直接使用调试输出
+fn console(_name: &str, record: &Record) {
+    let s = fmt_with_module(record);
+    if !crate::device::uart::write_log(s.as_bytes()) {
+        sel4::debug_print!("{}", s)
     }
 }
 
-fn fmt_with_module(record: &Record, f: &mut fmt::Formatter) -> fmt::Result {
+fn fmt_with_module(record: &Record) -> alloc::string::String {
     let target = match record.target().is_empty() {
         true => record.module_path().unwrap_or_default(),
         false => record.target(),
@@ -38,14 +45,13 @@ fn fmt_with_module(record: &Record, f: &mut fmt::Formatter) -> fmt::Result {
 
     let line = record.line();
 
-    write!(
-        f,
+    format!(
         "\u{1B}[{}m\
             [{}:{}] {}\
-            \u{1B}[0m",
+            \u{1B}[0m\n",
         color_code,
         target,
-        line.unwrap(),
+        line.unwrap_or_default(),
         record.args()
     )
 }
@@ -16,7 +16,6 @@ use libc_core::fcntl::OpenFlags;
 use crate::{
     child_test::TASK_MAP,
     consts::task::{VDSO_AREA_SIZE, VDSO_KADDR},
-    timer::handle_timer,
     utils::{blk::get_blk_dev, obj::OBJ_ALLOCATOR},
 };
 
@@ -40,6 +39,7 @@ pub mod consts;
 pub mod device;
 pub mod exception;
 pub mod fs;
+pub mod net;
 pub mod syscall;
 pub mod task;
 pub mod timer;
@@ -88,12 +88,17 @@ fn main() {
     ::fs::dentry::mount_fs(ext4fs::Ext4FileSystem::new(get_blk_dev()), "/");
     ::fs::dentry::mount_fs(allocfs::AllocFS::new(), "/tmp");
     ::fs::dentry::mount_fs(fs::devfs::DevFS::new(), "/dev");
+    ::fs::dentry::mount_fs(fs::devfs::pty::DevPtsFS::new(), "/dev/pts");
     ::fs::dentry::mount_fs(allocfs::AllocFS::new(), "/var");
     ::fs::dentry::mount_fs(allocfs::AllocFS::new(), "/dev/shm");
+    ::fs::dentry::mount_fs(fs::mqueue::MqueueFS::new(), "/dev/mqueue");
 
     // 初始化设备
     device::init();
 
+    // 初始化网络协议栈
+    net::init();
+
     // 初始化异常处理 Mixed IPC/Notification
     exception::init();
 
@@ -130,7 +135,8 @@ fn main() {
         }
         let (message, tid) = DEFAULT_SERVE_EP.recv(());
         match tid {
-            u64::MAX => handle_timer(),
+            // 时钟中断、串口中断和服务的通知
+            badge if badge & exception::NOTIFY_BADGE != 0 => exception::handle_notify(badge),
             _ => spawner
                 .spawn_local(exception::waiting_and_handle(tid, message))
                 .unwrap(),
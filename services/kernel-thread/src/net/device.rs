@@ -0,0 +1,141 @@
+//! 以太网设备
+//!
+//! 通过和 net-thread 共享的帧环收发以太网帧，发送环满或者从满的接收环中取出帧的时候才通过 IPC 通知网络服务。
+//! 网络服务在接收环中放入新的帧之后通过 [NOTIFY_NET] 通知 kernel-thread。
+//!
+//! 网络服务重启之后没有通道，调用网络服务时重新把通道交给它。网络服务不可用时发送环中的帧无法发送。
+use alloc::vec::Vec;
+use common::root::{create_channel, set_channel_notify};
+use core::sync::atomic::{AtomicBool, Ordering};
+use smoltcp::{
+    phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken},
+    time::Instant,
+};
+use srv_gate::{
+    NET_IMPLS,
+    net::{
+        FrameRing, NET_CHANNEL_PAGES, NET_MAX_FRAME_LEN, NET_NOT_READY, NET_RING_SLOTS,
+        NET_UNAVAILABLE, NetIface,
+    },
+};
+
+use crate::exception::{NOTIFY_NET, badged_notify};
+
+/// 和网络服务共享的内存通道的地址
+const NET_CHANNEL_ADDR: usize = 0x3_1000_0000;
+
+/// 是否已经记录过网络服务不可用
+static NET_UNAVAILABLE_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// 调用网络服务，`channel_id` 为和网络服务共享的通道
+///
+/// 网络服务重启之后返回 [NET_NOT_READY]，重新把通道交给网络服务之后再调用一次
+fn call_net(channel_id: usize, f: impl Fn(&mut dyn NetIface) -> usize) {
+    let mut net = NET_IMPLS[0].lock();
+    let mut ret = f(&mut *net);
+    if ret == NET_NOT_READY {
+        log::warn!("net service restarted, hand over channel {}", channel_id);
+        net.init(channel_id);
+        ret = f(&mut *net);
+    }
+    if ret == NET_UNAVAILABLE && !NET_UNAVAILABLE_LOGGED.swap(true, Ordering::Relaxed) {
+        log::error!("net service is unavailable");
+    }
+}
+
+/// 以太网设备
+pub struct EthDevice {
+    /// 和网络服务共享的通道
+    channel_id: usize,
+    /// 发送环
+    tx: &'static FrameRing,
+    /// 接收环
+    rx: &'static FrameRing,
+}
+
+impl EthDevice {
+    /// 创建共享内存通道并交给网络服务
+    pub fn new() -> Self {
+        let channel_id = create_channel(NET_CHANNEL_ADDR, NET_CHANNEL_PAGES);
+        // 网络服务加入通道时获取这个通知
+        if set_channel_notify(channel_id, badged_notify(NOTIFY_NET)).is_err() {
+            log::warn!(
+                "can't register the notification of net channel {}",
+                channel_id
+            );
+        }
+        NET_IMPLS[0].lock().init(channel_id);
+        let (tx, rx) = unsafe { FrameRing::channel(NET_CHANNEL_ADDR) };
+        Self { channel_id, tx, rx }
+    }
+}
+
+impl Device for EthDevice {
+    type RxToken<'a>
+        = EthRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = EthTxToken
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        // 接收环满的时候网络服务把帧留在网卡中，取出帧之后让网络服务继续接收
+        let full = self.rx.is_full();
+        let frame = self.rx.pop(|frame| frame.to_vec())?;
+        if full {
+            call_net(self.channel_id, |net| net.receive());
+        }
+        Some((EthRxToken(frame), EthTxToken(self.channel_id, self.tx)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        if self.tx.is_full() {
+            call_net(self.channel_id, |net| net.transmit());
+        }
+        match self.tx.is_full() {
+            true => None,
+            false => Some(EthTxToken(self.channel_id, self.tx)),
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = NET_MAX_FRAME_LEN.min(1514);
+        caps.max_burst_size = Some(NET_RING_SLOTS);
+        caps.medium = Medium::Ethernet;
+        caps
+    }
+}
+
+/// 接收到的以太网帧
+pub struct EthRxToken(Vec<u8>);
+
+impl RxToken for EthRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.0)
+    }
+}
+
+/// 向发送环中写入一个以太网帧，包含和网络服务共享的通道和发送环
+pub struct EthTxToken(usize, &'static FrameRing);
+
+impl TxToken for EthTxToken {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0u8; len];
+        let res = f(&mut frame);
+        if !self.1.push(&frame) {
+            log::warn!("net tx ring is full, drop frame: {} bytes", len);
+        }
+        // 发送环中的帧交给网络服务发送
+        call_net(self.0, |net| net.transmit());
+        res
+    }
+}
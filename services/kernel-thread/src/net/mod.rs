@@ -0,0 +1,238 @@
+//! 网络协议栈
+//!
+//! kernel-thread 在进程内运行基于 smoltcp 的 TCP/IP 协议栈，和 ext4 文件系统一样不需要额外的服务。
+//! 协议栈分为两个：环回协议栈处理发往本机地址的数据，以太网协议栈通过 net-thread 提供的
+//! [srv_gate::net::NetIface] 收发帧。smoltcp 中一个 socket 只能属于一个协议栈，
+//! 所以绑定到 `INADDR_ANY` 的 socket 会在每个协议栈中各创建一个。
+//!
+//! net-thread 收到帧之后通过 [crate::exception::NOTIFY_NET] 通知 kernel-thread 轮询协议栈。
+//! 协议栈中的重传、保活等超时通过定时器处理，定时器按照 smoltcp 给出的下一次需要轮询的时间设置。
+//!
+//! net-thread 需要作为独立的服务运行才能使用以太网，例如
+//! `tools/app-parser.py kernel-thread block-thread uart-thread net-thread`，
+//! 这时 srv-gate 使用 `net_ipc` 编译。没有选择 net-thread 时只有环回协议栈。
+//!
+//! Unix 域 socket 不使用协议栈，实现在 [unix] 中。
+pub mod device;
+pub mod socket;
+pub mod unix;
+
+use core::time::Duration;
+
+use alloc::vec::Vec;
+use sel4_kit::arch::current_time;
+use smoltcp::{
+    iface::{Config, Interface, SocketSet},
+    phy::{Device, Loopback, Medium},
+    time::Instant,
+    wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, Ipv4Address},
+};
+use spin::{Lazy, Mutex};
+use srv_gate::NET_IMPLS;
+
+use crate::timer::set_net_timer;
+use device::EthDevice;
+
+/// 以太网接口的地址，和 QEMU 用户网络的默认配置一致
+pub const ETH_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+
+/// 以太网接口的网关
+const ETH_GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+/// 以太网接口的子网前缀长度
+const ETH_PREFIX_LEN: u8 = 24;
+
+/// 协议栈编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackId {
+    /// 环回协议栈
+    Loopback,
+    /// 以太网协议栈
+    Ethernet,
+}
+
+/// 一个网络接口和属于它的 socket
+struct NetStack<D: Device> {
+    iface: Interface,
+    device: D,
+    sockets: SocketSet<'static>,
+}
+
+impl<D: Device> NetStack<D> {
+    /// 创建一个协议栈
+    ///
+    /// ## 参数
+    /// - `device`  网络设备
+    /// - `hw_addr` 网络接口的硬件地址
+    /// - `addrs`   网络接口的 IP 地址
+    /// - `gateway` 默认网关
+    fn new(
+        mut device: D,
+        hw_addr: HardwareAddress,
+        addrs: &[IpCidr],
+        gateway: Option<Ipv4Address>,
+    ) -> Self {
+        let mut config = Config::new(hw_addr);
+        config.random_seed = current_time().as_nanos() as _;
+        let mut iface = Interface::new(config, &mut device, now());
+        iface.update_ip_addrs(|ip_addrs| {
+            addrs.iter().for_each(|addr| ip_addrs.push(*addr).unwrap());
+        });
+        if let Some(gateway) = gateway {
+            iface.routes_mut().add_default_ipv4_route(gateway).unwrap();
+        }
+        Self {
+            iface,
+            device,
+            sockets: SocketSet::new(Vec::new()),
+        }
+    }
+
+    /// 处理网络接口收到和需要发送的数据
+    fn poll(&mut self) {
+        self.iface.poll(now(), &mut self.device, &mut self.sockets);
+    }
+
+    /// 距离下一次需要轮询的时间，没有需要处理的超时时为 [Option::None]
+    fn poll_delay(&mut self) -> Option<Duration> {
+        self.iface
+            .poll_delay(now(), &self.sockets)
+            .map(|delay| Duration::from_micros(delay.total_micros()))
+    }
+}
+
+/// 环回协议栈，只处理 `127.0.0.0/8` 的地址
+static LOOPBACK: Lazy<Mutex<NetStack<Loopback>>> = Lazy::new(|| {
+    Mutex::new(NetStack::new(
+        Loopback::new(Medium::Ip),
+        HardwareAddress::Ip,
+        &[IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)],
+        None,
+    ))
+});
+
+/// 以太网协议栈，没有网络服务时为 [Option::None]
+static ETHERNET: Lazy<Option<Mutex<NetStack<EthDevice>>>> = Lazy::new(|| {
+    if NET_IMPLS.is_empty() {
+        return None;
+    }
+    let mac = match NET_IMPLS[0].lock().mac_address() {
+        Ok(mac) => mac.to_be_bytes(),
+        Err(errno) => {
+            log::warn!("Can't get mac address from net service: {:?}", errno);
+            return None;
+        }
+    };
+    let device = EthDevice::new();
+    Some(Mutex::new(NetStack::new(
+        device,
+        HardwareAddress::Ethernet(EthernetAddress::from_bytes(&mac[2..])),
+        &[IpCidr::new(IpAddress::Ipv4(ETH_IP), ETH_PREFIX_LEN)],
+        Some(ETH_GATEWAY),
+    )))
+});
+
+/// 已经设置的轮询定时器的到期时间
+static POLL_DEADLINE: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// smoltcp 使用的当前时间
+fn now() -> Instant {
+    Instant::from_micros(current_time().as_micros() as i64)
+}
+
+/// 初始化网络协议栈
+pub fn init() {
+    Lazy::force(&LOOPBACK);
+    if ETHERNET.is_none() {
+        log::warn!("net service not found, only loopback is available");
+    }
+}
+
+/// 当前可用的协议栈
+pub fn stacks() -> Vec<StackId> {
+    match ETHERNET.is_some() {
+        true => vec![StackId::Loopback, StackId::Ethernet],
+        false => vec![StackId::Loopback],
+    }
+}
+
+/// 根据目标地址选择协议栈，以太网地址 [ETH_IP] 属于以太网协议栈
+pub fn route(addr: IpAddress) -> StackId {
+    match addr {
+        _ if ETHERNET.is_none() => StackId::Loopback,
+        IpAddress::Ipv4(addr) if addr.is_loopback() => StackId::Loopback,
+        _ => StackId::Ethernet,
+    }
+}
+
+/// 本机是否拥有地址 `addr`，没有网络服务时只有环回地址
+pub fn is_local_addr(addr: Ipv4Address) -> bool {
+    addr.is_loopback() || (addr == ETH_IP && ETHERNET.is_some())
+}
+
+/// 在指定的协议栈中操作 socket
+///
+/// ## 参数
+/// - `id` 协议栈编号，必须是 [stacks] 中返回的协议栈
+/// - `f`  操作函数，参数为网络接口和 socket 集合
+pub fn with_stack<R>(
+    id: StackId,
+    f: impl FnOnce(&mut Interface, &mut SocketSet<'static>) -> R,
+) -> R {
+    match id {
+        StackId::Loopback => {
+            let mut stack = LOOPBACK.lock();
+            let stack = &mut *stack;
+            f(&mut stack.iface, &mut stack.sockets)
+        }
+        StackId::Ethernet => {
+            let mut stack = ETHERNET.as_ref().expect("ethernet is not available").lock();
+            let stack = &mut *stack;
+            f(&mut stack.iface, &mut stack.sockets)
+        }
+    }
+}
+
+/// 轮询所有的协议栈，并唤醒等待 socket 的任务
+pub fn poll() {
+    LOOPBACK.lock().poll();
+    if let Some(ethernet) = ETHERNET.as_ref() {
+        ethernet.lock().poll();
+    }
+    socket::reap_closed();
+    socket::wake_all();
+    schedule_poll();
+}
+
+/// 按照协议栈下一次需要轮询的时间设置定时器
+///
+/// 已经设置的定时器不晚于需要轮询的时间时不会重复设置，没有需要处理的超时时不设置定时器
+pub fn schedule_poll() {
+    let delay = [
+        LOOPBACK.lock().poll_delay(),
+        ETHERNET.as_ref().and_then(|x| x.lock().poll_delay()),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let Some(delay) = delay else {
+        return;
+    };
+    let next = current_time() + delay;
+    let mut deadline = POLL_DEADLINE.lock();
+    if deadline.is_none_or(|deadline| deadline > next) {
+        *deadline = Some(next);
+        set_net_timer(next);
+    }
+}
+
+/// 轮询定时器到期，由时间队列调用
+pub fn handle_poll_timer() {
+    {
+        let mut deadline = POLL_DEADLINE.lock();
+        if deadline.is_some_and(|deadline| deadline <= current_time()) {
+            *deadline = None;
+        }
+    }
+    poll();
+}
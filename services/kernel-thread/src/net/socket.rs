@@ -0,0 +1,734 @@
+//! IPv4 socket
+//!
+//! [InetSocket] 实现了 [INodeInterface]，可以和普通文件一样使用 `read`、`write`、`ppoll`
+//! 和 `epoll`。`readat` 和 `writeat` 不会阻塞，无法继续时返回 `EAGAIN`，由系统调用根据
+//! `O_NONBLOCK` 决定是否在等待队列上等待。
+use alloc::{collections::btree_set::BTreeSet, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use libc_core::{
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use smoltcp::{
+    iface::SocketHandle,
+    socket::{tcp, udp},
+    wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use super::{StackId, poll, route, schedule_poll, stacks, with_stack};
+use crate::fs::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    wait_queue::WaitQueue,
+};
+
+/// 所有存活的 IPv4 socket
+static INET_INODES: InodeRegistry<InetSocket> = InodeRegistry::new();
+
+/// 已经关闭但是还在等待连接结束的 TCP socket
+static CLOSING: Mutex<Vec<(StackId, SocketHandle)>> = Mutex::new(Vec::new());
+
+/// 已经被占用的端口 (socket 类型, 端口)
+static BOUND_PORTS: Mutex<BTreeSet<(usize, u16)>> = Mutex::new(BTreeSet::new());
+
+/// 下一个临时端口
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(EPHEMERAL_PORT_START);
+
+/// IPv4 地址族
+pub const AF_INET: usize = 2;
+/// 流式 socket
+pub const SOCK_STREAM: usize = 1;
+/// 数据报 socket
+pub const SOCK_DGRAM: usize = 2;
+/// socket 类型掩码
+pub const SOCK_TYPE_MASK: usize = 0xf;
+/// 创建非阻塞的 socket
+pub const SOCK_NONBLOCK: usize = 0o4000;
+/// 创建 `O_CLOEXEC` 的 socket
+pub const SOCK_CLOEXEC: usize = 0o2000000;
+
+/// TCP 协议
+pub const IPPROTO_TCP: usize = 6;
+/// UDP 协议
+pub const IPPROTO_UDP: usize = 17;
+
+/// 关闭读
+pub const SHUT_RD: usize = 0;
+/// 关闭写
+pub const SHUT_WR: usize = 1;
+/// 关闭读写
+pub const SHUT_RDWR: usize = 2;
+
+/// 临时端口的范围
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// TCP 收发缓冲区的大小
+pub const TCP_BUFFER_SIZE: usize = 0x8000;
+
+/// UDP 收发缓冲区的大小
+const UDP_BUFFER_SIZE: usize = 0x10000;
+
+/// UDP 缓冲区中最多保存的数据报数量
+const UDP_PACKET_COUNT: usize = 64;
+
+/// 每个协议栈中监听 socket 的最大数量
+const MAX_BACKLOG: usize = 8;
+
+/// `struct sockaddr_in`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct SockAddrIn {
+    /// 地址族，[AF_INET]
+    pub family: u16,
+    /// 端口，网络字节序
+    pub port: [u8; 2],
+    /// IPv4 地址，网络字节序
+    pub addr: [u8; 4],
+    _zero: [u8; 8],
+}
+
+impl SockAddrIn {
+    /// 从 smoltcp 的 [IpEndpoint] 创建
+    pub fn from_endpoint(endpoint: IpEndpoint) -> Self {
+        let IpAddress::Ipv4(addr) = endpoint.addr;
+        Self {
+            family: AF_INET as _,
+            port: endpoint.port.to_be_bytes(),
+            addr: addr.octets(),
+            _zero: [0; 8],
+        }
+    }
+
+    /// 转换为 smoltcp 的 [IpEndpoint]
+    pub fn endpoint(&self) -> IpEndpoint {
+        IpEndpoint::new(
+            IpAddress::Ipv4(Ipv4Address::from(self.addr)),
+            u16::from_be_bytes(self.port),
+        )
+    }
+}
+
+/// socket 的连接状态
+enum InetState {
+    /// 刚创建或者只绑定了地址
+    Idle,
+    /// TCP 正在监听，每个协议栈中有多个监听同一个端口的 socket
+    Listening(Vec<(StackId, SocketHandle)>),
+    /// TCP 连接，包括正在建立的连接
+    Stream(StackId, SocketHandle),
+    /// UDP socket (每个协议栈中的 socket, 默认的目标地址)
+    Datagram(Vec<(StackId, SocketHandle)>, Option<IpEndpoint>),
+}
+
+/// socket 选项
+#[derive(Default, Clone, Copy)]
+pub struct SockOptions {
+    /// `SO_REUSEADDR`
+    pub reuse_addr: bool,
+    /// `SO_KEEPALIVE`
+    pub keep_alive: bool,
+    /// `TCP_NODELAY`
+    pub no_delay: bool,
+}
+
+struct InetInner {
+    /// 连接状态
+    state: InetState,
+    /// 绑定的本地地址，地址为 `0.0.0.0` 时表示所有的协议栈
+    local: Option<IpEndpoint>,
+    /// 绑定时是否占用了端口
+    port_owned: bool,
+    /// 读端是否已经关闭
+    read_shutdown: bool,
+    /// socket 选项
+    options: SockOptions,
+}
+
+/// IPv4 socket
+pub struct InetSocket {
+    /// socket 类型，[SOCK_STREAM] 或者 [SOCK_DGRAM]
+    ty: usize,
+    /// socket 状态
+    inner: Mutex<InetInner>,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+/// 创建一个 smoltcp 的 TCP socket
+fn new_tcp_socket(options: &SockOptions) -> tcp::Socket<'static> {
+    let mut socket = tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    );
+    socket.set_nagle_enabled(!options.no_delay);
+    if options.keep_alive {
+        socket.set_keep_alive(Some(smoltcp::time::Duration::from_secs(75)));
+    }
+    socket
+}
+
+/// 创建一个 smoltcp 的 UDP socket
+fn new_udp_socket() -> udp::Socket<'static> {
+    udp::Socket::new(
+        udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; UDP_PACKET_COUNT],
+            vec![0; UDP_BUFFER_SIZE],
+        ),
+        udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; UDP_PACKET_COUNT],
+            vec![0; UDP_BUFFER_SIZE],
+        ),
+    )
+}
+
+/// 本地地址对应的协议栈，`0.0.0.0` 对应所有的协议栈
+fn local_stacks(local: &IpEndpoint) -> Vec<StackId> {
+    match local.addr.is_unspecified() {
+        true => stacks(),
+        false => vec![route(local.addr)],
+    }
+}
+
+/// 转换为 smoltcp 监听使用的地址
+fn listen_endpoint(local: &IpEndpoint) -> IpListenEndpoint {
+    IpListenEndpoint {
+        addr: (!local.addr.is_unspecified()).then_some(local.addr),
+        port: local.port,
+    }
+}
+
+/// 分配一个临时端口
+fn alloc_ephemeral_port(ty: usize) -> Result<u16, Errno> {
+    let mut ports = BOUND_PORTS.lock();
+    for _ in EPHEMERAL_PORT_START..=u16::MAX {
+        let port = NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::SeqCst);
+        if port == u16::MAX {
+            NEXT_EPHEMERAL_PORT.store(EPHEMERAL_PORT_START, Ordering::SeqCst);
+        }
+        if ports.insert((ty, port)) {
+            return Ok(port);
+        }
+    }
+    Err(Errno::EADDRINUSE)
+}
+
+/// 唤醒所有等待 socket 的任务，在协议栈轮询之后调用
+pub fn wake_all() {
+    INET_INODES.values().iter().for_each(|x| x.wait.wake_all());
+}
+
+/// 回收已经关闭的 TCP socket
+pub fn reap_closed() {
+    CLOSING.lock().retain(|&(id, handle)| {
+        let closed = with_stack(id, |_, sockets| {
+            let closed = sockets.get::<tcp::Socket>(handle).state() == tcp::State::Closed;
+            if closed {
+                sockets.remove(handle);
+            }
+            closed
+        });
+        !closed
+    });
+}
+
+impl InetSocket {
+    /// 创建一个新的 socket
+    ///
+    /// ## 参数
+    /// - `ty`       socket 类型，[SOCK_STREAM] 或者 [SOCK_DGRAM]
+    /// - `protocol` 协议，为 0 时使用默认的协议
+    pub fn new(ty: usize, protocol: usize) -> Result<Arc<Self>, Errno> {
+        match (ty, protocol) {
+            (SOCK_STREAM, 0 | IPPROTO_TCP) | (SOCK_DGRAM, 0 | IPPROTO_UDP) => {}
+            (SOCK_STREAM | SOCK_DGRAM, _) => return Err(Errno::EPROTONOSUPPORT),
+            _ => return Err(Errno::ESOCKTNOSUPPORT),
+        }
+        Ok(Self::with_state(ty, InetState::Idle, None))
+    }
+
+    fn with_state(ty: usize, state: InetState, local: Option<IpEndpoint>) -> Arc<Self> {
+        let ino = alloc_ino();
+        let socket = Arc::new(Self {
+            ty,
+            inner: Mutex::new(InetInner {
+                state,
+                local,
+                port_owned: false,
+                read_shutdown: false,
+                options: SockOptions::default(),
+            }),
+            wait: WaitQueue::new(ino),
+        });
+        INET_INODES.insert(ino, &socket);
+        schedule_poll();
+        socket
+    }
+
+    /// 从打开的 [fs::file::File] 中获取 [InetSocket]
+    ///
+    /// 如果文件不是 IPv4 socket，返回 [Option::None]
+    pub fn from_file(file: &fs::file::File) -> Option<Arc<Self>> {
+        INET_INODES.from_file(file)
+    }
+
+    /// socket 类型
+    pub const fn ty(&self) -> usize {
+        self.ty
+    }
+
+    /// 获取 socket 选项
+    pub fn options(&self) -> SockOptions {
+        self.inner.lock().options
+    }
+
+    /// 修改 socket 选项，对已经建立的 TCP 连接立即生效
+    pub fn set_options(&self, options: SockOptions) {
+        let mut inner = self.inner.lock();
+        inner.options = options;
+        if let InetState::Stream(id, handle) = inner.state {
+            with_stack(id, |_, sockets| {
+                let socket = sockets.get_mut::<tcp::Socket>(handle);
+                socket.set_nagle_enabled(!options.no_delay);
+                socket.set_keep_alive(
+                    options
+                        .keep_alive
+                        .then(|| smoltcp::time::Duration::from_secs(75)),
+                );
+            });
+        }
+    }
+
+    /// 绑定本地地址
+    pub fn bind(&self, mut local: IpEndpoint) -> Result<(), Errno> {
+        let mut inner = self.inner.lock();
+        if inner.local.is_some() {
+            return Err(Errno::EINVAL);
+        }
+        let IpAddress::Ipv4(addr) = local.addr;
+        if !addr.is_unspecified() && !super::is_local_addr(addr) {
+            return Err(Errno::EADDRNOTAVAIL);
+        }
+        // 使用 SO_REUSEADDR 重复绑定时端口属于第一个绑定的 socket
+        let port_owned = match local.port {
+            0 => {
+                local.port = alloc_ephemeral_port(self.ty)?;
+                true
+            }
+            port => match BOUND_PORTS.lock().insert((self.ty, port)) {
+                false if !inner.options.reuse_addr => return Err(Errno::EADDRINUSE),
+                owned => owned,
+            },
+        };
+        inner.local = Some(local);
+        inner.port_owned = port_owned;
+        Ok(())
+    }
+
+    /// 没有绑定地址时绑定到 `0.0.0.0` 和一个临时端口
+    fn auto_bind(&self, inner: &mut InetInner) -> Result<IpEndpoint, Errno> {
+        if let Some(local) = inner.local {
+            return Ok(local);
+        }
+        let port = alloc_ephemeral_port(self.ty)?;
+        let local = IpEndpoint::new(IpAddress::v4(0, 0, 0, 0), port);
+        inner.local = Some(local);
+        inner.port_owned = true;
+        Ok(local)
+    }
+
+    /// 开始监听 TCP 连接
+    pub fn listen(&self, backlog: usize) -> Result<(), Errno> {
+        if self.ty != SOCK_STREAM {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let mut inner = self.inner.lock();
+        match inner.state {
+            InetState::Idle => {}
+            InetState::Listening(_) => return Ok(()),
+            _ => return Err(Errno::EINVAL),
+        }
+        let local = self.auto_bind(&mut inner)?;
+        let backlog = backlog.clamp(1, MAX_BACKLOG);
+        let mut handles = Vec::new();
+        for id in local_stacks(&local) {
+            for _ in 0..backlog {
+                let handle = with_stack(id, |_, sockets| {
+                    let mut socket = new_tcp_socket(&inner.options);
+                    socket
+                        .listen(listen_endpoint(&local))
+                        .map_err(|_| Errno::EADDRINUSE)?;
+                    Ok(sockets.add(socket))
+                })?;
+                handles.push((id, handle));
+            }
+        }
+        inner.state = InetState::Listening(handles);
+        Ok(())
+    }
+
+    /// 接受一个 TCP 连接，没有已经建立的连接时返回 `EAGAIN`
+    pub fn accept(&self) -> Result<Arc<Self>, Errno> {
+        let mut inner = self.inner.lock();
+        let local = inner.local;
+        let options = inner.options;
+        let InetState::Listening(handles) = &mut inner.state else {
+            return Err(Errno::EINVAL);
+        };
+        for (id, handle) in handles.iter_mut() {
+            let accepted = with_stack(*id, |_, sockets| {
+                let state = sockets.get::<tcp::Socket>(*handle).state();
+                if matches!(state, tcp::State::Listen | tcp::State::SynReceived) {
+                    return Ok(None);
+                }
+                // 连接已经建立，使用一个新的 socket 继续监听
+                let mut socket = new_tcp_socket(&options);
+                socket
+                    .listen(listen_endpoint(&local.unwrap()))
+                    .map_err(|_| Errno::EADDRINUSE)?;
+                let accepted = core::mem::replace(handle, sockets.add(socket));
+                if state == tcp::State::Closed {
+                    sockets.remove(accepted);
+                    return Ok(None);
+                }
+                let local = sockets.get::<tcp::Socket>(accepted).local_endpoint();
+                Ok(Some((accepted, local)))
+            })?;
+            if let Some((accepted, local)) = accepted {
+                let socket = Self::with_state(SOCK_STREAM, InetState::Stream(*id, accepted), local);
+                socket.inner.lock().options = options;
+                return Ok(socket);
+            }
+        }
+        Err(Errno::EAGAIN)
+    }
+
+    /// 开始连接，TCP 连接需要通过 [Self::connect_result] 获取结果
+    pub fn connect(&self, remote: IpEndpoint) -> Result<(), Errno> {
+        let mut inner = self.inner.lock();
+        if self.ty == SOCK_DGRAM {
+            self.ensure_datagram(&mut inner)?;
+            if let InetState::Datagram(_, peer) = &mut inner.state {
+                *peer = Some(remote);
+            }
+            return Ok(());
+        }
+        match inner.state {
+            InetState::Idle => {}
+            InetState::Stream(..) => return Err(Errno::EISCONN),
+            InetState::Listening(_) | InetState::Datagram(..) => return Err(Errno::EINVAL),
+        }
+        let local = self.auto_bind(&mut inner)?;
+        let id = route(remote.addr);
+        let options = inner.options;
+        let handle = with_stack(id, |iface, sockets| {
+            let mut socket = new_tcp_socket(&options);
+            socket
+                .connect(iface.context(), remote, listen_endpoint(&local))
+                .map_err(|_| Errno::EADDRNOTAVAIL)?;
+            Ok(sockets.add(socket))
+        })?;
+        inner.state = InetState::Stream(id, handle);
+        drop(inner);
+        poll();
+        Ok(())
+    }
+
+    /// 获取 TCP 连接的结果
+    ///
+    /// 连接正在建立时返回 `EINPROGRESS`，被拒绝时返回 `ECONNREFUSED`
+    pub fn connect_result(&self) -> Result<(), Errno> {
+        let InetState::Stream(id, handle) = self.inner.lock().state else {
+            return Ok(());
+        };
+        with_stack(id, |_, sockets| {
+            match sockets.get::<tcp::Socket>(handle).state() {
+                tcp::State::SynSent | tcp::State::SynReceived => Err(Errno::EINPROGRESS),
+                tcp::State::Closed => Err(Errno::ECONNREFUSED),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// UDP socket 第一次使用时在本地地址对应的协议栈中创建 smoltcp socket
+    fn ensure_datagram(&self, inner: &mut InetInner) -> Result<(), Errno> {
+        if let InetState::Datagram(..) = inner.state {
+            return Ok(());
+        }
+        let local = self.auto_bind(inner)?;
+        let mut handles = Vec::new();
+        for id in local_stacks(&local) {
+            let handle = with_stack(id, |_, sockets| {
+                let mut socket = new_udp_socket();
+                socket
+                    .bind(listen_endpoint(&local))
+                    .map_err(|_| Errno::EADDRINUSE)?;
+                Ok(sockets.add(socket))
+            })?;
+            handles.push((id, handle));
+        }
+        inner.state = InetState::Datagram(handles, None);
+        Ok(())
+    }
+
+    /// 发送数据
+    ///
+    /// ## 参数
+    /// - `data` 需要发送的数据
+    /// - `to`   目标地址，TCP 会忽略这个参数，UDP 为空时使用 `connect` 设置的地址
+    pub fn send(&self, data: &[u8], to: Option<IpEndpoint>) -> Result<usize, Errno> {
+        let mut inner = self.inner.lock();
+        if self.ty == SOCK_DGRAM {
+            self.ensure_datagram(&mut inner)?;
+        }
+        let res = match &inner.state {
+            InetState::Stream(id, handle) => with_stack(*id, |_, sockets| {
+                let socket = sockets.get_mut::<tcp::Socket>(*handle);
+                match socket.state() {
+                    tcp::State::SynSent | tcp::State::SynReceived => Err(Errno::EAGAIN),
+                    _ if !socket.may_send() => Err(Errno::EPIPE),
+                    _ => match socket.send_slice(data) {
+                        Ok(0) if !data.is_empty() => Err(Errno::EAGAIN),
+                        Ok(len) => Ok(len),
+                        Err(_) => Err(Errno::EPIPE),
+                    },
+                }
+            }),
+            InetState::Datagram(handles, peer) => {
+                let remote = to.or(*peer).ok_or(Errno::EDESTADDRREQ)?;
+                let id = route(remote.addr);
+                let (id, handle) = handles
+                    .iter()
+                    .find(|x| x.0 == id)
+                    .ok_or(Errno::ENETUNREACH)?;
+                with_stack(*id, |_, sockets| {
+                    let socket = sockets.get_mut::<udp::Socket>(*handle);
+                    match socket.send_slice(data, remote) {
+                        Ok(()) => Ok(data.len()),
+                        Err(udp::SendError::BufferFull) => Err(Errno::EAGAIN),
+                        Err(udp::SendError::Unaddressable) => Err(Errno::ENETUNREACH),
+                    }
+                })
+            }
+            InetState::Idle | InetState::Listening(_) => Err(Errno::ENOTCONN),
+        };
+        drop(inner);
+        if res.is_ok() {
+            poll();
+        }
+        res
+    }
+
+    /// 接收数据
+    ///
+    /// ## 参数
+    /// - `buffer` 接收数据的缓冲区
+    /// - `peek`   是否保留接收到的数据 (`MSG_PEEK`)
+    ///
+    /// 返回 (接收的长度, 发送方的地址)
+    pub fn recv(
+        &self,
+        buffer: &mut [u8],
+        peek: bool,
+    ) -> Result<(usize, Option<IpEndpoint>), Errno> {
+        poll();
+        let inner = self.inner.lock();
+        match &inner.state {
+            _ if inner.read_shutdown => Ok((0, None)),
+            InetState::Stream(id, handle) => with_stack(*id, |_, sockets| {
+                let socket = sockets.get_mut::<tcp::Socket>(*handle);
+                let remote = socket.remote_endpoint();
+                if socket.can_recv() {
+                    let len = match peek {
+                        true => socket.peek_slice(buffer),
+                        false => socket.recv_slice(buffer),
+                    };
+                    return len.map(|len| (len, remote)).map_err(|_| Errno::ECONNRESET);
+                }
+                match socket.state() {
+                    tcp::State::SynSent | tcp::State::SynReceived => Err(Errno::EAGAIN),
+                    _ if !socket.may_recv() => Ok((0, remote)),
+                    _ => Err(Errno::EAGAIN),
+                }
+            }),
+            InetState::Datagram(handles, peer) => {
+                for (id, handle) in handles {
+                    let res = with_stack(*id, |_, sockets| {
+                        let socket = sockets.get_mut::<udp::Socket>(*handle);
+                        while socket.can_recv() {
+                            let res = match peek {
+                                true => socket.peek_slice(buffer).map(|(len, meta)| (len, *meta)),
+                                false => socket.recv_slice(buffer),
+                            };
+                            let Ok((len, meta)) = res else {
+                                break;
+                            };
+                            // 已经连接的 UDP socket 只接收来自目标地址的数据报
+                            if peek || peer.is_none_or(|peer| peer == meta.endpoint) {
+                                return Some((len, Some(meta.endpoint)));
+                            }
+                        }
+                        None
+                    });
+                    if let Some(res) = res {
+                        return Ok(res);
+                    }
+                }
+                Err(Errno::EAGAIN)
+            }
+            InetState::Idle | InetState::Listening(_) => Err(Errno::ENOTCONN),
+        }
+    }
+
+    /// 关闭连接的读端或者写端
+    pub fn shutdown(&self, how: usize) -> Result<(), Errno> {
+        let mut inner = self.inner.lock();
+        if !matches!(how, SHUT_RD | SHUT_WR | SHUT_RDWR) {
+            return Err(Errno::EINVAL);
+        }
+        if how != SHUT_WR {
+            inner.read_shutdown = true;
+        }
+        match inner.state {
+            InetState::Stream(id, handle) if how != SHUT_RD => {
+                with_stack(id, |_, sockets| {
+                    sockets.get_mut::<tcp::Socket>(handle).close()
+                });
+            }
+            InetState::Stream(..) | InetState::Datagram(..) => {}
+            InetState::Idle | InetState::Listening(_) => return Err(Errno::ENOTCONN),
+        }
+        drop(inner);
+        poll();
+        Ok(())
+    }
+
+    /// 本地地址
+    pub fn local_endpoint(&self) -> IpEndpoint {
+        let inner = self.inner.lock();
+        let local = match inner.state {
+            InetState::Stream(id, handle) => with_stack(id, |_, sockets| {
+                sockets.get::<tcp::Socket>(handle).local_endpoint()
+            }),
+            _ => None,
+        };
+        local
+            .or(inner.local)
+            .unwrap_or(IpEndpoint::new(IpAddress::v4(0, 0, 0, 0), 0))
+    }
+
+    /// 对端地址，没有连接时返回 `ENOTCONN`
+    pub fn peer_endpoint(&self) -> Result<IpEndpoint, Errno> {
+        match self.inner.lock().state {
+            InetState::Stream(id, handle) => with_stack(id, |_, sockets| {
+                sockets.get::<tcp::Socket>(handle).remote_endpoint()
+            }),
+            InetState::Datagram(_, peer) => peer,
+            _ => None,
+        }
+        .ok_or(Errno::ENOTCONN)
+    }
+}
+
+impl Drop for InetSocket {
+    fn drop(&mut self) {
+        INET_INODES.remove(self.wait.ino());
+        let inner = self.inner.get_mut();
+        match core::mem::replace(&mut inner.state, InetState::Idle) {
+            InetState::Idle => {}
+            InetState::Listening(handles) | InetState::Datagram(handles, _) => {
+                handles.into_iter().for_each(|(id, handle)| {
+                    with_stack(id, |_, sockets| {
+                        sockets.remove(handle);
+                    });
+                });
+            }
+            // 正常关闭连接，连接结束后再回收
+            InetState::Stream(id, handle) => {
+                with_stack(id, |_, sockets| {
+                    sockets.get_mut::<tcp::Socket>(handle).close()
+                });
+                CLOSING.lock().push((id, handle));
+                schedule_poll();
+            }
+        }
+        if let (Some(local), true) = (inner.local, inner.port_owned) {
+            BOUND_PORTS.lock().remove(&(self.ty, local.port));
+        }
+    }
+}
+
+impl INodeInterface for InetSocket {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        self.recv(buffer, false).map(|x| x.0)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        self.send(buffer, None)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let inner = self.inner.lock();
+        let (readable, writable, hangup) = match &inner.state {
+            InetState::Idle => (false, false, false),
+            InetState::Listening(handles) => {
+                let readable = handles.iter().any(|(id, handle)| {
+                    with_stack(*id, |_, sockets| {
+                        !matches!(
+                            sockets.get::<tcp::Socket>(*handle).state(),
+                            tcp::State::Listen | tcp::State::SynReceived | tcp::State::Closed
+                        )
+                    })
+                });
+                (readable, false, false)
+            }
+            InetState::Stream(id, handle) => with_stack(*id, |_, sockets| {
+                let socket = sockets.get::<tcp::Socket>(*handle);
+                let connecting = matches!(
+                    socket.state(),
+                    tcp::State::SynSent | tcp::State::SynReceived
+                );
+                (
+                    socket.can_recv() || (!connecting && !socket.may_recv()),
+                    socket.can_send(),
+                    socket.state() == tcp::State::Closed,
+                )
+            }),
+            InetState::Datagram(handles, _) => {
+                let readable = handles.iter().any(|(id, handle)| {
+                    with_stack(*id, |_, sockets| {
+                        sockets.get::<udp::Socket>(*handle).can_recv()
+                    })
+                });
+                let writable = handles.iter().any(|(id, handle)| {
+                    with_stack(*id, |_, sockets| {
+                        sockets.get::<udp::Socket>(*handle).can_send()
+                    })
+                });
+                (readable, writable, false)
+            }
+        };
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && (readable || inner.read_shutdown) {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) && writable {
+            res |= PollEvent::OUT;
+        }
+        if hangup {
+            res |= PollEvent::HUP;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::SOCKET;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
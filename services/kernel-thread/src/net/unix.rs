@@ -0,0 +1,718 @@
+//! Unix 域 socket
+//!
+//! 支持流式、数据报和有序数据包三种类型。Unix socket 不经过网络协议栈，发送的消息直接
+//! 放入对端的接收队列。绑定到路径上的 socket 在文件系统中创建 socket 类型的占位文件，
+//! 通过 [special::bind_socket] 按照占位文件的 inode 登记，抽象名字登记在 [ABSTRACT_NAMES] 中。
+//!
+//! 消息可以携带 `SCM_RIGHTS` 传递的文件，文件在队列中以 [File] 的形式保存，接收时再放入
+//! 接收方任务的文件描述符表，所以可以在不同任务之间传递。
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::file::File;
+use libc_core::{
+    fcntl::OpenFlags,
+    poll::PollEvent,
+    types::{Stat, StatMode},
+};
+use spin::Mutex;
+use syscalls::Errno;
+use vfscore::{INodeInterface, VfsResult};
+
+use super::socket::{SHUT_RD, SHUT_RDWR, SHUT_WR, SOCK_DGRAM, SOCK_STREAM};
+use crate::fs::{
+    registry::{ANON_DEV, InodeRegistry, alloc_ino},
+    special,
+    wait_queue::WaitQueue,
+};
+
+/// 所有存活的 Unix socket
+static UNIX_INODES: InodeRegistry<UnixSocket> = InodeRegistry::new();
+
+/// 绑定了抽象名字的 socket
+static ABSTRACT_NAMES: Mutex<BTreeMap<Vec<u8>, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+
+/// 下一个自动绑定使用的名字
+static NEXT_AUTOBIND: AtomicUsize = AtomicUsize::new(0);
+
+/// Unix 地址族
+pub const AF_UNIX: usize = 1;
+/// 有序数据包 socket
+pub const SOCK_SEQPACKET: usize = 5;
+
+/// `sun_path` 的最大长度
+pub const UNIX_PATH_MAX: usize = 108;
+
+/// 一条消息最多携带的文件数量
+pub const SCM_MAX_FD: usize = 253;
+
+/// 接收队列的大小
+pub const UNIX_BUFFER_SIZE: usize = 0x10000;
+
+/// 数据报和有序数据包接收队列中最多保存的消息数量
+const UNIX_MAX_MESSAGES: usize = 64;
+
+/// 监听队列的最大长度
+const UNIX_MAX_BACKLOG: usize = 128;
+
+/// Unix socket 地址
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnixAddr {
+    /// 没有绑定
+    #[default]
+    Unnamed,
+    /// 绑定到文件系统中的路径，保存的是绝对路径
+    Path(String),
+    /// 抽象名字，不出现在文件系统中
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    /// 解析 `struct sockaddr_un`
+    ///
+    /// `bytes` 为用户传入的完整地址，长度就是 `addrlen`。相对路径需要由调用者转换为
+    /// 绝对路径。
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Errno> {
+        if bytes.len() < size_of::<u16>() || bytes.len() > size_of::<u16>() + UNIX_PATH_MAX {
+            return Err(Errno::EINVAL);
+        }
+        if u16::from_ne_bytes([bytes[0], bytes[1]]) as usize != AF_UNIX {
+            return Err(Errno::EAFNOSUPPORT);
+        }
+        let path = &bytes[size_of::<u16>()..];
+        match path.first() {
+            None => Ok(Self::Unnamed),
+            Some(0) => Ok(Self::Abstract(path[1..].to_vec())),
+            Some(_) => {
+                let end = path.iter().position(|x| *x == 0).unwrap_or(path.len());
+                let path = String::from_utf8(path[..end].to_vec()).map_err(|_| Errno::EINVAL)?;
+                Ok(Self::Path(path))
+            }
+        }
+    }
+
+    /// 转换为 `struct sockaddr_un`，长度和 Linux 返回的地址长度一致
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (AF_UNIX as u16).to_ne_bytes().to_vec();
+        match self {
+            Self::Unnamed => {}
+            Self::Path(path) => {
+                bytes.extend_from_slice(path.as_bytes());
+                bytes.push(0);
+            }
+            Self::Abstract(name) => {
+                bytes.push(0);
+                bytes.extend_from_slice(name);
+            }
+        }
+        bytes
+    }
+}
+
+/// 接收队列中的消息
+struct UnixMessage {
+    /// 消息的数据
+    data: Vec<u8>,
+    /// 已经读取的长度，只有流式 socket 会读取一部分
+    offset: usize,
+    /// `SCM_RIGHTS` 传递的文件，第一次读取消息时取出
+    files: Vec<Arc<File>>,
+    /// 发送方的地址
+    from: UnixAddr,
+    /// 发送方，消息被读取之后唤醒等待发送的任务
+    sender: Weak<UnixSocket>,
+}
+
+/// 接收到的数据
+pub struct UnixRecv {
+    /// 读取的长度
+    pub len: usize,
+    /// 消息的完整长度，数据报被截断时大于 `len`
+    pub msg_len: usize,
+    /// 消息携带的文件
+    pub files: Vec<Arc<File>>,
+    /// 发送方的地址
+    pub from: UnixAddr,
+}
+
+/// socket 的连接状态
+enum UnixState {
+    /// 刚创建或者只绑定了地址
+    Idle,
+    /// 正在监听
+    Listening {
+        /// 监听队列的长度
+        backlog: usize,
+        /// 已经建立但是还没有 `accept` 的连接
+        pending: VecDeque<Arc<UnixSocket>>,
+        /// 监听队列已满时等待的 socket
+        connecting: Vec<Weak<UnixSocket>>,
+    },
+    /// 已经连接到对端，数据报 socket 为 `connect` 设置的默认目标
+    Connected(Weak<UnixSocket>),
+}
+
+struct UnixInner {
+    /// 连接状态
+    state: UnixState,
+    /// 绑定的地址
+    local: UnixAddr,
+    /// 接收队列
+    rx: VecDeque<UnixMessage>,
+    /// 接收队列中还没有读取的字节数
+    rx_bytes: usize,
+    /// 读端是否已经关闭
+    read_shutdown: bool,
+    /// 写端是否已经关闭
+    write_shutdown: bool,
+    /// 对端是否已经不会再发送数据
+    eof: bool,
+    /// 对端是否已经关闭
+    peer_closed: bool,
+}
+
+/// Unix 域 socket
+pub struct UnixSocket {
+    /// socket 类型，[SOCK_STREAM]、[SOCK_DGRAM] 或者 [SOCK_SEQPACKET]
+    ty: usize,
+    /// 创建 socket 的进程，`SO_PEERCRED` 返回对端的进程
+    pid: usize,
+    /// 指向自己，发送消息和绑定地址时使用
+    this: Weak<UnixSocket>,
+    /// socket 状态
+    inner: Mutex<UnixInner>,
+    /// 等待队列
+    wait: Arc<WaitQueue>,
+}
+
+/// 根据地址查找绑定的 socket
+///
+/// 路径不存在时返回 `ENOENT`，路径上没有存活的 socket 时返回 `ECONNREFUSED`
+pub fn lookup(addr: &UnixAddr) -> Result<Arc<UnixSocket>, Errno> {
+    match addr {
+        UnixAddr::Unnamed => Err(Errno::EINVAL),
+        UnixAddr::Path(path) => {
+            let file = File::open(path.clone().into(), OpenFlags::RDONLY)?;
+            special::lookup(&file)
+                .and_then(|node| node.socket())
+                .ok_or(Errno::ECONNREFUSED)
+        }
+        UnixAddr::Abstract(name) => ABSTRACT_NAMES
+            .lock()
+            .get(name)
+            .and_then(Weak::upgrade)
+            .ok_or(Errno::ECONNREFUSED),
+    }
+}
+
+impl UnixSocket {
+    /// 创建一个新的 socket
+    ///
+    /// ## 参数
+    /// - `ty`       socket 类型
+    /// - `protocol` 协议，只能为 0
+    /// - `pid`      创建 socket 的进程
+    pub fn new(ty: usize, protocol: usize, pid: usize) -> Result<Arc<Self>, Errno> {
+        match (ty, protocol) {
+            (SOCK_STREAM | SOCK_DGRAM | SOCK_SEQPACKET, 0 | AF_UNIX) => {}
+            (SOCK_STREAM | SOCK_DGRAM | SOCK_SEQPACKET, _) => return Err(Errno::EPROTONOSUPPORT),
+            _ => return Err(Errno::ESOCKTNOSUPPORT),
+        }
+        Ok(Self::with_state(ty, pid, UnixState::Idle))
+    }
+
+    /// 创建一对互相连接的 socket，`socketpair` 使用
+    pub fn pair(ty: usize, protocol: usize, pid: usize) -> Result<(Arc<Self>, Arc<Self>), Errno> {
+        let first = Self::new(ty, protocol, pid)?;
+        let second = Self::with_state(ty, pid, UnixState::Connected(Arc::downgrade(&first)));
+        first.inner.lock().state = UnixState::Connected(Arc::downgrade(&second));
+        Ok((first, second))
+    }
+
+    fn with_state(ty: usize, pid: usize, state: UnixState) -> Arc<Self> {
+        let ino = alloc_ino();
+        let socket = Arc::new_cyclic(|this| Self {
+            ty,
+            pid,
+            this: this.clone(),
+            inner: Mutex::new(UnixInner {
+                state,
+                local: UnixAddr::Unnamed,
+                rx: VecDeque::new(),
+                rx_bytes: 0,
+                read_shutdown: false,
+                write_shutdown: false,
+                eof: false,
+                peer_closed: false,
+            }),
+            wait: WaitQueue::new(ino),
+        });
+        UNIX_INODES.insert(ino, &socket);
+        socket
+    }
+
+    /// 从打开的 [File] 中获取 [UnixSocket]
+    ///
+    /// 如果文件不是 Unix socket，返回 [Option::None]
+    pub fn from_file(file: &File) -> Option<Arc<Self>> {
+        UNIX_INODES.from_file(file)
+    }
+
+    /// socket 类型
+    pub const fn ty(&self) -> usize {
+        self.ty
+    }
+
+    /// 绑定地址，[UnixAddr::Unnamed] 表示自动绑定一个抽象名字
+    pub fn bind(&self, addr: UnixAddr) -> Result<(), Errno> {
+        let mut inner = self.inner.lock();
+        if inner.local != UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        let addr = match addr {
+            UnixAddr::Unnamed => self.autobind(),
+            UnixAddr::Path(path) => {
+                // 路径已经存在时不能绑定，socket 关闭后需要先 unlink
+                if File::open(path.clone().into(), OpenFlags::RDONLY).is_ok() {
+                    return Err(Errno::EADDRINUSE);
+                }
+                let file = File::open(path.clone().into(), OpenFlags::CREAT | OpenFlags::RDWR)?;
+                special::bind_socket(&file, self.this.clone())?;
+                UnixAddr::Path(path)
+            }
+            UnixAddr::Abstract(name) => {
+                let mut names = ABSTRACT_NAMES.lock();
+                if names.get(&name).is_some_and(|x| x.strong_count() > 0) {
+                    return Err(Errno::EADDRINUSE);
+                }
+                names.insert(name.clone(), self.this.clone());
+                UnixAddr::Abstract(name)
+            }
+        };
+        inner.local = addr;
+        Ok(())
+    }
+
+    /// 分配一个没有使用的抽象名字
+    fn autobind(&self) -> UnixAddr {
+        let mut names = ABSTRACT_NAMES.lock();
+        loop {
+            let id = NEXT_AUTOBIND.fetch_add(1, Ordering::SeqCst) & 0xfffff;
+            let name = format!("{:05x}", id).into_bytes();
+            if names.get(&name).is_none_or(|x| x.strong_count() == 0) {
+                names.insert(name.clone(), self.this.clone());
+                return UnixAddr::Abstract(name);
+            }
+        }
+    }
+
+    /// 开始监听连接
+    pub fn listen(&self, backlog: usize) -> Result<(), Errno> {
+        if self.ty == SOCK_DGRAM {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let mut inner = self.inner.lock();
+        if inner.local == UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        let backlog = backlog.clamp(1, UNIX_MAX_BACKLOG);
+        match &mut inner.state {
+            UnixState::Idle => {}
+            UnixState::Listening { backlog: old, .. } => {
+                *old = backlog;
+                return Ok(());
+            }
+            UnixState::Connected(_) => return Err(Errno::EINVAL),
+        }
+        inner.state = UnixState::Listening {
+            backlog,
+            pending: VecDeque::new(),
+            connecting: Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// 接受一个连接，没有等待的连接时返回 `EAGAIN`
+    pub fn accept(&self) -> Result<Arc<Self>, Errno> {
+        let mut inner = self.inner.lock();
+        let UnixState::Listening {
+            pending,
+            connecting,
+            ..
+        } = &mut inner.state
+        else {
+            return Err(Errno::EINVAL);
+        };
+        let accepted = pending.pop_front().ok_or(Errno::EAGAIN)?;
+        let connecting = core::mem::take(connecting);
+        drop(inner);
+        // 监听队列有了空位，唤醒等待连接的 socket
+        connecting
+            .iter()
+            .filter_map(Weak::upgrade)
+            .for_each(|x| x.wait.wake_all());
+        Ok(accepted)
+    }
+
+    /// 连接到 `target`
+    ///
+    /// 流式和有序数据包 socket 的连接会立即建立，目标的监听队列已满时返回 `EAGAIN`。
+    /// 数据报 socket 只设置默认的目标。
+    pub fn connect(&self, target: &Arc<Self>) -> Result<(), Errno> {
+        if target.ty != self.ty {
+            return Err(Errno::EPROTOTYPE);
+        }
+        if self.ty == SOCK_DGRAM {
+            self.inner.lock().state = UnixState::Connected(Arc::downgrade(target));
+            return Ok(());
+        }
+        match self.inner.lock().state {
+            UnixState::Idle => {}
+            UnixState::Connected(_) => return Err(Errno::EISCONN),
+            UnixState::Listening { .. } => return Err(Errno::EINVAL),
+        }
+
+        let mut target_inner = target.inner.lock();
+        let local = target_inner.local.clone();
+        let UnixState::Listening {
+            backlog,
+            pending,
+            connecting,
+        } = &mut target_inner.state
+        else {
+            return Err(Errno::ECONNREFUSED);
+        };
+        if pending.len() >= *backlog {
+            connecting.push(self.this.clone());
+            return Err(Errno::EAGAIN);
+        }
+        // 服务端的 socket 使用监听 socket 的地址
+        let server = Self::with_state(self.ty, target.pid, UnixState::Connected(self.this.clone()));
+        server.inner.lock().local = local;
+        pending.push_back(server.clone());
+        drop(target_inner);
+        target.wait.wake_all();
+
+        self.inner.lock().state = UnixState::Connected(Arc::downgrade(&server));
+        Ok(())
+    }
+
+    /// 已经连接的对端
+    fn peer(&self) -> Option<Weak<Self>> {
+        match &self.inner.lock().state {
+            UnixState::Connected(peer) => Some(peer.clone()),
+            _ => None,
+        }
+    }
+
+    /// 发送消息
+    ///
+    /// ## 参数
+    /// - `data`  需要发送的数据，流式 socket 可能只发送一部分
+    /// - `files` `SCM_RIGHTS` 传递的文件
+    /// - `to`    数据报的目标，为空时使用 `connect` 设置的目标
+    pub fn send(
+        &self,
+        data: &[u8],
+        files: &[Arc<File>],
+        to: Option<&Arc<Self>>,
+    ) -> Result<usize, Errno> {
+        let (peer, from) = {
+            let inner = self.inner.lock();
+            if inner.write_shutdown {
+                return Err(Errno::EPIPE);
+            }
+            let peer = match &inner.state {
+                UnixState::Connected(peer) => Some(peer.clone()),
+                _ => None,
+            };
+            (peer, inner.local.clone())
+        };
+        let peer = match (self.ty, to, peer) {
+            (SOCK_DGRAM, Some(to), _) => to.clone(),
+            (SOCK_DGRAM, None, Some(peer)) => peer.upgrade().ok_or(Errno::ECONNREFUSED)?,
+            (_, Some(_), Some(_)) => return Err(Errno::EISCONN),
+            (_, Some(_), None) => return Err(Errno::EOPNOTSUPP),
+            (_, None, Some(peer)) => peer.upgrade().ok_or(Errno::EPIPE)?,
+            (_, None, None) => return Err(Errno::ENOTCONN),
+        };
+        if self.ty == SOCK_DGRAM && peer.ty != SOCK_DGRAM {
+            return Err(Errno::EPROTOTYPE);
+        }
+
+        let mut peer_inner = peer.inner.lock();
+        let len = match self.ty {
+            SOCK_STREAM => {
+                if peer_inner.read_shutdown {
+                    return Err(Errno::EPIPE);
+                }
+                let free = UNIX_BUFFER_SIZE - peer_inner.rx_bytes;
+                if data.is_empty() && files.is_empty() {
+                    return Ok(0);
+                } else if free == 0 {
+                    return Err(Errno::EAGAIN);
+                }
+                data.len().min(free)
+            }
+            _ => {
+                if self.ty == SOCK_SEQPACKET && peer_inner.read_shutdown {
+                    return Err(Errno::EPIPE);
+                }
+                if data.len() > UNIX_BUFFER_SIZE {
+                    return Err(Errno::EMSGSIZE);
+                }
+                if peer_inner.rx_bytes + data.len() > UNIX_BUFFER_SIZE
+                    || peer_inner.rx.len() >= UNIX_MAX_MESSAGES
+                {
+                    return Err(Errno::EAGAIN);
+                }
+                data.len()
+            }
+        };
+        peer_inner.rx.push_back(UnixMessage {
+            data: data[..len].to_vec(),
+            offset: 0,
+            files: files.to_vec(),
+            from,
+            sender: self.this.clone(),
+        });
+        peer_inner.rx_bytes += len;
+        drop(peer_inner);
+        peer.wait.wake_all();
+        Ok(len)
+    }
+
+    /// 接收消息
+    ///
+    /// ## 参数
+    /// - `buffer` 接收数据的缓冲区
+    /// - `peek`   是否保留接收到的数据 (`MSG_PEEK`)
+    ///
+    /// 流式 socket 会合并多条消息，但是不会越过携带文件的消息。数据报和有序数据包每次
+    /// 只接收一条消息，缓冲区不够时丢弃剩下的数据。
+    pub fn recv(&self, buffer: &mut [u8], peek: bool) -> Result<UnixRecv, Errno> {
+        let mut inner = self.inner.lock();
+        match inner.state {
+            UnixState::Listening { .. } => return Err(Errno::EINVAL),
+            UnixState::Idle if self.ty != SOCK_DGRAM => return Err(Errno::ENOTCONN),
+            _ => {}
+        }
+        let mut res = UnixRecv {
+            len: 0,
+            msg_len: 0,
+            files: Vec::new(),
+            from: UnixAddr::Unnamed,
+        };
+        if inner.read_shutdown {
+            return Ok(res);
+        }
+        if inner.rx.is_empty() {
+            return match self.ty != SOCK_DGRAM && inner.eof {
+                true => Ok(res),
+                false => Err(Errno::EAGAIN),
+            };
+        }
+
+        let mut senders = Vec::new();
+        if self.ty == SOCK_STREAM {
+            let mut index = 0;
+            let mut offset = inner.rx[0].offset;
+            while res.len < buffer.len() && index < inner.rx.len() {
+                let message = &mut inner.rx[index];
+                if res.len > 0 && !message.files.is_empty() {
+                    break;
+                }
+                let len = (message.data.len() - offset).min(buffer.len() - res.len);
+                buffer[res.len..res.len + len].copy_from_slice(&message.data[offset..offset + len]);
+                res.len += len;
+                res.from = message.from.clone();
+                let has_files = !message.files.is_empty();
+                if has_files {
+                    res.files = match peek {
+                        true => message.files.clone(),
+                        false => core::mem::take(&mut message.files),
+                    };
+                }
+                if !peek {
+                    message.offset += len;
+                }
+                if offset + len < message.data.len() {
+                    break;
+                }
+                index += 1;
+                offset = inner.rx.get(index).map_or(0, |x| x.offset);
+                if has_files {
+                    break;
+                }
+            }
+            res.msg_len = res.len;
+            if !peek {
+                inner.rx_bytes -= res.len;
+                while inner.rx.front().is_some_and(|x| x.offset == x.data.len()) {
+                    senders.push(inner.rx.pop_front().unwrap().sender);
+                }
+            }
+        } else {
+            let message = inner.rx.front_mut().unwrap();
+            res.len = message.data.len().min(buffer.len());
+            res.msg_len = message.data.len();
+            buffer[..res.len].copy_from_slice(&message.data[..res.len]);
+            res.from = message.from.clone();
+            res.files = match peek {
+                true => message.files.clone(),
+                false => core::mem::take(&mut message.files),
+            };
+            if !peek {
+                let message = inner.rx.pop_front().unwrap();
+                inner.rx_bytes -= message.data.len();
+                senders.push(message.sender);
+            }
+        }
+        drop(inner);
+        // 接收队列有了空间，唤醒等待发送的 socket
+        senders
+            .iter()
+            .filter_map(Weak::upgrade)
+            .for_each(|x| x.wait.wake_all());
+        Ok(res)
+    }
+
+    /// 关闭连接的读端或者写端
+    pub fn shutdown(&self, how: usize) -> Result<(), Errno> {
+        if !matches!(how, SHUT_RD | SHUT_WR | SHUT_RDWR) {
+            return Err(Errno::EINVAL);
+        }
+        let peer = {
+            let mut inner = self.inner.lock();
+            let UnixState::Connected(peer) = &inner.state else {
+                return Err(Errno::ENOTCONN);
+            };
+            let peer = peer.upgrade();
+            if how != SHUT_WR {
+                inner.read_shutdown = true;
+            }
+            if how != SHUT_RD {
+                inner.write_shutdown = true;
+            }
+            peer
+        };
+        self.wait.wake_all();
+        // 对端读取完剩下的数据之后读到文件结尾
+        if let Some(peer) = peer.filter(|_| how != SHUT_RD) {
+            peer.inner.lock().eof = true;
+            peer.wait.wake_all();
+        }
+        Ok(())
+    }
+
+    /// 绑定的地址
+    pub fn local_addr(&self) -> UnixAddr {
+        self.inner.lock().local.clone()
+    }
+
+    /// 对端的地址，没有连接时返回 `ENOTCONN`
+    pub fn peer_addr(&self) -> Result<UnixAddr, Errno> {
+        let peer = self.peer().ok_or(Errno::ENOTCONN)?;
+        let peer = peer.upgrade().ok_or(Errno::ENOTCONN)?;
+        Ok(peer.local_addr())
+    }
+
+    /// 对端的进程，`SO_PEERCRED` 使用
+    pub fn peer_pid(&self) -> Option<usize> {
+        Some(self.peer()?.upgrade()?.pid)
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        UNIX_INODES.remove(self.wait.ino());
+        let inner = self.inner.get_mut();
+        // 路径上的占位文件保留，和 Linux 一样需要 unlink 之后才能重新绑定
+        if let UnixAddr::Abstract(name) = &inner.local {
+            let mut names = ABSTRACT_NAMES.lock();
+            if names
+                .get(name)
+                .is_some_and(|x| x.as_ptr() == self as *const Self)
+            {
+                names.remove(name);
+            }
+        }
+        // 数据报 socket 不会通知默认的目标
+        let peer = match &inner.state {
+            UnixState::Connected(peer) if self.ty != SOCK_DGRAM => peer.upgrade(),
+            _ => None,
+        };
+        if let Some(peer) = peer {
+            let mut peer_inner = peer.inner.lock();
+            peer_inner.eof = true;
+            peer_inner.peer_closed = true;
+            drop(peer_inner);
+            peer.wait.wake_all();
+        }
+    }
+}
+
+impl INodeInterface for UnixSocket {
+    fn readat(&self, _offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        // 通过 read 接收时消息携带的文件被丢弃
+        self.recv(buffer, false).map(|x| x.len)
+    }
+
+    fn writeat(&self, _offset: usize, buffer: &[u8]) -> VfsResult<usize> {
+        self.send(buffer, &[], None)
+    }
+
+    fn poll(&self, events: PollEvent) -> VfsResult<PollEvent> {
+        let inner = self.inner.lock();
+        let connected = self.ty != SOCK_DGRAM;
+        let (readable, peer) = match &inner.state {
+            UnixState::Listening { pending, .. } => (!pending.is_empty(), None),
+            UnixState::Connected(peer) => (
+                !inner.rx.is_empty() || (connected && inner.eof),
+                Some(peer.clone()),
+            ),
+            UnixState::Idle => (!inner.rx.is_empty(), None),
+        };
+        let readable = readable || inner.read_shutdown;
+        let hangup =
+            connected && (inner.peer_closed || (inner.read_shutdown && inner.write_shutdown));
+        let write_shutdown = inner.write_shutdown;
+        let listening = matches!(inner.state, UnixState::Listening { .. });
+        drop(inner);
+
+        // 对端关闭时写入会立即返回 EPIPE，也认为是可写的
+        let writable = match peer.map(|x| x.upgrade()) {
+            _ if write_shutdown || listening => false,
+            Some(Some(peer)) => {
+                let peer_inner = peer.inner.lock();
+                peer_inner.rx_bytes < UNIX_BUFFER_SIZE && peer_inner.rx.len() < UNIX_MAX_MESSAGES
+            }
+            Some(None) => true,
+            None => self.ty == SOCK_DGRAM,
+        };
+        let mut res = PollEvent::NONE;
+        if events.contains(PollEvent::IN) && readable {
+            res |= PollEvent::IN;
+        }
+        if events.contains(PollEvent::OUT) && writable {
+            res |= PollEvent::OUT;
+        }
+        if hangup {
+            res |= PollEvent::HUP;
+        }
+        Ok(res)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> VfsResult<()> {
+        stat.dev = ANON_DEV as _;
+        stat.ino = self.wait.ino() as _;
+        stat.mode = StatMode::SOCKET;
+        stat.nlink = 1;
+        Ok(())
+    }
+}
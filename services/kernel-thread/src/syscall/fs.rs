@@ -2,26 +2,99 @@
 //!
 //!
 
-use core::time::Duration;
+use core::{cmp, pin::pin, time::Duration};
 
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use bit_field::BitArray;
 use fs::{FileType, SeekFrom, file::File};
+use futures::future::{Either, select};
 use libc_core::{
     consts::UTIME_NOW,
     fcntl::{AT_FDCWD, AT_SYMLINK_NOFOLLOW, FcntlCmd, OpenFlags},
     poll::{PollEvent, PollFd},
-    types::{IoVec, Stat, StatFS, TimeSpec},
+    signal::SignalNum,
+    types::{IoVec, SigSet, Stat, StatFS, TimeSpec},
 };
 use num_enum::TryFromPrimitive;
 use sel4_kit::arch::current_time;
 use syscalls::Errno;
 use zerocopy::{FromBytes, FromZeros, IntoBytes};
 
-use crate::{fs::pipe::create_pipe, task::Sel4Task, timer::wait_time};
+use crate::{
+    child_test::TASK_MAP,
+    fs::{
+        devfs::{self, pty::PtyMaster},
+        epoll::{EpollCtl, EpollEvent, EpollFile},
+        eventfd::{EFD_SEMAPHORE, EventFd},
+        ipc_fs,
+        pipe::{F_GETPIPE_SZ, F_SETPIPE_SZ, Pipe, create_pipe},
+        special,
+        tty::{
+            Tty,
+            ldisc::{Termios, WinSize},
+        },
+        wait_queue::WaitQueue,
+    },
+    task::Sel4Task,
+    timer::wait_time,
+};
 
 use super::SysResult;
 
+/// `copy_file_range` 每次复制的最大长度
+const COPY_CHUNK_SIZE: usize = 0x10000;
+
+/// 获取终端设置
+const TCGETS: usize = 0x5401;
+/// 修改终端设置
+const TCSETS: usize = 0x5402;
+/// 等待输出完成后修改终端设置
+const TCSETSW: usize = 0x5403;
+/// 等待输出完成并清空输入后修改终端设置
+const TCSETSF: usize = 0x5404;
+/// 发送 BREAK
+const TCSBRK: usize = 0x5409;
+/// 暂停或恢复输入输出
+const TCXONC: usize = 0x540A;
+/// 清空输入或者输出
+const TCFLSH: usize = 0x540B;
+/// 设置控制终端
+const TIOCSCTTY: usize = 0x540E;
+/// 获取前台进程组
+const TIOCGPGRP: usize = 0x540F;
+/// 设置前台进程组
+const TIOCSPGRP: usize = 0x5410;
+/// 获取输出缓冲区中的字节数
+const TIOCOUTQ: usize = 0x5411;
+/// 获取窗口大小
+const TIOCGWINSZ: usize = 0x5413;
+/// 设置窗口大小
+const TIOCSWINSZ: usize = 0x5414;
+/// 获取可以读取的字节数
+const FIONREAD: usize = 0x541B;
+/// 放弃控制终端
+const TIOCNOTTY: usize = 0x5422;
+/// 获取终端所属的会话
+const TIOCGSID: usize = 0x5429;
+
+/// 获取伪终端编号
+const TIOCGPTN: usize = 0x80045430;
+/// 锁定或者解锁伪终端从设备
+const TIOCSPTLCK: usize = 0x40045431;
+/// 获取伪终端从设备是否被锁定
+const TIOCGPTLCK: usize = 0x80045439;
+/// 设置伪终端包模式
+const TIOCPKT: usize = 0x5420;
+/// 打开伪终端从设备
+const TIOCGPTPEER: usize = 0x5441;
+
+/// `TCFLSH` 清空输入
+const TCIFLUSH: usize = 0;
+/// `TCFLSH` 清空输出
+const TCOFLUSH: usize = 1;
+/// `TCFLSH` 清空输入和输出
+const TCIOFLUSH: usize = 2;
+
 pub(super) fn sys_chdir(task: &Sel4Task, path: *const u8) -> SysResult {
     let dir = task.fd_open(AT_FDCWD, path, OpenFlags::DIRECTORY)?;
     // 确保路径存在
@@ -79,6 +152,7 @@ pub(super) fn sys_fstatat(
     };
     let mut stat: Stat = Stat::default();
     file.stat(&mut stat)?;
+    special::fill_stat(&file, &mut stat);
 
     task.write_bytes(stat_ptr as _, stat.as_bytes());
     Ok(0)
@@ -115,19 +189,23 @@ pub(super) fn sys_getdents64(
 }
 
 pub(super) fn sys_pipe2(task: &Sel4Task, fdsp: *const u32, flags: u64) -> SysResult {
-    if flags != 0 {
-        panic!("flags != 0 is not supported");
-    }
     log::debug!("pipe2 {:#p} {:#x}", fdsp, flags);
+    let flags = OpenFlags::from_bits(flags as _).ok_or(Errno::EINVAL)?;
+    if !(flags - OpenFlags::NONBLOCK - OpenFlags::CLOEXEC).is_empty() {
+        return Err(Errno::EINVAL);
+    }
     let (rxp, txp) = create_pipe();
-    let mut file_table = task.file.file_ds.lock();
+    let rx_file = File::new_dev(rxp);
+    *rx_file.flags.lock() = OpenFlags::RDONLY | (flags & OpenFlags::NONBLOCK);
+    let tx_file = File::new_dev(txp);
+    *tx_file.flags.lock() = OpenFlags::WRONLY | (flags & OpenFlags::NONBLOCK);
 
-    let rx = file_table
-        .add(File::new_dev(rxp))
-        .map_err(|_| Errno::EMFILE)? as u32;
-    let tx = file_table
-        .add(File::new_dev(txp))
-        .map_err(|_| Errno::EMFILE)? as u32;
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() + 2 > task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    let rx = file_table.add(rx_file).map_err(|_| Errno::EMFILE)? as u32;
+    let tx = file_table.add(tx_file).map_err(|_| Errno::EMFILE)? as u32;
     task.write_bytes(fdsp as _, [rx, tx].as_bytes());
 
     Ok(0)
@@ -149,7 +227,11 @@ pub(super) async fn sys_read(
         .get_mut(fd)
         .ok_or(Errno::EBADF)?
         .clone();
+    if let Some(tty) = Tty::from_file(&file) {
+        tty.check_background(task, false)?;
+    }
     let mut buffer = vec![0u8; count];
+    let wait = WaitQueue::from_file(&file);
     let rlen = loop {
         let res = file.read(&mut buffer);
         if let Ok(rlen) = res {
@@ -158,7 +240,16 @@ pub(super) async fn sys_read(
         if task.has_unmasked_signal() {
             return Err(Errno::EINTR);
         } else if let Err(Errno::EAGAIN) = res {
-            wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
+            if file.flags.lock().contains(OpenFlags::NONBLOCK) {
+                res?;
+            }
+            // 有等待队列的文件等待状态变化，否则定时轮询
+            match &wait {
+                Some(wait) => wait.wait(task.tid).await?,
+                None => {
+                    wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
+                }
+            }
         } else {
             res?;
         }
@@ -208,26 +299,97 @@ pub(super) fn sys_pread64(
 }
 
 pub(super) fn sys_unlinkat(task: &Sel4Task, fd: isize, path: *const u8, _flags: u64) -> SysResult {
-    task.fd_open(fd, path, OpenFlags::RDONLY)?.remove_self()?;
+    let path = task.fd_resolve(fd, path)?;
+    let file = File::open(path, OpenFlags::RDONLY)?;
+    special::remove(&file);
+    file.remove_self()?;
+    Ok(0)
+}
+
+pub(super) fn sys_mknodat(
+    task: &Sel4Task,
+    dirfd: isize,
+    path_ptr: *const u8,
+    mode: usize,
+    dev: usize,
+) -> SysResult {
+    let path = task.fd_resolve(dirfd, path_ptr)?;
+    debug!(
+        "[task {}] sys_mknodat @ path: {}, mode: {:#o}, dev: {:#x}",
+        task.tid,
+        path.path(),
+        mode,
+        dev
+    );
+    let mode = mode as u32;
+    let file_type = mode & special::S_IFMT;
+    if !matches!(
+        file_type,
+        0 | special::S_IFREG
+            | special::S_IFIFO
+            | special::S_IFCHR
+            | special::S_IFBLK
+            | special::S_IFSOCK
+    ) {
+        return Err(Errno::EINVAL);
+    }
+    if File::open(path.clone(), OpenFlags::RDONLY).is_ok() {
+        return Err(Errno::EEXIST);
+    }
+    // 特殊文件在底层文件系统中使用普通文件占位，文件类型登记在 special 模块中
+    let file = File::open(path, OpenFlags::CREAT | OpenFlags::RDWR)?;
+    if file_type != 0 && file_type != special::S_IFREG {
+        special::mknod(&file, mode, dev as _)?;
+    }
     Ok(0)
 }
 
 pub(super) async fn sys_write(task: &Sel4Task, fd: usize, buf: *const u8, len: usize) -> SysResult {
     let buf = task.read_bytes(buf as _, len).unwrap();
-    let mut file_table = task.file.file_ds.lock();
-    let file = file_table.get_mut(fd).ok_or(Errno::EBADF)?;
+    let file = task
+        .file
+        .file_ds
+        .lock()
+        .get(fd)
+        .ok_or(Errno::EBADF)?
+        .clone();
+    if let Some(tty) = Tty::from_file(&file) {
+        tty.check_background(task, true)?;
+    }
+    let wait = WaitQueue::from_file(&file);
 
+    // 管道等文件可能只写入一部分，阻塞模式下需要全部写入之后才返回
+    let mut written = 0;
     loop {
-        let res = file.write(&buf);
-        if let Ok(wlen) = res {
-            break Ok(wlen);
+        let res = file.write(&buf[written..]);
+        let nonblock = file.flags.lock().contains(OpenFlags::NONBLOCK);
+        match res {
+            Ok(wlen) => {
+                written += wlen;
+                if written == buf.len() || wlen == 0 || nonblock {
+                    break Ok(written);
+                }
+                continue;
+            }
+            Err(Errno::EAGAIN) if !nonblock => {}
+            Err(_) if written > 0 => break Ok(written),
+            Err(Errno::EPIPE) => {
+                task.add_signal(SignalNum::PIPE, task.tid);
+                return Err(Errno::EPIPE);
+            }
+            Err(err) => return Err(err),
         }
         if task.has_unmasked_signal() {
-            return Err(Errno::EINTR);
-        } else if let Err(Errno::EAGAIN) = res {
-            wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
-        } else {
-            res?;
+            return match written {
+                0 => Err(Errno::EINTR),
+                _ => Ok(written),
+            };
+        }
+        match &wait {
+            Some(wait) => wait.wait(task.tid).await?,
+            None => {
+                wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
+            }
         }
     }
 }
@@ -245,8 +407,11 @@ pub(super) async fn sys_writev(
 
     let iovec = <[IoVec]>::ref_from_bytes_with_elems(&iovec_bytes, iocnt).unwrap();
     for item in iovec.iter() {
-        sys_write(task, fd, item.base as _, item.len).await?;
-        wsize += item.len;
+        let wlen = sys_write(task, fd, item.base as _, item.len).await?;
+        wsize += wlen;
+        if wlen < item.len {
+            break;
+        }
     }
 
     Ok(wsize)
@@ -302,7 +467,7 @@ pub(super) fn sys_mkdirat(
     Ok(0)
 }
 
-pub(super) fn sys_openat(
+pub(super) async fn sys_openat(
     task: &Sel4Task,
     fd: isize,
     path: *const u8,
@@ -310,13 +475,38 @@ pub(super) fn sys_openat(
     _mode: usize,
 ) -> SysResult {
     let flags = OpenFlags::from_bits_truncate(flags);
-    let file = task.fd_open(fd, path, flags)?;
+    let path = task.fd_resolve(fd, path)?;
+    // 先检查已经存在的文件是否是 mknod 创建的特殊文件，避免 O_TRUNC 等标志修改占位文件
+    let node = special::lookup_path(&path);
+    let file = match node {
+        Some(_) if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) => {
+            return Err(Errno::EEXIST);
+        }
+        // mknod 创建的命名管道和设备文件
+        Some(node) => {
+            let file = File::new_dev(special::open(node, flags, task.tid).await?);
+            *file.flags.lock() = flags;
+            file
+        }
+        None => {
+            let file = Arc::new(File::open(path, flags)?);
+            // 每次打开都会创建新设备的文件，例如 /dev/ptmx
+            match devfs::open_cloned(&file, task.pid) {
+                Some(node) => {
+                    let file = File::new_dev(node?);
+                    *file.flags.lock() = flags;
+                    file
+                }
+                None => file,
+            }
+        }
+    };
 
     if task.file.file_ds.lock().count() >= task.file.rlimit.lock().curr {
         return Err(Errno::EMFILE);
     }
 
-    match task.file.file_ds.lock().add(Arc::new(file)) {
+    match task.file.file_ds.lock().add(file) {
         Ok(idx) => Ok(idx),
         Err(_) => Err(Errno::EMFILE),
     }
@@ -351,7 +541,6 @@ pub(super) fn sys_mount(
 
 /// TODO: 检查 `arg` 参数，完善 `fcntl` 系统调用
 pub(super) fn sys_fcntl(task: &Sel4Task, fd: usize, cmd: u32, arg: usize) -> SysResult {
-    let cmd = FcntlCmd::try_from_primitive(cmd).map_err(|_| Errno::EINVAL)?;
     // 检查文件是否存在
     let file = task
         .file
@@ -360,6 +549,17 @@ pub(super) fn sys_fcntl(task: &Sel4Task, fd: usize, cmd: u32, arg: usize) -> Sys
         .get_mut(fd)
         .ok_or(Errno::EBADF)?
         .clone();
+    // 管道相关的命令不在 FcntlCmd 中
+    match cmd {
+        F_GETPIPE_SZ => return Ok(Pipe::from_file(&file).ok_or(Errno::EBADF)?.capacity()),
+        F_SETPIPE_SZ => {
+            return Pipe::from_file(&file)
+                .ok_or(Errno::EBADF)?
+                .set_capacity(arg);
+        }
+        _ => {}
+    }
+    let cmd = FcntlCmd::try_from_primitive(cmd).map_err(|_| Errno::EINVAL)?;
     match cmd {
         FcntlCmd::DUPFD | FcntlCmd::DUPFDCLOEXEC => sys_dup(task, fd),
         FcntlCmd::SETFD => Ok(0),
@@ -475,6 +675,278 @@ pub(super) fn sys_sendfile(
     outfile.write(&buffer)
 }
 
+/// `splice` 和 `tee` 不阻塞
+const SPLICE_F_NONBLOCK: usize = 2;
+
+/// 根据文件描述符获取打开的文件
+fn get_file(task: &Sel4Task, fd: usize) -> Result<Arc<File>, Errno> {
+    task.file
+        .file_ds
+        .lock()
+        .get(fd)
+        .cloned()
+        .ok_or(Errno::EBADF)
+}
+
+/// 读取 `loff_t *` 类型的偏移，指针为空时返回 [Option::None]
+fn read_loff(task: &Sel4Task, off_ptr: *mut u64) -> Result<Option<usize>, Errno> {
+    if off_ptr.is_null() {
+        return Ok(None);
+    }
+    let off_bytes = task
+        .read_bytes(off_ptr as _, size_of::<u64>())
+        .ok_or(Errno::EFAULT)?;
+    Ok(Some(u64::read_from_bytes(&off_bytes).unwrap() as _))
+}
+
+/// 写回 `loff_t *` 类型的偏移
+fn write_loff(task: &Sel4Task, off_ptr: *mut u64, offset: Option<usize>) {
+    if let Some(offset) = offset {
+        task.write_bytes(off_ptr as _, (offset as u64).as_bytes());
+    }
+}
+
+pub(super) async fn sys_splice(
+    task: &Sel4Task,
+    fd_in: usize,
+    off_in_ptr: *mut u64,
+    fd_out: usize,
+    off_out_ptr: *mut u64,
+    len: usize,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_splice @ fd_in: {}, off_in: {:p}, fd_out: {}, off_out: {:p}, len: {:#x}, flags: {:#x}",
+        task.tid, fd_in, off_in_ptr, fd_out, off_out_ptr, len, flags
+    );
+    let file_in = get_file(task, fd_in)?;
+    let file_out = get_file(task, fd_out)?;
+    if file_in.flags.lock().contains(OpenFlags::WRONLY)
+        || !file_out
+            .flags
+            .lock()
+            .intersects(OpenFlags::WRONLY | OpenFlags::RDWR)
+    {
+        return Err(Errno::EBADF);
+    }
+    let pipe_in = Pipe::from_file(&file_in);
+    let pipe_out = Pipe::from_file(&file_out);
+    if (pipe_in.is_some() && !off_in_ptr.is_null())
+        || (pipe_out.is_some() && !off_out_ptr.is_null())
+    {
+        return Err(Errno::ESPIPE);
+    }
+    let off_in = read_loff(task, off_in_ptr)?;
+    let off_out = read_loff(task, off_out_ptr)?;
+    let queues: Vec<_> = [&file_in, &file_out]
+        .into_iter()
+        .filter_map(|x| WaitQueue::from_file(x))
+        .collect();
+    loop {
+        let res = match (&pipe_in, &pipe_out) {
+            (Some(pipe_in), Some(pipe_out)) => pipe_in.transfer_to(pipe_out, len, false),
+            (Some(pipe_in), None) => pipe_in.drain_to(&file_out, off_out, len),
+            (None, Some(pipe_out)) => pipe_out.fill_from(&file_in, off_in, len),
+            (None, None) => return Err(Errno::EINVAL),
+        };
+        match res {
+            Ok(n) => {
+                write_loff(task, off_in_ptr, off_in.map(|x| x + n));
+                write_loff(task, off_out_ptr, off_out.map(|x| x + n));
+                return Ok(n);
+            }
+            Err(Errno::EPIPE) => {
+                task.add_signal(SignalNum::PIPE, task.tid);
+                return Err(Errno::EPIPE);
+            }
+            Err(Errno::EAGAIN) if flags & SPLICE_F_NONBLOCK == 0 => {}
+            Err(err) => return Err(err),
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        }
+        WaitQueue::wait_any(queues.clone(), task.tid).await?;
+    }
+}
+
+pub(super) async fn sys_tee(
+    task: &Sel4Task,
+    fd_in: usize,
+    fd_out: usize,
+    len: usize,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_tee @ fd_in: {}, fd_out: {}, len: {:#x}, flags: {:#x}",
+        task.tid, fd_in, fd_out, len, flags
+    );
+    let file_in = get_file(task, fd_in)?;
+    let file_out = get_file(task, fd_out)?;
+    let pipe_in = Pipe::from_file(&file_in).ok_or(Errno::EINVAL)?;
+    let pipe_out = Pipe::from_file(&file_out).ok_or(Errno::EINVAL)?;
+    loop {
+        match pipe_in.transfer_to(&pipe_out, len, true) {
+            Ok(n) => return Ok(n),
+            Err(Errno::EPIPE) => {
+                task.add_signal(SignalNum::PIPE, task.tid);
+                return Err(Errno::EPIPE);
+            }
+            Err(Errno::EAGAIN) if flags & SPLICE_F_NONBLOCK == 0 => {}
+            Err(err) => return Err(err),
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        }
+        let queues = [&file_in, &file_out]
+            .into_iter()
+            .filter_map(|x| WaitQueue::from_file(x))
+            .collect();
+        WaitQueue::wait_any(queues, task.tid).await?;
+    }
+}
+
+pub(super) async fn sys_vmsplice(
+    task: &Sel4Task,
+    fd: usize,
+    iov: *const IoVec,
+    nr_segs: usize,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_vmsplice @ fd: {}, iov: {:p}, nr_segs: {}, flags: {:#x}",
+        task.tid, fd, iov, nr_segs, flags
+    );
+    let file = get_file(task, fd)?;
+    Pipe::from_file(&file).ok_or(Errno::EBADF)?;
+    // 用户空间和管道不在同一个地址空间，只能复制一次数据
+    match file
+        .flags
+        .lock()
+        .intersects(OpenFlags::WRONLY | OpenFlags::RDWR)
+    {
+        true => sys_writev(task, fd, iov, nr_segs).await,
+        false => sys_readv(task, fd, iov, nr_segs).await,
+    }
+}
+
+pub(super) fn sys_copy_file_range(
+    task: &Sel4Task,
+    fd_in: usize,
+    off_in_ptr: *mut u64,
+    fd_out: usize,
+    off_out_ptr: *mut u64,
+    len: usize,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_copy_file_range @ fd_in: {}, off_in: {:p}, fd_out: {}, off_out: {:p}, len: {:#x}",
+        task.tid, fd_in, off_in_ptr, fd_out, off_out_ptr, len
+    );
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let file_in = get_file(task, fd_in)?;
+    let file_out = get_file(task, fd_out)?;
+    let out_flags = *file_out.flags.lock();
+    if file_in.flags.lock().contains(OpenFlags::WRONLY)
+        || !out_flags.intersects(OpenFlags::WRONLY | OpenFlags::RDWR)
+        || out_flags.contains(OpenFlags::APPEND)
+    {
+        return Err(Errno::EBADF);
+    }
+    if file_in.file_type()? != FileType::File || file_out.file_type()? != FileType::File {
+        return Err(Errno::EINVAL);
+    }
+    let mut off_in = read_loff(task, off_in_ptr)?;
+    let mut off_out = read_loff(task, off_out_ptr)?;
+
+    // 同一个文件中的源区间和目标区间不能重叠
+    let start_in = off_in.map_or_else(|| file_in.seek(SeekFrom::CURRENT(0)), Ok)?;
+    let start_out = off_out.map_or_else(|| file_out.seek(SeekFrom::CURRENT(0)), Ok)?;
+    let (mut stat_in, mut stat_out) = (Stat::new_zeroed(), Stat::new_zeroed());
+    file_in.stat(&mut stat_in)?;
+    file_out.stat(&mut stat_out)?;
+    if (stat_in.dev, stat_in.ino) == (stat_out.dev, stat_out.ino)
+        && start_in < start_out + len
+        && start_out < start_in + len
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    // 两个文件在同一个文件系统服务中时由服务直接复制
+    if let Some(copied) = ipc_fs::copy_range(&file_in, start_in, &file_out, start_out, len) {
+        let copied = copied?;
+        if off_in.is_none() {
+            file_in.seek(SeekFrom::SET(start_in + copied))?;
+        }
+        if off_out.is_none() {
+            file_out.seek(SeekFrom::SET(start_out + copied))?;
+        }
+        write_loff(task, off_in_ptr, off_in.map(|_| start_in + copied));
+        write_loff(task, off_out_ptr, off_out.map(|_| start_out + copied));
+        return Ok(copied);
+    }
+
+    // 跨文件系统时只在 kernel-thread 中复制，不经过用户空间
+    let mut buffer = vec![0u8; cmp::min(len, COPY_CHUNK_SIZE)];
+    let mut copied = 0;
+    while copied < len {
+        let chunk = cmp::min(len - copied, buffer.len());
+        let rlen = match off_in {
+            Some(offset) => file_in.readat(offset, &mut buffer[..chunk]),
+            None => file_in.read(&mut buffer[..chunk]),
+        };
+        let rlen = match rlen {
+            Ok(0) => break,
+            Ok(rlen) => rlen,
+            Err(_) if copied > 0 => break,
+            Err(err) => return Err(err),
+        };
+        let wlen = match off_out {
+            Some(offset) => file_out.writeat(offset, &buffer[..rlen]),
+            None => file_out.write(&buffer[..rlen]),
+        };
+        let wlen = match wlen {
+            Ok(wlen) => wlen,
+            Err(_) if copied > 0 => break,
+            Err(err) => return Err(err),
+        };
+        off_in = off_in.map(|x| x + wlen);
+        off_out = off_out.map(|x| x + wlen);
+        copied += wlen;
+        if wlen < rlen {
+            break;
+        }
+    }
+    write_loff(task, off_in_ptr, off_in);
+    write_loff(task, off_out_ptr, off_out);
+    Ok(copied)
+}
+
+/// 等待文件状态变化或者到达 `etime`
+///
+/// 所有文件都有等待队列时在等待队列上等待，否则每 1ms 轮询一次
+async fn wait_files(task: &Sel4Task, files: &[Arc<File>], etime: Duration) -> Result<(), Errno> {
+    let queues: Option<Vec<_>> = files.iter().map(|x| WaitQueue::from_file(x)).collect();
+    let Some(queues) = queues else {
+        let next = etime.min(current_time() + Duration::new(0, 1000000));
+        wait_time(next, task.tid).await?;
+        return Ok(());
+    };
+    if etime == Duration::MAX {
+        return WaitQueue::wait_any(queues, task.tid).await;
+    }
+    match select(
+        pin!(WaitQueue::wait_any(queues, task.tid)),
+        pin!(wait_time(etime, task.tid)),
+    )
+    .await
+    {
+        Either::Left((res, _)) => res,
+        Either::Right((res, _)) => res.map(|_| ()),
+    }
+}
+
 pub(super) async fn sys_ppoll(
     task: &Sel4Task,
     poll_fds_ptr: *const PollFd,
@@ -500,23 +972,23 @@ pub(super) async fn sys_ppoll(
     };
     let n = loop {
         let mut num = 0;
+        let mut files = Vec::new();
         for poll_fd in poll_fds.iter_mut().take(nfds) {
-            poll_fd.revents = task
-                .file
-                .file_ds
-                .lock()
-                .get(poll_fd.fd as _)
+            let file = task.file.file_ds.lock().get(poll_fd.fd as _).cloned();
+            poll_fd.revents = file
+                .as_ref()
                 .map_or(PollEvent::NONE, |x| x.poll(poll_fd.events.clone()).unwrap());
             if poll_fd.revents != PollEvent::NONE {
                 num += 1;
             }
+            files.extend(file);
         }
 
         if current_time() >= etime || num > 0 {
             break num;
         }
 
-        wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
+        wait_files(task, &files, etime).await?;
     };
     task.write_bytes(poll_fds_ptr as _, poll_fds.as_bytes());
     Ok(n)
@@ -561,6 +1033,10 @@ pub(super) fn sys_renameat2(
         old_file.read(&mut buffer)?;
         new_file.write(&buffer)?;
         new_file.truncate(buffer.len())?;
+        // 特殊文件的登记需要跟随到新的文件
+        if let Some(node) = special::lookup(&old_file) {
+            special::moved(&node, &new_file);
+        }
     } else if old_file_type == FileType::Directory {
         task.fd_open(
             newdir_fd,
@@ -586,13 +1062,162 @@ pub(super) fn sys_ioctl(
         "[task {}] ioctl: fd: {}, request: {:#x}, args: {:#x} {:#x} {:#x}",
         task.tid, fd, request, arg1, arg2, arg3
     );
-    task.file
+    let file = task
+        .file
         .file_ds
         .lock()
         .get(fd)
         .ok_or(Errno::EINVAL)?
-        .ioctl(request, arg1)
-        .map_err(|_| Errno::ENOTTY)
+        .clone();
+    if let Some(tty) = Tty::from_file(&file) {
+        return tty_ioctl(task, &tty, request, arg1);
+    }
+    if let Some(master) = PtyMaster::from_file(&file) {
+        return pty_ioctl(task, &master, request, arg1);
+    }
+    file.ioctl(request, arg1).map_err(|_| Errno::ENOTTY)
+}
+
+/// 处理终端的 ioctl 请求
+fn tty_ioctl(task: &Sel4Task, tty: &Tty, request: usize, arg: usize) -> SysResult {
+    match request {
+        TCGETS => {
+            task.write_bytes(arg, tty.termios().as_bytes());
+        }
+        TCSETS | TCSETSW | TCSETSF => {
+            let bytes = task
+                .read_bytes(arg, size_of::<Termios>())
+                .ok_or(Errno::EFAULT)?;
+            if request == TCSETSF {
+                tty.flush_input();
+            }
+            tty.set_termios(Termios::read_from_bytes(&bytes).unwrap());
+        }
+        // 输出没有缓冲，不需要等待或者暂停输出
+        TCSBRK | TCXONC => {}
+        TIOCOUTQ | FIONREAD => {
+            let len = match request {
+                FIONREAD => tty.available() as i32,
+                _ => 0,
+            };
+            task.write_bytes(arg, len.as_bytes());
+        }
+        TCFLSH => match arg {
+            TCIFLUSH | TCIOFLUSH => tty.flush_input(),
+            TCOFLUSH => {}
+            _ => return Err(Errno::EINVAL),
+        },
+        TIOCGWINSZ => {
+            task.write_bytes(arg, tty.winsize().as_bytes());
+        }
+        TIOCSWINSZ => {
+            let bytes = task
+                .read_bytes(arg, size_of::<WinSize>())
+                .ok_or(Errno::EFAULT)?;
+            tty.set_winsize(WinSize::read_from_bytes(&bytes).unwrap());
+        }
+        TIOCSCTTY => {
+            let sid = task.pcb.sid();
+            if tty.session() == sid {
+                return Ok(0);
+            }
+            // 只有会话首进程可以获取控制终端，已经被其他会话使用的终端需要 arg 为 1 才能抢占
+            if sid != task.pid || (tty.session() != 0 && arg != 1) {
+                return Err(Errno::EPERM);
+            }
+            tty.set_session(sid);
+            tty.set_foreground(task.pcb.pgid());
+        }
+        TIOCNOTTY => {
+            if tty.session() != task.pcb.sid() {
+                return Err(Errno::ENOTTY);
+            }
+            tty.set_session(0);
+        }
+        TIOCGPGRP => {
+            check_ctty(task, tty)?;
+            task.write_bytes(arg, (tty.foreground() as i32).as_bytes());
+        }
+        TIOCSPGRP => {
+            check_ctty(task, tty)?;
+            let bytes = task
+                .read_bytes(arg, size_of::<i32>())
+                .ok_or(Errno::EFAULT)?;
+            let pgid = i32::read_from_bytes(&bytes).unwrap();
+            if pgid < 0 {
+                return Err(Errno::EINVAL);
+            }
+            let sid = task.pcb.sid();
+            if !TASK_MAP
+                .lock()
+                .values()
+                .any(|x| x.pid == x.tid && x.pcb.pgid() == pgid as usize && x.pcb.sid() == sid)
+            {
+                return Err(Errno::EPERM);
+            }
+            tty.set_foreground(pgid as _);
+        }
+        TIOCGSID => {
+            let sid = tty.session();
+            if sid == 0 {
+                return Err(Errno::ENOTTY);
+            }
+            task.write_bytes(arg, (sid as i32).as_bytes());
+        }
+        _ => return Err(Errno::ENOTTY),
+    }
+    Ok(0)
+}
+
+/// 处理伪终端主设备的 ioctl 请求，其他请求交给从设备的终端处理
+fn pty_ioctl(task: &Sel4Task, master: &Arc<PtyMaster>, request: usize, arg: usize) -> SysResult {
+    match request {
+        TIOCGPTN => {
+            task.write_bytes(arg, master.index().as_bytes());
+        }
+        TIOCSPTLCK => {
+            let bytes = task
+                .read_bytes(arg, size_of::<i32>())
+                .ok_or(Errno::EFAULT)?;
+            master.set_locked(i32::read_from_bytes(&bytes).unwrap() != 0);
+        }
+        TIOCGPTLCK => {
+            task.write_bytes(arg, (master.locked() as i32).as_bytes());
+        }
+        // 不支持包模式，只允许关闭
+        TIOCPKT => {
+            let bytes = task
+                .read_bytes(arg, size_of::<i32>())
+                .ok_or(Errno::EFAULT)?;
+            if i32::read_from_bytes(&bytes).unwrap() != 0 {
+                return Err(Errno::EINVAL);
+            }
+        }
+        // 直接打开从设备，不需要通过路径
+        TIOCGPTPEER => {
+            let flags = OpenFlags::from_bits_truncate(arg);
+            let file = File::new_dev(master.open_slave()?);
+            *file.flags.lock() = flags;
+            let mut file_table = task.file.file_ds.lock();
+            if file_table.count() >= task.file.rlimit.lock().curr {
+                return Err(Errno::EMFILE);
+            }
+            return file_table.add(file).map_err(|_| Errno::EMFILE);
+        }
+        _ => return tty_ioctl(task, master.tty(), request, arg),
+    }
+    Ok(0)
+}
+
+/// 检查终端是否为任务的控制终端
+///
+/// 还没有分配给任何会话的终端可以被任何任务使用
+fn check_ctty(task: &Sel4Task, tty: &Tty) -> Result<(), Errno> {
+    match tty.session() {
+        0 => Ok(()),
+        sid if sid == task.pcb.sid() => Ok(()),
+        _ => Err(Errno::ENOTTY),
+    }
 }
 
 pub(super) fn sys_ftruncate(task: &Sel4Task, fd: usize, len: usize) -> SysResult {
@@ -670,7 +1295,8 @@ pub(super) async fn sys_pselect(
                 let file = file_ds.get(i).unwrap();
                 match file.poll(PollEvent::IN) {
                     Ok(res) => {
-                        if res.contains(PollEvent::IN) {
+                        // 和 Linux 一样，挂断和出错的文件也是可读的
+                        if res.intersects(PollEvent::IN | PollEvent::HUP | PollEvent::ERR) {
                             num += 1;
                             rfds_r.set_bit(i, true);
                         } else {
@@ -727,6 +1353,10 @@ pub(super) async fn sys_pselect(
                 }
             }
         }
+        let files: Vec<_> = (0..max_fdp1)
+            .filter(|i| ori_rfds.get_bit(*i) || ori_wfds.get_bit(*i) || ori_efds.get_bit(*i))
+            .filter_map(|i| file_ds.get(i).cloned())
+            .collect();
         drop(file_ds);
         if num != 0 {
             if !readfds_ptr.is_null() {
@@ -753,6 +1383,142 @@ pub(super) async fn sys_pselect(
             }
             return Ok(0);
         }
-        wait_time(current_time() + Duration::from_micros(10), task.tid).await;
+        wait_files(task, &files, timeout).await?;
+    }
+}
+
+pub(super) fn sys_eventfd2(task: &Sel4Task, initval: usize, flags: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_eventfd2 @ initval: {}, flags: {:#x}",
+        task.tid, initval, flags
+    );
+    let open_flags = OpenFlags::from_bits_truncate(flags);
+    if flags & !(EFD_SEMAPHORE | (OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).bits()) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let file = File::new_dev(EventFd::new(initval as _, flags & EFD_SEMAPHORE != 0));
+    *file.flags.lock() = OpenFlags::RDWR | (open_flags & OpenFlags::NONBLOCK);
+
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
+
+pub(super) fn sys_epoll_create1(task: &Sel4Task, flags: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_epoll_create1 @ flags: {:#x}",
+        task.tid, flags
+    );
+    let flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if !(flags - OpenFlags::CLOEXEC).is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table
+        .add(File::new_dev(EpollFile::new()))
+        .map_err(|_| Errno::EMFILE)
+}
+
+pub(super) fn sys_epoll_ctl(
+    task: &Sel4Task,
+    epfd: usize,
+    op: usize,
+    fd: usize,
+    event_ptr: *const EpollEvent,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_epoll_ctl @ epfd: {}, op: {}, fd: {}, event: {:p}",
+        task.tid, epfd, op, fd, event_ptr
+    );
+    let op = EpollCtl::try_from_primitive(op).map_err(|_| Errno::EINVAL)?;
+    if epfd == fd {
+        return Err(Errno::EINVAL);
+    }
+    let file_table = task.file.file_ds.lock();
+    let epoll_file = file_table.get(epfd).ok_or(Errno::EBADF)?.clone();
+    let file = file_table.get(fd).ok_or(Errno::EBADF)?.clone();
+    drop(file_table);
+    let epoll = EpollFile::from_file(&epoll_file).ok_or(Errno::EINVAL)?;
+
+    let event = match op {
+        EpollCtl::Del => EpollEvent::new_zeroed(),
+        _ => {
+            let event_bytes = task
+                .read_bytes(event_ptr as _, size_of::<EpollEvent>())
+                .ok_or(Errno::EFAULT)?;
+            EpollEvent::read_from_bytes(&event_bytes).unwrap()
+        }
+    };
+    epoll.ctl(op, fd, &file, event)?;
+    Ok(0)
+}
+
+pub(super) async fn sys_epoll_pwait(
+    task: &Sel4Task,
+    epfd: usize,
+    events_ptr: *mut EpollEvent,
+    maxevents: isize,
+    timeout: isize,
+    sigmask_ptr: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_epoll_pwait @ epfd: {}, events: {:p}, maxevents: {}, timeout: {}, sigmask: {:#x}",
+        task.tid, epfd, events_ptr, maxevents, timeout, sigmask_ptr
+    );
+    if maxevents <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let epoll_file = task
+        .file
+        .file_ds
+        .lock()
+        .get(epfd)
+        .ok_or(Errno::EBADF)?
+        .clone();
+    let epoll = EpollFile::from_file(&epoll_file).ok_or(Errno::EINVAL)?;
+    // 等待期间使用 sigmask 作为信号屏蔽位，系统调用返回之后恢复
+    if sigmask_ptr != 0 {
+        let bytes = task
+            .read_bytes(sigmask_ptr, size_of::<SigSet>())
+            .ok_or(Errno::EFAULT)?;
+        task.set_temp_sigmask(SigSet::read_from_bytes(&bytes).map_err(|_| Errno::EFAULT)?);
+    }
+    let etime = if timeout < 0 {
+        Duration::MAX
+    } else {
+        current_time() + Duration::from_millis(timeout as _)
+    };
+    loop {
+        let events = epoll.collect(maxevents as _);
+        if !events.is_empty() {
+            task.write_bytes(events_ptr as _, events.as_bytes());
+            return Ok(events.len());
+        }
+        if timeout == 0 || current_time() >= etime {
+            return Ok(0);
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        }
+        // 存在没有等待队列的文件时需要定时轮询
+        let next = match epoll.has_polling_items() {
+            true => etime.min(current_time() + Duration::new(0, 1000000)),
+            false => etime,
+        };
+        if next == Duration::MAX {
+            epoll.wait(task.tid).await?;
+        } else {
+            match select(pin!(epoll.wait(task.tid)), pin!(wait_time(next, task.tid))).await {
+                Either::Left((res, _)) => res?,
+                Either::Right((res, _)) => {
+                    res?;
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,229 @@
+//! 进程间通信相关系统调用
+//!
+//! 目前包含 POSIX 消息队列
+
+use core::{pin::pin, time::Duration};
+
+use alloc::{string::String, sync::Arc};
+use fs::file::File;
+use futures::future::{Either, select};
+use libc_core::{fcntl::OpenFlags, signal::SignalNum, types::TimeSpec};
+use sel4_kit::arch::current_time;
+use syscalls::Errno;
+use zerocopy::{FromBytes, IntoBytes};
+
+use crate::{
+    child_test::TASK_MAP,
+    fs::mqueue::{self, MessageQueue, MqAttr, SIGEV_NONE, SIGEV_SIGNAL, SigEvent},
+    task::Sel4Task,
+    timer::wait_time,
+};
+
+use super::SysResult;
+
+/// 根据文件描述符获取打开的消息队列
+fn get_mqueue(task: &Sel4Task, mqdes: usize) -> Result<(Arc<File>, Arc<MessageQueue>), Errno> {
+    let file = task
+        .file
+        .file_ds
+        .lock()
+        .get(mqdes)
+        .ok_or(Errno::EBADF)?
+        .clone();
+    let queue = MessageQueue::from_file(&file).ok_or(Errno::EBADF)?;
+    Ok((file, queue))
+}
+
+/// 读取绝对超时时间，如果指针为空则永不超时
+fn read_abs_timeout(task: &Sel4Task, timeout_ptr: *const TimeSpec) -> Result<Duration, Errno> {
+    if timeout_ptr.is_null() {
+        return Ok(Duration::MAX);
+    }
+    let timeout_bytes = task
+        .read_bytes(timeout_ptr as _, size_of::<TimeSpec>())
+        .ok_or(Errno::EFAULT)?;
+    Ok(TimeSpec::read_from_bytes(&timeout_bytes).unwrap().into())
+}
+
+/// 等待消息队列的状态变化或者到达 `etime`，调用者需要重新检查消息队列
+async fn wait_mqueue(task: &Sel4Task, queue: &MessageQueue, etime: Duration) -> Result<(), Errno> {
+    let wait = queue.wait_queue();
+    if etime == Duration::MAX {
+        return wait.wait(task.tid).await;
+    }
+    match select(pin!(wait.wait(task.tid)), pin!(wait_time(etime, task.tid))).await {
+        Either::Left((res, _)) => res,
+        Either::Right((res, _)) => res.map(|_| ()),
+    }
+}
+
+pub(super) fn sys_mq_open(
+    task: &Sel4Task,
+    name: *const u8,
+    oflag: usize,
+    mode: usize,
+    attr_ptr: *const MqAttr,
+) -> SysResult {
+    let name = String::from_utf8(task.read_cstr(name as _).ok_or(Errno::EFAULT)?)
+        .map_err(|_| Errno::EINVAL)?;
+    debug!(
+        "sys_mq_open @ name: {}, oflag: {:#x}, mode: {:#o}, attr: {:p}",
+        name, oflag, mode, attr_ptr
+    );
+    let flags = OpenFlags::from_bits_truncate(oflag);
+    let attr = match attr_ptr.is_null() {
+        true => None,
+        false => {
+            let attr_bytes = task
+                .read_bytes(attr_ptr as _, size_of::<MqAttr>())
+                .ok_or(Errno::EFAULT)?;
+            Some(MqAttr::read_from_bytes(&attr_bytes).unwrap())
+        }
+    };
+    let queue = mqueue::mq_open(
+        name.strip_prefix('/').unwrap_or(&name),
+        flags.contains(OpenFlags::CREAT),
+        flags.contains(OpenFlags::EXCL),
+        attr,
+    )?;
+
+    let file = File::new_dev(queue);
+    *file.flags.lock() = flags;
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
+
+pub(super) fn sys_mq_unlink(task: &Sel4Task, name: *const u8) -> SysResult {
+    let name = String::from_utf8(task.read_cstr(name as _).ok_or(Errno::EFAULT)?)
+        .map_err(|_| Errno::EINVAL)?;
+    debug!("sys_mq_unlink @ name: {}", name);
+    mqueue::mq_unlink(name.strip_prefix('/').unwrap_or(&name))?;
+    Ok(0)
+}
+
+pub(super) async fn sys_mq_timedsend(
+    task: &Sel4Task,
+    mqdes: usize,
+    msg_ptr: *const u8,
+    msg_len: usize,
+    msg_prio: u32,
+    timeout_ptr: *const TimeSpec,
+) -> SysResult {
+    debug!(
+        "sys_mq_timedsend @ mqdes: {}, msg_ptr: {:p}, msg_len: {}, prio: {}",
+        mqdes, msg_ptr, msg_len, msg_prio
+    );
+    let (file, queue) = get_mqueue(task, mqdes)?;
+    let data = task
+        .read_bytes(msg_ptr as _, msg_len)
+        .ok_or(Errno::EFAULT)?;
+    let etime = read_abs_timeout(task, timeout_ptr)?;
+    loop {
+        let res = queue.send(msg_prio, &data);
+        if let Ok(notify) = res {
+            // 空队列中有新消息到达时通知注册的进程
+            // 信号的默认动作可能需要访问任务表，发送信号之前释放任务表
+            if let Some((pid, signal)) = notify {
+                let target = TASK_MAP.lock().values().find(|x| x.pid == pid).cloned();
+                if let Some(target) = target {
+                    target.add_signal(signal, task.tid);
+                }
+            }
+            break Ok(0);
+        }
+        if !matches!(res, Err(Errno::EAGAIN)) || file.flags.lock().contains(OpenFlags::NONBLOCK) {
+            res?;
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        } else if current_time() >= etime {
+            return Err(Errno::ETIMEDOUT);
+        }
+        wait_mqueue(task, &queue, etime).await?;
+    }
+}
+
+pub(super) async fn sys_mq_timedreceive(
+    task: &Sel4Task,
+    mqdes: usize,
+    msg_ptr: *mut u8,
+    msg_len: usize,
+    prio_ptr: *mut u32,
+    timeout_ptr: *const TimeSpec,
+) -> SysResult {
+    debug!(
+        "sys_mq_timedreceive @ mqdes: {}, msg_ptr: {:p}, msg_len: {}, prio_ptr: {:p}",
+        mqdes, msg_ptr, msg_len, prio_ptr
+    );
+    let (file, queue) = get_mqueue(task, mqdes)?;
+    let etime = read_abs_timeout(task, timeout_ptr)?;
+    let (prio, data) = loop {
+        let res = queue.receive(msg_len);
+        if let Ok(message) = res {
+            break message;
+        }
+        if !matches!(res, Err(Errno::EAGAIN)) || file.flags.lock().contains(OpenFlags::NONBLOCK) {
+            res?;
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        } else if current_time() >= etime {
+            return Err(Errno::ETIMEDOUT);
+        }
+        wait_mqueue(task, &queue, etime).await?;
+    };
+    task.write_bytes(msg_ptr as _, &data);
+    if !prio_ptr.is_null() {
+        task.write_bytes(prio_ptr as _, prio.as_bytes());
+    }
+    Ok(data.len())
+}
+
+pub(super) fn sys_mq_notify(task: &Sel4Task, mqdes: usize, sevp: *const SigEvent) -> SysResult {
+    debug!("sys_mq_notify @ mqdes: {}, sevp: {:p}", mqdes, sevp);
+    let (_, queue) = get_mqueue(task, mqdes)?;
+    if sevp.is_null() {
+        queue.clear_notify(task.pid);
+        return Ok(0);
+    }
+    let sev_bytes = task
+        .read_bytes(sevp as _, size_of::<SigEvent>())
+        .ok_or(Errno::EFAULT)?;
+    let sev = SigEvent::read_from_bytes(&sev_bytes).unwrap();
+    let signal = match sev.notify {
+        SIGEV_NONE => None,
+        SIGEV_SIGNAL => Some(SignalNum::from_num(sev.signo as _).ok_or(Errno::EINVAL)?),
+        _ => return Err(Errno::EINVAL),
+    };
+    queue.set_notify(task.pid, signal)?;
+    Ok(0)
+}
+
+pub(super) fn sys_mq_getsetattr(
+    task: &Sel4Task,
+    mqdes: usize,
+    new_attr: *const MqAttr,
+    old_attr: *mut MqAttr,
+) -> SysResult {
+    let (file, queue) = get_mqueue(task, mqdes)?;
+    if !old_attr.is_null() {
+        let mut attr = queue.attr();
+        attr.flags = (*file.flags.lock() & OpenFlags::NONBLOCK).bits() as _;
+        task.write_bytes(old_attr as _, attr.as_bytes());
+    }
+    if !new_attr.is_null() {
+        let attr_bytes = task
+            .read_bytes(new_attr as _, size_of::<MqAttr>())
+            .ok_or(Errno::EFAULT)?;
+        let attr = MqAttr::read_from_bytes(&attr_bytes).unwrap();
+        let mut flags = file.flags.lock();
+        flags.set(
+            OpenFlags::NONBLOCK,
+            OpenFlags::from_bits_truncate(attr.flags as _).contains(OpenFlags::NONBLOCK),
+        );
+    }
+    Ok(0)
+}
@@ -5,20 +5,24 @@
 use super::SysResult;
 use crate::{
     consts::task::DEF_HEAP_ADDR,
+    fs::memfd::MemFd,
     task::{
         Sel4Task,
-        shm::{MapedSharedMemory, SHARED_MEMORY, SharedMemory},
+        shm::{SHARED_MEMORY, SHM_KEY_PRIVATE, SharedMemory},
     },
-    utils::obj::alloc_untyped_unit,
 };
-use alloc::{sync::Arc, vec::Vec};
-use common::{config::PAGE_SIZE, mem::CapMemSet, page::PhysPage, slot::alloc_slot};
-use libc_core::mman::MapFlags;
-use sel4::{Cap, CapRights, cap_type};
+use alloc::{string::String, sync::Arc};
+use common::{config::PAGE_SIZE, slot::recycle_slot};
+use fs::file::File;
+use libc_core::{fcntl::OpenFlags, mman::MapFlags};
 use sel4_kit::slot_manager::LeafSlot;
-use spin::Mutex;
 use syscalls::Errno;
 
+/// `memfd_create` 时设置 `O_CLOEXEC`
+const MFD_CLOEXEC: u32 = 0x1;
+/// `memfd_create` 时允许设置 seal
+const MFD_ALLOW_SEALING: u32 = 0x2;
+
 #[inline]
 pub(super) fn sys_brk(task: &Sel4Task, heap: usize) -> SysResult {
     debug!("BRK @ heap: {heap:#x}");
@@ -36,9 +40,6 @@ pub(super) fn sys_mmap(
     off: usize,
 ) -> SysResult {
     let flags = MapFlags::from_bits_truncate(flags as _);
-    if flags.contains(MapFlags::SHARED) {
-        log::warn!("mmap share is not supported now!");
-    }
     assert_eq!(start % PAGE_SIZE, 0);
     debug!("MMAP @ {start:#x} {size:#x} {prot:#x} {flags:#x} {fd:#x} {off:#x}");
     warn!("mmap is just map a regular page RWX");
@@ -46,18 +47,32 @@ pub(super) fn sys_mmap(
         warn!("Only supported the case that calling brk before");
         return Ok(start);
     }
+    // 是否为匿名映射由 MAP_ANONYMOUS 决定，fd 0 也是合法的文件描述符
+    let file = match flags.contains(MapFlags::ANONYMOUS) {
+        true => None,
+        false => Some(
+            task.file
+                .file_ds
+                .lock()
+                .get(fd as _)
+                .ok_or(Errno::EBADF)?
+                .clone(),
+        ),
+    };
+    // 共享映射 memfd，直接映射 memfd 使用的物理页
+    // 普通文件的共享映射需要把写入同步回文件，暂不支持，不能退化成私有映射
+    if flags.contains(MapFlags::SHARED) {
+        match file.as_deref().map(MemFd::from_file) {
+            Some(Some(memfd)) => return mmap_memfd(task, start, size, off, memfd),
+            Some(None) => return Err(Errno::ENODEV),
+            None => log::warn!("anonymous shared mmap is mapped as private"),
+        }
+    }
     let start = task.find_free_area(start, size);
-    if fd > 0 {
-        let file = task
-            .file
-            .file_ds
-            .lock()
-            .get(fd as _)
-            .ok_or(Errno::EINVAL)?
-            .clone();
+    if let Some(file) = file {
         let file_len = file.file_size()?;
-        let mut data = vec![0u8; file_len];
-        file.readat(0, &mut data)?;
+        let mut data = vec![0u8; file_len.saturating_sub(off).min(size)];
+        file.readat(off, &mut data)?;
         for addr in (start..start + size).step_by(PAGE_SIZE) {
             task.map_blank_page(addr);
         }
@@ -70,8 +85,53 @@ pub(super) fn sys_mmap(
     Ok(start)
 }
 
+/// 以共享的方式映射 memfd
+///
+/// memfd 的物理页由 [SharedMemory] 持有，映射时复制物理页的 Capability，
+/// 并记录到 [Sel4Task::shm] 中，`fork` 的时候子进程会映射相同的物理页
+fn mmap_memfd(
+    task: &Sel4Task,
+    start: usize,
+    size: usize,
+    off: usize,
+    memfd: Arc<MemFd>,
+) -> SysResult {
+    if off % PAGE_SIZE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mem = memfd.shared_memory();
+    if off + size.div_ceil(PAGE_SIZE) * PAGE_SIZE > mem.size() {
+        return Err(Errno::ENXIO);
+    }
+    let start = task.find_free_area(start, size);
+    task.map_shared_memory(SHM_KEY_PRIVATE, mem, start, size, off);
+    Ok(start)
+}
+
 pub(super) fn sys_munmap(task: &Sel4Task, start: usize, len: usize) -> SysResult {
     debug!("sys_munmap @ start: {:#x}, len: {:#x}", start, len);
+    // 取消映射共享内存，共享的物理页不能回收到当前任务中
+    let mut shm = task.shm.lock();
+    task.mem.lock().mapped_page.retain(|vaddr, x| {
+        if (start..start + len).contains(vaddr) && shm.iter().any(|m| m.contains(*vaddr)) {
+            x.cap().frame_unmap().unwrap();
+            let slot = LeafSlot::from_cap(x.cap());
+            slot.delete().unwrap();
+            recycle_slot(slot);
+            false
+        } else {
+            true
+        }
+    });
+    // 部分取消映射时只保留剩下的部分，`fork` 时不会重新映射已经取消映射的范围
+    let end = start + len.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let rest = shm
+        .drain(..)
+        .flat_map(|m| m.remove_range(start, end))
+        .collect();
+    *shm = rest;
+    drop(shm);
+
     task.mem.lock().mapped_page.retain(|vaddr, x| {
         if (start..start + len).contains(vaddr) {
             x.cap().frame_unmap().unwrap();
@@ -106,13 +166,9 @@ pub(super) fn sys_shmget(
         return Ok(key);
     }
     if shmflg & 0o1000 > 0 {
-        let capset = Mutex::new(CapMemSet::new(Some(alloc_untyped_unit)));
-        let vector: Vec<Cap<cap_type::Granule>> = (0..size.div_ceil(PAGE_SIZE))
-            .map(|_| capset.lock().alloc_page())
-            .collect();
         SHARED_MEMORY
             .lock()
-            .insert(key, Arc::new(SharedMemory::new(capset, vector)));
+            .insert(key, Arc::new(SharedMemory::alloc(size)));
         return Ok(key);
     }
     Err(Errno::ENOENT)
@@ -124,29 +180,16 @@ pub(super) fn sys_shmat(task: &Sel4Task, shmid: usize, shmaddr: usize, shmflg: u
         shmid, shmaddr, shmflg
     );
 
-    let trackers = SHARED_MEMORY.lock().get(&shmid).cloned();
-    if trackers.is_none() {
-        return Err(Errno::ENOENT);
-    }
-    let trackers = trackers.unwrap();
+    let mem = SHARED_MEMORY
+        .lock()
+        .get(&shmid)
+        .cloned()
+        .ok_or(Errno::ENOENT)?;
 
-    let vaddr = task.find_free_area(shmaddr, trackers.trackers.len() * PAGE_SIZE);
+    let vaddr = task.find_free_area(shmaddr, mem.size());
     let vaddr = if shmaddr == 0 { vaddr } else { shmaddr };
-
-    for (i, page) in trackers.trackers.iter().enumerate() {
-        let new_slot = alloc_slot();
-        new_slot
-            .copy_from(&LeafSlot::from_cap(*page), CapRights::all())
-            .unwrap();
-        task.map_page(vaddr + i * PAGE_SIZE, PhysPage::new(new_slot.cap()));
-    }
-
-    task.shm.lock().push(Arc::new(MapedSharedMemory {
-        key: shmid,
-        mem: SHARED_MEMORY.lock().get(&shmid).unwrap().clone(),
-        start: vaddr,
-        size: trackers.trackers.len() * PAGE_SIZE,
-    }));
+    let size = mem.size();
+    task.map_shared_memory(shmid, mem, vaddr, size, 0);
 
     Ok(vaddr)
 }
@@ -162,3 +205,23 @@ pub(super) fn sys_shmctl(_task: &Sel4Task, shmid: usize, cmd: usize, arg: usize)
     }
     Err(Errno::EPERM)
 }
+
+pub(super) fn sys_memfd_create(task: &Sel4Task, name: *const u8, flags: u32) -> SysResult {
+    let name = String::from_utf8(task.read_cstr(name as _).ok_or(Errno::EFAULT)?)
+        .map_err(|_| Errno::EINVAL)?;
+    debug!("sys_memfd_create @ name: {}, flags: {:#x}", name, flags);
+    if flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let file = File::new_dev(MemFd::new(name));
+    *file.flags.lock() = match flags & MFD_CLOEXEC {
+        0 => OpenFlags::RDWR,
+        _ => OpenFlags::RDWR | OpenFlags::CLOEXEC,
+    };
+
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
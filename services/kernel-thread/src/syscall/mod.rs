@@ -2,14 +2,18 @@
 //!
 //!
 pub mod fs;
+pub mod ipc;
 pub mod mm;
+pub mod net;
 pub mod signal;
 pub mod sys;
 pub mod thread;
 
 use fs::*;
+use ipc::*;
 use libc_core::fcntl::OpenFlags;
 use mm::*;
+use net::*;
 use sel4::UserContext;
 use signal::*;
 use sys::*;
@@ -39,12 +43,20 @@ pub async fn handle_syscall(task: &ArcTask, ctx: &mut UserContext) -> SysResult
         return Err(Errno::ENOSYS);
     }
     match id.unwrap() {
+        Sysno::accept => sys_accept4(task, a0, a1 as _, a2 as _, 0).await,
+        Sysno::accept4 => sys_accept4(task, a0, a1 as _, a2 as _, a3).await,
+        Sysno::bind => sys_bind(task, a0, a1 as _, a2),
         Sysno::brk => sys_brk(task, a0),
         Sysno::chdir => sys_chdir(task, a0 as _),
         Sysno::clone => sys_clone(task, a0 as _, a1, a2 as _, a3, a4 as _).await,
         Sysno::close => sys_close(task, a0),
+        Sysno::connect => sys_connect(task, a0, a1 as _, a2).await,
         Sysno::dup => sys_dup(task, a0),
         Sysno::dup3 => sys_dup3(task, a0, a1),
+        Sysno::epoll_create1 => sys_epoll_create1(task, a0),
+        Sysno::epoll_ctl => sys_epoll_ctl(task, a0, a1, a2, a3 as _),
+        Sysno::epoll_pwait => sys_epoll_pwait(task, a0, a1 as _, a2 as _, a3 as _, a4).await,
+        Sysno::eventfd2 => sys_eventfd2(task, a0, a1),
         Sysno::execve => sys_execve(task, ctx, a0 as _, a1 as _, a2 as _),
         Sysno::exit => sys_exit(task, a0 as _),
         Sysno::faccessat => sys_faccessat(task, a0 as _, a1 as _, a2 as _, a3 as _),
@@ -56,24 +68,45 @@ pub async fn handle_syscall(task: &ArcTask, ctx: &mut UserContext) -> SysResult
         Sysno::futex => sys_futex(task.clone(), a0 as _, a1, a2, a3, a4, a5).await,
         Sysno::getcwd => sys_getcwd(task, a0 as _, a1),
         Sysno::getdents64 => sys_getdents64(task, a0, a1 as _, a2),
+        Sysno::getpgid => sys_getpgid(task, a0),
         Sysno::getpid => sys_getpid(task),
         Sysno::getppid => sys_getppid(task),
+        Sysno::getpeername => sys_getpeername(task, a0, a1 as _, a2 as _),
+        Sysno::getsockname => sys_getsockname(task, a0, a1 as _, a2 as _),
+        Sysno::getsockopt => sys_getsockopt(task, a0, a1, a2, a3 as _, a4 as _),
+        Sysno::getsid => sys_getsid(task, a0),
         Sysno::gettid => sys_gettid(task),
         Sysno::getrusage => sys_getrusage(task, a0, a1 as _),
+        Sysno::memfd_create => sys_memfd_create(task, a0 as _, a1 as _),
         Sysno::lseek => sys_lseek(task, a0 as _, a1 as _, a2 as _),
         Sysno::ioctl => sys_ioctl(task, a0, a1, a2, a3, a4),
+        Sysno::copy_file_range => sys_copy_file_range(task, a0, a1 as _, a2, a3 as _, a4, a5),
         Sysno::clock_gettime => sys_clock_gettime(task, a0 as _, a1 as _),
         Sysno::gettimeofday => sys_gettimeofday(task, a0 as _, a1),
         Sysno::kill => sys_kill(task, a0, a1),
+        Sysno::listen => sys_listen(task, a0, a1),
         Sysno::mkdirat => sys_mkdirat(task, a0 as _, a1 as _, a2),
+        Sysno::mknodat => sys_mknodat(task, a0 as _, a1 as _, a2, a3),
         Sysno::mmap => sys_mmap(task, a0, a1, a2, a3, a4 as _, a5),
         Sysno::mount => sys_mount(task, a0 as _, a1 as _, a2 as _, a3 as _, a4),
         Sysno::munmap => sys_munmap(task, a0, a1),
+        Sysno::mq_open => sys_mq_open(task, a0 as _, a1, a2, a3 as _),
+        Sysno::mq_unlink => sys_mq_unlink(task, a0 as _),
+        Sysno::mq_timedsend => sys_mq_timedsend(task, a0, a1 as _, a2, a3 as _, a4 as _).await,
+        Sysno::mq_timedreceive => {
+            sys_mq_timedreceive(task, a0, a1 as _, a2, a3 as _, a4 as _).await
+        }
+        Sysno::mq_notify => sys_mq_notify(task, a0, a1 as _),
+        Sysno::mq_getsetattr => sys_mq_getsetattr(task, a0, a1 as _, a2 as _),
         Sysno::nanosleep => sys_nanosleep(task, a0 as _, a1 as _).await,
-        Sysno::openat => sys_openat(task, a0 as _, a1 as _, a2 as _, a3),
+        Sysno::openat => sys_openat(task, a0 as _, a1 as _, a2 as _, a3).await,
         Sysno::pipe2 => sys_pipe2(task, a0 as _, a1 as _),
         Sysno::read => sys_read(task, a0, a1 as _, a2).await,
         Sysno::readv => sys_readv(task, a0, a1 as _, a2).await,
+        Sysno::recvfrom => sys_recvfrom(task, a0, a1 as _, a2, a3, a4 as _, a5 as _).await,
+        Sysno::recvmsg => sys_recvmsg(task, a0, a1 as _, a2).await,
+        Sysno::setpgid => sys_setpgid(task, a0, a1),
+        Sysno::setsid => sys_setsid(task),
         Sysno::setitimer => sys_setitimer(task, a0, a1 as _, a2 as _),
         Sysno::pread64 => sys_pread64(task, a0, a1 as _, a2, a3),
         Sysno::write => sys_write(task, a0, a1 as _, a2).await,
@@ -88,6 +121,16 @@ pub async fn handle_syscall(task: &ArcTask, ctx: &mut UserContext) -> SysResult
             OpenFlags::RDWR.bits(),
         ),
         Sysno::sendfile => sys_sendfile(task, a0, a1, a2, a3),
+        Sysno::sendmsg => sys_sendmsg(task, a0, a1 as _, a2).await,
+        Sysno::sendto => sys_sendto(task, a0, a1 as _, a2, a3, a4 as _, a5).await,
+        Sysno::setsockopt => sys_setsockopt(task, a0, a1, a2, a3 as _, a4),
+        Sysno::shutdown => sys_shutdown(task, a0, a1),
+        Sysno::socket => sys_socket(task, a0, a1, a2),
+        Sysno::socketpair => sys_socketpair(task, a0, a1, a2, a3 as _),
+        Sysno::splice => sys_splice(task, a0, a1 as _, a2, a3 as _, a4, a5).await,
+        Sysno::syslog => sys_syslog(task, a0, a1 as _, a2).await,
+        Sysno::tee => sys_tee(task, a0, a1, a2, a3).await,
+        Sysno::vmsplice => sys_vmsplice(task, a0, a1 as _, a2, a3).await,
         Sysno::shmget => sys_shmget(task, a0 as _, a1 as _, a2),
         Sysno::shmat => sys_shmat(task, a0, a1, a2),
         Sysno::shmctl => sys_shmctl(task, a0 as _, a1 as _, a2 as _),
@@ -97,6 +140,10 @@ pub async fn handle_syscall(task: &ArcTask, ctx: &mut UserContext) -> SysResult
         Sysno::rt_sigprocmask => sys_sigprocmask(task, a0 as _, a1 as _, a2 as _),
         Sysno::rt_sigreturn => sys_sigreturn(task, ctx),
         Sysno::rt_sigtimedwait => sys_sigtimedwait(task),
+        Sysno::signalfd4 => sys_signalfd4(task, a0 as _, a1 as _, a2, a3),
+        Sysno::timerfd_create => sys_timerfd_create(task, a0, a1),
+        Sysno::timerfd_settime => sys_timerfd_settime(task, a0, a1, a2 as _, a3 as _),
+        Sysno::timerfd_gettime => sys_timerfd_gettime(task, a0, a1 as _),
         Sysno::tkill => sys_tkill(task, a0, a1),
         Sysno::sched_yield => sys_sched_yield(task),
         Sysno::set_tid_address => sys_set_tid_addr(task, a0),
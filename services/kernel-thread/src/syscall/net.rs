@@ -0,0 +1,865 @@
+//! 网络相关的系统调用
+//!
+//!
+use core::time::Duration;
+
+use alloc::{sync::Arc, vec::Vec};
+use fs::file::File;
+use libc_core::{fcntl::OpenFlags, signal::SignalNum, types::IoVec};
+use sel4_kit::arch::current_time;
+use smoltcp::wire::IpEndpoint;
+use syscalls::Errno;
+use vfscore::INodeInterface;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{
+    fs::wait_queue::WaitQueue,
+    net::{
+        socket::{
+            AF_INET, InetSocket, SOCK_CLOEXEC, SOCK_NONBLOCK, SOCK_STREAM, SOCK_TYPE_MASK,
+            SockAddrIn, TCP_BUFFER_SIZE,
+        },
+        unix::{self, AF_UNIX, SCM_MAX_FD, UNIX_BUFFER_SIZE, UNIX_PATH_MAX, UnixAddr, UnixSocket},
+    },
+    task::Sel4Task,
+    timer::wait_time,
+};
+
+use super::SysResult;
+
+/// socket 选项层级
+const SOL_SOCKET: usize = 1;
+/// IP 协议选项层级
+const IPPROTO_IP: usize = 0;
+/// TCP 协议选项层级
+const IPPROTO_TCP: usize = 6;
+
+const SO_REUSEADDR: usize = 2;
+const SO_TYPE: usize = 3;
+const SO_ERROR: usize = 4;
+const SO_SNDBUF: usize = 7;
+const SO_RCVBUF: usize = 8;
+const SO_KEEPALIVE: usize = 9;
+const SO_LINGER: usize = 13;
+const SO_REUSEPORT: usize = 15;
+const SO_PASSCRED: usize = 16;
+const SO_PEERCRED: usize = 17;
+const SO_RCVTIMEO: usize = 20;
+const SO_SNDTIMEO: usize = 21;
+const TCP_NODELAY: usize = 1;
+
+/// 控制消息中传递的文件描述符
+const SCM_RIGHTS: i32 = 1;
+
+/// 只查看数据，不从接收缓冲区中移除
+const MSG_PEEK: usize = 0x2;
+/// 控制消息被截断
+const MSG_CTRUNC: u32 = 0x8;
+/// 数据报被截断，作为参数时返回数据报的完整长度
+const MSG_TRUNC: usize = 0x20;
+/// 本次操作不阻塞
+const MSG_DONTWAIT: usize = 0x40;
+/// 对端关闭时不发送 `SIGPIPE`
+const MSG_NOSIGNAL: usize = 0x4000;
+
+/// `struct sockaddr_storage` 的大小
+const SOCKADDR_MAX: usize = 128;
+
+/// `sendmsg` 和 `recvmsg` 最多使用的 [IoVec] 数量
+const IOV_MAX: usize = 1024;
+
+/// `struct msghdr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct MsgHdr {
+    name: usize,
+    name_len: u32,
+    _pad0: u32,
+    iov: usize,
+    iov_len: usize,
+    control: usize,
+    control_len: usize,
+    flags: u32,
+    _pad1: u32,
+}
+
+/// `struct cmsghdr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct CmsgHdr {
+    len: usize,
+    level: i32,
+    ty: i32,
+}
+
+/// `struct ucred`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable)]
+struct UCred {
+    pid: i32,
+    uid: u32,
+    gid: u32,
+}
+
+/// 控制消息按照 `usize` 对齐
+const fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// 文件描述符对应的 socket
+enum Socket {
+    Inet(Arc<InetSocket>),
+    Unix(Arc<UnixSocket>),
+}
+
+/// 用户传入的 socket 地址
+enum SockAddr {
+    Inet(IpEndpoint),
+    Unix(UnixAddr),
+}
+
+/// 已经找到的发送目标
+enum Target {
+    /// 使用 `connect` 设置的目标
+    Connected,
+    Inet(IpEndpoint),
+    Unix(Arc<UnixSocket>),
+}
+
+/// 接收到的消息
+struct Received {
+    /// 读取的长度
+    len: usize,
+    /// 消息的完整长度
+    msg_len: usize,
+    /// `SCM_RIGHTS` 传递的文件
+    files: Vec<Arc<File>>,
+    /// 发送方的地址
+    from: Option<Vec<u8>>,
+}
+
+/// IPv4 地址转换为 `struct sockaddr_in`
+fn inet_addr_bytes(endpoint: IpEndpoint) -> Vec<u8> {
+    SockAddrIn::from_endpoint(endpoint).as_bytes().to_vec()
+}
+
+impl Socket {
+    fn ty(&self) -> usize {
+        match self {
+            Socket::Inet(socket) => socket.ty(),
+            Socket::Unix(socket) => socket.ty(),
+        }
+    }
+
+    /// 根据地址找到发送目标
+    fn target(&self, to: Option<SockAddr>) -> Result<Target, Errno> {
+        match (self, to) {
+            (_, None) => Ok(Target::Connected),
+            (Socket::Inet(_), Some(SockAddr::Inet(to))) => Ok(Target::Inet(to)),
+            (Socket::Unix(_), Some(SockAddr::Unix(to))) => Ok(Target::Unix(unix::lookup(&to)?)),
+            (Socket::Inet(_), Some(_)) => Err(Errno::EAFNOSUPPORT),
+            (Socket::Unix(_), Some(_)) => Err(Errno::EINVAL),
+        }
+    }
+
+    fn send(&self, data: &[u8], files: &[Arc<File>], to: &Target) -> Result<usize, Errno> {
+        match (self, to) {
+            (Socket::Inet(_), _) if !files.is_empty() => Err(Errno::EINVAL),
+            (Socket::Inet(socket), Target::Connected) => socket.send(data, None),
+            (Socket::Inet(socket), Target::Inet(to)) => socket.send(data, Some(*to)),
+            (Socket::Unix(socket), Target::Connected) => socket.send(data, files, None),
+            (Socket::Unix(socket), Target::Unix(to)) => socket.send(data, files, Some(to)),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn recv(&self, buffer: &mut [u8], peek: bool) -> Result<Received, Errno> {
+        match self {
+            Socket::Inet(socket) => socket.recv(buffer, peek).map(|(len, from)| Received {
+                len,
+                msg_len: len,
+                files: Vec::new(),
+                from: from.map(inet_addr_bytes),
+            }),
+            Socket::Unix(socket) => socket.recv(buffer, peek).map(|res| Received {
+                len: res.len,
+                msg_len: res.msg_len,
+                files: res.files,
+                from: Some(res.from.to_bytes()),
+            }),
+        }
+    }
+}
+
+/// 根据文件描述符获取 socket
+fn get_socket(task: &Sel4Task, fd: usize) -> Result<(Arc<File>, Socket), Errno> {
+    let file = task
+        .file
+        .file_ds
+        .lock()
+        .get(fd)
+        .cloned()
+        .ok_or(Errno::EBADF)?;
+    if let Some(socket) = InetSocket::from_file(&file) {
+        return Ok((file, Socket::Inet(socket)));
+    }
+    let socket = UnixSocket::from_file(&file).ok_or(Errno::ENOTSOCK)?;
+    Ok((file, Socket::Unix(socket)))
+}
+
+/// 从用户空间读取 socket 地址，Unix socket 的相对路径转换为绝对路径
+fn read_sockaddr(task: &Sel4Task, addr: *const u8, addrlen: usize) -> Result<SockAddr, Errno> {
+    if addrlen < size_of::<u16>() {
+        return Err(Errno::EINVAL);
+    }
+    let bytes = task
+        .read_bytes(addr as _, addrlen.min(SOCKADDR_MAX))
+        .ok_or(Errno::EFAULT)?;
+    match u16::from_ne_bytes([bytes[0], bytes[1]]) as usize {
+        AF_INET => {
+            let (sockaddr, _) = SockAddrIn::read_from_prefix(&bytes).map_err(|_| Errno::EINVAL)?;
+            Ok(SockAddr::Inet(sockaddr.endpoint()))
+        }
+        AF_UNIX if addrlen <= size_of::<u16>() + UNIX_PATH_MAX => {
+            match UnixAddr::from_bytes(&bytes)? {
+                UnixAddr::Path(path) if !path.starts_with('/') => {
+                    let path = task.file.work_dir.lock().path_buf().join(&path);
+                    Ok(SockAddr::Unix(UnixAddr::Path(path.path())))
+                }
+                addr => Ok(SockAddr::Unix(addr)),
+            }
+        }
+        AF_UNIX => Err(Errno::EINVAL),
+        _ => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+/// 将 socket 地址写入用户空间，并更新地址长度
+///
+/// `addr` 为空时不写入任何数据，地址被截断时地址长度返回完整的长度
+fn write_sockaddr(
+    task: &Sel4Task,
+    addr: *mut u8,
+    addrlen: *mut u32,
+    sockaddr: &[u8],
+) -> Result<(), Errno> {
+    if addr.is_null() {
+        return Ok(());
+    }
+    let len_bytes = task
+        .read_bytes(addrlen as _, size_of::<u32>())
+        .ok_or(Errno::EFAULT)?;
+    let len = u32::read_from_bytes(&len_bytes).unwrap() as usize;
+    task.write_bytes(addr as _, &sockaddr[..len.min(sockaddr.len())]);
+    task.write_bytes(addrlen as _, (sockaddr.len() as u32).as_bytes());
+    Ok(())
+}
+
+/// 把 socket 放入文件描述符表
+fn add_socket_file(task: &Sel4Task, socket: Arc<dyn INodeInterface>, flags: usize) -> SysResult {
+    let file = File::new_dev(socket);
+    *file.flags.lock() = match flags & SOCK_NONBLOCK != 0 {
+        true => OpenFlags::RDWR | OpenFlags::NONBLOCK,
+        false => OpenFlags::RDWR,
+    };
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
+
+/// 重复执行 `f` 直到不再返回 `EAGAIN`
+///
+/// ## 参数
+/// - `task`     执行操作的任务
+/// - `file`     socket 对应的文件，在文件的等待队列上等待
+/// - `nonblock` 是否为非阻塞操作，非阻塞时直接返回 `EAGAIN`
+/// - `f`        需要执行的操作
+async fn block_on<T>(
+    task: &Sel4Task,
+    file: &File,
+    nonblock: bool,
+    mut f: impl FnMut() -> Result<T, Errno>,
+) -> Result<T, Errno> {
+    let wait = WaitQueue::from_file(file);
+    loop {
+        match f() {
+            Err(Errno::EAGAIN) if !nonblock => {}
+            res => return res,
+        }
+        if task.has_unmasked_signal() {
+            return Err(Errno::EINTR);
+        }
+        match &wait {
+            Some(wait) => wait.wait(task.tid).await?,
+            None => {
+                wait_time(current_time() + Duration::new(0, 1000000), task.tid).await?;
+            }
+        }
+    }
+}
+
+/// 发送消息，`sendto` 和 `sendmsg` 使用
+///
+/// 阻塞模式下流式 socket 会发送全部的数据，携带的文件和第一部分数据一起发送
+async fn send_message(
+    task: &Sel4Task,
+    fd: usize,
+    data: &[u8],
+    files: Vec<Arc<File>>,
+    to: Option<SockAddr>,
+    flags: usize,
+) -> SysResult {
+    let (file, socket) = get_socket(task, fd)?;
+    let target = socket.target(to)?;
+    let nonblock = flags & MSG_DONTWAIT != 0 || file.flags.lock().contains(OpenFlags::NONBLOCK);
+    let mut sent = 0;
+    let res = loop {
+        let files: &[Arc<File>] = match sent {
+            0 => &files[..],
+            _ => &[],
+        };
+        let res = block_on(task, &file, nonblock, || {
+            socket.send(&data[sent..], files, &target)
+        })
+        .await;
+        match res {
+            Ok(len) => {
+                sent += len;
+                if sent >= data.len() || nonblock || socket.ty() != SOCK_STREAM {
+                    break Ok(sent);
+                }
+            }
+            // 已经发送了一部分数据时返回发送的长度
+            Err(_) if sent > 0 => break Ok(sent),
+            Err(err) => break Err(err),
+        }
+    };
+    if res == Err(Errno::EPIPE) && flags & MSG_NOSIGNAL == 0 {
+        task.add_signal(SignalNum::PIPE, task.tid);
+    }
+    res
+}
+
+/// 接收消息，`recvfrom` 和 `recvmsg` 使用
+///
+/// 返回接收到的消息和数据
+async fn recv_message(
+    task: &Sel4Task,
+    fd: usize,
+    len: usize,
+    flags: usize,
+) -> Result<(Received, Vec<u8>), Errno> {
+    let (file, socket) = get_socket(task, fd)?;
+    let nonblock = flags & MSG_DONTWAIT != 0 || file.flags.lock().contains(OpenFlags::NONBLOCK);
+    let mut buffer = vec![0u8; len];
+    let received = block_on(task, &file, nonblock, || {
+        socket.recv(&mut buffer, flags & MSG_PEEK != 0)
+    })
+    .await?;
+    Ok((received, buffer))
+}
+
+/// 从用户空间读取 [IoVec] 数组，返回 (地址, 长度)
+fn read_iovecs(task: &Sel4Task, iov: usize, iov_len: usize) -> Result<Vec<(usize, usize)>, Errno> {
+    if iov_len > IOV_MAX {
+        return Err(Errno::EMSGSIZE);
+    }
+    let bytes = task
+        .read_bytes(iov, size_of::<IoVec>() * iov_len)
+        .ok_or(Errno::EFAULT)?;
+    let iovecs = <[IoVec]>::ref_from_bytes_with_elems(&bytes, iov_len).unwrap();
+    Ok(iovecs.iter().map(|x| (x.base as usize, x.len)).collect())
+}
+
+/// 读取控制消息中 `SCM_RIGHTS` 传递的文件
+fn read_rights(
+    task: &Sel4Task,
+    control: usize,
+    control_len: usize,
+) -> Result<Vec<Arc<File>>, Errno> {
+    let mut files = Vec::new();
+    let mut offset = 0;
+    while offset + size_of::<CmsgHdr>() <= control_len {
+        let bytes = task
+            .read_bytes(control + offset, size_of::<CmsgHdr>())
+            .ok_or(Errno::EFAULT)?;
+        let cmsg = CmsgHdr::read_from_bytes(&bytes).unwrap();
+        if cmsg.len < size_of::<CmsgHdr>() || offset + cmsg.len > control_len {
+            return Err(Errno::EINVAL);
+        }
+        if cmsg.level as usize == SOL_SOCKET && cmsg.ty == SCM_RIGHTS {
+            let count = (cmsg.len - size_of::<CmsgHdr>()) / size_of::<i32>();
+            if files.len() + count > SCM_MAX_FD {
+                return Err(Errno::EINVAL);
+            }
+            let bytes = task
+                .read_bytes(
+                    control + offset + size_of::<CmsgHdr>(),
+                    count * size_of::<i32>(),
+                )
+                .ok_or(Errno::EFAULT)?;
+            let file_table = task.file.file_ds.lock();
+            for fd in bytes.chunks_exact(size_of::<i32>()) {
+                let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+                let file = file_table.get(fd as _).ok_or(Errno::EBADF)?;
+                files.push(file.clone());
+            }
+        }
+        offset += cmsg_align(cmsg.len);
+    }
+    Ok(files)
+}
+
+/// 把接收到的文件放入文件描述符表，并写入 `SCM_RIGHTS` 控制消息
+///
+/// 返回 (使用的控制消息长度, 控制消息是否被截断)
+fn write_rights(
+    task: &Sel4Task,
+    control: usize,
+    control_len: usize,
+    files: Vec<Arc<File>>,
+) -> (usize, bool) {
+    if files.is_empty() {
+        return (0, false);
+    }
+    if control == 0 || control_len < size_of::<CmsgHdr>() + size_of::<i32>() {
+        return (0, true);
+    }
+    let space = (control_len - size_of::<CmsgHdr>()) / size_of::<i32>();
+    let mut fds: Vec<i32> = Vec::new();
+    {
+        let mut file_table = task.file.file_ds.lock();
+        for file in files.iter().take(space) {
+            if file_table.count() >= task.file.rlimit.lock().curr {
+                break;
+            }
+            match file_table.add(file.clone()) {
+                Ok(fd) => fds.push(fd as _),
+                Err(_) => break,
+            }
+        }
+    }
+    let cmsg = CmsgHdr {
+        len: size_of::<CmsgHdr>() + fds.len() * size_of::<i32>(),
+        level: SOL_SOCKET as _,
+        ty: SCM_RIGHTS,
+    };
+    task.write_bytes(control, cmsg.as_bytes());
+    task.write_bytes(control + size_of::<CmsgHdr>(), fds.as_bytes());
+    (
+        cmsg_align(cmsg.len).min(control_len),
+        fds.len() < files.len(),
+    )
+}
+
+pub(super) fn sys_socket(task: &Sel4Task, domain: usize, ty: usize, protocol: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_socket @ domain: {}, type: {:#x}, protocol: {}",
+        task.tid, domain, ty, protocol
+    );
+    if ty & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let socket: Arc<dyn INodeInterface> = match domain {
+        AF_INET => InetSocket::new(ty & SOCK_TYPE_MASK, protocol)?,
+        AF_UNIX => UnixSocket::new(ty & SOCK_TYPE_MASK, protocol, task.pid)?,
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    add_socket_file(task, socket, ty)
+}
+
+pub(super) fn sys_socketpair(
+    task: &Sel4Task,
+    domain: usize,
+    ty: usize,
+    protocol: usize,
+    sv: *mut u32,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_socketpair @ domain: {}, type: {:#x}, protocol: {}",
+        task.tid, domain, ty, protocol
+    );
+    if ty & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (first, second) = match domain {
+        AF_UNIX => UnixSocket::pair(ty & SOCK_TYPE_MASK, protocol, task.pid)?,
+        AF_INET => return Err(Errno::EOPNOTSUPP),
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    let first = add_socket_file(task, first, ty)?;
+    let second = match add_socket_file(task, second, ty) {
+        Ok(fd) => fd,
+        Err(err) => {
+            let _ = task.file.file_ds.lock().remove(first);
+            return Err(err);
+        }
+    };
+    task.write_bytes(sv as _, [first as u32, second as u32].as_bytes());
+    Ok(0)
+}
+
+pub(super) fn sys_bind(task: &Sel4Task, fd: usize, addr: *const u8, addrlen: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_bind @ fd: {}, addr: {:p}, addrlen: {}",
+        task.tid, fd, addr, addrlen
+    );
+    let (_, socket) = get_socket(task, fd)?;
+    match (socket, read_sockaddr(task, addr, addrlen)?) {
+        (Socket::Inet(socket), SockAddr::Inet(local)) => socket.bind(local)?,
+        (Socket::Unix(socket), SockAddr::Unix(local)) => socket.bind(local)?,
+        (Socket::Inet(_), _) => return Err(Errno::EAFNOSUPPORT),
+        (Socket::Unix(_), _) => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
+
+pub(super) fn sys_listen(task: &Sel4Task, fd: usize, backlog: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_listen @ fd: {}, backlog: {}",
+        task.tid, fd, backlog
+    );
+    match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => socket.listen(backlog)?,
+        Socket::Unix(socket) => socket.listen(backlog)?,
+    }
+    Ok(0)
+}
+
+pub(super) async fn sys_accept4(
+    task: &Sel4Task,
+    fd: usize,
+    addr: *mut u8,
+    addrlen: *mut u32,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_accept4 @ fd: {}, addr: {:p}, flags: {:#x}",
+        task.tid, fd, addr, flags
+    );
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (file, socket) = get_socket(task, fd)?;
+    let nonblock = file.flags.lock().contains(OpenFlags::NONBLOCK);
+    let (new_fd, peer) = match socket {
+        Socket::Inet(socket) => {
+            let accepted = block_on(task, &file, nonblock, || socket.accept()).await?;
+            let peer = inet_addr_bytes(accepted.peer_endpoint()?);
+            (add_socket_file(task, accepted, flags)?, peer)
+        }
+        Socket::Unix(socket) => {
+            let accepted = block_on(task, &file, nonblock, || socket.accept()).await?;
+            let peer = accepted.peer_addr().unwrap_or_default().to_bytes();
+            (add_socket_file(task, accepted, flags)?, peer)
+        }
+    };
+    write_sockaddr(task, addr, addrlen, &peer)?;
+    Ok(new_fd)
+}
+
+pub(super) async fn sys_connect(
+    task: &Sel4Task,
+    fd: usize,
+    addr: *const u8,
+    addrlen: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_connect @ fd: {}, addr: {:p}, addrlen: {}",
+        task.tid, fd, addr, addrlen
+    );
+    let (file, socket) = get_socket(task, fd)?;
+    let nonblock = file.flags.lock().contains(OpenFlags::NONBLOCK);
+    let socket = match (socket, read_sockaddr(task, addr, addrlen)?) {
+        (Socket::Inet(socket), SockAddr::Inet(remote)) => {
+            socket.connect(remote)?;
+            socket
+        }
+        // Unix socket 的连接会立即建立，只有对端的监听队列已满时需要等待
+        (Socket::Unix(socket), SockAddr::Unix(remote)) => {
+            let target = unix::lookup(&remote)?;
+            block_on(task, &file, nonblock, || socket.connect(&target)).await?;
+            return Ok(0);
+        }
+        (Socket::Inet(_), _) => return Err(Errno::EAFNOSUPPORT),
+        (Socket::Unix(_), _) => return Err(Errno::EINVAL),
+    };
+    if socket.ty() != SOCK_STREAM {
+        return Ok(0);
+    }
+    if nonblock {
+        socket.connect_result()?;
+        return Ok(0);
+    }
+    block_on(task, &file, false, || match socket.connect_result() {
+        Err(Errno::EINPROGRESS) => Err(Errno::EAGAIN),
+        res => res,
+    })
+    .await?;
+    Ok(0)
+}
+
+pub(super) async fn sys_sendto(
+    task: &Sel4Task,
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    flags: usize,
+    addr: *const u8,
+    addrlen: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_sendto @ fd: {}, buf: {:p}, len: {:#x}, flags: {:#x}, addr: {:p}",
+        task.tid, fd, buf, len, flags, addr
+    );
+    let to = match addr.is_null() {
+        true => None,
+        false => Some(read_sockaddr(task, addr, addrlen)?),
+    };
+    let data = task.read_bytes(buf as _, len).ok_or(Errno::EFAULT)?;
+    send_message(task, fd, &data, Vec::new(), to, flags).await
+}
+
+pub(super) async fn sys_recvfrom(
+    task: &Sel4Task,
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    flags: usize,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_recvfrom @ fd: {}, buf: {:p}, len: {:#x}, flags: {:#x}, addr: {:p}",
+        task.tid, fd, buf, len, flags, addr
+    );
+    // 通过 recvfrom 接收时消息携带的文件被丢弃
+    let (received, buffer) = recv_message(task, fd, len, flags).await?;
+    task.write_bytes(buf as _, &buffer[..received.len]);
+    if let Some(from) = received.from {
+        write_sockaddr(task, addr, addrlen, &from)?;
+    }
+    match flags & MSG_TRUNC != 0 {
+        true => Ok(received.msg_len),
+        false => Ok(received.len),
+    }
+}
+
+pub(super) async fn sys_sendmsg(
+    task: &Sel4Task,
+    fd: usize,
+    msg: *const u8,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_sendmsg @ fd: {}, msg: {:p}, flags: {:#x}",
+        task.tid, fd, msg, flags
+    );
+    let bytes = task
+        .read_bytes(msg as _, size_of::<MsgHdr>())
+        .ok_or(Errno::EFAULT)?;
+    let hdr = MsgHdr::read_from_bytes(&bytes).unwrap();
+    let to = match hdr.name != 0 && hdr.name_len != 0 {
+        true => Some(read_sockaddr(task, hdr.name as _, hdr.name_len as _)?),
+        false => None,
+    };
+    let mut data = Vec::new();
+    for (base, len) in read_iovecs(task, hdr.iov, hdr.iov_len)? {
+        data.extend(task.read_bytes(base, len).ok_or(Errno::EFAULT)?);
+    }
+    let files = read_rights(task, hdr.control, hdr.control_len)?;
+    send_message(task, fd, &data, files, to, flags).await
+}
+
+pub(super) async fn sys_recvmsg(
+    task: &Sel4Task,
+    fd: usize,
+    msg: *mut u8,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_recvmsg @ fd: {}, msg: {:p}, flags: {:#x}",
+        task.tid, fd, msg, flags
+    );
+    let bytes = task
+        .read_bytes(msg as _, size_of::<MsgHdr>())
+        .ok_or(Errno::EFAULT)?;
+    let mut hdr = MsgHdr::read_from_bytes(&bytes).unwrap();
+    let iovecs = read_iovecs(task, hdr.iov, hdr.iov_len)?;
+    let len = iovecs.iter().map(|x| x.1).sum();
+    let (received, buffer) = recv_message(task, fd, len, flags).await?;
+
+    let mut offset = 0;
+    for (base, len) in iovecs {
+        if offset >= received.len {
+            break;
+        }
+        let len = len.min(received.len - offset);
+        task.write_bytes(base, &buffer[offset..offset + len]);
+        offset += len;
+    }
+
+    hdr.flags = 0;
+    if received.msg_len > received.len {
+        hdr.flags |= MSG_TRUNC as u32;
+    }
+    match (hdr.name, received.from) {
+        (0, _) | (_, None) => hdr.name_len = 0,
+        (name, Some(from)) => {
+            task.write_bytes(name, &from[..from.len().min(hdr.name_len as _)]);
+            hdr.name_len = from.len() as _;
+        }
+    }
+    let (control_len, truncated) = write_rights(task, hdr.control, hdr.control_len, received.files);
+    hdr.control_len = control_len;
+    if truncated {
+        hdr.flags |= MSG_CTRUNC;
+    }
+    task.write_bytes(msg as _, hdr.as_bytes());
+    match flags & MSG_TRUNC != 0 {
+        true => Ok(received.msg_len),
+        false => Ok(received.len),
+    }
+}
+
+pub(super) fn sys_shutdown(task: &Sel4Task, fd: usize, how: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_shutdown @ fd: {}, how: {}",
+        task.tid, fd, how
+    );
+    match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => socket.shutdown(how)?,
+        Socket::Unix(socket) => socket.shutdown(how)?,
+    }
+    Ok(0)
+}
+
+pub(super) fn sys_getsockname(
+    task: &Sel4Task,
+    fd: usize,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> SysResult {
+    let sockaddr = match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => inet_addr_bytes(socket.local_endpoint()),
+        Socket::Unix(socket) => socket.local_addr().to_bytes(),
+    };
+    write_sockaddr(task, addr, addrlen, &sockaddr)?;
+    Ok(0)
+}
+
+pub(super) fn sys_getpeername(
+    task: &Sel4Task,
+    fd: usize,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> SysResult {
+    let sockaddr = match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => inet_addr_bytes(socket.peer_endpoint()?),
+        Socket::Unix(socket) => socket.peer_addr()?.to_bytes(),
+    };
+    write_sockaddr(task, addr, addrlen, &sockaddr)?;
+    Ok(0)
+}
+
+pub(super) fn sys_setsockopt(
+    task: &Sel4Task,
+    fd: usize,
+    level: usize,
+    optname: usize,
+    optval: *const u8,
+    optlen: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_setsockopt @ fd: {}, level: {}, optname: {}, optlen: {}",
+        task.tid, fd, level, optname, optlen
+    );
+    let value = match optlen >= size_of::<u32>() {
+        true => {
+            let bytes = task
+                .read_bytes(optval as _, size_of::<u32>())
+                .ok_or(Errno::EFAULT)?;
+            u32::read_from_bytes(&bytes).unwrap() != 0
+        }
+        false => false,
+    };
+    let socket = match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => socket,
+        // Unix socket 的选项都是固定的
+        Socket::Unix(_) => match (level, optname) {
+            (
+                SOL_SOCKET,
+                SO_REUSEADDR | SO_KEEPALIVE | SO_SNDBUF | SO_RCVBUF | SO_LINGER | SO_REUSEPORT
+                | SO_PASSCRED | SO_RCVTIMEO | SO_SNDTIMEO,
+            ) => return Ok(0),
+            _ => return Err(Errno::ENOPROTOOPT),
+        },
+    };
+    let mut options = socket.options();
+    match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => options.reuse_addr = value,
+        (SOL_SOCKET, SO_KEEPALIVE) => options.keep_alive = value,
+        (IPPROTO_TCP, TCP_NODELAY) => options.no_delay = value,
+        // 缓冲区大小和超时时间是固定的，忽略这些选项
+        (
+            SOL_SOCKET,
+            SO_SNDBUF | SO_RCVBUF | SO_LINGER | SO_REUSEPORT | SO_RCVTIMEO | SO_SNDTIMEO,
+        )
+        | (IPPROTO_IP, _) => {}
+        _ => return Err(Errno::ENOPROTOOPT),
+    }
+    socket.set_options(options);
+    Ok(0)
+}
+
+pub(super) fn sys_getsockopt(
+    task: &Sel4Task,
+    fd: usize,
+    level: usize,
+    optname: usize,
+    optval: *mut u8,
+    optlen: *mut u32,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_getsockopt @ fd: {}, level: {}, optname: {}",
+        task.tid, fd, level, optname
+    );
+    let value = match get_socket(task, fd)?.1 {
+        Socket::Inet(socket) => {
+            let options = socket.options();
+            match (level, optname) {
+                (SOL_SOCKET, SO_TYPE) => socket.ty(),
+                (SOL_SOCKET, SO_ERROR) => match socket.connect_result() {
+                    Err(Errno::EINPROGRESS) | Ok(()) => 0,
+                    Err(err) => err.into_raw() as _,
+                },
+                (SOL_SOCKET, SO_REUSEADDR) => options.reuse_addr as _,
+                (SOL_SOCKET, SO_KEEPALIVE) => options.keep_alive as _,
+                (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF) => TCP_BUFFER_SIZE,
+                (IPPROTO_TCP, TCP_NODELAY) => options.no_delay as _,
+                _ => return Err(Errno::ENOPROTOOPT),
+            }
+        }
+        Socket::Unix(socket) => match (level, optname) {
+            (SOL_SOCKET, SO_PEERCRED) => {
+                let cred = UCred {
+                    pid: socket.peer_pid().ok_or(Errno::ENOTCONN)? as _,
+                    uid: 0,
+                    gid: 0,
+                };
+                task.write_bytes(optval as _, cred.as_bytes());
+                task.write_bytes(optlen as _, (size_of::<UCred>() as u32).as_bytes());
+                return Ok(0);
+            }
+            (SOL_SOCKET, SO_TYPE) => socket.ty(),
+            (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF) => UNIX_BUFFER_SIZE,
+            (SOL_SOCKET, SO_ERROR | SO_REUSEADDR | SO_KEEPALIVE | SO_PASSCRED) => 0,
+            _ => return Err(Errno::ENOPROTOOPT),
+        },
+    };
+    task.write_bytes(optval as _, (value as u32).as_bytes());
+    task.write_bytes(optlen as _, (size_of::<u32>() as u32).as_bytes());
+    Ok(0)
+}
@@ -2,7 +2,10 @@
 //!
 //!
 
+use alloc::vec::Vec;
+use fs::file::File;
 use libc_core::{
+    fcntl::OpenFlags,
     internal::SigAction,
     signal::SignalNum,
     types::{SigMaskHow, SigSet},
@@ -11,7 +14,11 @@ use sel4::UserContext;
 use syscalls::Errno;
 use zerocopy::{FromBytes, IntoBytes};
 
-use crate::{child_test::TASK_MAP, task::Sel4Task};
+use crate::{
+    child_test::TASK_MAP,
+    fs::signalfd::SignalFd,
+    task::{Sel4Task, kill_pgrp},
+};
 
 use super::SysResult;
 
@@ -55,18 +62,104 @@ pub(super) fn sys_sigaction(
 }
 
 pub(super) fn sys_kill(task: &Sel4Task, pid: usize, sig: usize) -> SysResult {
+    let pid = pid as isize;
+    let signal = match sig {
+        0 => None,
+        _ => Some(SignalNum::from_num(sig).ok_or(Errno::EINVAL)?),
+    };
+    // pid <= 0 时向进程组发送信号
+    if pid <= 0 {
+        let count = match pid {
+            0 => kill_group(task.pcb.pgid(), signal, task.tid),
+            -1 => {
+                let targets: Vec<_> = TASK_MAP
+                    .lock()
+                    .values()
+                    .filter(|x| x.pid == x.tid && x.pid != 1 && x.pid != task.pid)
+                    .cloned()
+                    .collect();
+                if let Some(signal) = signal {
+                    targets.iter().for_each(|x| x.add_signal(signal, task.tid));
+                }
+                targets.len()
+            }
+            _ => kill_group(-pid as _, signal, task.tid),
+        };
+        return match count {
+            0 => Err(Errno::ESRCH),
+            _ => Ok(0),
+        };
+    }
     let target = TASK_MAP
         .lock()
         .get(&(pid as _))
         .ok_or(Errno::ESRCH)?
         .clone();
-    if sig == 0 {
-        return Ok(0);
+    if let Some(signal) = signal {
+        target.add_signal(signal, task.tid);
     }
-    target.add_signal(SignalNum::from_num(sig).ok_or(Errno::EINVAL)?, task.tid);
     Ok(0)
 }
 
+/// 向进程组发送信号，`signal` 为 [None] 时仅检查进程组是否存在
+fn kill_group(pgid: usize, signal: Option<SignalNum>, from: usize) -> usize {
+    match signal {
+        Some(signal) => kill_pgrp(pgid, signal, from),
+        None => TASK_MAP
+            .lock()
+            .values()
+            .filter(|x| x.pid == x.tid && x.pcb.pgid() == pgid)
+            .count(),
+    }
+}
+
+pub(super) fn sys_signalfd4(
+    task: &Sel4Task,
+    fd: isize,
+    mask_ptr: *const SigSet,
+    sizemask: usize,
+    flags: usize,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_signalfd4 @ fd: {}, mask: {:p}, sizemask: {}, flags: {:#x}",
+        task.tid, fd, mask_ptr, sizemask, flags
+    );
+    if sizemask != size_of::<SigSet>() {
+        return Err(Errno::EINVAL);
+    }
+    let open_flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if !(open_flags - OpenFlags::NONBLOCK - OpenFlags::CLOEXEC).is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    let mask_bytes = task
+        .read_bytes(mask_ptr as _, size_of::<SigSet>())
+        .ok_or(Errno::EFAULT)?;
+    let mask = SigSet::read_from_bytes(&mask_bytes).unwrap();
+
+    // 修改已有 signalfd 的信号集合
+    if fd != -1 {
+        let file = task
+            .file
+            .file_ds
+            .lock()
+            .get(fd as _)
+            .ok_or(Errno::EBADF)?
+            .clone();
+        SignalFd::from_file(&file)
+            .ok_or(Errno::EINVAL)?
+            .set_mask(mask);
+        return Ok(fd as _);
+    }
+
+    let file = File::new_dev(SignalFd::new(task.tid, mask));
+    *file.flags.lock() = OpenFlags::RDONLY | (open_flags & OpenFlags::NONBLOCK);
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
+
 pub(super) fn sys_sigreturn(task: &Sel4Task, ctx: &mut UserContext) -> SysResult {
     task.read_ucontext(ctx);
     *ctx.pc_mut() -= 4;
@@ -2,18 +2,34 @@
 //!
 //!
 
-use core::time::Duration;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
+use alloc::{string::String, sync::Arc, vec::Vec};
+use fs::file::File;
 use libc_core::{
+    fcntl::OpenFlags,
     resource::{Rlimit, Rusage},
     types::{TimeSpec, TimeVal},
     utsname::UTSName,
 };
+use log::LevelFilter;
 use sel4_kit::arch::current_time;
+use spin::Mutex;
+use srv_gate::klog::{self, LOG_MSG_LEN, LOG_RECORDS, LogCursor};
 use syscalls::Errno;
 use zerocopy::{FromBytes, IntoBytes};
 
-use crate::{task::Sel4Task, timer::wait_time};
+use crate::{
+    fs::{
+        devfs::kmsg::format_syslog,
+        timerfd::{ITimerSpec, TFD_TIMER_ABSTIME, TimerFd},
+    },
+    task::Sel4Task,
+    timer::wait_time,
+};
 
 use super::SysResult;
 
@@ -105,6 +121,84 @@ pub(super) async fn sys_nanosleep(
     Ok(0)
 }
 
+/// 根据文件描述符获取打开的 timerfd
+fn get_timerfd(task: &Sel4Task, fd: usize) -> Result<Arc<TimerFd>, Errno> {
+    let file = task
+        .file
+        .file_ds
+        .lock()
+        .get(fd)
+        .ok_or(Errno::EBADF)?
+        .clone();
+    TimerFd::from_file(&file).ok_or(Errno::EINVAL)
+}
+
+pub(super) fn sys_timerfd_create(task: &Sel4Task, clock_id: usize, flags: usize) -> SysResult {
+    debug!(
+        "[task {}] sys_timerfd_create @ clock_id: {}, flags: {:#x}",
+        task.tid, clock_id, flags
+    );
+    // 目前 CLOCK_REALTIME 和 CLOCK_MONOTONIC 使用同一个时钟
+    if clock_id > 1 {
+        return Err(Errno::EINVAL);
+    }
+    let open_flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if !(open_flags - OpenFlags::NONBLOCK - OpenFlags::CLOEXEC).is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    let file = File::new_dev(TimerFd::new());
+    *file.flags.lock() = OpenFlags::RDONLY | (open_flags & OpenFlags::NONBLOCK);
+
+    let mut file_table = task.file.file_ds.lock();
+    if file_table.count() >= task.file.rlimit.lock().curr {
+        return Err(Errno::EMFILE);
+    }
+    file_table.add(file).map_err(|_| Errno::EMFILE)
+}
+
+pub(super) fn sys_timerfd_settime(
+    task: &Sel4Task,
+    fd: usize,
+    flags: usize,
+    new_ptr: *const ITimerSpec,
+    old_ptr: *mut ITimerSpec,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_timerfd_settime @ fd: {}, flags: {:#x}, new: {:p}, old: {:p}",
+        task.tid, fd, flags, new_ptr, old_ptr
+    );
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let timer = get_timerfd(task, fd)?;
+    let new_bytes = task
+        .read_bytes(new_ptr as _, size_of::<ITimerSpec>())
+        .ok_or(Errno::EFAULT)?;
+    let new = ITimerSpec::read_from_bytes(&new_bytes).unwrap();
+    if new.value.nsec >= 1_000_000_000 || new.interval.nsec >= 1_000_000_000 {
+        return Err(Errno::EINVAL);
+    }
+    let old = timer.set(new, flags & TFD_TIMER_ABSTIME != 0);
+    if !old_ptr.is_null() {
+        task.write_bytes(old_ptr as _, old.as_bytes());
+    }
+    Ok(0)
+}
+
+pub(super) fn sys_timerfd_gettime(
+    task: &Sel4Task,
+    fd: usize,
+    curr_ptr: *mut ITimerSpec,
+) -> SysResult {
+    debug!(
+        "[task {}] sys_timerfd_gettime @ fd: {}, curr: {:p}",
+        task.tid, fd, curr_ptr
+    );
+    let timer = get_timerfd(task, fd)?;
+    task.write_bytes(curr_ptr as _, timer.get().as_bytes());
+    Ok(0)
+}
+
 pub(super) fn sys_prlimit64(
     task: &Sel4Task,
     pid: usize,
@@ -146,3 +240,134 @@ pub(super) fn sys_getrusage(_task: &Sel4Task, _who: usize, _usage_ptr: *mut Rusa
     // Ok(0)
     Err(Errno::EPERM)
 }
+
+// `syslog` 的操作类型
+const SYSLOG_ACTION_CLOSE: usize = 0;
+const SYSLOG_ACTION_OPEN: usize = 1;
+const SYSLOG_ACTION_READ: usize = 2;
+const SYSLOG_ACTION_READ_ALL: usize = 3;
+const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+const SYSLOG_ACTION_CLEAR: usize = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: usize = 6;
+const SYSLOG_ACTION_CONSOLE_ON: usize = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: usize = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+/// `SYSLOG_ACTION_READ` 读取日志的位置
+static SYSLOG_CURSOR: Mutex<LogCursor> = Mutex::new(LogCursor::new());
+
+/// `SYSLOG_ACTION_CLEAR` 之后的第一条日志的序号
+static SYSLOG_CLEAR_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 按照 syslog 的格式读取 `cursor` 之后所有已经提交的日志
+fn syslog_entries(mut cursor: LogCursor) -> Vec<String> {
+    let Some(ring) = klog::ring() else {
+        return Vec::new();
+    };
+    cursor.poll(ring).iter().map(format_syslog).collect()
+}
+
+/// 读取和控制日志环
+///
+/// ## 参数
+/// - `action` 操作类型，`SYSLOG_ACTION_*`
+/// - `buf`    读取日志时使用的缓冲区
+/// - `len`    缓冲区的长度，`SYSLOG_ACTION_CONSOLE_LEVEL` 时表示控制台的日志级别
+pub(super) async fn sys_syslog(
+    task: &Sel4Task,
+    action: usize,
+    buf: *mut u8,
+    len: usize,
+) -> SysResult {
+    debug!(
+        "sys_syslog @ action: {}, buf: {:p}, len: {}",
+        action, buf, len
+    );
+    let ring = klog::ring().ok_or(Errno::ENOSYS)?;
+    match action {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => Ok(0),
+        SYSLOG_ACTION_CONSOLE_OFF | SYSLOG_ACTION_CONSOLE_ON => Ok(0),
+        // 读取还没有读取过的日志，没有日志时等待
+        SYSLOG_ACTION_READ => {
+            if buf.is_null() {
+                return Err(Errno::EINVAL);
+            }
+            if len == 0 {
+                return Ok(0);
+            }
+            while SYSLOG_CURSOR.lock().seq() >= ring.next_seq() {
+                if task.has_unmasked_signal() {
+                    return Err(Errno::EINTR);
+                }
+                wait_time(current_time() + Duration::from_millis(10), task.tid).await?;
+            }
+            let mut cursor = SYSLOG_CURSOR.lock();
+            let mut data = Vec::new();
+            loop {
+                // 缓冲区放不下的日志留到下一次读取
+                let mut next = cursor.clone();
+                let Some(entry) = next.next(ring) else {
+                    break;
+                };
+                let line = format_syslog(&entry);
+                if data.len() + line.len() > len {
+                    break;
+                }
+                data.extend_from_slice(line.as_bytes());
+                *cursor = next;
+            }
+            drop(cursor);
+            task.write_bytes(buf as _, &data);
+            Ok(data.len())
+        }
+        // 读取日志环中最新的日志，尽可能填满缓冲区
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            if buf.is_null() {
+                return Err(Errno::EINVAL);
+            }
+            let end = ring.next_seq();
+            let lines = syslog_entries(LogCursor::at(SYSLOG_CLEAR_SEQ.load(Ordering::SeqCst)));
+            let mut size = 0;
+            let count = lines
+                .iter()
+                .rev()
+                .take_while(|x| {
+                    size += x.len();
+                    size <= len
+                })
+                .count();
+            let data: Vec<u8> = lines[lines.len() - count..]
+                .iter()
+                .flat_map(|x| x.bytes())
+                .collect();
+            task.write_bytes(buf as _, &data);
+            if action == SYSLOG_ACTION_READ_CLEAR {
+                SYSLOG_CLEAR_SEQ.store(end, Ordering::SeqCst);
+            }
+            Ok(data.len())
+        }
+        SYSLOG_ACTION_CLEAR => {
+            SYSLOG_CLEAR_SEQ.store(ring.next_seq(), Ordering::SeqCst);
+            Ok(0)
+        }
+        // 修改 kernel-thread 的日志级别，只输出优先级小于 `len` 的日志
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            let level = match len {
+                1..=4 => LevelFilter::Error,
+                5 => LevelFilter::Warn,
+                6 | 7 => LevelFilter::Info,
+                8 => LevelFilter::Debug,
+                _ => return Err(Errno::EINVAL),
+            };
+            ring.set_level("kernel-thread", level);
+            Ok(0)
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(syslog_entries(SYSLOG_CURSOR.lock().clone())
+            .iter()
+            .map(|x| x.len())
+            .sum()),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(LOG_RECORDS * LOG_MSG_LEN),
+        _ => Err(Errno::EINVAL),
+    }
+}
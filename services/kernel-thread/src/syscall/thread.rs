@@ -52,6 +52,70 @@ pub fn sys_getppid(task: &Sel4Task) -> SysResult {
     Ok(task.ppid)
 }
 
+/// 根据 pid 查找进程，pid 为 0 时表示当前进程
+fn find_process(task: &Sel4Task, pid: usize) -> Result<ArcTask, Errno> {
+    let pid = if pid == 0 { task.pid } else { pid };
+    TASK_MAP
+        .lock()
+        .get(&(pid as _))
+        .filter(|x| x.pid == x.tid && x.exit.lock().is_none())
+        .cloned()
+        .ok_or(Errno::ESRCH)
+}
+
+/// 设置进程组 id
+///
+/// 只能设置当前进程或者其子进程，并且只能加入同一会话中的进程组
+pub(super) fn sys_setpgid(task: &Sel4Task, pid: usize, pgid: usize) -> SysResult {
+    if (pgid as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let target = find_process(task, pid)?;
+    if target.pid != task.pid && target.ppid != task.pid {
+        return Err(Errno::ESRCH);
+    }
+    let pgid = if pgid == 0 { target.pid } else { pgid };
+    let sid = target.pcb.sid();
+    // 会话首进程不能修改进程组，也不能修改其他会话中的进程
+    if sid == target.pid || sid != task.pcb.sid() {
+        return Err(Errno::EPERM);
+    }
+    if pgid != target.pid
+        && !TASK_MAP
+            .lock()
+            .values()
+            .any(|x| x.pid == x.tid && x.pcb.pgid() == pgid && x.pcb.sid() == sid)
+    {
+        return Err(Errno::EPERM);
+    }
+    target.pcb.set_pgid(pgid);
+    Ok(0)
+}
+
+/// 获取进程组 id
+pub(super) fn sys_getpgid(task: &Sel4Task, pid: usize) -> SysResult {
+    Ok(find_process(task, pid)?.pcb.pgid())
+}
+
+/// 创建新的会话，当前进程成为会话首进程和进程组组长
+pub(super) fn sys_setsid(task: &Sel4Task) -> SysResult {
+    if TASK_MAP
+        .lock()
+        .values()
+        .any(|x| x.pid == x.tid && x.pcb.pgid() == task.pid)
+    {
+        return Err(Errno::EPERM);
+    }
+    task.pcb.set_sid(task.pid);
+    task.pcb.set_pgid(task.pid);
+    Ok(task.pid)
+}
+
+/// 获取会话 id
+pub(super) fn sys_getsid(task: &Sel4Task, pid: usize) -> SysResult {
+    Ok(find_process(task, pid)?.pcb.sid())
+}
+
 #[inline]
 pub(super) fn sys_set_tid_addr(task: &Sel4Task, addr: usize) -> SysResult {
     *task.clear_child_tid.lock() = addr;
@@ -75,21 +139,19 @@ pub(super) async fn sys_wait4(
 ) -> SysResult {
     log::warn!("wait for {} ptr: {:p} option: {}", pid, status, option);
     let options = WaitOption::from_bits_truncate(option);
-    if options.contains(WaitOption::WUNTRACED) {
-        panic!("option({:?}  {}) is not supported", options, option);
-    }
 
     if !TASK_MAP.lock().iter().any(|x| x.1.ppid == task.pid) {
         return Err(Errno::ECHILD);
     }
 
     let finded = if pid == -1 {
-        WaitAnyChild(task.pid as _, options.contains(WaitOption::WHOHANG)).await
+        WaitAnyChild(task.pid as _, options.contains(WaitOption::WHOHANG), option).await
     } else if pid > 0 {
         WaitPid(
             task.pid as _,
             pid as _,
             options.contains(WaitOption::WHOHANG),
+            option,
         )
         .await
     } else {
@@ -110,7 +172,11 @@ pub(super) async fn sys_wait4(
     let (idx, exit_code) = finded.unwrap()?;
     task.write_bytes(status as _, exit_code.as_bytes());
 
-    TASK_MAP.lock().remove(&idx);
+    // 停止和继续事件不会回收子进程
+    let mut task_map = TASK_MAP.lock();
+    if task_map.get(&idx).is_some_and(|x| x.exit.lock().is_some()) {
+        task_map.remove(&idx);
+    }
     Ok(idx as _)
 }
 
@@ -158,6 +224,11 @@ pub(super) async fn sys_clone(
     new_task.signal.lock().exit_sig = SignalNum::from_num(signal as _);
     new_task.signal.lock().mask = task.signal.lock().mask;
     new_task.ppid = task.pid;
+    // 子进程继承父进程的进程组和会话
+    if !flags.contains(CloneFlags::CLONE_THREAD) {
+        new_task.pcb.set_pgid(task.pcb.pgid());
+        new_task.pcb.set_sid(task.pcb.sid());
+    }
 
     let mut regs = task.tcb.tcb_read_all_registers(true).unwrap();
     *regs.c_param_mut(0) = 0;
@@ -236,9 +307,7 @@ pub(super) async fn sys_clone(
                 return;
             }
             maped_shared_memory
-                .mem
-                .trackers
-                .iter()
+                .pages()
                 .enumerate()
                 .for_each(|(i, page)| {
                     let new_slot = alloc_slot();
@@ -294,6 +363,18 @@ pub(super) fn sys_execve(
 
     let file = File::open(path, OpenFlags::RDONLY)?;
 
+    // 关闭设置了 O_CLOEXEC 的文件描述符
+    let mut file_table = task.file.file_ds.lock();
+    for fd in 0..0x200 {
+        if file_table
+            .get(fd)
+            .is_some_and(|file| file.flags.lock().contains(OpenFlags::CLOEXEC))
+        {
+            file_table.remove(fd);
+        }
+    }
+    drop(file_table);
+
     task.clear_maped();
     task.mem.lock().heap = DEF_HEAP_ADDR;
 
@@ -2,7 +2,7 @@ use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 use common::config::{DEFAULT_PARENT_EP, LINUX_APP_CNODE_RADIX_BITS, PAGE_SIZE, STACK_ALIGN_SIZE};
 use libc_core::elf::AuxType;
 use memory_addr::MemoryAddr;
-use sel4::{CNodeCapData, init_thread::slot};
+use sel4::CNodeCapData;
 use sel4_kit::slot_manager::LeafSlot;
 
 use crate::consts::task::{DEF_STACK_TOP, VDSO_APP_ADDR};
@@ -112,10 +112,10 @@ impl Sel4Task {
 
     /// 初始化 [sel4::cap::Tcb] 信息
     ///
-    /// 初始化 tcb 信息，设置调度优先级，目前设定为固定值
-    /// TODO: 更改调度优先级的设置，更加灵活自由
+    /// 初始化 tcb 信息，按照 root-task 分配的调度参数设置优先级
     pub fn init_tcb(&self) -> Result<(), sel4::Error> {
         self.tcb.tcb_configure(
+            #[cfg(not(feature = "mcs"))]
             DEFAULT_PARENT_EP.cptr(),
             self.cnode,
             CNodeCapData::new(0, sel4::WORD_SIZE - LINUX_APP_CNODE_RADIX_BITS),
@@ -123,6 +123,6 @@ impl Sel4Task {
             0,
             LeafSlot::new(0).cap(),
         )?;
-        self.tcb.tcb_set_sched_params(slot::TCB.cap(), 0, 255)
+        self.init_sched()
     }
 }
@@ -6,6 +6,7 @@ mod info;
 mod init;
 mod mem;
 mod pcb;
+mod sched;
 pub mod shm;
 mod signal;
 
@@ -18,7 +19,7 @@ use common::{
 };
 use core::{
     cmp,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     task::Waker,
 };
 use file::TaskFileInfo;
@@ -32,6 +33,7 @@ use sel4::{
 };
 use sel4_kit::slot_manager::LeafSlot;
 use signal::TaskSignal;
+pub use signal::kill_pgrp;
 use spin::mutex::Mutex;
 use zerocopy::IntoBytes;
 
@@ -49,8 +51,6 @@ pub struct Sel4Task {
     pub pid: usize,
     /// 父进程 ID
     pub ppid: usize,
-    /// 进程组 ID
-    pub pgid: usize,
     /// 任务 ID (线程 ID)
     pub tid: usize,
     /// 资源内存分配器
@@ -87,6 +87,10 @@ pub struct Sel4Task {
     pub pcb: Arc<ProcessControlBlock>,
     /// 异步 await 时存储的结构
     pub waker: Mutex<Option<(PollWakeEvent, Waker)>>,
+    /// 线程是否正在等待 kernel-thread 处理系统调用
+    pub in_syscall: AtomicBool,
+    /// 线程因为进程被停止而暂停，进程继续运行时恢复
+    parked: AtomicBool,
 }
 
 /// 在 Poll 的时候唤醒协程的事件类型
@@ -144,7 +148,6 @@ impl Sel4Task {
         Ok(Sel4Task {
             tid,
             pid: tid,
-            pgid: 0,
             ppid: 1,
             tcb,
             cnode,
@@ -159,8 +162,10 @@ impl Sel4Task {
             file: TaskFileInfo::default(),
             info: Mutex::new(TaskInfo::default()),
             thread_counter: Mutex::new(Some(Arc::new(()))),
-            pcb: Arc::new(ProcessControlBlock::new()),
+            pcb: Arc::new(ProcessControlBlock::with_group(tid, tid)),
             waker: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            parked: AtomicBool::new(false),
         })
     }
 
@@ -192,7 +197,6 @@ impl Sel4Task {
         Ok(Sel4Task {
             pid: self.pid,
             ppid: self.ppid,
-            pgid: self.pgid,
             tid,
             tcb,
             cnode,
@@ -209,6 +213,8 @@ impl Sel4Task {
             thread_counter: Mutex::new(self.thread_counter.lock().clone()),
             pcb: self.pcb.clone(),
             waker: Mutex::new(None),
+            in_syscall: AtomicBool::new(false),
+            parked: AtomicBool::new(false),
         })
     }
 
@@ -388,12 +394,10 @@ impl Sel4Task {
             futex_wake(self.futex_table.clone(), uaddr, 1);
         }
         if self.ppid != self.pid {
-            if let Some(signal) = self.signal.lock().exit_sig {
-                TASK_MAP
-                    .lock()
-                    .iter()
-                    .find(|x| *x.0 == self.ppid as _)
-                    .inspect(|parent| parent.1.add_signal(signal, self.tid));
+            let exit_sig = self.signal.lock().exit_sig;
+            let parent = TASK_MAP.lock().get(&(self.ppid as _)).cloned();
+            if let (Some(signal), Some(parent)) = (exit_sig, parent) {
+                parent.add_signal(signal, self.tid);
             }
         }
         // 释放资源
@@ -404,6 +408,8 @@ impl Sel4Task {
         root_cnode.absolute_cptr(self.cnode).delete().unwrap();
         recycle_slot(self.tcb.into());
         recycle_slot(self.cnode.into());
+        #[cfg(feature = "mcs")]
+        self.release_sched();
 
         if Arc::strong_count(self.thread_counter.lock().as_ref().unwrap()) == 1 {
             root_cnode.absolute_cptr(self.vspace).revoke().unwrap();
@@ -1,10 +1,22 @@
 //! 进程控制块和进程信息
 
-use core::time::Duration;
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
 
-use libc_core::time::ITimerVal;
+use libc_core::{signal::SignalNum, time::ITimerVal};
 use spin::Mutex;
 
+/// `wait` 报告被停止的子进程
+pub const WUNTRACED: u32 = 2;
+
+/// `wait` 报告继续运行的子进程
+pub const WCONTINUED: u32 = 8;
+
+/// 进程继续运行时 `wait` 返回的状态
+const CONTINUED_STATUS: u32 = 0xffff;
+
 /// 进程控制块
 pub struct ProcessControlBlock {
     // /// 进程 ID
@@ -13,6 +25,14 @@ pub struct ProcessControlBlock {
     // pub ppid: usize,
     /// 定时器信息
     pub itimer: Mutex<[ProcessTimer; 3]>,
+    /// 进程组 ID
+    pgid: AtomicUsize,
+    /// 会话 ID
+    sid: AtomicUsize,
+    /// 进程是否被停止信号停止
+    stopped: AtomicBool,
+    /// 还没有被父进程 `wait` 取走的停止或者继续事件，值为 `wait` 返回的状态
+    job_event: Mutex<Option<u32>>,
 }
 
 #[derive(Debug, Clone, Default, zerocopy::KnownLayout)]
@@ -31,6 +51,76 @@ impl ProcessControlBlock {
                 ProcessTimer::default(),
                 ProcessTimer::default(),
             ]),
+            pgid: AtomicUsize::new(0),
+            sid: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+            job_event: Mutex::new(None),
+        }
+    }
+
+    /// 创建一个属于指定进程组和会话的进程控制块
+    pub fn with_group(pgid: usize, sid: usize) -> Self {
+        let pcb = Self::new();
+        pcb.set_pgid(pgid);
+        pcb.set_sid(sid);
+        pcb
+    }
+
+    /// 进程组 ID
+    pub fn pgid(&self) -> usize {
+        self.pgid.load(Ordering::SeqCst)
+    }
+
+    /// 设置进程组 ID
+    pub fn set_pgid(&self, pgid: usize) {
+        self.pgid.store(pgid, Ordering::SeqCst);
+    }
+
+    /// 会话 ID
+    pub fn sid(&self) -> usize {
+        self.sid.load(Ordering::SeqCst)
+    }
+
+    /// 设置会话 ID
+    pub fn set_sid(&self, sid: usize) {
+        self.sid.store(sid, Ordering::SeqCst);
+    }
+
+    /// 进程是否被停止
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// 标记进程被信号 `signal` 停止，返回进程之前是否在运行
+    pub fn stop(&self, signal: SignalNum) -> bool {
+        let running = !self.stopped.swap(true, Ordering::SeqCst);
+        if running {
+            *self.job_event.lock() = Some(((signal.num() as u32) << 8) | 0x7f);
+        }
+        running
+    }
+
+    /// 标记被停止的进程继续运行，返回进程之前是否被停止
+    pub fn resume(&self) -> bool {
+        let stopped = self.stopped.swap(false, Ordering::SeqCst);
+        if stopped {
+            *self.job_event.lock() = Some(CONTINUED_STATUS);
+        }
+        stopped
+    }
+
+    /// 取出父进程 `wait` 等待的停止或者继续事件
+    ///
+    /// `options` 中包含 [WUNTRACED] 时报告停止事件，包含 [WCONTINUED] 时报告继续事件
+    pub fn take_job_event(&self, options: u32) -> Option<u32> {
+        let mut event = self.job_event.lock();
+        let wanted = match (*event)? {
+            CONTINUED_STATUS => options & WCONTINUED != 0,
+            _ => options & WUNTRACED != 0,
+        };
+        match wanted {
+            true => event.take(),
+            false => None,
         }
     }
 }
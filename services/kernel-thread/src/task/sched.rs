@@ -0,0 +1,84 @@
+//! Linux 应用的调度参数
+//!
+//! 应用的优先级和调度上下文由 root-task 按照 apps.toml 中 kernel-thread 的 `app-sched` 分配，
+//! 优先级不会超过 kernel-thread 自己的优先级。MCS 内核上每个线程绑定一个调度上下文，
+//! 线程退出之后调度上下文放回空闲列表，之后创建的线程继续使用
+#[cfg(feature = "mcs")]
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use common::root::alloc_sched_context;
+#[cfg(feature = "mcs")]
+use common::{
+    config::DEFAULT_SERVE_EP,
+    slot::{alloc_slot, recycle_slot},
+};
+use sel4::init_thread::slot;
+#[cfg(feature = "mcs")]
+use sel4::{CapRights, cap_type};
+#[cfg(feature = "mcs")]
+use sel4_kit::slot_manager::LeafSlot;
+#[cfg(feature = "mcs")]
+use spin::Mutex;
+use spin::Once;
+
+use super::Sel4Task;
+
+/// root-task 分配的 Linux 应用的优先级
+static APP_PRIORITY: Once<usize> = Once::new();
+
+/// 线程使用的 (调度上下文, 异常端口)，键为线程编号
+#[cfg(feature = "mcs")]
+static THREAD_SCHED: Mutex<BTreeMap<usize, (LeafSlot, LeafSlot)>> = Mutex::new(BTreeMap::new());
+
+/// 已经退出的线程留下的调度上下文
+#[cfg(feature = "mcs")]
+static FREE_SCHED_CONTEXTS: Mutex<Vec<LeafSlot>> = Mutex::new(Vec::new());
+
+impl Sel4Task {
+    /// 设置线程的优先级，MCS 内核上同时绑定调度上下文和异常端口
+    pub(super) fn init_sched(&self) -> Result<(), sel4::Error> {
+        #[cfg(not(feature = "mcs"))]
+        {
+            let priority = *APP_PRIORITY
+                .try_call_once(|| alloc_sched_context().map(|(priority, _)| priority))?;
+            self.tcb
+                .tcb_set_sched_params(slot::TCB.cap(), 0, priority as _)
+        }
+        #[cfg(feature = "mcs")]
+        {
+            let sched_context = match FREE_SCHED_CONTEXTS.lock().pop() {
+                Some(sched_context) => sched_context,
+                None => {
+                    let (priority, sched_context) = alloc_sched_context()?;
+                    APP_PRIORITY.call_once(|| priority);
+                    sched_context.ok_or(sel4::Error::IllegalOperation)?
+                }
+            };
+            let priority = *APP_PRIORITY.get().unwrap();
+
+            // MCS 内核在设置调度参数时指定异常端口，标记为线程编号
+            let fault_ep = alloc_slot();
+            LeafSlot::from(DEFAULT_SERVE_EP).mint_to(fault_ep, CapRights::all(), self.tid)?;
+            THREAD_SCHED
+                .lock()
+                .insert(self.tid, (sched_context, fault_ep));
+            self.tcb.tcb_set_sched_params(
+                slot::TCB.cap(),
+                0,
+                priority as _,
+                sched_context.cap::<cap_type::SchedContext>(),
+                fault_ep.cap(),
+            )
+        }
+    }
+
+    /// 线程退出之后回收调度上下文和异常端口，需要在删除 TCB 之后调用
+    #[cfg(feature = "mcs")]
+    pub(super) fn release_sched(&self) {
+        let Some((sched_context, fault_ep)) = THREAD_SCHED.lock().remove(&self.tid) else {
+            return;
+        };
+        fault_ep.delete().unwrap();
+        recycle_slot(fault_ep);
+        FREE_SCHED_CONTEXTS.lock().push(sched_context);
+    }
+}
@@ -1,13 +1,25 @@
 //! 共享内存模块
 //!
 //!
+use core::cmp;
+
 use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
-use common::{mem::CapMemSet, slot::recycle_slot};
-use sel4::cap::Granule;
+use common::{
+    config::PAGE_SIZE,
+    mem::CapMemSet,
+    page::PhysPage,
+    slot::{alloc_slot, recycle_slot},
+};
+use sel4::{CapRights, cap::Granule};
 use sel4_kit::slot_manager::LeafSlot;
 use spin::Mutex;
 
-use crate::utils::obj::recycle_untyped_unit;
+use crate::utils::obj::{alloc_untyped_unit, recycle_untyped_unit};
+
+use super::Sel4Task;
+
+/// 不在 [SHARED_MEMORY] 中登记的共享内存使用的键，例如 `memfd`
+pub const SHM_KEY_PRIVATE: usize = usize::MAX;
 
 /// 共享内存的全局静态变量
 pub static SHARED_MEMORY: Mutex<BTreeMap<usize, Arc<SharedMemory>>> = Mutex::new(BTreeMap::new());
@@ -20,6 +32,8 @@ pub struct SharedMemory {
     pub trackers: Vec<Granule>,
     /// 是否已删除
     pub deleted: Mutex<bool>,
+    /// 扩展之前的共享内存，持有部分物理页
+    base: Option<Arc<SharedMemory>>,
 }
 
 impl SharedMemory {
@@ -33,8 +47,97 @@ impl SharedMemory {
             capset,
             trackers,
             deleted: Mutex::new(false),
+            base: None,
         }
     }
+
+    /// 申请一块新的共享内存
+    ///
+    /// # 参数
+    /// - `size`: 共享内存的大小，会向上对齐到页
+    pub fn alloc(size: usize) -> Self {
+        let capset = Mutex::new(CapMemSet::new(Some(alloc_untyped_unit)));
+        let trackers = (0..size.div_ceil(PAGE_SIZE))
+            .map(|_| capset.lock().alloc_page())
+            .collect();
+        Self::new(capset, trackers)
+    }
+
+    /// 扩展共享内存，返回扩展之后的共享内存
+    ///
+    /// 新的共享内存复制原来的物理页的 Capability，已经建立的映射和新的映射仍然共享原来的数据，
+    /// 增加的部分使用新申请的物理页。新的共享内存持有原来的共享内存，保证原来的物理页不会先被回收。
+    ///
+    /// # 参数
+    /// - `size`: 扩展之后的大小，会向上对齐到页
+    pub fn grow(self: &Arc<Self>, size: usize) -> Self {
+        let capset = Mutex::new(CapMemSet::new(Some(alloc_untyped_unit)));
+        let mut trackers: Vec<Granule> = self
+            .trackers
+            .iter()
+            .map(|page| {
+                let slot = alloc_slot();
+                slot.copy_from(&LeafSlot::from_cap(*page), CapRights::all())
+                    .unwrap();
+                slot.cap()
+            })
+            .collect();
+        trackers
+            .extend((trackers.len()..size.div_ceil(PAGE_SIZE)).map(|_| capset.lock().alloc_page()));
+        Self {
+            capset,
+            trackers,
+            deleted: Mutex::new(false),
+            base: Some(self.clone()),
+        }
+    }
+
+    /// 共享内存的大小（按页对齐）
+    pub fn size(&self) -> usize {
+        self.trackers.len() * PAGE_SIZE
+    }
+
+    /// 从共享内存中读取数据
+    ///
+    /// # 参数
+    /// - `offset`: 读取的起始偏移
+    /// - `buffer`: 读取的目标缓冲区
+    ///
+    /// 返回实际读取的长度
+    pub fn read_at(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        let end = cmp::min(offset + buffer.len(), self.size());
+        let mut curr = offset;
+        while curr < end {
+            let page_off = curr % PAGE_SIZE;
+            let len = cmp::min(PAGE_SIZE - page_off, end - curr);
+            let page = PhysPage::new(self.trackers[curr / PAGE_SIZE]);
+            buffer[curr - offset..curr - offset + len]
+                .copy_from_slice(&page.lock()[page_off..page_off + len]);
+            curr += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// 向共享内存中写入数据
+    ///
+    /// # 参数
+    /// - `offset`: 写入的起始偏移
+    /// - `buffer`: 需要写入的数据
+    ///
+    /// 返回实际写入的长度，超出共享内存大小的部分会被丢弃
+    pub fn write_at(&self, offset: usize, buffer: &[u8]) -> usize {
+        let end = cmp::min(offset + buffer.len(), self.size());
+        let mut curr = offset;
+        while curr < end {
+            let page_off = curr % PAGE_SIZE;
+            let len = cmp::min(PAGE_SIZE - page_off, end - curr);
+            let page = PhysPage::new(self.trackers[curr / PAGE_SIZE]);
+            page.lock()[page_off..page_off + len]
+                .copy_from_slice(&buffer[curr - offset..curr - offset + len]);
+            curr += len;
+        }
+        end.saturating_sub(offset)
+    }
 }
 
 impl Drop for SharedMemory {
@@ -66,13 +169,53 @@ pub struct MapedSharedMemory {
     pub start: usize,
     /// 映射的大小
     pub size: usize,
+    /// 映射的起始位置在共享内存中的偏移，对齐到页
+    pub offset: usize,
 }
 
 impl MapedSharedMemory {
+    /// 映射范围内的物理页
+    pub fn pages(&self) -> impl Iterator<Item = &Granule> {
+        self.mem
+            .trackers
+            .iter()
+            .skip(self.offset / PAGE_SIZE)
+            .take(self.size / PAGE_SIZE)
+    }
+
     /// 检测虚拟地址是否在映射范围内
     pub fn contains(&self, vaddr: usize) -> bool {
         vaddr >= self.start && vaddr < self.start + self.size
     }
+
+    /// 去掉 `[start, end)` 之后剩下的映射
+    ///
+    /// 和范围不相交时返回原来的映射，范围在映射中间时拆分成两段
+    pub fn remove_range(self: Arc<Self>, start: usize, end: usize) -> Vec<Arc<Self>> {
+        let map_end = self.start + self.size;
+        if end <= self.start || start >= map_end {
+            return vec![self];
+        }
+        let mut rest = Vec::new();
+        if self.start < start {
+            rest.push(self.slice(self.start, start));
+        }
+        if end < map_end {
+            rest.push(self.slice(end, map_end));
+        }
+        rest
+    }
+
+    /// 映射中 `[start, end)` 的部分
+    fn slice(&self, start: usize, end: usize) -> Arc<Self> {
+        Arc::new(Self {
+            key: self.key,
+            mem: self.mem.clone(),
+            start,
+            size: end - start,
+            offset: self.offset + (start - self.start),
+        })
+    }
 }
 
 impl Drop for MapedSharedMemory {
@@ -83,3 +226,40 @@ impl Drop for MapedSharedMemory {
         }
     }
 }
+
+impl Sel4Task {
+    /// 将共享内存映射到当前任务的地址空间
+    ///
+    /// # 参数
+    /// - `key`: 共享内存的键，没有登记在 [SHARED_MEMORY] 中的使用 [SHM_KEY_PRIVATE]
+    /// - `mem`: 需要映射的共享内存
+    /// - `vaddr`: 映射的起始地址，需要对齐到页
+    /// - `size`: 映射的大小，和 `offset` 相加不能超过共享内存的大小
+    /// - `offset`: 映射的起始位置在共享内存中的偏移，需要对齐到页
+    pub fn map_shared_memory(
+        &self,
+        key: usize,
+        mem: Arc<SharedMemory>,
+        vaddr: usize,
+        size: usize,
+        offset: usize,
+    ) {
+        let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        assert!(offset % PAGE_SIZE == 0 && offset + size <= mem.size());
+        let maped = MapedSharedMemory {
+            key,
+            mem,
+            start: vaddr,
+            size,
+            offset,
+        };
+        for (i, page) in maped.pages().enumerate() {
+            let new_slot = alloc_slot();
+            new_slot
+                .copy_from(&LeafSlot::from_cap(*page), CapRights::all())
+                .unwrap();
+            self.map_page(vaddr + i * PAGE_SIZE, PhysPage::new(new_slot.cap()));
+        }
+        self.shm.lock().push(Arc::new(maped));
+    }
+}
@@ -1,4 +1,6 @@
-use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+
+use alloc::{sync::Arc, vec::Vec};
 use libc_core::{
     internal::SigAction,
     signal::{SignalNum, UContext},
@@ -9,7 +11,11 @@ use spin::Mutex;
 use syscalls::Errno;
 use zerocopy::{FromBytes, FromZeros};
 
-use crate::{child_test::futex_signal_task, task::PollWakeEvent};
+use crate::{
+    child_test::{TASK_MAP, futex_signal_task, wake_hangs},
+    fs::signalfd::SignalFd,
+    task::PollWakeEvent,
+};
 
 use super::Sel4Task;
 
@@ -18,17 +24,42 @@ pub struct TaskSignal {
     pub exit_sig: Option<SignalNum>,
     /// 信号屏蔽位
     pub mask: SigSet,
+    /// 系统调用临时替换屏蔽位之前的屏蔽位，例如 `epoll_pwait`
+    saved_mask: Option<SigSet>,
     /// 信号处理函数
     pub actions: Arc<Mutex<[SigAction; 65]>>,
     /// 等待处理的信号
     pedings: SigSet,
 }
 
+/// 向进程组 `pgid` 中的所有进程发送信号
+///
+/// 信号发送给进程的主线程，返回收到信号的进程数量
+pub fn kill_pgrp(pgid: usize, signal: SignalNum, from: usize) -> usize {
+    let targets: Vec<_> = TASK_MAP
+        .lock()
+        .values()
+        .filter(|x| x.pid == x.tid && x.pcb.pgid() == pgid && x.exit.lock().is_none())
+        .cloned()
+        .collect();
+    targets.iter().for_each(|x| x.add_signal(signal, from));
+    targets.len()
+}
+
+/// 默认动作为停止进程的信号
+const STOP_SIGNALS: [SignalNum; 4] = [
+    SignalNum::STOP,
+    SignalNum::TSTP,
+    SignalNum::TTIN,
+    SignalNum::TTOU,
+];
+
 impl Default for TaskSignal {
     fn default() -> Self {
         Self {
             exit_sig: None,
             mask: SigSet::default(),
+            saved_mask: None,
             actions: Arc::new(Mutex::new([const { SigAction::empty() }; 65])),
             pedings: SigSet::empty(),
         }
@@ -48,6 +79,11 @@ impl Sel4Task {
                 self.exit_with(signal.num() as u32 + 128);
                 return;
             }
+            // SIGSTOP 不能被捕获或者忽略
+            if signal == SignalNum::STOP {
+                self.stop_process(signal);
+                return;
+            }
             let mut task_signal = self.signal.lock();
             // 保存处理信号前的上下文，信号处理结束后恢复
             let actions = task_signal.actions.lock();
@@ -59,14 +95,28 @@ impl Sel4Task {
                 return;
             } else if action.handler == 0 || action.handler == SigAction::SIG_DFL {
                 // if there doesn't have signal handler.
-                // Then use default handler. Exit or do nothing.
-                if matches!(signal, SignalNum::CANCEL | SignalNum::SEGV | SignalNum::ILL) {
+                // Then use default handler. Exit, stop or do nothing.
+                if STOP_SIGNALS.contains(&signal) {
+                    drop(task_signal);
+                    self.stop_process(signal);
+                } else if matches!(
+                    signal,
+                    SignalNum::CANCEL
+                        | SignalNum::SEGV
+                        | SignalNum::ILL
+                        | SignalNum::INT
+                        | SignalNum::QUIT
+                        | SignalNum::TERM
+                        | SignalNum::HUP
+                ) {
                     drop(task_signal);
                     self.exit_with(signal.num() as u32);
                 }
                 return;
             }
-            let new_sp = self.write_ucontext(ctx, task_signal.mask);
+            // 信号处理函数返回之后恢复系统调用替换之前的屏蔽位
+            let old_mask = task_signal.saved_mask.take().unwrap_or(task_signal.mask);
+            let new_sp = self.write_ucontext(ctx, old_mask);
             task_signal.mask = action.mask;
             *ctx.c_param_mut(0) = signal.num() as _;
             *ctx.c_param_mut(1) = 0;
@@ -95,7 +145,21 @@ impl Sel4Task {
         if self.exit.lock().is_some() {
             return;
         }
+        // SIGCONT 在发送时就让进程继续运行，并且和停止信号互相取消
+        {
+            let pendings = &mut self.signal.lock().pedings;
+            if signal == SignalNum::CONT {
+                STOP_SIGNALS.iter().for_each(|x| pendings.remove(*x));
+            } else if STOP_SIGNALS.contains(&signal) {
+                pendings.remove(SignalNum::CONT);
+            }
+        }
+        if signal == SignalNum::CONT {
+            self.continue_process();
+        }
         self.signal.lock().pedings.insert(signal);
+        // signalfd 一般用于读取被屏蔽的信号，需要在检查屏蔽位之前通知
+        SignalFd::notify(self.tid);
         // 如果当前信号被屏蔽，那么并不会打断任何操作
         if self.signal.lock().mask.has(signal) {
             return;
@@ -107,10 +171,103 @@ impl Sel4Task {
             waker.wake_by_ref();
         }
 
-        if from != self.tid {
+        // 正在处理系统调用的线程在系统调用返回时检查信号，被停止的进程只处理 SIGKILL
+        let stopped = self.pcb.is_stopped() && signal != SignalNum::KILL;
+        if from != self.tid && !self.in_syscall.load(Ordering::SeqCst) && !stopped {
             let mut ctx = self.tcb.tcb_read_all_registers(true).unwrap();
             self.check_signal(&mut ctx);
-            self.tcb.tcb_resume().unwrap();
+            if self.exit.lock().is_none() {
+                self.resume_user();
+            }
+        }
+    }
+
+    /// 信号是否被屏蔽或者忽略，用于后台进程组读写终端时的检查
+    pub fn signal_blocked_or_ignored(&self, signal: SignalNum) -> bool {
+        let task_signal = self.signal.lock();
+        let handler = task_signal.actions.lock()[signal.num()].handler;
+        task_signal.mask.has(signal) || handler == SigAction::SIG_IGN
+    }
+
+    /// 恢复线程运行，进程被停止时线程保持暂停，进程继续运行时再恢复
+    pub fn resume_user(&self) {
+        self.in_syscall.store(false, Ordering::SeqCst);
+        if self.pcb.is_stopped() {
+            self.parked.store(true, Ordering::SeqCst);
+            return;
+        }
+        self.tcb.tcb_resume().unwrap();
+    }
+
+    /// 当前进程中所有没有退出的线程
+    fn threads(&self) -> Vec<Arc<Sel4Task>> {
+        TASK_MAP
+            .lock()
+            .values()
+            .filter(|x| x.pid == self.pid && x.exit.lock().is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// 信号的默认动作为停止时停止当前进程
+    ///
+    /// 正在运行的线程立即暂停，正在处理系统调用的线程在系统调用返回时暂停 ([Self::resume_user])。
+    /// 之后向父进程发送 `SIGCHLD` 并唤醒等待子进程状态变化的父进程
+    fn stop_process(&self, signal: SignalNum) {
+        if !self.pcb.stop(signal) {
+            return;
+        }
+        for thread in self.threads() {
+            if !thread.in_syscall.load(Ordering::SeqCst)
+                && !thread.parked.swap(true, Ordering::SeqCst)
+            {
+                thread.tcb.tcb_suspend().unwrap();
+            }
+        }
+        self.notify_parent();
+    }
+
+    /// 收到 `SIGCONT` 时让被停止的进程继续运行
+    fn continue_process(&self) {
+        if !self.pcb.resume() {
+            return;
+        }
+        for thread in self.threads() {
+            if thread.parked.swap(false, Ordering::SeqCst) {
+                thread.tcb.tcb_resume().unwrap();
+            }
+        }
+        self.notify_parent();
+    }
+
+    /// 进程停止或者继续运行时通知父进程
+    fn notify_parent(&self) {
+        wake_hangs(self);
+        let parent = TASK_MAP.lock().get(&(self.ppid as _)).cloned();
+        if let Some(parent) = parent {
+            parent.add_signal(SignalNum::CHLD, self.tid);
+        }
+    }
+
+    /// 在系统调用期间临时替换信号屏蔽位
+    ///
+    /// 系统调用返回后通过 [Self::restore_sigmask] 恢复，如果返回前有信号需要处理，
+    /// 原来的屏蔽位保存到信号上下文中，在信号处理函数返回之后恢复
+    pub fn set_temp_sigmask(&self, mut mask: SigSet) {
+        mask.remove(SignalNum::KILL);
+        mask.remove(SignalNum::STOP);
+        let mut task_signal = self.signal.lock();
+        if task_signal.saved_mask.is_none() {
+            task_signal.saved_mask = Some(task_signal.mask);
+        }
+        task_signal.mask = mask;
+    }
+
+    /// 恢复 [Self::set_temp_sigmask] 替换之前的信号屏蔽位
+    pub fn restore_sigmask(&self) {
+        let mut task_signal = self.signal.lock();
+        if let Some(mask) = task_signal.saved_mask.take() {
+            task_signal.mask = mask;
         }
     }
 
@@ -125,6 +282,26 @@ impl Sel4Task {
         pendings.pop_one(Some(sigmask))
     }
 
+    /// 检查是否有属于 `set` 的信号待处理，用于 signalfd
+    pub fn has_signal_in(&self, set: SigSet) -> bool {
+        let pendings = self.signal.lock().pedings;
+        (1..65)
+            .filter_map(SignalNum::from_num)
+            .any(|x| x != SignalNum::KILL && pendings.has(x) && set.has(x))
+    }
+
+    /// 取出一个属于 `set` 的待处理信号，用于 signalfd
+    ///
+    /// `SIGKILL` 不能通过 signalfd 读取
+    pub fn take_signal_in(&self, set: SigSet) -> Option<SignalNum> {
+        let pendings = &mut self.signal.lock().pedings;
+        let signal = (1..65)
+            .filter_map(SignalNum::from_num)
+            .find(|x| *x != SignalNum::KILL && pendings.has(*x) && set.has(*x))?;
+        pendings.remove(signal);
+        Some(signal)
+    }
+
     /// 检查当前任务是否有未屏蔽的信号待处理
     pub fn has_unmasked_signal(&self) -> bool {
         let pendings = self.signal.lock().pedings;
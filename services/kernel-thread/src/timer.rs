@@ -7,9 +7,9 @@ use core::{
     time::Duration,
 };
 
-use alloc::vec::Vec;
+use alloc::{sync::Weak, vec::Vec};
 use common::slot::alloc_slot;
-use sel4::{CapRights, cap::Notification};
+use sel4::cap::Notification;
 use sel4_kit::{
     arch::{GENERIC_TIMER_PCNT_IRQ, current_time, set_timer},
     slot_manager::LeafSlot,
@@ -17,17 +17,15 @@ use sel4_kit::{
 use spin::{Lazy, Mutex};
 use syscalls::Errno;
 
-use crate::{child_test::TASK_MAP, exception::GLOBAL_NOTIFY, task::PollWakeEvent};
+use crate::{
+    child_test::TASK_MAP,
+    exception::{NOTIFY_TIMER, badged_notify},
+    fs::timerfd::TimerFd,
+    task::PollWakeEvent,
+};
 
 static TIMER_IRQ_SLOT: Lazy<LeafSlot> = Lazy::new(alloc_slot);
-static TIMER_IRQ_NOTIFY: Lazy<Notification> = Lazy::new(|| {
-    // 从 Global_Notify 复制一个具有 badge 的Notification
-    let slot = alloc_slot();
-    LeafSlot::from_cap(*GLOBAL_NOTIFY)
-        .mint_to(slot, CapRights::all(), usize::MAX)
-        .unwrap();
-    slot.cap()
-});
+static TIMER_IRQ_NOTIFY: Lazy<Notification> = Lazy::new(|| badged_notify(NOTIFY_TIMER).cap());
 
 /// 初始化定时器相关的任务
 pub fn init() {
@@ -50,6 +48,10 @@ enum TimerType {
     WaitTime(usize, Waker),
     /// (pid)
     ITimer(usize),
+    /// (timerfd, 设置编号)
+    TimerFd(Weak<TimerFd>, u64),
+    /// 轮询网络协议栈
+    NetPoll,
 }
 
 /// 时间等待队列 (目标时间，任务 id, Waker)
@@ -62,15 +64,23 @@ pub fn handle_timer() {
     // 处理已经到时间的定时器
     let curr_time = current_time();
 
-    TIME_QUEUE.lock().retain(|(duration, timer_ty)| {
-        if curr_time >= *duration {
-            match timer_ty {
-                TimerType::WaitTime(_tid, waker) => waker.wake_by_ref(),
-                TimerType::ITimer(pid) => handle_process_timer(curr_time, *pid),
-            };
-        }
-        curr_time < *duration
-    });
+    // 先取出到期的定时器再处理，处理时可能会重新加入时间队列
+    let expired: Vec<_> = TIME_QUEUE
+        .lock()
+        .extract_if(.., |(duration, _)| curr_time >= *duration)
+        .collect();
+    for (_, timer_ty) in expired {
+        match timer_ty {
+            TimerType::WaitTime(_tid, waker) => waker.wake(),
+            TimerType::ITimer(pid) => handle_process_timer(curr_time, pid),
+            TimerType::TimerFd(timer, generation) => {
+                if let Some(timer) = timer.upgrade() {
+                    timer.expire(generation, curr_time);
+                }
+            }
+            TimerType::NetPoll => crate::net::handle_poll_timer(),
+        };
+    }
 
     // 设置下一个定时器
     let next = TIME_QUEUE
@@ -147,6 +157,31 @@ pub fn set_process_timer(pid: usize, next: Duration) {
     set_timer(TIME_QUEUE.lock().first().unwrap().0);
 }
 
+/// 设置 timerfd 定时器
+///
+/// ## 参数
+/// - `next`       定时器到期的时间
+/// - `timer`      到期时通知的 [TimerFd]
+/// - `generation` 设置定时器时的编号，到期时用于判断定时器是否已经被重新设置
+pub fn set_timerfd_timer(next: Duration, timer: Weak<TimerFd>, generation: u64) {
+    TIME_QUEUE
+        .lock()
+        .push((next, TimerType::TimerFd(timer, generation)));
+    TIME_QUEUE
+        .lock()
+        .sort_by(|(dura_a, ..), (dura_b, ..)| dura_a.cmp(dura_b));
+    set_timer(TIME_QUEUE.lock().first().unwrap().0);
+}
+
+/// 设置网络协议栈的轮询定时器
+pub fn set_net_timer(next: Duration) {
+    TIME_QUEUE.lock().push((next, TimerType::NetPoll));
+    TIME_QUEUE
+        .lock()
+        .sort_by(|(dura_a, ..), (dura_b, ..)| dura_a.cmp(dura_b));
+    set_timer(TIME_QUEUE.lock().first().unwrap().0);
+}
+
 /// 处理进程 Timer 时间
 pub fn handle_process_timer(curr_time: Duration, pid: usize) {
     log::debug!("handle process tiemr: {:?}, pid: {}", curr_time, pid);
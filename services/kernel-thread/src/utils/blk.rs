@@ -13,7 +13,7 @@ impl BlockDevice for BlockDev {
         assert_eq!(buffer.len() % BLOCK_SIZE, 0);
         let rlen = core::cmp::min(buffer.len(), 0x4000);
         let ptr = 0x3_0000_0000 as *const u8;
-        BLK_IMPLS[0].lock().read_block(block, rlen / BLOCK_SIZE);
+        BLK_IMPLS[0].lock().read_block(block, rlen / BLOCK_SIZE)?;
         unsafe {
             ptr.copy_to_nonoverlapping(buffer.as_mut_ptr(), rlen);
         }
@@ -27,12 +27,12 @@ impl BlockDevice for BlockDev {
         unsafe {
             ptr.copy_from_nonoverlapping(buf.as_ptr(), wlen);
         }
-        BLK_IMPLS[0].lock().write_block(block, wlen / BLOCK_SIZE);
+        BLK_IMPLS[0].lock().write_block(block, wlen / BLOCK_SIZE)?;
         Ok(wlen)
     }
 
     fn capacity(&self) -> vfscore::VfsResult<u64> {
-        Ok(BLK_IMPLS[0].lock().capacity())
+        Ok(BLK_IMPLS[0].lock().capacity()?)
     }
 }
 
@@ -20,7 +20,10 @@ impl KernelDevOp for Ext4Disk {
     }
 
     fn seek(dev: &mut Self::DevType, off: i64, whence: i32) -> Result<i64, i32> {
-        let size = BLK_IMPLS[0].lock().capacity() as i64;
+        let size = BLK_IMPLS[0]
+            .lock()
+            .capacity()
+            .map_err(|errno| errno.into_raw())? as i64;
         let new_pos = match whence as u32 {
             lwext4_rust::bindings::SEEK_SET => Some(off),
             lwext4_rust::bindings::SEEK_CUR => {
@@ -78,7 +81,8 @@ impl Ext4Disk {
         let ptr = 0x3_0000_0000 as *const u8;
         BLK_IMPLS[0]
             .lock()
-            .read_block(self.block_id, buf.len() / BLOCK_SIZE);
+            .read_block(self.block_id, buf.len() / BLOCK_SIZE)
+            .map_err(|errno| errno.into_raw())?;
         unsafe {
             ptr.copy_to_nonoverlapping(buf.as_mut_ptr(), buf.len());
         }
@@ -97,7 +101,8 @@ impl Ext4Disk {
         }
         BLK_IMPLS[0]
             .lock()
-            .write_block(self.block_id, buf.len() / BLOCK_SIZE);
+            .write_block(self.block_id, buf.len() / BLOCK_SIZE)
+            .map_err(|errno| errno.into_raw())?;
         self.set_position(self.position() + buf.len() as u64);
         Ok(buf.len())
     }
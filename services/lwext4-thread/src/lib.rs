@@ -10,7 +10,7 @@ mod imp;
 
 use core::iter::zip;
 
-use alloc::string::String;
+use alloc::{string::String, vec};
 use common::root::create_channel;
 use flatten_objects::FlattenObjects;
 use imp::Ext4Disk;
@@ -54,22 +54,42 @@ impl Default for EXT4FSImpl {
 impl FSIface for EXT4FSImpl {
     fn init(&mut self, _channel_id: usize, _addr: usize, _size: usize) {}
 
-    fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> usize {
-        if let Some(ext4_file) = self.stores.get_mut(inode as _) {
-            ext4_file.file_seek(offset as _, 0).unwrap();
-            ext4_file.file_read(buf).unwrap()
-        } else {
-            panic!("Can't Find File")
-        }
+    fn read_at(&mut self, inode: u64, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        let ext4_file = self.stores.get_mut(inode as _).ok_or(Errno::EBADF)?;
+        ext4_file.file_seek(offset as _, 0).map_err(Errno::new)?;
+        ext4_file.file_read(buf).map_err(Errno::new)
     }
 
-    fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> usize {
-        if let Some(ext4_file) = self.stores.get_mut(inode as _) {
-            ext4_file.file_seek(offset as _, 0).unwrap();
-            ext4_file.file_write(data).unwrap()
-        } else {
-            panic!("Can't Find File")
+    fn write_at(&mut self, inode: u64, offset: usize, data: &[u8]) -> Result<usize, Errno> {
+        let ext4_file = self.stores.get_mut(inode as _).ok_or(Errno::EBADF)?;
+        ext4_file.file_seek(offset as _, 0).map_err(Errno::new)?;
+        ext4_file.file_write(data).map_err(Errno::new)
+    }
+
+    fn copy_range(
+        &mut self,
+        src: u64,
+        src_off: usize,
+        dst: u64,
+        dst_off: usize,
+        len: usize,
+    ) -> Result<usize, Errno> {
+        // 数据在服务内部复制，不需要经过共享内存
+        let mut buffer = vec![0u8; core::cmp::min(len, 0x10000)];
+        let mut copied = 0;
+        while copied < len {
+            let chunk = core::cmp::min(len - copied, buffer.len());
+            let rlen = self.read_at(src, src_off + copied, &mut buffer[..chunk])?;
+            if rlen == 0 {
+                break;
+            }
+            let wlen = self.write_at(dst, dst_off + copied, &buffer[..rlen])?;
+            copied += wlen;
+            if wlen < rlen {
+                break;
+            }
         }
+        Ok(copied)
     }
 
     fn open(&mut self, path: &str, flags: u32) -> Result<(usize, usize), Errno> {
@@ -116,9 +136,9 @@ impl FSIface for EXT4FSImpl {
         }
     }
 
-    fn stat(&mut self, inode: usize) -> Stat {
+    fn stat(&mut self, inode: usize) -> Result<Stat, Errno> {
         if let Some(ext4_file) = self.stores.get_mut(inode) {
-            let mode = StatMode::from_bits_retain(ext4_file.file_mode_get().unwrap())
+            let mode = StatMode::from_bits_retain(ext4_file.file_mode_get().map_err(Errno::new)?)
                 | match ext4_file.get_type() {
                     InodeTypes::EXT4_DE_REG_FILE => StatMode::FILE,
                     InodeTypes::EXT4_DE_DIR => StatMode::DIR,
@@ -129,22 +149,27 @@ impl FSIface for EXT4FSImpl {
                     InodeTypes::EXT4_DE_SYMLINK => StatMode::LINK,
                     _ => StatMode::FILE,
                 };
-            Stat {
+            Ok(Stat {
                 blksize: 0x200,
                 ino: inode as _,
                 mode,
                 nlink: 1,
                 size: ext4_file.file_size(),
                 ..Default::default()
-            }
+            })
         } else {
-            panic!("Can't Find File")
+            Err(Errno::EBADF)
         }
     }
 
-    fn getdents64(&mut self, inode: u64, mut offset: usize, buf: &mut [u8]) -> (usize, usize) {
+    fn getdents64(
+        &mut self,
+        inode: u64,
+        mut offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(usize, usize), Errno> {
         if let Some(ext4_file) = self.stores.get_mut(inode as _) {
-            let entries = ext4_file.lwext4_dir_entries().unwrap();
+            let entries = ext4_file.lwext4_dir_entries().map_err(Errno::new)?;
             let mut real_rlen: usize = 0;
             let mut base_ptr = buf.as_ptr() as usize;
             for (name, ty) in zip(entries.0, entries.1).skip(offset) {
@@ -169,9 +194,9 @@ impl FSIface for EXT4FSImpl {
                 base_ptr += aligned;
                 offset += 1;
             }
-            (real_rlen as _, offset as _)
+            Ok((real_rlen as _, offset as _))
         } else {
-            panic!("Can't find folder")
+            Err(Errno::EBADF)
         }
     }
 }
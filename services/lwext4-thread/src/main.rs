@@ -13,12 +13,24 @@ use flatten_objects::FlattenObjects;
 use libc_core::types::Stat;
 use sel4::{IpcBuffer, MessageInfoBuilder, with_ipc_buffer_mut};
 use sel4_runtime::utils::alloc_free_addr;
-use srv_gate::fs::{FSIface, FSIfaceEvent};
+use srv_gate::{
+    fs::{FSIface, FSIfaceEvent},
+    klog,
+};
 
 sel4_runtime::define_heap!(common::config::SERVICE_HEAP_SIZE);
 
 #[sel4_runtime::main]
 fn main() {
+    // 加入日志环，日志由串口服务输出到服务日志的虚拟控制台，也可以通过 dmesg 查看
+    let log_addr = alloc_free_addr(0);
+    alloc_free_addr(klog::init(
+        "fs-thread",
+        log_addr,
+        log::LevelFilter::Debug,
+        None,
+    ));
+
     log::info!("Booting...");
 
     // let channel_id = create_channel(0x3_0000_0000, 4);
@@ -64,10 +76,7 @@ fn handle_events(
                     ib.msg_regs_mut()[1] = size as _;
                     sel4::reply(ib, rev_msg.length(2).build());
                 }
-                Err(errno) => {
-                    ib.msg_regs_mut()[0] = errno.into_raw() as _;
-                    sel4::reply(ib, rev_msg.length(1).build());
-                }
+                Err(errno) => sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build()),
             }
         }
         FSIfaceEvent::read_at => {
@@ -77,12 +86,25 @@ fn handle_events(
 
             let buffer = unsafe { core::slice::from_raw_parts_mut(addr as _, buf_len) };
 
-            reply_with!(ib, fs.read_at(inode, offset, buffer));
+            match fs.read_at(inode, offset, buffer) {
+                Ok(rlen) => reply_with!(ib, rlen),
+                Err(errno) => sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build()),
+            }
         }
         FSIfaceEvent::write_at => {
             let (inode, offset, data) = read_types!(ib, u64, usize, &[u8]);
 
-            reply_with!(ib, fs.write_at(inode, offset, &data))
+            match fs.write_at(inode, offset, &data) {
+                Ok(wlen) => reply_with!(ib, wlen),
+                Err(errno) => sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build()),
+            }
+        }
+        FSIfaceEvent::copy_range => {
+            let (src, src_off, dst, dst_off, len) = read_types!(ib, u64, usize, u64, usize, usize);
+            match fs.copy_range(src, src_off, dst, dst_off, len) {
+                Ok(copied) => reply_with!(ib, copied),
+                Err(errno) => sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build()),
+            }
         }
         FSIfaceEvent::mkdir => {
             let path = read_types!(ib, &str);
@@ -102,7 +124,13 @@ fn handle_events(
         FSIfaceEvent::stat => {
             let inode = read_types!(ib, usize);
 
-            let stat = fs.stat(inode);
+            let stat = match fs.stat(inode) {
+                Ok(stat) => stat,
+                Err(errno) => {
+                    sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build());
+                    return;
+                }
+            };
             let len = size_of::<Stat>() / REG_LEN;
             unsafe {
                 (ib.msg_bytes_mut().as_ptr() as *mut Stat).copy_from(&stat, 1);
@@ -112,7 +140,13 @@ fn handle_events(
         FSIfaceEvent::getdents64 => {
             let (inode, offset, mut buf) = read_types!(ib, u64, usize, &[u8]);
 
-            let (real_rlen, offset) = fs.getdents64(inode, offset, buf.as_mut_slice());
+            let (real_rlen, offset) = match fs.getdents64(inode, offset, buf.as_mut_slice()) {
+                Ok(ret) => ret,
+                Err(errno) => {
+                    sel4::reply(ib, rev_msg.label(errno.into_raw() as _).build());
+                    return;
+                }
+            };
             ib.msg_regs_mut()[0] = real_rlen as _;
             ib.msg_regs_mut()[1] = offset as _;
             sel4::reply(ib, rev_msg.length(2 + real_rlen.div_ceil(REG_LEN)).build());
@@ -0,0 +1,200 @@
+#![no_std]
+#![no_main]
+#![feature(used_with_arg)]
+
+extern crate alloc;
+
+use core::ptr::NonNull;
+
+use common::{
+    config::{VIRTIO_MMIO_NET_VIRT_ADDR, VIRTIO_MMIO_VIRT_ADDR, VIRTIO_NET_IRQ},
+    root::{
+        DeviceKind, channel_notify, find_device_or, join_channel, register_irq, register_notify,
+    },
+    slot::{alloc_slot, recycle_slot},
+    virtio::HalImpl,
+};
+use sel4::{
+    cap::{IrqHandler, Notification},
+    init_thread,
+};
+use sel4_kit::slot_manager::LeafSlot;
+use sel4_runtime::utils::alloc_free_addr;
+use spin::Mutex;
+use srv_gate::{
+    Errno, def_net_impl,
+    net::{FrameRing, NET_NOT_READY, NetIface},
+};
+use virtio_drivers::{
+    device::net::VirtIONet,
+    transport::mmio::{MmioTransport, VirtIOHeader},
+};
+
+/// virtio 队列的大小
+const NET_QUEUE_SIZE: usize = 16;
+
+/// 接收缓冲区的大小
+const NET_BUFFER_LEN: usize = 2048;
+
+def_net_impl!(VIRTIONET, {
+    // 使用 root-task 从设备树中找到的第一个网卡
+    let (addr, irq) = find_device_or(
+        DeviceKind::VirtIONet,
+        0,
+        VIRTIO_MMIO_VIRT_ADDR,
+        (VIRTIO_MMIO_NET_VIRT_ADDR, VIRTIO_NET_IRQ),
+    );
+    VirtIONetImpl::new(addr, irq)
+});
+
+pub struct VirtIONetImpl {
+    device: VirtIONet<HalImpl, MmioTransport, NET_QUEUE_SIZE>,
+    /// 共享内存通道的 (发送环, 接收环)
+    rings: Option<(&'static FrameRing, &'static FrameRing)>,
+    irq_handler: IrqHandler,
+}
+
+/// 客户端为通道登记的通知，接收环中放入新的帧之后通知客户端
+static CLIENT_NOTIFY: Mutex<Option<Notification>> = Mutex::new(None);
+
+/// 通知客户端接收环中有新的帧
+///
+/// 收到网卡中断并且放入了新的帧时调用，接收环已满时帧留在网卡中，
+/// 客户端取走帧之后通过 [NetIface::receive] 继续接收
+pub fn notify_client() {
+    if let Some(notify) = *CLIENT_NOTIFY.lock() {
+        notify.signal();
+    }
+}
+
+unsafe impl Sync for VirtIONetImpl {}
+unsafe impl Send for VirtIONetImpl {}
+
+impl VirtIONetImpl {
+    pub fn new(addr: usize, irq: usize) -> Self {
+        let ptr = addr as *mut VirtIOHeader;
+        let device = VirtIONet::<HalImpl, MmioTransport, NET_QUEUE_SIZE>::new(
+            unsafe { MmioTransport::new(NonNull::new(ptr).unwrap()).unwrap() },
+            NET_BUFFER_LEN,
+        )
+        .unwrap();
+
+        // 向 root-task 申请一个中断
+        let irq_handler = alloc_slot().cap();
+        register_irq(irq as _, irq_handler.into());
+
+        // 向 root-task 申请一个通知，绑定在 TCB 上，中断和 IPC 请求在同一个循环中处理
+        let ntfn: Notification = alloc_slot().cap();
+        register_notify(ntfn.into(), usize::MAX).expect("Can't register notification");
+        init_thread::slot::TCB
+            .cap()
+            .tcb_bind_notification(ntfn)
+            .unwrap();
+
+        // 设置中断信息
+        irq_handler.irq_handler_set_notification(ntfn).unwrap();
+        irq_handler.irq_handler_ack().unwrap();
+
+        Self {
+            device,
+            rings: None,
+            irq_handler,
+        }
+    }
+
+    /// 应答网卡中断
+    fn ack_interrupt(&mut self) {
+        self.device.ack_interrupt();
+        self.irq_handler.irq_handler_ack().unwrap();
+    }
+}
+
+impl NetIface for VirtIONetImpl {
+    fn init(&mut self, channel_id: usize) {
+        // TODO: 支持多个程序的 channel 初始化，根据 badge 区分不同的程序
+        let ptr = alloc_free_addr(0) as *mut u8;
+        let size = join_channel(channel_id, ptr as usize);
+        alloc_free_addr(size);
+        let (tx, rx) = unsafe { FrameRing::channel(ptr as usize) };
+        tx.reset();
+        rx.reset();
+        self.rings = Some((tx, rx));
+
+        // 客户端没有登记通知时只能由客户端调用 receive 接收
+        let mut client_notify = CLIENT_NOTIFY.lock();
+        if let Some(notify) = client_notify.take() {
+            let slot = LeafSlot::from_cap(notify);
+            slot.delete().unwrap();
+            recycle_slot(slot);
+        }
+        let slot = alloc_slot();
+        match channel_notify(channel_id, slot) {
+            Ok(()) => *client_notify = Some(slot.cap()),
+            Err(_) => {
+                log::warn!("Client of channel {} has no notification", channel_id);
+                recycle_slot(slot);
+            }
+        }
+    }
+
+    fn mac_address(&self) -> Result<u64, Errno> {
+        Ok(self
+            .device
+            .mac_address()
+            .iter()
+            .fold(0, |acc, x| (acc << 8) | *x as u64))
+    }
+
+    fn transmit(&mut self) -> usize {
+        // 重启之后客户端还没有重新交给服务通道
+        let Some((tx, _)) = self.rings else {
+            return NET_NOT_READY;
+        };
+        let mut count = 0;
+        while self.device.can_send() {
+            let mut tx_buf = None;
+            tx.pop(|frame| {
+                let mut buf = self.device.new_tx_buffer(frame.len());
+                buf.packet_mut().copy_from_slice(frame);
+                tx_buf = Some(buf);
+            });
+            let Some(tx_buf) = tx_buf else {
+                break;
+            };
+            if let Err(err) = self.device.send(tx_buf) {
+                log::warn!("Failed to send frame: {:?}", err);
+                break;
+            }
+            count += 1;
+        }
+        self.ack_interrupt();
+        count
+    }
+
+    fn receive(&mut self) -> usize {
+        let Some((_, rx)) = self.rings else {
+            // 帧留在网卡中，应答中断之后才能收到之后的中断
+            self.ack_interrupt();
+            return NET_NOT_READY;
+        };
+        let mut count = 0;
+        // 接收环已满时把帧留在网卡中，等待客户端取走之后再接收
+        while !rx.is_full() && self.device.can_recv() {
+            let rx_buf = match self.device.receive() {
+                Ok(rx_buf) => rx_buf,
+                Err(err) => {
+                    log::warn!("Failed to receive frame: {:?}", err);
+                    break;
+                }
+            };
+            if rx.push(rx_buf.packet()) {
+                count += 1;
+            } else {
+                log::warn!("Drop oversized frame: {} bytes", rx_buf.packet_len());
+            }
+            self.device.recycle_rx_buffer(rx_buf).unwrap();
+        }
+        self.ack_interrupt();
+        count
+    }
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate net_thread;
+
+use common::{config::DEFAULT_SERVE_EP, read_types, reply_with};
+use net_thread::{VIRTIONET, notify_client};
+use sel4::{MessageInfoBuilder, with_ipc_buffer_mut};
+use sel4_runtime::{main, utils::alloc_free_addr};
+use srv_gate::{klog, net::NetIfaceEvent};
+
+sel4_runtime::define_heap!(common::config::SERVICE_HEAP_SIZE);
+
+#[main]
+fn main() {
+    // 加入日志环，日志由串口服务输出到服务日志的虚拟控制台，也可以通过 dmesg 查看
+    let log_addr = alloc_free_addr(0);
+    alloc_free_addr(klog::init(
+        "net-thread",
+        log_addr,
+        log::LevelFilter::Debug,
+        None,
+    ));
+
+    let mut virtio_net = VIRTIONET.lock();
+
+    log::debug!(
+        "Net device mac address: {:#014x?}",
+        virtio_net.mac_address()
+    );
+
+    let rev_msg = MessageInfoBuilder::default();
+
+    with_ipc_buffer_mut(|ib| {
+        loop {
+            // TODO: use badge to parse shared memory
+            let (msg, badge) = DEFAULT_SERVE_EP.recv(());
+            // 网卡中断，把收到的帧放入接收环并通知客户端
+            if badge == u64::MAX {
+                if virtio_net.receive() > 0 {
+                    notify_client();
+                }
+                continue;
+            }
+            let msg_label = match NetIfaceEvent::try_from(msg.label()) {
+                Ok(label) => label,
+                Err(_) => continue,
+            };
+            match msg_label {
+                NetIfaceEvent::init => {
+                    let channel_id = read_types!(ib, usize);
+                    virtio_net.init(channel_id);
+                    sel4::reply(ib, rev_msg.build());
+                }
+                NetIfaceEvent::mac_address => {
+                    reply_with!(ib, virtio_net.mac_address().unwrap_or(0))
+                }
+                NetIfaceEvent::transmit => reply_with!(ib, virtio_net.transmit()),
+                NetIfaceEvent::receive => reply_with!(ib, virtio_net.receive()),
+            }
+        }
+    })
+}
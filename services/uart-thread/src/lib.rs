@@ -3,75 +3,186 @@
 
 extern crate alloc;
 
+pub mod vconsole;
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
 use arm_pl011::pl011::Pl011Uart;
 use common::{
-    config::{SERIAL_DEVICE_IRQ, VIRT_PL011_ADDR},
-    root::{register_irq, register_notify},
-    slot::alloc_slot,
+    config::{
+        LOG_CHANNEL_ID, PAGE_SIZE, SERIAL_DEVICE_IRQ, VIRT_PL011_ADDR, VIRT_PL011_MIRROR_ADDR,
+    },
+    root::{
+        DeviceKind, channel_notify, find_device, find_device_or, join_channel, map_device,
+        register_irq, register_notify, set_channel_notify,
+    },
+    slot::{alloc_slot, recycle_slot},
 };
+use log::Level;
 use sel4::{
-    Cap, MessageInfo,
+    Cap,
     cap_type::{IrqHandler, Notification},
-    debug_println,
+    init_thread,
 };
 use sel4_kit::slot_manager::LeafSlot;
-use srv_gate::{def_event_handler, def_uart_impl, uart::UartIface};
-
-def_uart_impl!(PL011DRV, Pl011UartIfaceImpl::new(VIRT_PL011_ADDR));
-def_event_handler!(PL011_IRQ, usize::MAX, irq_handler);
-
-fn irq_handler(msg: &MessageInfo, badge: u64) {
-    log::debug!("receive {} from {}", msg.label(), badge);
-    // let char = PL011.lock().getchar().unwrap();
-    // PL011.lock().ack_interrupts();
-    // IRQ_HANDLER.irq_handler_ack().unwrap();
-    // let mut ipc_saver = IPC_SAVER.lock();
-    // if ipc_saver.queue_len() > 0 {
-    //     with_ipc_buffer_mut(|ib| {
-    //         ib.msg_bytes_mut()[0] = char;
-    //         ipc_saver.reply_one(REV_MSG.length(1).build()).unwrap();
-    //     });
-    // } else {
-    //     BUFFER.lock().push_back(char);
-    // }
+use sel4_runtime::utils::alloc_free_addr;
+use spin::{Mutex, Once};
+use srv_gate::{
+    Errno, def_uart_impl,
+    klog::{self, KERNEL_LOG_SERVICE, LogCursor},
+    uart::{
+        ByteRing, UartIface, VC_APP, VC_COUNT, VC_KERNEL_LOG, VC_SERVICE_ERROR, VC_SERVICE_LOG,
+    },
+};
+use vconsole::{VConsoleMux, VcInput};
+
+/// 接收缓冲区的大小，缓冲区满时丢弃新收到的字符
+pub const RX_BUFFER_SIZE: usize = 4096;
+
+def_uart_impl!(PL011DRV, {
+    // 使用 root-task 从设备树中找到的第一个 PL011 串口
+    let (addr, irq) = find_device_or(
+        DeviceKind::Pl011,
+        0,
+        VIRT_PL011_ADDR,
+        (VIRT_PL011_ADDR, SERIAL_DEVICE_IRQ),
+    );
+    Pl011UartIfaceImpl::new(addr, irq)
+});
+
+/// 接收串口中断的通知
+static IRQ_NOTIFY: Once<Cap<Notification>> = Once::new();
+
+/// 客户端为应用程序控制台的发送环登记的通知
+///
+/// 收到输入或者发送环中的数据发送完成之后通知客户端，客户端不需要定时轮询
+static CLIENT_NOTIFY: Mutex<Option<Cap<Notification>>> = Mutex::new(None);
+
+/// 通知客户端有新的输入或者发送环有了空间
+pub fn notify_client() {
+    if let Some(notify) = *CLIENT_NOTIFY.lock() {
+        notify.signal();
+    }
+}
+
+/// 设置接收串口中断的通知，需要在 [UartIface::init] 之前调用
+///
+/// 串口驱动和其他服务合并在一起时，中断需要发送到这个服务已经绑定在 TCB 上的通知。
+/// 没有设置时 [UartIface::init] 会申请一个新的通知并绑定在当前的 TCB 上，
+/// 中断到来时服务会收到 badge 为 `u64::MAX` 的消息。
+pub fn set_irq_notification(notify: Cap<Notification>) {
+    IRQ_NOTIFY.call_once(|| notify);
 }
 
+/// 镜像日志控制台的第二个 PL011 串口
+struct MirrorUart(Pl011Uart);
+
+unsafe impl Send for MirrorUart {}
+
 pub struct Pl011UartIfaceImpl {
     device: Pl011Uart,
-    notify: Cap<Notification>,
     irq_handler: Cap<IrqHandler>,
+    /// 已经收到但还没有被读取的字符
+    rx: VecDeque<u8>,
+    /// 中断是否已经绑定到通知上
+    irq_enabled: bool,
+    /// 每个虚拟控制台和客户端共享的发送环
+    tx: [Option<&'static ByteRing>; VC_COUNT],
+    /// 虚拟控制台复用器
+    mux: VConsoleMux,
+    /// 读取服务日志的位置
+    logs: LogCursor,
 }
 
 impl Pl011UartIfaceImpl {
-    pub fn new(addr: usize) -> Self {
-        debug_println!("create new pl011 iface impl");
+    pub fn new(addr: usize, irq: usize) -> Self {
+        log::debug!("create new pl011 iface impl");
         let mut device = Pl011Uart::new(addr as _);
-        let notify = alloc_slot().cap();
         let irq_handler = alloc_slot().cap();
         // 向 root-task 申请一个中断
-        register_irq(SERIAL_DEVICE_IRQ, LeafSlot::from_cap(irq_handler));
-
-        // 向 root-task 申请一个通知
-        register_notify(LeafSlot::from_cap(notify), usize::MAX)
-            .expect("Can't register interrupt handler");
+        register_irq(irq, LeafSlot::from_cap(irq_handler));
 
         // 设置 pl011 地址空间
         device.init();
         device.ack_interrupts();
 
-        irq_handler.irq_handler_set_notification(notify).unwrap();
-        irq_handler.irq_handler_ack().unwrap();
-
-        // 将 Notification 绑定在 TCB 上,以便在接受 IPC 的时候也可以接受 notify
-        // sel4::init_thread::slot::TCB
-        //     .cap()
-        //     .tcb_bind_notification(notify)
-        //     .unwrap();
-
         Self {
             device,
-            notify,
             irq_handler,
+            rx: VecDeque::with_capacity(RX_BUFFER_SIZE),
+            irq_enabled: false,
+            tx: [None; VC_COUNT],
+            mux: VConsoleMux::new(),
+            logs: LogCursor::new(),
+        }
+    }
+
+    /// 存在第二个 PL011 串口时，把内核日志和服务日志的虚拟控制台镜像到这个串口
+    fn init_mirror(&mut self) {
+        let Some(device) = find_device(DeviceKind::Pl011, 1) else {
+            return;
+        };
+        let addr = match map_device(VIRT_PL011_MIRROR_ADDR, device.paddr, PAGE_SIZE, true) {
+            Ok(addr) => addr,
+            Err(_) => {
+                log::warn!("can't map the mirror pl011 at {:#x}", device.paddr);
+                return;
+            }
+        };
+        let mut uart = Pl011Uart::new(addr as _);
+        uart.init();
+        let uart = Arc::new(Mutex::new(MirrorUart(uart)));
+        for vc in [VC_KERNEL_LOG, VC_SERVICE_LOG] {
+            let uart = uart.clone();
+            let mirror = move |data: &[u8]| {
+                let mut uart = uart.lock();
+                data.iter().for_each(|c| uart.0.putchar(*c));
+            };
+            self.mux.set_mirror(vc, Some(Box::new(mirror)));
+        }
+    }
+
+    /// 向虚拟控制台输出，控制台正在显示时写入串口
+    fn output(&mut self, vc: usize, data: &[u8]) {
+        if self.mux.write(vc, data) {
+            data.iter().for_each(|c| self.device.putchar(*c));
+        }
+    }
+
+    /// 把硬件 FIFO 中的字符交给虚拟控制台复用器处理，并响应中断
+    fn drain(&mut self) {
+        while let Some(c) = self.device.getchar() {
+            match self.mux.input(c) {
+                VcInput::Deliver(c) if self.rx.len() < RX_BUFFER_SIZE => self.rx.push_back(c),
+                VcInput::Switch(vc) => {
+                    let device = &mut self.device;
+                    self.mux
+                        .switch(vc, |data| data.iter().for_each(|c| device.putchar(*c)));
+                }
+                _ => {}
+            }
+        }
+        self.device.ack_interrupts();
+        if self.irq_enabled {
+            self.irq_handler.irq_handler_ack().unwrap();
+        }
+        self.drain_logs();
+    }
+
+    /// 把日志环中新的服务日志输出到服务日志的虚拟控制台，警告和错误同时输出到 [VC_SERVICE_ERROR]
+    fn drain_logs(&mut self) {
+        let Some(ring) = klog::ring() else {
+            return;
+        };
+        for entry in self.logs.poll(ring) {
+            // kernel-thread 的日志已经通过发送环输出到内核日志的虚拟控制台
+            if entry.service == KERNEL_LOG_SERVICE {
+                continue;
+            }
+            let line = entry.colored();
+            self.output(VC_SERVICE_LOG, line.as_bytes());
+            if entry.level <= Level::Warn {
+                self.output(VC_SERVICE_ERROR, line.as_bytes());
+            }
         }
     }
 }
@@ -80,23 +191,114 @@ unsafe impl Sync for Pl011UartIfaceImpl {}
 unsafe impl Send for Pl011UartIfaceImpl {}
 
 impl UartIface for Pl011UartIfaceImpl {
-    fn init(&mut self) {}
+    fn init(&mut self) {
+        if self.irq_enabled {
+            return;
+        }
+        let notify = *IRQ_NOTIFY.call_once(|| {
+            // 向 root-task 申请一个通知
+            let notify = alloc_slot().cap();
+            register_notify(LeafSlot::from_cap(notify), usize::MAX)
+                .expect("Can't register interrupt handler");
+            // 将 Notification 绑定在 TCB 上,以便在接受 IPC 的时候也可以接受 notify
+            init_thread::slot::TCB
+                .cap()
+                .tcb_bind_notification(notify)
+                .unwrap();
+            notify
+        });
+        self.irq_handler
+            .irq_handler_set_notification(notify)
+            .unwrap();
+        self.irq_enabled = true;
+
+        // 服务放入日志之后通过同一个通知唤醒串口服务，输出到服务日志的虚拟控制台
+        if let Some(ring) = klog::ring() {
+            match set_channel_notify(LOG_CHANNEL_ID, LeafSlot::from_cap(notify)) {
+                Ok(()) => ring.enable_doorbell(),
+                Err(_) => log::warn!("can't register the notification of the log ring"),
+            }
+        }
+        self.init_mirror();
+        self.drain();
+    }
 
     fn putchar(&mut self, c: u8) {
-        self.device.putchar(c);
+        self.output(VC_APP, &[c]);
     }
 
-    fn getchar(&mut self) -> u8 {
-        self.notify.wait();
-        let char = self.device.getchar().unwrap();
-        self.device.ack_interrupts();
-        self.irq_handler.irq_handler_ack().unwrap();
-        char
+    /// 阻塞读取一个字符
+    ///
+    /// 会占用当前线程直到收到输入，服务中应该使用 [UartIface::try_getchar] 并保存调用者
+    fn getchar(&mut self) -> Result<u8, Errno> {
+        loop {
+            if let Some(c) = self.try_getchar() {
+                return Ok(c);
+            }
+            core::hint::spin_loop();
+        }
     }
 
     fn puts(&mut self, bytes: &[u8]) {
-        for &c in bytes {
-            self.putchar(c);
+        self.output(VC_APP, bytes);
+    }
+
+    fn try_getchar(&mut self) -> Option<u8> {
+        self.drain();
+        self.rx.pop_front()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.drain();
+        let rlen = buf.len().min(self.rx.len());
+        self.rx
+            .drain(..rlen)
+            .zip(buf.iter_mut())
+            .for_each(|(c, x)| *x = c);
+        Ok(rlen)
+    }
+
+    fn init_tx(&mut self, channel_id: usize, vc: usize) {
+        if vc >= VC_COUNT {
+            return;
+        }
+        // TODO: 支持多个程序的 channel 初始化，根据 badge 区分不同的程序
+        let ptr = alloc_free_addr(0) as *mut u8;
+        let size = join_channel(channel_id, ptr as usize);
+        alloc_free_addr(size);
+        let tx = unsafe { ByteRing::from_addr(ptr as usize) };
+        tx.reset();
+        self.tx[vc] = Some(tx);
+
+        // 应用程序控制台的客户端通过通道登记接收输入的通知，和客户端合并运行时不需要通知
+        if vc != VC_APP {
+            return;
+        }
+        let mut client_notify = CLIENT_NOTIFY.lock();
+        if let Some(notify) = client_notify.take() {
+            let slot = LeafSlot::from_cap(notify);
+            slot.delete().unwrap();
+            recycle_slot(slot);
+        }
+        let slot = alloc_slot();
+        match channel_notify(channel_id, slot) {
+            Ok(()) => *client_notify = Some(slot.cap()),
+            Err(_) => recycle_slot(slot),
+        }
+    }
+
+    /// 把发送环中的数据全部写入虚拟控制台
+    fn flush_tx(&mut self, vc: usize) {
+        let Some(tx) = self.tx.get(vc).copied().flatten() else {
+            return;
+        };
+        let mut buffer = [0u8; 64];
+        loop {
+            let len = tx.pop(&mut buffer);
+            if len == 0 {
+                break;
+            }
+            self.output(vc, &buffer[..len]);
         }
     }
 }
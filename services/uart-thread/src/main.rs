@@ -4,29 +4,66 @@
 extern crate alloc;
 extern crate uart_thread;
 
-use common::{config::DEFAULT_SERVE_EP, read_types, reply_with};
+use common::{config::DEFAULT_SERVE_EP, ipc_saver::IpcSaver, read_types, reply_with};
 use sel4::{MessageInfoBuilder, with_ipc_buffer_mut};
-use srv_gate::uart::UartIfaceEvent;
-use uart_thread::PL011DRV;
+use sel4_runtime::utils::alloc_free_addr;
+use srv_gate::{
+    klog,
+    uart::{UART_READ_MAX, UartIfaceEvent},
+};
+use uart_thread::{PL011DRV, notify_client};
 
 sel4_runtime::define_heap!(common::config::SERVICE_HEAP_SIZE);
 
 #[sel4_runtime::main]
 fn main() {
+    // 加入日志环，日志由串口服务输出到服务日志的虚拟控制台，也可以通过 dmesg 查看
+    let log_addr = alloc_free_addr(0);
+    alloc_free_addr(klog::init(
+        "uart-thread",
+        log_addr,
+        log::LevelFilter::Debug,
+        None,
+    ));
+
     log::info!("Booting...");
     let mut pl011 = PL011DRV.lock();
+    pl011.init();
+
+    // 等待输入的 getchar 调用者 (例如 simple-cli)，收到中断后按顺序回复，
+    // 剩余的输入通过通知交给使用 read 读取的客户端 (例如 kernel-thread)
+    let mut getchar_waiters = IpcSaver::new();
 
     with_ipc_buffer_mut(|ib| {
         loop {
-            // TODO: 根据 badge 保存 IPC reply，并在需要的时候发回
-            let (msg, _) = DEFAULT_SERVE_EP.recv(());
+            let (msg, badge) = DEFAULT_SERVE_EP.recv(());
+            // 串口中断
+            if badge == u64::MAX {
+                // 读取 0 个字符，只把硬件 FIFO 中的字符放入缓冲区并响应中断
+                let _ = pl011.read(&mut []);
+                while getchar_waiters.queue_len() > 0 {
+                    let Some(c) = pl011.try_getchar() else {
+                        break;
+                    };
+                    ib.msg_regs_mut()[0] = c as _;
+                    getchar_waiters
+                        .reply_one(MessageInfoBuilder::default().length(1).build())
+                        .unwrap();
+                }
+                notify_client();
+                continue;
+            }
             let msg_label = match UartIfaceEvent::try_from(msg.label()) {
                 Ok(label) => label,
                 Err(_) => continue,
             };
             match msg_label {
                 UartIfaceEvent::init => sel4::reply(ib, MessageInfoBuilder::default().build()),
-                UartIfaceEvent::getchar => reply_with!(ib, pl011.getchar()),
+                // 没有输入时保存调用者，收到输入后再回复
+                UartIfaceEvent::getchar => match pl011.try_getchar() {
+                    Some(c) => reply_with!(ib, c),
+                    None => getchar_waiters.save_caller().unwrap(),
+                },
                 UartIfaceEvent::putchar => {
                     pl011.putchar(read_types!(u8));
                     sel4::reply(ib, MessageInfoBuilder::default().build());
@@ -35,6 +72,36 @@ fn main() {
                     pl011.puts(&read_types!(&[u8]));
                     sel4::reply(ib, MessageInfoBuilder::default().build());
                 }
+                // 第一个寄存器表示是否读取到字符，第二个寄存器是读取到的字符
+                UartIfaceEvent::try_getchar => {
+                    let c = pl011.try_getchar();
+                    ib.msg_regs_mut()[0] = c.is_some() as _;
+                    ib.msg_regs_mut()[1] = c.unwrap_or(0) as _;
+                    sel4::reply(ib, MessageInfoBuilder::default().length(2).build());
+                }
+                UartIfaceEvent::read => {
+                    let mut buf = [0u8; UART_READ_MAX];
+                    let len = read_types!(usize).min(UART_READ_MAX);
+                    let rlen = pl011.read(&mut buf[..len]).unwrap_or(0);
+                    let offset = size_of::<sel4::Word>();
+                    ib.msg_regs_mut()[0] = rlen as _;
+                    ib.msg_bytes_mut()[offset..offset + rlen].copy_from_slice(&buf[..rlen]);
+                    let length = 1 + rlen.div_ceil(size_of::<sel4::Word>());
+                    sel4::reply(ib, MessageInfoBuilder::default().length(length).build());
+                }
+                UartIfaceEvent::init_tx => {
+                    let (channel_id, vc) = read_types!(ib, usize, usize);
+                    pl011.init_tx(channel_id, vc);
+                    sel4::reply(ib, MessageInfoBuilder::default().build());
+                }
+                // 先回复客户端再发送，客户端不需要等待串口发送完成
+                UartIfaceEvent::flush_tx => {
+                    let vc = read_types!(ib, usize);
+                    sel4::reply(ib, MessageInfoBuilder::default().build());
+                    pl011.flush_tx(vc);
+                    // 发送环已经清空，客户端可以继续写入
+                    notify_client();
+                }
             }
         }
     });
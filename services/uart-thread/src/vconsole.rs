@@ -0,0 +1,123 @@
+//! 虚拟控制台
+//!
+//! 串口上复用多个虚拟控制台：应用程序的控制台、内核日志和服务日志。每个虚拟控制台
+//! 保存最近的输出，只有当前显示的控制台会输出到串口。输入 [VC_HOTKEY] 之后再输入
+//! 控制台编号 (`1` 开始) 切换控制台，切换时清屏并重新输出新控制台保存的内容。
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, format};
+use srv_gate::uart::{VC_APP, VC_COUNT, VC_HOTKEY};
+
+/// 每个虚拟控制台保存的输出的大小
+pub const VC_SCROLLBACK_SIZE: usize = 8192;
+
+/// 虚拟控制台的名称，切换时显示
+const VC_NAMES: [&str; VC_COUNT] = ["app", "kernel log", "service log", "service errors"];
+
+/// 虚拟控制台的输出镜像，例如第二个串口或者 virtio-console
+pub type VcMirror = Box<dyn FnMut(&[u8]) + Send>;
+
+/// 处理输入字符的结果
+pub enum VcInput {
+    /// 交给应用程序的字符
+    Deliver(u8),
+    /// 切换到指定的虚拟控制台
+    Switch(usize),
+    /// 字符被丢弃
+    Discard,
+}
+
+/// 虚拟控制台复用器
+pub struct VConsoleMux {
+    /// 当前显示的虚拟控制台
+    active: usize,
+    /// 每个虚拟控制台最近的输出
+    scrollback: [VecDeque<u8>; VC_COUNT],
+    /// 每个虚拟控制台的输出镜像
+    mirrors: [Option<VcMirror>; VC_COUNT],
+    /// 上一个输入的字符是 [VC_HOTKEY]
+    hotkey: bool,
+}
+
+impl VConsoleMux {
+    /// 创建复用器，默认显示应用程序的控制台
+    pub fn new() -> Self {
+        Self {
+            active: VC_APP,
+            scrollback: core::array::from_fn(|_| VecDeque::new()),
+            mirrors: core::array::from_fn(|_| None),
+            hotkey: false,
+        }
+    }
+
+    /// 当前显示的虚拟控制台
+    pub const fn active(&self) -> usize {
+        self.active
+    }
+
+    /// 设置虚拟控制台的输出镜像，`mirror` 为 [Option::None] 时取消镜像
+    pub fn set_mirror(&mut self, vc: usize, mirror: Option<VcMirror>) {
+        if let Some(x) = self.mirrors.get_mut(vc) {
+            *x = mirror;
+        }
+    }
+
+    /// 向虚拟控制台写入数据
+    ///
+    /// 数据保存在控制台中并输出到镜像，返回控制台是否正在显示，正在显示时需要输出到串口
+    pub fn write(&mut self, vc: usize, data: &[u8]) -> bool {
+        let Some(scrollback) = self.scrollback.get_mut(vc) else {
+            return false;
+        };
+        scrollback.extend(data);
+        if scrollback.len() > VC_SCROLLBACK_SIZE {
+            let overflow = scrollback.len() - VC_SCROLLBACK_SIZE;
+            scrollback.drain(..overflow);
+        }
+        if let Some(mirror) = &mut self.mirrors[vc] {
+            mirror(data);
+        }
+        vc == self.active
+    }
+
+    /// 切换到指定的虚拟控制台
+    ///
+    /// 切换成功时通过 `out` 输出清屏、控制台名称和控制台保存的内容
+    pub fn switch(&mut self, vc: usize, mut out: impl FnMut(&[u8])) {
+        if vc >= VC_COUNT || vc == self.active {
+            return;
+        }
+        self.active = vc;
+        out(b"\x1b[2J\x1b[H");
+        out(format!("[vc{}: {}]\r\n", vc + 1, VC_NAMES[vc]).as_bytes());
+        let (head, tail) = self.scrollback[vc].as_slices();
+        out(head);
+        out(tail);
+    }
+
+    /// 处理一个输入字符
+    ///
+    /// [VC_HOTKEY] 之后的数字切换控制台，连续两个 [VC_HOTKEY] 输入一个 [VC_HOTKEY]。
+    /// 只有应用程序的控制台正在显示时才把输入交给应用程序
+    pub fn input(&mut self, c: u8) -> VcInput {
+        if core::mem::take(&mut self.hotkey) {
+            if (b'1'..b'1' + VC_COUNT as u8).contains(&c) {
+                return VcInput::Switch((c - b'1') as usize);
+            }
+            if c != VC_HOTKEY {
+                return VcInput::Discard;
+            }
+        } else if c == VC_HOTKEY {
+            self.hotkey = true;
+            return VcInput::Discard;
+        }
+        match self.active == VC_APP {
+            true => VcInput::Deliver(c),
+            false => VcInput::Discard,
+        }
+    }
+}
+
+impl Default for VConsoleMux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
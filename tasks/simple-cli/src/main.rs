@@ -43,7 +43,10 @@ fn main() {
         debug_print!("> ");
         let mut str = Vec::new();
         loop {
-            let char = UART_IMPLS[0].lock().getchar();
+            let Ok(char) = UART_IMPLS[0].lock().getchar() else {
+                log::error!("Uart service is unavailable");
+                return;
+            };
             debug_print!("{}", char::from_u32(char as _).unwrap());
 
             match char {
@@ -16,7 +16,19 @@ FILE_DIR = path.dirname(path.realpath(__file__))
 
 class Task:
     def __init__(
-        self, name: str, file: str, mem: list, dma: list, deplist: list, cfglist: list
+        self,
+        name: str,
+        file: str,
+        mem: list,
+        dma: list,
+        deplist: list,
+        cfglist: list,
+        restart: str,
+        backoff: int,
+        allow: list,
+        quota: dict,
+        sched: dict,
+        app_sched: dict,
     ):
         self.name = name
         self.file = file
@@ -24,6 +36,12 @@ class Task:
         self.dma = dma
         self.deplist = deplist
         self.cfglist = cfglist
+        self.restart = restart
+        self.backoff = backoff
+        self.allow = allow
+        self.quota = quota
+        self.sched = sched
+        self.app_sched = app_sched
         self.deptask = []
         self.in_degree = 0
 
@@ -58,6 +76,17 @@ class Task:
             ret.extend(task.get_mems())
         return ret
 
+    # 没有声明 allow 的任务不能查找任何服务
+    # 合并到这个任务中的依赖的 allow 也会合并
+    def get_allows(self):
+        ret = []
+        ret.extend(map(parse_allow, self.allow or []))
+        for task in self.deptask:
+            if task.in_degree > 1:
+                continue
+            ret.extend(task.get_allows())
+        return ret
+
     def get_dmas(self):
         ret = []
         ret.extend(self.dma)
@@ -70,6 +99,52 @@ class Task:
 
 tasks: List[Task] = {}
 
+# apps.toml 中的重启策略对应的 RestartPolicy
+RESTART_POLICIES = {
+    "never": "RestartPolicy::Never",
+    "on-failure": "RestartPolicy::OnFailure",
+    "always": "RestartPolicy::Always",
+}
+
+# apps.toml 中 quota 可以限制的资源，没有写出的资源使用 ResourceUsage::DEFAULT_QUOTA 中的配额
+QUOTA_KEYS = ["untyped", "pages", "channels", "slots"]
+
+# apps.toml 中 sched 和 app-sched 可以设置的调度参数，预算和周期的单位是微秒，没有写出的参数使用默认值
+SCHED_KEYS = ["priority", "budget", "period"]
+
+# apps.toml 中服务端口的权限对应的 ServiceRights 字段
+SERVICE_RIGHTS = ["read", "write", "grant", "grant-reply"]
+
+# 只写出服务名称时的权限，可以调用服务但是不能接收服务的消息或者传递 Capability
+CALL_RIGHTS = ["write", "grant-reply"]
+
+
+# allow 中的一项可以是服务名称 (只能调用服务)，也可以是 { name = "服务名称", rights = [权限] }
+def parse_allow(allow):
+    if isinstance(allow, str):
+        return (allow, CALL_RIGHTS)
+    for right in allow["rights"]:
+        if right not in SERVICE_RIGHTS:
+            print("unknown service right %s" % (right))
+            exit(1)
+    return (allow["name"], allow["rights"])
+
+
+# mem 中的一项是 [虚拟地址, 物理地址, 大小] 或者 [虚拟地址, 物理地址, 大小, "shared"]，
+# 只有写出 shared 的设备内存可以被多个任务同时映射
+def is_shared(mem):
+    if len(mem) > 3 and mem[3] != "shared":
+        print("unknown device memory flag %s" % (mem[3]))
+        exit(1)
+    return len(mem) > 3
+
+
+# 输出 SchedParams，没有写出的参数使用 default 中的值
+def format_sched(sched, default):
+    fields = ["%s: %s" % (key, sched[key]) for key in SCHED_KEYS if key in sched]
+    fields.append("..SchedParams::%s" % (default))
+    return "SchedParams { %s }" % (", ".join(fields))
+
 
 def get_all_standalone_tasks():
     ret = []
@@ -90,7 +165,24 @@ def parse_config(config):
             task.get("dma", []),
             task.get("deps", []),
             task.get("cfg", []),
+            task.get("restart", "never"),
+            task.get("backoff", 100),
+            task.get("allow"),
+            task.get("quota", {}),
+            task.get("sched", {}),
+            task.get("app-sched", {}),
         )
+        for key in task_obj.quota:
+            if key not in QUOTA_KEYS:
+                print("unknown quota %s of %s" % (key, task_obj.name))
+                exit(1)
+        for key in list(task_obj.sched) + list(task_obj.app_sched):
+            if key not in SCHED_KEYS:
+                print("unknown sched parameter %s of %s" % (key, task_obj.name))
+                exit(1)
+        if task_obj.restart not in RESTART_POLICIES:
+            print("unknown restart policy %s of %s" % (task_obj.restart, task_obj.name))
+            exit(1)
         tasks[task["name"]] = task_obj
     for task in tasks.values():
         task.init()
@@ -105,12 +197,34 @@ def write_to_file(file):
         output += 'name: "%s", \n' % (task.name)
         output += 'file: "%s", \n' % (task.file)
         mem_list = [
-            "(%s, %s, %s)" % (mem[0], mem[1], mem[2]) for mem in task.get_mems()
+            "(%s, %s, %s, %s)" % (mem[0], mem[1], mem[2], str(is_shared(mem)).lower())
+            for mem in task.get_mems()
         ]
         output += "mem: &[%s],\n" % (",\n".join(mem_list))
 
         dma_list = ["(%s, %s)" % (dma[0], dma[1]) for dma in task.get_dmas()]
         output += "dma: &[%s],\n" % (",\n".join(dma_list))
+        output += "restart: %s,\n" % (RESTART_POLICIES[task.restart])
+        output += "backoff: %d,\n" % (task.backoff)
+        allow_list = [
+            '("%s", ServiceRights { %s })'
+            % (
+                name,
+                ", ".join(
+                    "%s: %s" % (right.replace("-", "_"), str(right in rights).lower())
+                    for right in SERVICE_RIGHTS
+                ),
+            )
+            for (name, rights) in task.get_allows()
+        ]
+        output += "allow: &[%s],\n" % (",\n".join(allow_list))
+        quota_list = [
+            "%s: %s" % (key, task.quota.get(key, "ResourceUsage::DEFAULT_QUOTA.%s" % key))
+            for key in QUOTA_KEYS
+        ]
+        output += "quota: ResourceUsage { %s },\n" % (", ".join(quota_list))
+        output += "sched: %s,\n" % (format_sched(task.sched, "SERVICE"))
+        output += "app_sched: %s,\n" % (format_sched(task.app_sched, "APP"))
 
         output += "},"
 